@@ -6,15 +6,24 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use core::cell::RefCell;
+
 use defmt::*;
+use embedded_hal_bus::i2c::RefCellDevice;
 use esp_hal::{
     i2c::master::{
         I2c, Config
     },
     clock::CpuClock,
     delay::Delay,
+    time::Instant,
     main
 };
+use esp_storage::FlashStorage;
+use example_support::calibration_store::{self, CalibrationStore, StoredCalibration};
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use example_support::presence::{self, PresenceEvent, PresenceMonitor};
+use example_support::ppg::Spo2Curve;
 use hayasen::max30102_hayasen::{
     create_default_with_address, 
     read_fifo_batch, 
@@ -29,6 +38,27 @@ use esp_backtrace as _;
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// How often the sensor identity and configuration are verified.
+const PRESENCE_CHECK_MS: u64 = 2_000;
+
+/// `(R, SpO2)` pairs for fitting the SpO2 curve of this board: the R printed with
+/// each SpO2 reading, and what a reference oximeter on another finger showed at the
+/// same time. Collect them at a few saturations, e.g. by holding the breath, and
+/// flash again. With two or more pairs the fitted curve is saved and used from then
+/// on; leave it empty to keep the curve in flash, or the default without one.
+const SPO2_REFERENCE: &[(f32, f32)] = &[];
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => warn!("MAX30102 init attempt {} failed: {}, retrying in {} ms", attempt.number, Debug2Format(attempt.error), ms),
+        None => error!("MAX30102 init attempt {} failed: {}, giving up", attempt.number, Debug2Format(attempt.error)),
+    }
+}
+
 struct HeartRateDetector {
     samples: [u32; 8],
     index: usize,
@@ -149,10 +179,13 @@ struct SpO2Detector {
     red_dc_filter: i32,
     ir_dc_filter: i32,
     spo2_value: u32,
+    /// The last ratio of ratios, for fitting the curve.
+    ratio: f32,
+    curve: Spo2Curve,
 }
 
 impl SpO2Detector {
-    fn new() -> Self {
+    fn new(curve: Spo2Curve) -> Self {
         Self {
             red_ac_sum: 0,
             ir_ac_sum: 0,
@@ -162,6 +195,8 @@ impl SpO2Detector {
             red_dc_filter: 0,
             ir_dc_filter: 0,
             spo2_value: 0,
+            ratio: 0.0,
+            curve,
         }
     }
 
@@ -211,19 +246,9 @@ impl SpO2Detector {
                 if ir_ratio > 0 {
                     let r_ratio = (red_ratio * 1000) / ir_ratio;
 
-                    // SpO2 calibration formula (empirically derived)
-                    // SpO2 = 104 - 17 * R
-                    let spo2_calc = 104000 - (17 * r_ratio);
-                    let spo2_percent = spo2_calc / 1000;
-
-                    // Clamp SpO2 to reasonable range (70-100%)
-                    let spo2_final = if spo2_percent < 70 {
-                        70
-                    } else if spo2_percent > 100 {
-                        100
-                    } else {
-                        spo2_percent as u32
-                    };
+                    // Calibration curve, clamped to a reasonable range (70-100%)
+                    self.ratio = r_ratio as f32 / 1000.0;
+                    let spo2_final = self.curve.spo2(self.ratio) as u32;
 
                     // Simple averaging filter
                     if self.spo2_value == 0 {
@@ -245,6 +270,10 @@ impl SpO2Detector {
         Some(self.spo2_value)
     }
 
+    fn ratio(&self) -> f32 {
+        self.ratio
+    }
+
     fn get_signal_quality(&self, red: u32, ir: u32) -> bool {
         // Consider signal good if both values are above threshold
         red > 5000 && ir > 5000
@@ -259,7 +288,7 @@ fn main() -> ! {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
 
-    let delay = Delay::new();
+    let mut delay = Delay::new();
     let sda = peripherals.GPIO4;
     let scl = peripherals.GPIO5;
 
@@ -268,24 +297,65 @@ fn main() -> ! {
         .with_sda(sda)
         .with_scl(scl);
 
-    let mut sensor = match create_default_with_address(i2c) {
-        Ok(mut s) => {
-            info!("Sensor initialized successfully!");
+    // The driver gets a handle to the bus rather than the bus itself, so a failed
+    // init attempt does not lose it
+    let bus = RefCell::new(i2c);
+    let init_sensor = || {
+        create_default_with_address(RefCellDevice::new(&bus)).map(|mut s| {
             let _ = setup_high_performance_mode(&mut s);
             s
-        }
-        Err(_) => {
-            error!("Failed to initialize sensor");
-            loop {
-                delay.delay_millis(1000);
-            }
-        }
+        })
     };
+    let backoff = Backoff::default();
+
+    let initial = init_with_retry(
+        &backoff,
+        &mut delay,
+        init_sensor,
+        report_init_failure,
+    );
+    match initial {
+        Ok(_) => info!("Sensor initialized successfully!"),
+        Err(_) => warn!("MAX30102 unavailable, running degraded and retrying in the background"),
+    }
+    let mut monitor = PresenceMonitor::new(presence::MAX30102);
+    if initial.is_ok() {
+        let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+    }
+    let mut sensor = SensorSupervisor::new(initial, now_ms(), backoff);
+    let mut last_presence_check = now_ms();
 
     info!("Place finger on sensor and keep still...");
 
     let mut hr_detector = HeartRateDetector::new();
-    let mut spo2_detector = SpO2Detector::new();
+    // A curve fitted for this board against a reference oximeter, if one was saved
+    let mut store = CalibrationStore::in_partition(FlashStorage::new(), calibration_store::PARTITION_OFFSET, calibration_store::PARTITION_SIZE).ok();
+    let loaded = store.as_mut().map(|store| store.load());
+    // Saving over a record that could not be read would lose the other calibrations in it
+    let writable = matches!(loaded, Some(Ok(_)));
+    let mut stored = match loaded {
+        Some(Ok(stored)) => stored.unwrap_or_default(),
+        _ => StoredCalibration::default(),
+    };
+    if !SPO2_REFERENCE.is_empty() {
+        match Spo2Curve::fit(SPO2_REFERENCE) {
+            Some(fitted) => {
+                info!("Fitted SpO2 = {} + {} * R + {} * R^2", fitted.a, fitted.b, fitted.c);
+                stored.spo2 = Some(fitted);
+                match store.as_mut().filter(|_| writable).map(|store| store.save(&stored)) {
+                    Some(Ok(())) => info!("SpO2 calibration saved to flash"),
+                    Some(Err(e)) => error!("Failed to save the SpO2 calibration: {}", Debug2Format(&e)),
+                    None => warn!("Calibration store unavailable, the fitted curve is only used until the next reset"),
+                }
+            },
+            None => error!("The SpO2 reference readings do not determine a curve, they need two or more different R"),
+        }
+    }
+    let curve = stored.spo2;
+    if curve.is_none() {
+        info!("No SpO2 calibration in flash, using SpO2 = 104 - 17 * R");
+    }
+    let mut spo2_detector = SpO2Detector::new(curve.unwrap_or_default());
     let mut sample_buffer: [FifoSample; 16] = core::array::from_fn(|_| FifoSample { red: 0, ir: 0 });
 
     let mut time_ms: u32 = 0;
@@ -297,7 +367,33 @@ fn main() -> ! {
     let mut display_phase = 0; // 0 = HR, 1 = Temp, 2 = SpO2
 
     loop {
-        if let Ok(count) = read_fifo_batch(&mut sensor, &mut sample_buffer) {
+        if now_ms() - last_presence_check >= PRESENCE_CHECK_MS {
+            last_presence_check = now_ms();
+            let event = monitor.check(&mut RefCellDevice::new(&bus));
+            match event {
+                Some(PresenceEvent::Disconnected) => warn!("MAX30102 disconnected"),
+                Some(PresenceEvent::Reconnected) => info!("MAX30102 reconnected, re-applying configuration"),
+                Some(PresenceEvent::Reset) => warn!("MAX30102 lost its configuration, re-applying it"),
+                None => {}
+            }
+            // Drop the driver, the supervisor re-creates it once the device answers again
+            if event.is_some() {
+                sensor.mark_failed(now_ms());
+            }
+        }
+
+        let was_ready = sensor.is_ready();
+        let Some(ppg) = sensor.poll(now_ms(), init_sensor, report_init_failure) else {
+            // Nothing to sample yet, keep the loop (and anything else it drives) running
+            delay.delay_millis(20);
+            continue;
+        };
+        if !was_ready {
+            info!("Sensor initialized successfully!");
+            let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+        }
+
+        if let Ok(count) = read_fifo_batch(ppg, &mut sample_buffer) {
             if count > 0 {
                 for i in 0..count {
                     let red = sample_buffer[i].red;
@@ -353,7 +449,7 @@ fn main() -> ! {
                         let ir_sample = if sample_buffer.len() > 0 { sample_buffer[0].ir } else { 0 };
                         
                         if spo2_detector.get_signal_quality(red_sample, ir_sample) {
-                            info!("🫁 SpO2: {}%", current_spo2);
+                            info!("🫁 SpO2: {}% (R = {})", current_spo2, spo2_detector.ratio());
                         } else {
                             info!("🫁 Improving SpO2 signal...");
                         }
@@ -374,10 +470,10 @@ fn main() -> ! {
         temp_counter += 1;
         if temp_counter >= 250 {
             temp_counter = 0;
-            let _ = start_temperature_measurement(&mut sensor);
+            let _ = start_temperature_measurement(ppg);
             delay.delay_millis(30);
 
-            if let Ok(Some(temp)) = read_temperature(&mut sensor) {
+            if let Ok(Some(temp)) = read_temperature(ppg) {
                 current_temp = temp;
             }
         }
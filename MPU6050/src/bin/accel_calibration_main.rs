@@ -0,0 +1,203 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_backtrace as _;
+use log::{info, warn, error};
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    delay::Delay,
+    time::Instant,
+    main
+};
+use esp_storage::FlashStorage;
+use example_support::accel_calibration::{AccelCalibration, SixPosition, Step};
+use example_support::calibration::Orientation;
+use example_support::calibration_store::{self, CalibrationStore, StoredCalibration};
+use example_support::imu::Imu;
+use example_support::init::{init_with_retry, Attempt, Backoff};
+use hayasen::mpu6050_hayasen;
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// Still readings averaged in each orientation.
+const SAMPLES_PER_POSITION: u32 = 200;
+const SAMPLE_INTERVAL_MS: u32 = 5;
+
+/// Run the calibration even if one is saved in flash already.
+const RECALIBRATE: bool = false;
+
+fn now_us() -> u64 {
+    Instant::now().duration_since_epoch().as_micros()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => warn!("MPU6050 init attempt {} failed: {:?}, retrying in {} ms", attempt.number, attempt.error, ms),
+        None => error!("MPU6050 init attempt {} failed: {:?}", attempt.number, attempt.error),
+    }
+}
+
+fn describe(orientation: Orientation) -> &'static str {
+    match orientation {
+        Orientation::XUp => "X axis pointing up",
+        Orientation::XDown => "X axis pointing down",
+        Orientation::YUp => "Y axis pointing up",
+        Orientation::YDown => "Y axis pointing down",
+        Orientation::ZUp => "flat, component side up",
+        Orientation::ZDown => "flat, component side down",
+    }
+}
+
+/// Guides the sensor through all six orientations until a fit succeeds.
+fn six_position<I: Imu>(imu: &mut I, delay: &Delay) -> AccelCalibration {
+    let mut run = SixPosition::new(SAMPLES_PER_POSITION);
+    let mut waiting_logged = false;
+
+    loop {
+        let sample = match imu.sample(now_us()) {
+            Ok(sample) => sample,
+            Err(e) => {
+                error!("Failed to read sensor data: {:?}", e);
+                delay.delay_millis(500);
+                continue;
+            }
+        };
+
+        match run.add(&sample) {
+            Step::Waiting { orientation } => {
+                if !waiting_logged {
+                    info!("Hold the MPU6050 still, {}", describe(orientation));
+                    waiting_logged = true;
+                }
+            },
+            Step::Collecting { .. } => {},
+            Step::Restarted { .. } => warn!("Movement detected, hold still"),
+            Step::PositionDone { orientation, next } => {
+                info!("Done with {}", describe(orientation));
+                info!("Now turn the MPU6050 so it is {}", describe(next));
+                waiting_logged = true;
+            },
+            Step::Done(Ok(calibration)) => return calibration,
+            Step::Done(Err(e)) => {
+                error!("Calibration failed: {:?}, starting over", e);
+                run = SixPosition::new(SAMPLES_PER_POSITION);
+                waiting_logged = false;
+            }
+        }
+        delay.delay_millis(SAMPLE_INTERVAL_MS);
+    }
+}
+
+#[main]
+fn main() -> ! {
+    esp_println::logger::init_logger_from_env();
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let mut delay = Delay::new();
+
+    let sda = peripherals.GPIO21;
+    let scl = peripherals.GPIO22;
+
+    let mpu_address: u8 = 0x68;
+
+    let i2c = I2c::new(peripherals.I2C0, Config::default())
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    // The driver gets a handle to the bus rather than the bus itself, so a failed
+    // init attempt does not lose it
+    let bus = RefCell::new(i2c);
+    let backoff = Backoff::default();
+
+    // There is nothing to calibrate without the sensor, so keep waiting for it
+    let mut imu = loop {
+        let initial = init_with_retry(
+            &backoff,
+            &mut delay,
+            || mpu6050_hayasen::create_default(RefCellDevice::new(&bus), mpu_address),
+            report_init_failure,
+        );
+        match initial {
+            Ok(imu) => break imu,
+            Err(_) => error!(
+                "No MPU6050 answers at {:#04x}, check the wiring and power. Still retrying",
+                mpu_address
+            ),
+        }
+    };
+    info!("MPU6050 initialized");
+
+    // Without the store the calibration is still run and used, just not saved
+    let opened = CalibrationStore::in_partition(FlashStorage::new(), calibration_store::PARTITION_OFFSET, calibration_store::PARTITION_SIZE);
+    let mut store = match opened {
+        Ok(store) => Some(store),
+        Err(e) => {
+            error!("Failed to open the calibration store: {:?}", e);
+            None
+        }
+    };
+    let mut stored = match store.as_mut().map(|store| store.load()) {
+        Some(Ok(stored)) => stored.unwrap_or_default(),
+        Some(Err(e)) => {
+            error!("Failed to load calibration: {:?}", e);
+            StoredCalibration::default()
+        },
+        None => StoredCalibration::default(),
+    };
+
+    let saved = stored.accel.filter(|_| !RECALIBRATE);
+    if saved.is_some() {
+        info!("Using the calibration saved in flash");
+    }
+
+    let calibration = match saved {
+        Some(saved) => saved,
+        None => {
+            let calibration = six_position(&mut imu, &delay);
+            stored.accel = Some(calibration);
+            match store.as_mut().map(|store| store.save(&stored)) {
+                Some(Ok(())) => info!("Calibration saved to flash"),
+                Some(Err(e)) => error!("Failed to save calibration: {:?}", e),
+                None => {}
+            }
+            calibration
+        }
+    };
+
+    let [bx, by, bz] = calibration.bias.as_g();
+    let [sx, sy, sz] = calibration.scale();
+    info!("Bias [X, Y, Z] : [{:.4}, {:.4}, {:.4}] g", bx, by, bz);
+    info!("Scale [X, Y, Z] : [{:.4}, {:.4}, {:.4}]", sx, sy, sz);
+    for row in calibration.matrix {
+        info!("Matrix row : [{:.5}, {:.5}, {:.5}]", row[0], row[1], row[2]);
+    }
+
+    // Show the corrected readings so the result can be checked in any orientation
+    loop {
+        match imu.sample(now_us()) {
+            Ok(sample) => {
+                let corrected = calibration.apply(sample.acceleration);
+                let [x, y, z] = corrected.as_g();
+                info!("Corrected Acceleration [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g, |a| = {:.3} g", x, y, z, corrected.norm().as_g());
+            },
+            Err(e) => {
+                error!("Failed to read sensor data: {:?}", e);
+            }
+        }
+        delay.delay_millis(500);
+    }
+}
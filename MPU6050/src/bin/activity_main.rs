@@ -0,0 +1,151 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_backtrace as _;
+use log::{info, warn, error};
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    delay::Delay,
+    time::Instant,
+    main
+};
+use example_support::activity::{DecisionTree, FeatureExtractor};
+use example_support::fall::FallDetector;
+use example_support::imu::Imu;
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use example_support::pedometer::Pedometer;
+use example_support::tap::{TapDetector, TapKind};
+use hayasen::mpu6050_hayasen;
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// The sensor is read every 5 ms (200 Hz), fast enough to catch the few milliseconds
+/// of a tap.
+const SAMPLE_PERIOD_MS: u32 = 5;
+/// Log a summary every 1000th reading, i.e. every 5 seconds.
+const LOG_EVERY: u32 = 1000;
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn now_us() -> u64 {
+    Instant::now().duration_since_epoch().as_micros()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => warn!("MPU6050 init attempt {} failed: {:?}, retrying in {} ms", attempt.number, attempt.error, ms),
+        None => error!("MPU6050 init attempt {} failed: {:?}, giving up", attempt.number, attempt.error),
+    }
+}
+
+#[main]
+fn main() -> ! {
+    esp_println::logger::init_logger_from_env();
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let mut delay = Delay::new();
+
+    let sda = peripherals.GPIO21;
+    let scl = peripherals.GPIO22;
+
+    let mpu_address: u8 = 0x68;
+
+    let i2c = I2c::new(peripherals.I2C0, Config::default())
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    // The driver gets a handle to the bus rather than the bus itself, so a failed
+    // init attempt does not lose it
+    let bus = RefCell::new(i2c);
+    let init_sensor = || mpu6050_hayasen::create_default(RefCellDevice::new(&bus), mpu_address);
+    let backoff = Backoff::default();
+
+    let initial = init_with_retry(&backoff, &mut delay, init_sensor, report_init_failure);
+    match initial {
+        Ok(_) => info!("MPU6050 initialized"),
+        Err(_) => warn!("MPU6050 unavailable, running degraded and retrying in the background"),
+    }
+    let mut sensor = SensorSupervisor::new(initial, now_ms(), backoff);
+
+    let mut pedometer = Pedometer::default();
+    let mut falls = FallDetector::default();
+    let mut taps = TapDetector::default();
+    let mut extractor = FeatureExtractor::new();
+    let classifier = DecisionTree::default();
+    let mut activity = None;
+    let mut readings: u32 = 0;
+
+    loop {
+        let was_ready = sensor.is_ready();
+        let Some(imu) = sensor.poll(now_ms(), init_sensor, report_init_failure) else {
+            // Nothing to sample yet, keep the loop running until the sensor shows up
+            delay.delay_millis(SAMPLE_PERIOD_MS);
+            continue;
+        };
+        if !was_ready {
+            info!("MPU6050 initialized");
+        }
+
+        match imu.sample(now_us()) {
+            Ok(sample) => {
+                // Steps only count once a steady rhythm is found, so the first few
+                // arrive together
+                let added = pedometer.update(&sample);
+                if added > 0 {
+                    info!("Steps : {} (+{}), cadence {:.0} steps/min", pedometer.steps(), added, pedometer.cadence());
+                }
+
+                if let Some(fall) = falls.update(&sample) {
+                    warn!(
+                        "Fall detected at {:.1} s: {} ms drop, {:.1} g impact, {:.0} deg orientation change",
+                        fall.timestamp_us as f32 / 1e6, fall.free_fall_ms, fall.impact.as_g(), fall.orientation_change
+                    );
+                }
+
+                if let Some(tap) = taps.update(&sample) {
+                    let kind = match tap.kind {
+                        TapKind::Single => "Tap",
+                        TapKind::Double => "Double tap",
+                    };
+                    let sign = if tap.direction.positive { '+' } else { '-' };
+                    info!("{} : {}{:?}, {:.1} g", kind, sign, tap.direction.axis, tap.strength.as_g());
+                }
+
+                // A new classification every 2.5 s, logged when it changes
+                if let Some(features) = extractor.update(&sample) {
+                    let classified = classifier.classify(&features);
+                    if activity != Some(classified) {
+                        info!("Activity : {:?} ({:?})", classified, features);
+                        activity = Some(classified);
+                    }
+                }
+
+                readings += 1;
+                if readings % LOG_EVERY == 0 {
+                    let state = if pedometer.is_walking() { "walking" } else { "not walking" };
+                    info!("Total steps : {}, {}, activity {:?}", pedometer.steps(), state, activity);
+                }
+            },
+            Err(e) => {
+                error!("Failed to read sensor data: {:?}", e);
+            }
+        }
+        delay.delay_millis(SAMPLE_PERIOD_MS);
+    }
+}
@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_backtrace as _;
+use log::{info, warn, error};
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    delay::Delay,
+    time::{Instant, Rate},
+    main
+};
+use example_support::dual_imu::{DualSampler, PairError, RelativeOrientation};
+use example_support::imu::Imu;
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use example_support::tilt::Tilt;
+use hayasen::mpu6050_hayasen;
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// Both units are sampled every 20 ms (50 Hz).
+const SAMPLE_PERIOD_MS: u32 = 20;
+/// Log every 25th pair, i.e. twice per second.
+const LOG_EVERY: u32 = 25;
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn now_us() -> u64 {
+    Instant::now().duration_since_epoch().as_micros()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(unit: &str, attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => warn!("Unit {} init attempt {} failed: {:?}, retrying in {} ms", unit, attempt.number, attempt.error, ms),
+        None => error!("Unit {} init attempt {} failed: {:?}, giving up", unit, attempt.number, attempt.error),
+    }
+}
+
+#[main]
+fn main() -> ! {
+    esp_println::logger::init_logger_from_env();
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let mut delay = Delay::new();
+
+    let sda = peripherals.GPIO21;
+    let scl = peripherals.GPIO22;
+
+    // Unit A has AD0 tied low, unit B has AD0 tied high
+    let address_a: u8 = 0x68;
+    let address_b: u8 = 0x69;
+
+    // Fast mode keeps the two reads of a pair close together
+    let i2c = I2c::new(peripherals.I2C0, Config::default().with_frequency(Rate::from_khz(400)))
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    let bus = RefCell::new(i2c);
+
+    let init_a = || mpu6050_hayasen::create_default(RefCellDevice::new(&bus), address_a);
+    let init_b = || mpu6050_hayasen::create_default(RefCellDevice::new(&bus), address_b);
+
+    // A unit that is still missing after the retries is retried in the background,
+    // while the other one is read on its own
+    let backoff = Backoff::default();
+    let initial = init_with_retry(&backoff, &mut delay, init_a, |attempt| report_init_failure("A", attempt));
+    if initial.is_err() {
+        warn!("Unit A unavailable, running degraded and retrying in the background");
+    }
+    let mut unit_a = SensorSupervisor::new(initial, now_ms(), backoff);
+    let initial = init_with_retry(&backoff, &mut delay, init_b, |attempt| report_init_failure("B", attempt));
+    if initial.is_err() {
+        warn!("Unit B unavailable, running degraded and retrying in the background");
+    }
+    let mut unit_b = SensorSupervisor::new(initial, now_ms(), backoff);
+
+    let mut sampler = DualSampler::new();
+    let mut count: u32 = 0;
+    let mut max_skew_us: i64 = 0;
+
+    loop {
+        let (was_ready_a, was_ready_b) = (unit_a.is_ready(), unit_b.is_ready());
+        let a = unit_a.poll(now_ms(), init_a, |attempt| report_init_failure("A", attempt));
+        let b = unit_b.poll(now_ms(), init_b, |attempt| report_init_failure("B", attempt));
+        if !was_ready_a && a.is_some() {
+            info!("Unit A initialized");
+        }
+        if !was_ready_b && b.is_some() {
+            info!("Unit B initialized");
+        }
+
+        // Which unit is left when the other one is missing
+        let single_is_a = a.is_some();
+        let single = if single_is_a { "A" } else { "B" };
+        // A unit that fails a read is dropped and retried in the background, while the
+        // other one keeps being reported on its own
+        let (mut lost_a, mut lost_b) = (false, false);
+        match (a, b) {
+            (Some(a), Some(b)) => match sampler.sample(|| a.sample(now_us()), || b.sample(now_us()), now_us) {
+                Ok(pair) => {
+                    max_skew_us = max_skew_us.max(pair.skew_us.abs());
+                    count += 1;
+
+                    if count % LOG_EVERY == 0 {
+                        let tilt_a = Tilt::from_acceleration(pair.a.acceleration);
+                        let tilt_b = Tilt::from_acceleration(pair.b.acceleration);
+                        let relative = RelativeOrientation::between(pair.a.acceleration, pair.b.acceleration);
+
+                        info!("Unit A [Roll, Pitch] : [{:.1}, {:.1}] deg", tilt_a.roll, tilt_a.pitch);
+                        info!("Unit B [Roll, Pitch] : [{:.1}, {:.1}] deg", tilt_b.roll, tilt_b.pitch);
+                        info!("B relative to A [Roll, Pitch] : [{:.1}, {:.1}] deg, included angle {:.1} deg", relative.roll, relative.pitch, relative.included_angle);
+                        info!("Max skew between units : {} us", max_skew_us);
+                        max_skew_us = 0;
+                    }
+                },
+                Err(PairError::A(e)) => {
+                    error!("Failed to read unit A: {:?}", e);
+                    lost_a = true;
+                },
+                Err(PairError::B(e)) => {
+                    error!("Failed to read unit B: {:?}", e);
+                    lost_b = true;
+                }
+            },
+            // Degraded: the unit that is left is still reported, without a relative tilt
+            (Some(imu), None) | (None, Some(imu)) => {
+                match imu.sample(now_us()) {
+                    Ok(sample) => {
+                        count += 1;
+                        if count % LOG_EVERY == 0 {
+                            let tilt = Tilt::from_acceleration(sample.acceleration);
+                            info!("Unit {} [Roll, Pitch] : [{:.1}, {:.1}] deg, other unit not connected", single, tilt.roll, tilt.pitch);
+                        }
+                    },
+                    Err(e) => {
+                        error!("Failed to read unit {}: {:?}", single, e);
+                        lost_a = single_is_a;
+                        lost_b = !single_is_a;
+                    }
+                }
+            },
+            (None, None) => {}
+        }
+        if lost_a {
+            unit_a.mark_failed(now_ms());
+        }
+        if lost_b {
+            unit_b.mark_failed(now_ms());
+        }
+        delay.delay_millis(SAMPLE_PERIOD_MS);
+    }
+}
@@ -6,20 +6,91 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
 use esp_backtrace as _;
-use log::{info, error};
+use log::{info, warn, error};
 use esp_hal::{
     i2c::master::{
         I2c, Config
     },
     clock::CpuClock,
     delay::Delay,
+    gpio::{DriveMode, Flex, OutputConfig, Pull},
+    time::Instant,
     main
 };
+use esp_storage::FlashStorage;
+use example_support::bus_recovery::{bus_is_stuck, probe, recover_bus, BusHealth, Probe};
+use example_support::calibration::{Calibration, Calibrator, HardwareOffsets, Orientation, Progress, MPU6050_OFFSETS};
+use example_support::calibration_store::{self, CalibrationStore, StoredCalibration};
+use example_support::complementary::ComplementaryFilter;
+use example_support::imu::Imu;
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use example_support::presence::{self, PresenceEvent, PresenceMonitor};
 use hayasen::mpu6050_hayasen;
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// Consecutive failed reads, or re-inits on a stuck bus, after which the bus is recovered.
+const RECOVERY_THRESHOLD: u32 = 5;
+
+/// How often the sensor identity and configuration are verified.
+const PRESENCE_CHECK_MS: u64 = 2_000;
+
+/// Readings averaged for the gyro bias and accel offsets, 1 s worth.
+const CALIBRATION_SAMPLES: u32 = 200;
+const CALIBRATION_INTERVAL_MS: u32 = 5;
+
+/// Write the calibration into the sensor's offset registers instead of correcting
+/// every reading in software.
+const HARDWARE_OFFSETS: bool = false;
+
+/// The sensor is read every 10 ms so the attitude filter can follow quick motion.
+const SAMPLE_PERIOD_MS: u32 = 10;
+/// Print every 50th reading, i.e. twice per second.
+const PRINT_EVERY: u32 = 50;
+
+/// Below this frequency roll and pitch follow the accelerometer, above it the gyroscope.
+const CROSSOVER_HZ: f32 = 0.5;
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn now_us() -> u64 {
+    Instant::now().duration_since_epoch().as_micros()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => warn!("MPU6050 init attempt {} failed: {:?}, retrying in {} ms", attempt.number, attempt.error, ms),
+        None => error!("MPU6050 init attempt {} failed: {:?}, giving up", attempt.number, attempt.error),
+    }
+}
+
+/// Averages readings until the sensor has held still for `CALIBRATION_SAMPLES` of them.
+fn calibrate<I: Imu>(imu: &mut I, delay: &mut Delay) -> Result<Calibration, I::Error> {
+    let mut calibrator = Calibrator::new(Orientation::ZUp, CALIBRATION_SAMPLES);
+    loop {
+        match calibrator.add(&imu.sample(now_us())?) {
+            Progress::Done(calibration) => return Ok(calibration),
+            Progress::Restarted => warn!("Movement detected, restarting calibration"),
+            Progress::Collecting { .. } => {}
+        }
+        delay.delay_millis(CALIBRATION_INTERVAL_MS);
+    }
+}
+
+/// Turns a bus pin into an open-drain output that can still be read back.
+fn open_drain(pin: &mut Flex<'_>) {
+    pin.set_high();
+    pin.apply_output_config(&OutputConfig::default().with_drive_mode(DriveMode::OpenDrain).with_pull(Pull::Up));
+    pin.set_input_enable(true);
+    pin.set_output_enable(true);
+}
+
 #[main]
 fn main() -> ! {
     esp_println::logger::init_logger_from_env();
@@ -27,33 +98,252 @@ fn main() -> ! {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
 
-    let delay = Delay::new();
+    let mut delay = Delay::new();
 
-    let sda = peripherals.GPIO21;
-    let scl = peripherals.GPIO22;
+    let mut i2c0 = peripherals.I2C0;
+    let mut sda = peripherals.GPIO21;
+    let mut scl = peripherals.GPIO22;
 
     let mpu_address: u8 = 0x68;
 
-    let i2c = I2c::new(peripherals.I2C0, Config::default())
-        .unwrap()
-        .with_sda(sda)
-        .with_scl(scl);
+    let backoff = Backoff::default();
+    let mut health = BusHealth::new(RECOVERY_THRESHOLD);
+
+    // Calibration saved by an earlier run is used instead of calibrating again. Without
+    // the store the sensor is still calibrated, just not saved
+    let opened = CalibrationStore::in_partition(FlashStorage::new(), calibration_store::PARTITION_OFFSET, calibration_store::PARTITION_SIZE);
+    let mut store = match opened {
+        Ok(store) => Some(store),
+        Err(e) => {
+            error!("Failed to open the calibration store: {:?}", e);
+            None
+        }
+    };
+    let mut stored = match store.as_mut().map(|store| store.load()) {
+        Some(Ok(stored)) => stored.unwrap_or_default(),
+        Some(Err(e)) => {
+            error!("Failed to load calibration: {:?}", e);
+            StoredCalibration::default()
+        },
+        None => StoredCalibration::default(),
+    };
+
+    // The six-position calibration from `accel_calibration_mpu6050`, if one was saved.
+    // It corrects the accelerometer better than the flat calibration below, which then
+    // only supplies the gyro bias
+    let accel_calibration = stored.accel;
+    if accel_calibration.is_some() {
+        info!("Using the six-position accelerometer calibration saved in flash");
+    }
+
+    // Measured once, on the first successful init, and kept across bus recoveries
+    let mut calibration: Option<Calibration> = None;
+    let mut hardware_offsets: Option<HardwareOffsets> = None;
+
+    let mut attitude = ComplementaryFilter::new(CROSSOVER_HZ);
+    let mut readings: u32 = 0;
 
-    // Create sensor manually and test each step
-    let mut sensor = mpu6050_hayasen::create_default(i2c, mpu_address).unwrap();
-    
-    // Now try reading data
+    // Only the init at boot waits for the sensor. After a bus recovery the supervisor
+    // retries it in the background, so a sensor that is simply unplugged does not stall
+    // the loop again
+    let mut booting = true;
+
+    // Every pass through this loop owns a freshly initialized bus. Leaving it drops
+    // the I2C driver and hands the pins back for recovery.
     loop {
-        match mpu6050_hayasen::read_all(&mut sensor) {
-            Ok((temperature, acceleration, angular_velocity)) => {
-                info!("Temperature : {:.2} C", temperature);
-                info!("Acceleration [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g", acceleration[0], acceleration[1], acceleration[2]);
-                info!("Angular Velocity [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", angular_velocity[0], angular_velocity[1], angular_velocity[2]);
-            },
-            Err(e) => {
-                error!("Failed to read sensor data: {:?}", e);
+        let i2c = I2c::new(i2c0.reborrow(), Config::default())
+            .unwrap()
+            .with_sda(sda.reborrow())
+            .with_scl(scl.reborrow());
+
+        // The driver gets a handle to the bus rather than the bus itself, so a failed
+        // init attempt does not lose it
+        let bus = RefCell::new(i2c);
+
+        let initial = if booting {
+            init_with_retry(
+                &backoff,
+                &mut delay,
+                || mpu6050_hayasen::create_default(RefCellDevice::new(&bus), mpu_address),
+                report_init_failure,
+            )
+        } else {
+            mpu6050_hayasen::create_default(RefCellDevice::new(&bus), mpu_address)
+        };
+        booting = false;
+        if initial.is_err() {
+            warn!("MPU6050 unavailable, running degraded and retrying in the background");
+        }
+        let mut monitor = PresenceMonitor::new(presence::MPU6050.with_address(mpu_address));
+        if initial.is_ok() {
+            let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+        }
+        let mut sensor = SensorSupervisor::new(initial, now_ms(), backoff);
+        let mut last_presence_check = now_ms();
+
+        // Now try reading data
+        loop {
+            if now_ms() - last_presence_check >= PRESENCE_CHECK_MS {
+                last_presence_check = now_ms();
+                let event = monitor.check(&mut RefCellDevice::new(&bus));
+                match event {
+                    Some(PresenceEvent::Disconnected) => warn!("MPU6050 disconnected"),
+                    Some(PresenceEvent::Reconnected) => info!("MPU6050 reconnected, re-applying configuration"),
+                    Some(PresenceEvent::Reset) => warn!("MPU6050 lost its configuration, re-applying it"),
+                    None => {}
+                }
+                // Drop the driver, the supervisor re-creates it once the device answers again
+                if event.is_some() {
+                    sensor.mark_failed(now_ms());
+                }
+            }
+
+            let was_ready = sensor.is_ready();
+            let mut init_failed = false;
+            let imu = sensor.poll(
+                now_ms(),
+                || mpu6050_hayasen::create_default(RefCellDevice::new(&bus), mpu_address),
+                |attempt| {
+                    report_init_failure(attempt);
+                    init_failed = true;
+                },
+            );
+
+            // A bus stuck low looks like a missing sensor, so failed re-inits count too,
+            // but only when the bus rather than the sensor is what fails to answer
+            if init_failed && bus_is_stuck(&mut RefCellDevice::new(&bus), mpu_address) && health.record_failure() {
+                break;
             }
+
+            if let Some(imu) = imu {
+                if !was_ready {
+                    info!("MPU6050 initialized");
+
+                    if calibration.is_none() && hardware_offsets.is_none() {
+                        let measured = match stored.imu {
+                            Some(loaded) => {
+                                info!("Using the calibration saved in flash");
+                                Some(loaded)
+                            },
+                            None => {
+                                info!("Calibrating, keep the MPU6050 flat and still");
+                                match calibrate(imu, &mut delay) {
+                                    Ok(measured) => {
+                                        stored.imu = Some(measured);
+                                        if let Some(Err(e)) = store.as_mut().map(|store| store.save(&stored)) {
+                                            error!("Failed to save calibration: {:?}", e);
+                                        }
+                                        Some(measured)
+                                    },
+                                    Err(e) => {
+                                        error!("Calibration failed: {:?}", e);
+                                        None
+                                    }
+                                }
+                            }
+                        };
+
+                        let measured = match accel_calibration {
+                            Some(_) => measured.map(Calibration::gyro_only),
+                            None => measured,
+                        };
+                        if let Some(measured) = measured {
+                            let [bx, by, bz] = measured.gyro_bias.as_dps();
+                            let [ox, oy, oz] = measured.accel_offset.as_g();
+                            info!("Gyro bias [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", bx, by, bz);
+                            info!("Accel offset [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g", ox, oy, oz);
+
+                            // Falls back to correcting in software if the registers cannot be read
+                            let computed = if HARDWARE_OFFSETS {
+                                let mut i2c = RefCellDevice::new(&bus);
+                                // A restart that leaves the sensor powered keeps the offsets
+                                // written last time in the registers, so the factory trim is
+                                // read only once and kept in flash
+                                let factory = match stored.factory_accel_offsets {
+                                    Some(factory) => Some(factory),
+                                    None => {
+                                        let factory = HardwareOffsets::read_factory_accel(&mut i2c, mpu_address, MPU6050_OFFSETS).ok();
+                                        if factory.is_some() {
+                                            stored.factory_accel_offsets = factory;
+                                            if let Some(Err(e)) = store.as_mut().map(|store| store.save(&stored)) {
+                                                error!("Failed to save calibration: {:?}", e);
+                                            }
+                                        }
+                                        factory
+                                    }
+                                };
+                                factory.map(|factory| HardwareOffsets::new(MPU6050_OFFSETS, factory, &measured))
+                            } else {
+                                None
+                            };
+                            match computed {
+                                Some(offsets) => hardware_offsets = Some(offsets),
+                                None => calibration = Some(measured),
+                            }
+                        }
+                    }
+
+                    // The offset registers do not survive a power cycle, so they are
+                    // written again after every init
+                    if let Some(offsets) = &hardware_offsets {
+                        if let Err(e) = offsets.write(&mut RefCellDevice::new(&bus), mpu_address) {
+                            error!("Failed to write offset registers: {:?}", e);
+                        }
+                    }
+
+                    let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+                }
+
+                match imu.sample(now_us()) {
+                    Ok(sample) => {
+                        health.record_success();
+                        let mut sample = calibration.map_or(sample, |c| c.apply(sample));
+                        if let Some(accel) = &accel_calibration {
+                            sample.acceleration = accel.apply(sample.acceleration);
+                        }
+                        let tilt = attitude.update(&sample);
+
+                        readings += 1;
+                        if readings % PRINT_EVERY == 0 {
+                            info!("{}", sample);
+                            info!("Roll, Pitch : {:.1}, {:.1} deg", tilt.roll, tilt.pitch);
+                        }
+                    },
+                    Err(e) => {
+                        let recovery_due = health.record_failure();
+                        let counters = health.counters();
+                        error!(
+                            "Failed to read sensor data: {:?} ({} in a row, {} total, {} bus recoveries)",
+                            e, counters.consecutive, counters.total, counters.recoveries
+                        );
+                        // Only a bus held low calls for a recovery. A sensor that no longer
+                        // answers is dropped and re-initialized once it is back
+                        match probe(&mut RefCellDevice::new(&bus), mpu_address) {
+                            Probe::Stuck if recovery_due => break,
+                            Probe::Missing => {
+                                warn!("MPU6050 does not answer, retrying it in the background");
+                                sensor.mark_failed(now_ms());
+                            },
+                            _ => {}
+                        }
+                    }
+                }
+            }
+            delay.delay_millis(SAMPLE_PERIOD_MS);
+        }
+
+        drop(sensor);
+        drop(bus);
+
+        warn!("{} consecutive bus errors, recovering the I2C bus", RECOVERY_THRESHOLD);
+        let mut scl_pin = Flex::new(scl.reborrow());
+        let mut sda_pin = Flex::new(sda.reborrow());
+        open_drain(&mut scl_pin);
+        open_drain(&mut sda_pin);
+        match recover_bus(&mut scl_pin, &mut sda_pin, &mut delay) {
+            Ok(pulses) => info!("Bus released after {} clock pulses", pulses),
+            Err(e) => error!("Bus recovery failed: {:?}", e),
         }
-        delay.delay_millis(500);
+        health.record_recovery();
     }
 }
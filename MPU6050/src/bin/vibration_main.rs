@@ -0,0 +1,145 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_backtrace as _;
+use log::{info, warn, error};
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    delay::Delay,
+    time::{Instant, Rate},
+    main
+};
+use example_support::accel_fifo::{self, AccelFifo, AccelRange, Error};
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use example_support::units::Vector3;
+use example_support::vibration::{MachineClass, VibrationAnalyzer, VibrationConfig};
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// Readings per spectrum, about one second at 1 kHz and a resolution of 0.98 Hz.
+const BLOCK: usize = 1024;
+/// The FIFO is drained every 20 ms, well before its 170 readings fill up.
+const DRAIN_PERIOD_MS: u32 = 20;
+/// ISO 10816-1 class of the machine the sensor sits on, `None` to skip the
+/// severity classification.
+const MACHINE_CLASS: Option<MachineClass> = Some(MachineClass::I);
+const AXES: [&str; 3] = ["X", "Y", "Z"];
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => warn!("Accelerometer init attempt {} failed: {:?}, retrying in {} ms", attempt.number, attempt.error, ms),
+        None => error!("Accelerometer init attempt {} failed: {:?}, giving up", attempt.number, attempt.error),
+    }
+}
+
+#[main]
+fn main() -> ! {
+    esp_println::logger::init_logger_from_env();
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let mut delay = Delay::new();
+
+    let sda = peripherals.GPIO21;
+    let scl = peripherals.GPIO22;
+
+    let mpu_address: u8 = 0x68;
+
+    // 1000 readings of six bytes a second need fast mode
+    let i2c = I2c::new(peripherals.I2C0, Config::default().with_frequency(Rate::from_khz(400)))
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    // The FIFO reader gets a handle to the bus rather than the bus itself, so a failed
+    // init attempt does not lose it
+    let bus = RefCell::new(i2c);
+    let init_fifo = || AccelFifo::new(RefCellDevice::new(&bus), mpu_address, AccelRange::G4);
+    let backoff = Backoff::default();
+
+    let initial = init_with_retry(&backoff, &mut delay, init_fifo, report_init_failure);
+    if initial.is_err() {
+        warn!("Accelerometer unavailable, running degraded and retrying in the background");
+    }
+    let mut sensor = SensorSupervisor::new(initial, now_ms(), backoff);
+
+    let mut analyzer = VibrationAnalyzer::<BLOCK>::new(VibrationConfig {
+        sample_rate_hz: accel_fifo::SAMPLE_RATE_HZ,
+        machine_class: MACHINE_CLASS,
+        ..VibrationConfig::default()
+    });
+    let mut readings = [Vector3::from_g([0.0; 3]); 64];
+
+    info!("Sampling the accelerometer at 1 kHz, one spectrum every {} readings", BLOCK);
+
+    loop {
+        let was_ready = sensor.is_ready();
+        let Some(fifo) = sensor.poll(now_ms(), init_fifo, report_init_failure) else {
+            delay.delay_millis(DRAIN_PERIOD_MS);
+            continue;
+        };
+        if !was_ready {
+            // The readings from before the sensor went away do not belong to this block
+            info!("Accelerometer initialized");
+            analyzer.reset();
+        }
+
+        let count = match fifo.read(&mut readings) {
+            Ok(count) => count,
+            Err(Error::Overflow) => {
+                // A block with a hole in it would smear the spectrum
+                warn!("FIFO overflowed, readings lost; starting the block over");
+                analyzer.reset();
+                0
+            },
+            Err(e) => {
+                error!("Failed to read the FIFO: {:?}", e);
+                0
+            },
+        };
+
+        for &reading in &readings[..count] {
+            let Some(report) = analyzer.update(reading) else {
+                continue;
+            };
+
+            for (name, axis) in AXES.iter().zip(&report.axes) {
+                match axis.zone {
+                    Some(zone) => info!(
+                        "{} : {:.3} g RMS, {:.2} mm/s RMS, zone {:?}",
+                        name, axis.rms.as_g(), axis.velocity_rms_mm_s, zone
+                    ),
+                    None => info!("{} : {:.3} g RMS, {:.2} mm/s RMS", name, axis.rms.as_g(), axis.velocity_rms_mm_s),
+                }
+            }
+
+            // Peaks and bands of the axis that vibrates the most
+            let worst = report.worst_axis();
+            let axis = &report.axes[worst];
+            for peak in axis.peaks() {
+                info!("  {} peak : {:.1} Hz, {:.3} g", AXES[worst], peak.frequency_hz, peak.amplitude.as_g());
+            }
+            for (band, rms) in analyzer.config().bands.iter().zip(axis.bands()) {
+                info!("  {} {:.0}-{:.0} Hz : {:.3} g RMS", AXES[worst], band.low_hz, band.high_hz, rms.as_g());
+            }
+        }
+
+        delay.delay_millis(DRAIN_PERIOD_MS);
+    }
+}
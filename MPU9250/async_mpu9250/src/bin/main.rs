@@ -0,0 +1,189 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_executor::Spawner;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::{Duration, Instant, Ticker};
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_backtrace as _;
+use log::{info, warn, error};
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    time::Rate,
+    timer::timg::TimerGroup
+};
+use example_support::complementary::ComplementaryFilter;
+use example_support::tilt::Tilt;
+use example_support::imu::{Imu, ImuSample};
+use example_support::init::{Attempt, Backoff, SensorSupervisor};
+use example_support::presence::{self, PresenceEvent, PresenceMonitor};
+use example_support::sample_stats::SampleAverager;
+use hayasen::mpu9250_hayasen;
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// Rate at which the sensor is sampled, up to 1 kHz. At 400 kHz I2C the 23 bytes of
+/// an MPU9250 `read_all` (accelerometer, temperature, gyroscope and magnetometer)
+/// take about 0.6 ms, so above roughly 1.5 kHz the reads no longer fit in the period.
+const SAMPLE_RATE_HZ: u64 = 200;
+
+/// Number of processed samples averaged into a single report.
+const REPORT_DECIMATION: u32 = 100;
+
+/// How often the sensor identity and configuration are verified.
+const PRESENCE_CHECK_MS: u64 = 2_000;
+
+/// Below this frequency roll and pitch follow the accelerometer, above it the gyroscope.
+const CROSSOVER_HZ: f32 = 0.5;
+
+/// A reading together with the attitude the processing task derived from it.
+struct ProcessedSample {
+    sample: ImuSample,
+    tilt: Tilt,
+}
+
+static SAMPLES: Channel<CriticalSectionRawMutex, ImuSample, 32> = Channel::new();
+static PROCESSED: Channel<CriticalSectionRawMutex, ProcessedSample, 8> = Channel::new();
+
+/// Samples the sampling loop could not queue, reported and cleared with every summary.
+static DROPPED: AtomicU32 = AtomicU32::new(0);
+
+fn report_init_failure<E: core::fmt::Debug>(attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => warn!("MPU9250 init attempt {} failed: {:?}, retrying in {} ms", attempt.number, attempt.error, ms),
+        None => error!("MPU9250 init attempt {} failed: {:?}, giving up", attempt.number, attempt.error),
+    }
+}
+
+#[embassy_executor::task]
+async fn processing_task() {
+    // The filter integrates the gyroscope, so it has to see every sample
+    let mut attitude = ComplementaryFilter::new(CROSSOVER_HZ);
+
+    loop {
+        let sample = SAMPLES.receive().await;
+        let tilt = attitude.update(&sample);
+        PROCESSED.send(ProcessedSample { sample, tilt }).await;
+    }
+}
+
+#[embassy_executor::task]
+async fn reporting_task() {
+    let mut averager = SampleAverager::new(Duration::from_hz(SAMPLE_RATE_HZ).as_micros(), REPORT_DECIMATION);
+
+    loop {
+        let processed = PROCESSED.receive().await;
+        let Some(summary) = averager.update(&processed.sample) else {
+            continue;
+        };
+
+        info!("{}", summary);
+        info!("Roll, Pitch : {:.1}, {:.1} deg", processed.tilt.roll, processed.tilt.pitch);
+        let dropped = DROPPED.swap(0, Ordering::Relaxed);
+        if dropped > 0 {
+            warn!("Processing is falling behind, {} samples dropped", dropped);
+        }
+    }
+}
+
+#[esp_hal_embassy::main]
+async fn main(spawner: Spawner) {
+    esp_println::logger::init_logger_from_env();
+
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    esp_hal_embassy::init(timg0.timer0);
+
+    let sda = peripherals.GPIO4;
+    let scl = peripherals.GPIO5;
+
+    let mpu_address: u8 = 0x68;
+
+    // Fast mode keeps a full read_all transaction, about 0.6 ms, inside the sampling
+    // period even at 1 kHz
+    let i2c = I2c::new(peripherals.I2C0, Config::default().with_frequency(Rate::from_khz(400)))
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    // The driver gets a handle to the bus rather than the bus itself, so a failed
+    // init attempt does not lose it
+    let bus = RefCell::new(i2c);
+
+    // A missing sensor is retried from the sampling loop instead of stopping the firmware
+    let initial = mpu9250_hayasen::create_default(RefCellDevice::new(&bus), mpu_address);
+    if let Err(e) = &initial {
+        warn!("MPU9250 init failed: {:?}, retrying in the background", e);
+    }
+    let mut monitor = PresenceMonitor::new(presence::MPU9250.with_address(mpu_address));
+    if initial.is_ok() {
+        let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+    }
+    let mut sensor = SensorSupervisor::new(initial, Instant::now().as_millis(), Backoff::default());
+    let mut last_presence_check = Instant::now();
+
+    spawner.must_spawn(processing_task());
+    spawner.must_spawn(reporting_task());
+
+    // The ticker schedules against absolute deadlines, so the time spent reading and
+    // queueing a sample does not accumulate into the sampling period.
+    let mut ticker = Ticker::every(Duration::from_hz(SAMPLE_RATE_HZ));
+
+    loop {
+        ticker.next().await;
+
+        if last_presence_check.elapsed() >= Duration::from_millis(PRESENCE_CHECK_MS) {
+            last_presence_check = Instant::now();
+            let event = monitor.check(&mut RefCellDevice::new(&bus));
+            match event {
+                Some(PresenceEvent::Disconnected) => warn!("MPU9250 disconnected"),
+                Some(PresenceEvent::Reconnected) => info!("MPU9250 reconnected, re-applying configuration"),
+                Some(PresenceEvent::Reset) => warn!("MPU9250 lost its configuration, re-applying it"),
+                None => {}
+            }
+            // Drop the driver, the supervisor re-creates it once the device answers again
+            if event.is_some() {
+                sensor.mark_failed(Instant::now().as_millis());
+            }
+        }
+
+        let was_ready = sensor.is_ready();
+        let Some(imu) = sensor.poll(
+            Instant::now().as_millis(),
+            || mpu9250_hayasen::create_default(RefCellDevice::new(&bus), mpu_address),
+            report_init_failure,
+        ) else {
+            continue;
+        };
+        if !was_ready {
+            info!("MPU9250 initialized");
+            let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+        }
+
+        match imu.sample(Instant::now().as_micros()) {
+            Ok(sample) => {
+                // Counted rather than logged here, a log line per dropped sample
+                // would only slow the consumers down further
+                if SAMPLES.try_send(sample).is_err() {
+                    DROPPED.fetch_add(1, Ordering::Relaxed);
+                }
+            },
+            Err(e) => {
+                error!("Failed to read sensor data: {:?}", e);
+            }
+        }
+    }
+}
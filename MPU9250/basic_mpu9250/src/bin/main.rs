@@ -6,6 +6,9 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
 use esp_backtrace as _;
 use esp_hal::{
     i2c::master::{
@@ -13,45 +16,469 @@ use esp_hal::{
     },
     clock::CpuClock,
     delay::Delay,
+    gpio::{DriveMode, Flex, OutputConfig, Pull},
+    time::Instant,
     main
 };
 use esp_println::println;
+use esp_storage::FlashStorage;
+use example_support::ak8963::{self, enable_mpu9250_bypass, Ak8963, Mode, Resolution};
+use example_support::bus_recovery::{bus_is_stuck, probe, recover_bus, BusHealth, Probe};
+use example_support::calibration::{Calibration, Calibrator, HardwareOffsets, Orientation, Progress, MPU9250_OFFSETS};
+use example_support::calibration_store::{self, CalibrationStore, StoredCalibration};
+use example_support::compass::Compass;
+use example_support::ekf::{Ekf, Noise};
+use example_support::imu::{Imu, ImuSample};
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use example_support::madgwick::Madgwick;
+use example_support::mag_calibration::{FitError, MagCalibrator, MagFit, REGIONS};
+use example_support::presence::{self, PresenceEvent, PresenceMonitor};
 use hayasen::mpu9250_hayasen;
 
 esp_bootloader_esp_idf::esp_app_desc!();
 
+/// Consecutive failed reads, or re-inits on a stuck bus, after which the bus is recovered.
+const RECOVERY_THRESHOLD: u32 = 5;
+
+/// How often the sensor identity and configuration are verified.
+const PRESENCE_CHECK_MS: u64 = 2_000;
+
+/// Readings averaged for the gyro bias and accel offsets, 1 s worth.
+const CALIBRATION_SAMPLES: u32 = 200;
+const CALIBRATION_INTERVAL_MS: u32 = 5;
+
+/// Write the calibration into the sensor's offset registers instead of correcting
+/// every reading in software.
+const HARDWARE_OFFSETS: bool = false;
+
+/// The sensor is read every 10 ms so the orientation filter can follow quick motion.
+const SAMPLE_PERIOD_MS: u32 = 10;
+/// Print every 50th reading, i.e. twice per second.
+const PRINT_EVERY: u32 = 50;
+
+/// Madgwick filter gain in rad/s. It starts high so the orientation settles within a
+/// few seconds of boot, then drops to reject more vibration.
+const STARTUP_BETA: f32 = 1.0;
+const BETA: f32 = Madgwick::DEFAULT_BETA;
+const STARTUP_READINGS: u32 = 300;
+
+/// Readings kept for the magnetometer calibration, eight per region of the sphere.
+const MAG_CALIBRATION_SAMPLES: usize = 8 * REGIONS;
+/// The magnetometer calibration gives up waiting for full coverage after this long
+/// and fits whatever it has.
+const MAG_CALIBRATION_TIMEOUT_MS: u64 = 60_000;
+/// Run the magnetometer calibration even if one is saved in flash already.
+const RECALIBRATE_MAGNETOMETER: bool = false;
+
+/// Magnetic declination where the board is, in degrees east of true north. Look it up
+/// for your location, or generate a table with `tools/declination_grid.py` and use
+/// `DECLINATION.declination(latitude, longitude)` from it instead.
+const DECLINATION_DEG: f32 = 0.0;
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn now_us() -> u64 {
+    Instant::now().duration_since_epoch().as_micros()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => println!("MPU9250 init attempt {} failed: {:?}, retrying in {} ms", attempt.number, attempt.error, ms),
+        None => println!("MPU9250 init attempt {} failed: {:?}, giving up", attempt.number, attempt.error),
+    }
+}
+
+/// Averages readings until the sensor has held still for `CALIBRATION_SAMPLES` of them.
+fn calibrate<I: Imu>(imu: &mut I, delay: &mut Delay) -> Result<Calibration, I::Error> {
+    let mut calibrator = Calibrator::new(Orientation::ZUp, CALIBRATION_SAMPLES);
+    loop {
+        match calibrator.add(&imu.sample(now_us())?) {
+            Progress::Done(calibration) => return Ok(calibration),
+            Progress::Restarted => println!("Movement detected, restarting calibration"),
+            Progress::Collecting { .. } => {}
+        }
+        delay.delay_millis(CALIBRATION_INTERVAL_MS);
+    }
+}
+
+/// Collects magnetometer readings while the board is turned in every direction, then
+/// fits the hard and soft iron correction to them.
+fn calibrate_magnetometer<I: embedded_hal::i2c::I2c>(magnetometer: &mut Ak8963<I>, delay: &mut Delay) -> Result<MagFit, FitError> {
+    let mut calibrator = MagCalibrator::<MAG_CALIBRATION_SAMPLES>::new();
+    let mut shown_regions = 0;
+    let started = now_ms();
+    while now_ms() - started < MAG_CALIBRATION_TIMEOUT_MS && !calibrator.is_full() {
+        match magnetometer.read() {
+            Ok(Some(field)) => {
+                let coverage = calibrator.add(field);
+                if coverage.regions != shown_regions {
+                    shown_regions = coverage.regions;
+                    println!("Coverage {}/{} ({} readings)", coverage.regions, REGIONS, coverage.samples);
+                }
+                if coverage.regions == REGIONS {
+                    break;
+                }
+            },
+            Ok(None) => {},
+            Err(e) => println!("Failed to read magnetometer: {:?}", e),
+        }
+        delay.delay_millis(SAMPLE_PERIOD_MS);
+    }
+    calibrator.fit()
+}
+
+/// Turns a bus pin into an open-drain output that can still be read back.
+fn open_drain(pin: &mut Flex<'_>) {
+    pin.set_high();
+    pin.apply_output_config(&OutputConfig::default().with_drive_mode(DriveMode::OpenDrain).with_pull(Pull::Up));
+    pin.set_input_enable(true);
+    pin.set_output_enable(true);
+}
+
 #[main]
 fn main() -> ! {
     let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
     let peripherals = esp_hal::init(config);
 
-    let delay = Delay::new();
+    let mut delay = Delay::new();
 
-    let sda = peripherals.GPIO4;
-    let scl = peripherals.GPIO5;
+    let mut i2c0 = peripherals.I2C0;
+    let mut sda = peripherals.GPIO4;
+    let mut scl = peripherals.GPIO5;
 
     let mpu_address: u8 = 0x68;
 
-    let i2c = I2c::new(peripherals.I2C0, Config::default())
-        .unwrap()
-        .with_sda(sda)
-        .with_scl(scl);
+    let backoff = Backoff::default();
+    let mut health = BusHealth::new(RECOVERY_THRESHOLD);
 
-    // Create sensor manually and test each step
-    let mut sensor = mpu9250_hayasen::create_default(i2c, mpu_address).unwrap();
-    
-    // Now try reading data
+    // Calibration saved by an earlier run is used instead of calibrating again. Without
+    // the store the sensor is still calibrated, just not saved
+    let opened = CalibrationStore::in_partition(FlashStorage::new(), calibration_store::PARTITION_OFFSET, calibration_store::PARTITION_SIZE);
+    let mut store = match opened {
+        Ok(store) => Some(store),
+        Err(e) => {
+            println!("Failed to open the calibration store: {:?}", e);
+            None
+        }
+    };
+    let mut stored = match store.as_mut().map(|store| store.load()) {
+        Some(Ok(stored)) => stored.unwrap_or_default(),
+        Some(Err(e)) => {
+            println!("Failed to load calibration: {:?}", e);
+            StoredCalibration::default()
+        },
+        None => StoredCalibration::default(),
+    };
+
+    // A six-position accelerometer calibration, if one was saved. It corrects the
+    // accelerometer better than the flat calibration below, which then only supplies
+    // the gyro bias
+    let accel_calibration = stored.accel;
+    if accel_calibration.is_some() {
+        println!("Using the six-position accelerometer calibration saved in flash");
+    }
+
+    // Measured once, on the first successful init, and kept across bus recoveries
+    let mut calibration: Option<Calibration> = None;
+    let mut hardware_offsets: Option<HardwareOffsets> = None;
+    let mut mag_calibration = if RECALIBRATE_MAGNETOMETER { None } else { stored.magnetometer };
+    // The magnetometer calibration blocks sampling for up to a minute, so it is tried
+    // at most once per boot, even if the fit fails
+    let mut mag_calibration_tried = false;
+
+    let mut orientation = Madgwick::new(STARTUP_BETA);
+    // Tune the noise it assumes with e.g. `Noise { accel: 0.1, ..Noise::default() }`
+    let mut ekf = Ekf::new(Noise::default());
+    let mut compass = Compass::new(DECLINATION_DEG);
+    let mut heading = None;
+    let mut readings: u32 = 0;
+
+    // Only the init at boot waits for the sensor. After a bus recovery the supervisor
+    // retries it in the background, so a sensor that is simply unplugged does not stall
+    // the loop again
+    let mut booting = true;
+
+    // Every pass through this loop owns a freshly initialized bus. Leaving it drops
+    // the I2C driver and hands the pins back for recovery.
     loop {
-        match mpu9250_hayasen::read_all(&mut sensor) {
-            Ok((temperature, acceleration, angular_velocity)) => {
-                println!("Temperature : {:.2} C", temperature);
-                println!("Acceleration [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g", acceleration[0], acceleration[1], acceleration[2]);
-                println!("Angular Velocity [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", angular_velocity[0], angular_velocity[1], angular_velocity[2]);
-            },
-            Err(e) => {
-                println!("Failed to read sensor data: {:?}", e);
+        let i2c = I2c::new(i2c0.reborrow(), Config::default())
+            .unwrap()
+            .with_sda(sda.reborrow())
+            .with_scl(scl.reborrow());
+
+        // The driver gets a handle to the bus rather than the bus itself, so a failed
+        // init attempt does not lose it
+        let bus = RefCell::new(i2c);
+
+        let initial = if booting {
+            init_with_retry(
+                &backoff,
+                &mut delay,
+                || mpu9250_hayasen::create_default(RefCellDevice::new(&bus), mpu_address),
+                report_init_failure,
+            )
+        } else {
+            mpu9250_hayasen::create_default(RefCellDevice::new(&bus), mpu_address)
+        };
+        booting = false;
+        if initial.is_err() {
+            println!("MPU9250 unavailable, running degraded and retrying in the background");
+        }
+        let mut monitor = PresenceMonitor::new(presence::MPU9250.with_address(mpu_address));
+        if initial.is_ok() {
+            let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+        }
+        let mut sensor = SensorSupervisor::new(initial, now_ms(), backoff);
+        let mut last_presence_check = now_ms();
+
+        // Set up again after every init, since resetting the MPU9250 disables bypass
+        let mut magnetometer = None;
+        let mut latest_field = None;
+
+        // Now try reading data
+        loop {
+            if now_ms() - last_presence_check >= PRESENCE_CHECK_MS {
+                last_presence_check = now_ms();
+                let event = monitor.check(&mut RefCellDevice::new(&bus));
+                match event {
+                    Some(PresenceEvent::Disconnected) => println!("MPU9250 disconnected"),
+                    Some(PresenceEvent::Reconnected) => println!("MPU9250 reconnected, re-applying configuration"),
+                    Some(PresenceEvent::Reset) => println!("MPU9250 lost its configuration, re-applying it"),
+                    None => {}
+                }
+                // Drop the driver, the supervisor re-creates it once the device answers again
+                if event.is_some() {
+                    sensor.mark_failed(now_ms());
+                }
+            }
+
+            let was_ready = sensor.is_ready();
+            let mut init_failed = false;
+            let imu = sensor.poll(
+                now_ms(),
+                || mpu9250_hayasen::create_default(RefCellDevice::new(&bus), mpu_address),
+                |attempt| {
+                    report_init_failure(attempt);
+                    init_failed = true;
+                },
+            );
+
+            // A bus stuck low looks like a missing sensor, so failed re-inits count too,
+            // but only when the bus rather than the sensor is what fails to answer
+            if init_failed && bus_is_stuck(&mut RefCellDevice::new(&bus), mpu_address) && health.record_failure() {
+                break;
+            }
+
+            if let Some(imu) = imu {
+                if !was_ready {
+                    println!("MPU9250 initialized");
+
+                    // The AK8963 only appears on the bus once the MPU9250 bypasses to it
+                    magnetometer = match enable_mpu9250_bypass(&mut RefCellDevice::new(&bus), mpu_address) {
+                        Ok(()) => match Ak8963::new(RefCellDevice::new(&bus), ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut delay) {
+                            Ok(sensor) => {
+                                let [ax, ay, az] = sensor.adjustment();
+                                println!("AK8963 initialized, sensitivity adjustment [X, Y, Z] : [{:.3}, {:.3}, {:.3}]", ax, ay, az);
+                                Some(sensor)
+                            },
+                            Err(e) => {
+                                println!("AK8963 init failed: {:?}, continuing without magnetometer", e);
+                                None
+                            }
+                        },
+                        Err(e) => {
+                            println!("Failed to enable I2C bypass: {:?}, continuing without magnetometer", e);
+                            None
+                        }
+                    };
+
+                    if let (Some(sensor), None, false) = (magnetometer.as_mut(), mag_calibration, mag_calibration_tried) {
+                        mag_calibration_tried = true;
+                        println!("Calibrating the magnetometer, turn the MPU9250 slowly in every direction");
+                        match calibrate_magnetometer(sensor, &mut delay) {
+                            Ok(fit) => {
+                                let [hx, hy, hz] = fit.calibration.hard_iron.as_microtesla();
+                                println!("Hard iron [X, Y, Z] : [{:.1}, {:.1}, {:.1}] uT", hx, hy, hz);
+                                println!("Field strength {:.1} uT, residual {:.1} %", fit.field_strength.as_microtesla(), fit.residual * 100.0);
+                                mag_calibration = Some(fit.calibration);
+                                stored.magnetometer = Some(fit.calibration);
+                                if let Some(Err(e)) = store.as_mut().map(|store| store.save(&stored)) {
+                                    println!("Failed to save calibration: {:?}", e);
+                                }
+                            },
+                            Err(e) => println!("Magnetometer calibration failed: {:?}, using raw readings until the next boot", e),
+                        }
+                    }
+
+                    if calibration.is_none() && hardware_offsets.is_none() {
+                        let measured = match stored.imu {
+                            Some(loaded) => {
+                                println!("Using the calibration saved in flash");
+                                Some(loaded)
+                            },
+                            None => {
+                                println!("Calibrating, keep the MPU9250 flat and still");
+                                match calibrate(imu, &mut delay) {
+                                    Ok(measured) => {
+                                        stored.imu = Some(measured);
+                                        if let Some(Err(e)) = store.as_mut().map(|store| store.save(&stored)) {
+                                            println!("Failed to save calibration: {:?}", e);
+                                        }
+                                        Some(measured)
+                                    },
+                                    Err(e) => {
+                                        println!("Calibration failed: {:?}", e);
+                                        None
+                                    }
+                                }
+                            }
+                        };
+
+                        let measured = match accel_calibration {
+                            Some(_) => measured.map(Calibration::gyro_only),
+                            None => measured,
+                        };
+                        if let Some(measured) = measured {
+                            let [bx, by, bz] = measured.gyro_bias.as_dps();
+                            let [ox, oy, oz] = measured.accel_offset.as_g();
+                            println!("Gyro bias [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", bx, by, bz);
+                            println!("Accel offset [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g", ox, oy, oz);
+
+                            // Falls back to correcting in software if the registers cannot be read
+                            let computed = if HARDWARE_OFFSETS {
+                                let mut i2c = RefCellDevice::new(&bus);
+                                // A restart that leaves the sensor powered keeps the offsets
+                                // written last time in the registers, so the factory trim is
+                                // read only once and kept in flash
+                                let factory = match stored.factory_accel_offsets {
+                                    Some(factory) => Some(factory),
+                                    None => {
+                                        let factory = HardwareOffsets::read_factory_accel(&mut i2c, mpu_address, MPU9250_OFFSETS).ok();
+                                        if factory.is_some() {
+                                            stored.factory_accel_offsets = factory;
+                                            if let Some(Err(e)) = store.as_mut().map(|store| store.save(&stored)) {
+                                                println!("Failed to save calibration: {:?}", e);
+                                            }
+                                        }
+                                        factory
+                                    }
+                                };
+                                factory.map(|factory| HardwareOffsets::new(MPU9250_OFFSETS, factory, &measured))
+                            } else {
+                                None
+                            };
+                            match computed {
+                                Some(offsets) => hardware_offsets = Some(offsets),
+                                None => calibration = Some(measured),
+                            }
+                        }
+                    }
+
+                    // The offset registers do not survive a power cycle, so they are
+                    // written again after every init
+                    if let Some(offsets) = &hardware_offsets {
+                        if let Err(e) = offsets.write(&mut RefCellDevice::new(&bus), mpu_address) {
+                            println!("Failed to write offset registers: {:?}", e);
+                        }
+                    }
+
+                    let _ = monitor.capture(&mut RefCellDevice::new(&bus));
+                }
+
+                match imu.sample(now_us()) {
+                    Ok(sample) => {
+                        health.record_success();
+                        let mut sample = calibration.map_or(sample, |c| c.apply(sample));
+                        if let Some(accel) = &accel_calibration {
+                            sample.acceleration = accel.apply(sample.acceleration);
+                        }
+                        // The magnetometer measures at 100 Hz; samples in between carry none
+                        if let Some(magnetometer) = magnetometer.as_mut() {
+                            sample.magnetic_field = match magnetometer.read() {
+                                Ok(field) => field.map(|field| mag_calibration.map_or(field, |c| c.apply(field))),
+                                Err(ak8963::Error::Overflow) => {
+                                    println!("Magnetic field out of range, is there a magnet nearby?");
+                                    None
+                                },
+                                Err(e) => {
+                                    println!("Failed to read magnetometer: {:?}", e);
+                                    None
+                                }
+                            };
+                            latest_field = sample.magnetic_field.or(latest_field);
+                            if let Some(field) = sample.magnetic_field {
+                                heading = compass.heading(sample.acceleration, field).or(heading);
+                            }
+                        }
+                        // Uses the magnetometer for yaw whenever the sample carries it
+                        let q = orientation.update(&sample);
+                        ekf.update(&sample);
+
+                        readings += 1;
+                        if readings == STARTUP_READINGS {
+                            orientation.set_beta(BETA);
+                        }
+                        if readings % PRINT_EVERY == 0 {
+                            let angles = q.to_euler();
+                            let shown = ImuSample { magnetic_field: sample.magnetic_field.or(latest_field), ..sample };
+                            println!("{}", shown);
+                            println!("Quaternion [W, X, Y, Z] : [{:.3}, {:.3}, {:.3}, {:.3}]", q.w, q.x, q.y, q.z);
+                            println!("Roll, Pitch, Yaw : {:.1}, {:.1}, {:.1} deg", angles.roll, angles.pitch, angles.yaw);
+
+                            let angles = ekf.euler();
+                            let [bx, by, bz] = ekf.gyro_bias().as_dps();
+                            println!("EKF Roll, Pitch, Yaw : {:.1}, {:.1}, {:.1} deg", angles.roll, angles.pitch, angles.yaw);
+                            println!("EKF gyro bias [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", bx, by, bz);
+
+                            if let Some(heading) = heading {
+                                println!("Heading : {:.1} deg true, {:.1} deg magnetic", heading.true_north, heading.magnetic);
+                                if heading.disturbed {
+                                    let expected = compass.reference().map_or(0.0, |r| r.as_microtesla());
+                                    println!(
+                                        "Magnetic disturbance: field is {:.1} uT instead of {:.1} uT, heading unreliable",
+                                        heading.field_strength.as_microtesla(), expected
+                                    );
+                                }
+                            }
+                        }
+                    },
+                    Err(e) => {
+                        let recovery_due = health.record_failure();
+                        let counters = health.counters();
+                        println!(
+                            "Failed to read sensor data: {:?} ({} in a row, {} total, {} bus recoveries)",
+                            e, counters.consecutive, counters.total, counters.recoveries
+                        );
+                        // Only a bus held low calls for a recovery. A sensor that no longer
+                        // answers is dropped and re-initialized once it is back
+                        match probe(&mut RefCellDevice::new(&bus), mpu_address) {
+                            Probe::Stuck if recovery_due => break,
+                            Probe::Missing => {
+                                println!("MPU9250 does not answer, retrying it in the background");
+                                sensor.mark_failed(now_ms());
+                            },
+                            _ => {}
+                        }
+                    }
+                }
             }
+            delay.delay_millis(SAMPLE_PERIOD_MS);
+        }
+
+        drop(sensor);
+        drop(bus);
+
+        println!("{} consecutive bus errors, recovering the I2C bus", RECOVERY_THRESHOLD);
+        let mut scl_pin = Flex::new(scl.reborrow());
+        let mut sda_pin = Flex::new(sda.reborrow());
+        open_drain(&mut scl_pin);
+        open_drain(&mut sda_pin);
+        match recover_bus(&mut scl_pin, &mut sda_pin, &mut delay) {
+            Ok(pulses) => println!("Bus released after {} clock pulses", pulses),
+            Err(e) => println!("Bus recovery failed: {:?}", e),
         }
-        delay.delay_millis(500);
+        health.record_recovery();
     }
 }
@@ -0,0 +1,167 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_backtrace as _;
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    delay::Delay,
+    Blocking,
+    main
+};
+use esp_println::println;
+use example_support::ak8963::{Ak8963, Mode, Resolution};
+use example_support::scan::{enable_mpu9250_bypass, BusInventory, Detected, SensorKind, MAX_DEVICES};
+use hayasen::max30102::{FifoSample, Max30102};
+use hayasen::mpu6050::Mpu6050;
+use hayasen::mpu9250::Mpu9250;
+use hayasen::{max30102_hayasen, mpu6050_hayasen, mpu9250_hayasen};
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+type Bus<'a> = RefCellDevice<'a, I2c<'static, Blocking>>;
+
+/// A hayasen driver for whatever sensor the scan found.
+enum Driver<'a> {
+    Mpu6050(Mpu6050<Bus<'a>>),
+    Mpu9250(Mpu9250<Bus<'a>>),
+    Max30102(Max30102<Bus<'a>>),
+    Ak8963(Ak8963<Bus<'a>>),
+}
+
+impl<'a> Driver<'a> {
+    /// Creates the matching driver, `None` for devices hayasen has no driver for.
+    fn create(device: &Detected, bus: &'a RefCell<I2c<'static, Blocking>>, delay: &mut Delay) -> Option<Self> {
+        let i2c = RefCellDevice::new(bus);
+        let driver = match device.kind {
+            SensorKind::Mpu6050 => mpu6050_hayasen::create_default(i2c, device.address).map(Driver::Mpu6050),
+            SensorKind::Mpu9250 => mpu9250_hayasen::create_default(i2c, device.address).map(Driver::Mpu9250),
+            SensorKind::Max30102 => max30102_hayasen::create_default_with_address(i2c).map(|mut sensor| {
+                let _ = max30102_hayasen::setup_high_performance_mode(&mut sensor);
+                Driver::Max30102(sensor)
+            }),
+            // Only reachable through the bypass of the MPU9250 it sits in
+            SensorKind::Ak8963 => {
+                return match Ak8963::new(i2c, device.address, Resolution::Bits16, Mode::Continuous8Hz, delay) {
+                    Ok(sensor) => Some(Driver::Ak8963(sensor)),
+                    Err(e) => {
+                        println!("  {} : driver init failed: {:?}", device, e);
+                        None
+                    }
+                };
+            },
+            SensorKind::Unknown => return None,
+        };
+
+        match driver {
+            Ok(driver) => Some(driver),
+            Err(e) => {
+                println!("  {} : driver init failed: {:?}", device, e);
+                None
+            }
+        }
+    }
+
+    fn read(&mut self, address: u8) {
+        match self {
+            Driver::Mpu6050(sensor) => match mpu6050_hayasen::read_all(sensor) {
+                Ok((temperature, acceleration, angular_velocity)) => println!(
+                    "0x{:02X} MPU6050 : {:.2} C, [{:.3}, {:.3}, {:.3}] g, [{:.3}, {:.3}, {:.3}] dps",
+                    address, temperature, acceleration[0], acceleration[1], acceleration[2], angular_velocity[0], angular_velocity[1], angular_velocity[2]
+                ),
+                Err(e) => println!("0x{:02X} MPU6050 : read failed: {:?}", address, e),
+            },
+            Driver::Mpu9250(sensor) => match mpu9250_hayasen::read_all(sensor) {
+                Ok((temperature, acceleration, angular_velocity)) => println!(
+                    "0x{:02X} MPU9250 : {:.2} C, [{:.3}, {:.3}, {:.3}] g, [{:.3}, {:.3}, {:.3}] dps",
+                    address, temperature, acceleration[0], acceleration[1], acceleration[2], angular_velocity[0], angular_velocity[1], angular_velocity[2]
+                ),
+                Err(e) => println!("0x{:02X} MPU9250 : read failed: {:?}", address, e),
+            },
+            Driver::Max30102(sensor) => {
+                let mut samples: [FifoSample; 16] = core::array::from_fn(|_| FifoSample { red: 0, ir: 0 });
+                match max30102_hayasen::read_fifo_batch(sensor, &mut samples) {
+                    Ok(0) => println!("0x{:02X} MAX30102 : FIFO empty", address),
+                    Ok(count) => println!("0x{:02X} MAX30102 : {} samples, last red {} ir {}", address, count, samples[count - 1].red, samples[count - 1].ir),
+                    Err(e) => println!("0x{:02X} MAX30102 : read failed: {:?}", address, e),
+                }
+            },
+            Driver::Ak8963(sensor) => match sensor.read() {
+                Ok(Some(field)) => {
+                    let [x, y, z] = field.as_microtesla();
+                    println!("0x{:02X} AK8963 : [{:.1}, {:.1}, {:.1}] uT", address, x, y, z);
+                },
+                Ok(None) => println!("0x{:02X} AK8963 : no new measurement", address),
+                Err(e) => println!("0x{:02X} AK8963 : read failed: {:?}", address, e),
+            }
+        }
+    }
+}
+
+#[main]
+fn main() -> ! {
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let mut delay = Delay::new();
+
+    let sda = peripherals.GPIO4;
+    let scl = peripherals.GPIO5;
+
+    let i2c = I2c::new(peripherals.I2C0, Config::default())
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    let bus = RefCell::new(i2c);
+
+    // Enables bypass on an MPU9250, so that its magnetometer is listed and read too
+    let inventory = BusInventory::scan_with_bypass(&mut RefCellDevice::new(&bus));
+
+    println!("I2C bus inventory: {} device(s)", inventory.len());
+    if inventory.overflowed() {
+        println!("  (more than {} devices answered, the rest are not listed)", MAX_DEVICES);
+    }
+
+    let mut drivers: [Option<(u8, Driver)>; MAX_DEVICES] = core::array::from_fn(|_| None);
+    for (slot, device) in drivers.iter_mut().zip(inventory.iter()) {
+        println!("  {}", device);
+        if device.kind != SensorKind::Ak8963 {
+            *slot = Driver::create(device, &bus, &mut delay).map(|driver| (device.address, driver));
+        }
+    }
+
+    // Setting up an MPU9250 can switch its bypass off again, so the magnetometers are
+    // started once every MPU9250 has its driver
+    for device in inventory.iter().filter(|device| device.kind == SensorKind::Mpu9250) {
+        if let Err(e) = enable_mpu9250_bypass(&mut RefCellDevice::new(&bus), device.address) {
+            println!("0x{:02X} MPU9250 : failed to enable bypass again: {:?}", device.address, e);
+        }
+    }
+    for (slot, device) in drivers.iter_mut().zip(inventory.iter()) {
+        if device.kind == SensorKind::Ak8963 {
+            *slot = Driver::create(device, &bus, &mut delay).map(|driver| (device.address, driver));
+        }
+    }
+
+    if drivers.iter().all(Option::is_none) {
+        println!("No supported sensor found, check the wiring");
+    }
+
+    loop {
+        for (address, driver) in drivers.iter_mut().flatten() {
+            driver.read(*address);
+        }
+        delay.delay_millis(1000);
+    }
+}
@@ -0,0 +1,252 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+
+use embedded_hal_bus::i2c::RefCellDevice;
+use esp_backtrace as _;
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    delay::Delay,
+    time::{Instant, Rate},
+    main
+};
+use esp_println::println;
+use example_support::imu::Imu;
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use example_support::motion_artifact::{MotionGate, NlmsCanceller, ReferenceHistory};
+use example_support::presence::{self, PresenceEvent, PresenceMonitor};
+use example_support::ppg::{DcTracker, PulseRate, Spo2Curve, Spo2Estimator};
+use hayasen::max30102::FifoSample;
+use hayasen::{max30102_hayasen, mpu6050_hayasen};
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// Both sensors are read every 10 ms. The MAX30102 samples at 100 Hz in high
+/// performance mode, so there is about one PPG sample per accelerometer reading.
+const SAMPLE_PERIOD_MS: u32 = 10;
+const PPG_SAMPLE_US: u64 = 10_000;
+/// Accelerometer readings kept to line up with the PPG samples, a full FIFO's worth.
+const REFERENCE_HISTORY: usize = 32;
+/// Taps of the noise canceller per accelerometer axis, 80 ms of history.
+const CANCELLER_TAPS: usize = 8;
+/// Below this the IR channel sees no finger.
+const FINGER_IR: u32 = 50_000;
+/// Print the readings every 100th loop, i.e. every second.
+const REPORT_EVERY: u32 = 100;
+/// How often the identity and configuration of both sensors are verified.
+const PRESENCE_CHECK_MS: u64 = 2_000;
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn now_us() -> u64 {
+    Instant::now().duration_since_epoch().as_micros()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(name: &str, attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => println!("{} init attempt {} failed: {:?}, retrying in {} ms", name, attempt.number, attempt.error, ms),
+        None => println!("{} init attempt {} failed: {:?}, giving up", name, attempt.number, attempt.error),
+    }
+}
+
+fn report_presence(name: &str, event: Option<PresenceEvent>) {
+    match event {
+        Some(PresenceEvent::Disconnected) => println!("{} disconnected", name),
+        Some(PresenceEvent::Reconnected) => println!("{} reconnected, re-applying configuration", name),
+        Some(PresenceEvent::Reset) => println!("{} lost its configuration, re-applying it", name),
+        None => {}
+    }
+}
+
+#[main]
+fn main() -> ! {
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let mut delay = Delay::new();
+
+    let sda = peripherals.GPIO4;
+    let scl = peripherals.GPIO5;
+
+    let mpu_address: u8 = 0x68;
+
+    let i2c = I2c::new(peripherals.I2C0, Config::default().with_frequency(Rate::from_khz(400)))
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    let bus = RefCell::new(i2c);
+    let init_imu = || mpu6050_hayasen::create_default(RefCellDevice::new(&bus), mpu_address);
+    // The timestamps below assume the 100 Hz of high performance mode, so a sensor
+    // left at its defaults counts as a failed init
+    let init_ppg = || {
+        max30102_hayasen::create_default_with_address(RefCellDevice::new(&bus))
+            .and_then(|mut ppg| max30102_hayasen::setup_high_performance_mode(&mut ppg).map(|_| ppg))
+    };
+
+    // A sensor that is still missing after the retries is retried in the background
+    let backoff = Backoff::default();
+    let initial = init_with_retry(&backoff, &mut delay, init_imu, |attempt| report_init_failure("MPU6050", attempt));
+    if initial.is_err() {
+        println!("MPU6050 unavailable, running degraded and retrying in the background");
+    }
+    let mut imu_monitor = PresenceMonitor::new(presence::MPU6050.with_address(mpu_address));
+    if initial.is_ok() {
+        let _ = imu_monitor.capture(&mut RefCellDevice::new(&bus));
+    }
+    let mut imu = SensorSupervisor::new(initial, now_ms(), backoff);
+
+    let initial = init_with_retry(&backoff, &mut delay, init_ppg, |attempt| report_init_failure("MAX30102", attempt));
+    if initial.is_err() {
+        println!("MAX30102 unavailable, running degraded and retrying in the background");
+    }
+    let mut ppg_monitor = PresenceMonitor::new(presence::MAX30102);
+    if initial.is_ok() {
+        let _ = ppg_monitor.capture(&mut RefCellDevice::new(&bus));
+    }
+    let mut ppg = SensorSupervisor::new(initial, now_ms(), backoff);
+    let mut last_presence_check = now_ms();
+
+    println!("Place a finger on the MAX30102, the MPU6050 strapped next to it");
+
+    let mut gate = MotionGate::default();
+    let mut references = ReferenceHistory::<REFERENCE_HISTORY>::new();
+    let mut canceller = NlmsCanceller::<CANCELLER_TAPS>::default();
+    let mut ir_dc = DcTracker::new(0.01);
+    let mut pulse = PulseRate::new();
+    let mut spo2 = Spo2Estimator::new(Spo2Curve::default(), 100);
+    let mut sample_buffer: [FifoSample; 32] = core::array::from_fn(|_| FifoSample { red: 0, ir: 0 });
+
+    let mut bpm: Option<f32> = None;
+    let mut saturation: Option<f32> = None;
+    let mut finger = false;
+    let mut loops: u32 = 0;
+
+    loop {
+        if now_ms() - last_presence_check >= PRESENCE_CHECK_MS {
+            last_presence_check = now_ms();
+            // Drop a driver whose sensor went away, its supervisor re-creates it once
+            // the device answers again
+            let event = imu_monitor.check(&mut RefCellDevice::new(&bus));
+            report_presence("MPU6050", event);
+            if event.is_some() {
+                imu.mark_failed(now_ms());
+            }
+            let event = ppg_monitor.check(&mut RefCellDevice::new(&bus));
+            report_presence("MAX30102", event);
+            if event.is_some() {
+                ppg.mark_failed(now_ms());
+            }
+        }
+
+        let now = now_us();
+        let was_ready = imu.is_ready();
+        let reading = match imu.poll(now_ms(), init_imu, |attempt| report_init_failure("MPU6050", attempt)) {
+            Some(sensor) => {
+                if !was_ready {
+                    println!("MPU6050 initialized");
+                    let _ = imu_monitor.capture(&mut RefCellDevice::new(&bus));
+                }
+                match sensor.sample(now) {
+                    Ok(sample) => Some(sample),
+                    Err(e) => {
+                        println!("Failed to read the MPU6050: {:?}", e);
+                        None
+                    },
+                }
+            },
+            None => None,
+        };
+        match reading {
+            Some(sample) => {
+                gate.update(&sample);
+                references.push(now, gate.linear_acceleration());
+            },
+            None => {
+                // Without the accelerometer there is no telling whether the wearer
+                // moves, so the readings are held until it has seen them still again
+                gate.reset();
+                references.clear();
+            },
+        }
+
+        let was_ready = ppg.is_ready();
+        if let Some(sensor) = ppg.poll(now_ms(), init_ppg, |attempt| report_init_failure("MAX30102", attempt)) {
+            if !was_ready {
+                println!("MAX30102 initialized");
+                let _ = ppg_monitor.capture(&mut RefCellDevice::new(&bus));
+            }
+            if let Ok(count) = max30102_hayasen::read_fifo_batch(sensor, &mut sample_buffer) {
+                for (i, sample) in sample_buffer[..count].iter().enumerate() {
+                    // The FIFO holds the oldest sample first
+                    let timestamp = now.saturating_sub((count - 1 - i) as u64 * PPG_SAMPLE_US);
+
+                    if sample.ir < FINGER_IR {
+                        if finger {
+                            println!("Finger removed");
+                        }
+                        finger = false;
+                        continue;
+                    }
+                    if !finger {
+                        // Start over, the old levels and beats belong to another placement
+                        finger = true;
+                        ir_dc.reset();
+                        canceller.reset();
+                        pulse.reset();
+                        spo2.reset();
+                        bpm = None;
+                        saturation = None;
+                    }
+
+                    // The motion at the time of this sample. With the accelerometer
+                    // gone there is none, and the gate holds the readings anyway
+                    let reference = references.at(timestamp).unwrap_or([0.0; 3]);
+                    let ac = ir_dc.update(sample.ir as f32);
+                    let cleaned = canceller.update(ac, reference);
+                    // Both keep running during motion, only their results are held back
+                    let beat = pulse.update(timestamp, cleaned);
+                    let estimate = spo2.update(sample.red as f32, sample.ir as f32);
+                    if gate.is_steady() {
+                        bpm = beat.or(bpm);
+                        saturation = estimate.or(saturation);
+                    }
+                }
+            }
+        }
+
+        loops += 1;
+        if loops % REPORT_EVERY == 0 {
+            if !ppg.is_ready() {
+                println!("MAX30102 : not connected");
+            } else if !finger {
+                println!("No finger on the sensor");
+            } else {
+                let state = if gate.is_steady() { "steady" } else { "moving, readings held" };
+                match (bpm, saturation) {
+                    (Some(bpm), Some(saturation)) => println!("Heart rate : {:.0} BPM, SpO2 : {:.0} %", bpm, saturation),
+                    (Some(bpm), None) => println!("Heart rate : {:.0} BPM, SpO2 : measuring", bpm),
+                    _ => println!("Measuring..."),
+                }
+                if imu.is_ready() {
+                    println!("Motion : {:.3} g, {}", gate.motion().as_g(), state);
+                } else {
+                    println!("MPU6050 : not connected, readings held");
+                }
+            }
+        }
+
+        delay.delay_millis(SAMPLE_PERIOD_MS);
+    }
+}
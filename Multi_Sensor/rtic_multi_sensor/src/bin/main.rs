@@ -0,0 +1,462 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+#[rtic::app(device = esp32c6, dispatchers = [FROM_CPU_INTR0, FROM_CPU_INTR1])]
+mod app {
+    use core::cell::RefCell;
+
+    use embedded_hal::i2c::I2c as _;
+    use embedded_hal_bus::i2c::RefCellDevice;
+    use esp_backtrace as _;
+    use esp_hal::{
+        i2c::master::{
+            I2c, Config, Error as I2cError
+        },
+        gpio::{Event, Input, InputConfig, Pull},
+        time::{Duration, Instant, Rate},
+        timer::{timg::TimerGroup, PeriodicTimer},
+        Blocking
+    };
+    use esp_println::println;
+    use example_support::init::{Attempt, Backoff, SensorSupervisor};
+    use example_support::presence::{self, PresenceEvent, PresenceMonitor};
+    use hayasen::max30102::Max30102;
+    use hayasen::max30102_hayasen;
+    use hayasen::mpu6050::Mpu6050;
+    use hayasen::mpu6050_hayasen;
+    use static_cell::StaticCell;
+
+    /// Period of the IMU sampling timer.
+    const IMU_PERIOD_MS: u64 = 10;
+    /// Number of IMU samples between two reports.
+    const REPORT_EVERY: u32 = 100;
+    /// Number of IMU ticks between two runs of the supervisor, which retries missing
+    /// sensors.
+    const SUPERVISE_EVERY: u32 = 10;
+    /// How often the sensor identities and configurations are verified.
+    const PRESENCE_CHECK_MS: u64 = 2_000;
+
+    const MPU_ADDRESS: u8 = 0x68;
+    const MAX30102_ADDRESS: u8 = 0x57;
+    const MAX30102_INT_STATUS_1: u8 = 0x00;
+    const MAX30102_INT_ENABLE_1: u8 = 0x02;
+    const MAX30102_FIFO_WR_PTR: u8 = 0x04;
+    const MAX30102_FIFO_DATA: u8 = 0x07;
+    const MAX30102_FIFO_CONFIG: u8 = 0x08;
+    /// Slots in the MAX30102 FIFO.
+    const MAX30102_FIFO_DEPTH: u8 = 32;
+    /// Red and IR, three bytes each.
+    const MAX30102_SAMPLE_BYTES: usize = 6;
+    /// Samples read per bus lock. Four take about 0.7 ms at 400 kHz, so that is the
+    /// longest the IMU tick can be held up.
+    const PPG_CHUNK: usize = 4;
+    const MAX30102_A_FULL_EN: u8 = 0x80;
+    /// Fire the almost-full interrupt with 15 free slots left, i.e. 17 unread samples.
+    const MAX30102_FIFO_A_FULL: u8 = 0x0F;
+    /// Failed attempts in a row at releasing the INT line after which the interrupt is
+    /// switched off until `supervise` has set the MAX30102 up again.
+    const MAX_CLEAR_FAILURES: u32 = 5;
+
+    type Bus = RefCell<I2c<'static, Blocking>>;
+    type BusDevice = RefCellDevice<'static, I2c<'static, Blocking>>;
+
+    /// Everything that talks on the I2C bus.
+    ///
+    /// The drivers and a raw handle for the FIFO and the presence checks share one bus
+    /// through `RefCellDevice`. Keeping them together in a single RTIC resource means
+    /// every bus transaction happens inside a lock, so the `RefCell` can never be
+    /// borrowed twice, even when the timer task preempts the FIFO task in the middle of
+    /// a transfer.
+    pub struct SensorBus {
+        /// For handing out devices to drivers that are re-created.
+        bus: &'static Bus,
+        imu: SensorSupervisor<Mpu6050<BusDevice>>,
+        ppg: SensorSupervisor<Max30102<BusDevice>>,
+        imu_monitor: PresenceMonitor,
+        ppg_monitor: PresenceMonitor,
+        raw: BusDevice,
+    }
+
+    #[derive(Clone, Copy)]
+    pub struct ImuSnapshot {
+        temperature: f32,
+        acceleration: [f32; 3],
+        angular_velocity: [f32; 3],
+        worst_latency_us: u64,
+        errors: u32,
+    }
+
+    #[derive(Clone, Copy, Default)]
+    pub struct PpgSnapshot {
+        samples: u32,
+        last: Option<(u32, u32)>,
+        errors: u32,
+    }
+
+    #[shared]
+    struct Shared {
+        bus: SensorBus,
+        ppg: PpgSnapshot,
+        /// Listened to by `ppg_fifo`, re-armed by `supervise` after it gave up on it.
+        ppg_int: Input<'static>,
+    }
+
+    #[local]
+    struct Local {
+        timer: PeriodicTimer<'static, Blocking>,
+    }
+
+    #[init]
+    fn init(_: init::Context) -> (Shared, Local) {
+        let peripherals = esp_hal::init(esp_hal::Config::default());
+
+        let i2c = I2c::new(peripherals.I2C0, Config::default().with_frequency(Rate::from_khz(400)))
+            .unwrap()
+            .with_sda(peripherals.GPIO4)
+            .with_scl(peripherals.GPIO5);
+
+        static BUS: StaticCell<Bus> = StaticCell::new();
+        let bus = BUS.init(RefCell::new(i2c));
+        let mut raw = RefCellDevice::new(bus);
+
+        // A sensor that is missing at boot is retried by `supervise` instead of stopping
+        // the firmware
+        let backoff = Backoff::default();
+        let imu = init_imu(bus);
+        if let Err(e) = &imu {
+            println!("MPU6050 init failed: {:?}, retrying in the background", e);
+        }
+        let mut imu_monitor = PresenceMonitor::new(presence::MPU6050.with_address(MPU_ADDRESS));
+        if imu.is_ok() {
+            let _ = imu_monitor.capture(&mut raw);
+        }
+
+        let ppg = init_ppg(bus);
+        if let Err(e) = &ppg {
+            println!("MAX30102 init failed: {:?}, retrying in the background", e);
+        }
+        let mut ppg = SensorSupervisor::new(ppg, now_ms(), backoff);
+        let mut ppg_monitor = PresenceMonitor::new(presence::MAX30102);
+        if ppg.is_ready() {
+            match enable_ppg_interrupt(&mut raw) {
+                Ok(()) => {
+                    let _ = ppg_monitor.capture(&mut raw);
+                },
+                // Without its interrupt the MAX30102 is never drained, so it is set up again
+                Err(e) => {
+                    println!("Failed to enable the MAX30102 interrupt: {:?}, retrying in the background", e);
+                    ppg.mark_failed(now_ms());
+                }
+            }
+        }
+
+        // On the level rather than the edge: should clearing INT_STATUS fail, the line
+        // stays low and the task runs again instead of waiting for an edge that never
+        // comes. A line that stays low for good is unlistened by `ppg_fifo`
+        let mut ppg_int = Input::new(peripherals.GPIO6, InputConfig::default().with_pull(Pull::Up));
+        ppg_int.listen(Event::LowLevel);
+
+        let timg0 = TimerGroup::new(peripherals.TIMG0);
+        let mut timer = PeriodicTimer::new(timg0.timer0);
+        timer.enable_interrupt(true);
+        timer.start(Duration::from_millis(IMU_PERIOD_MS)).unwrap();
+
+        println!("RTIC multi-sensor: MPU6050 every {} ms, MAX30102 on FIFO interrupt", IMU_PERIOD_MS);
+
+        (
+            Shared {
+                bus: SensorBus {
+                    bus,
+                    imu: SensorSupervisor::new(imu, now_ms(), backoff),
+                    ppg,
+                    imu_monitor,
+                    ppg_monitor,
+                    raw,
+                },
+                ppg: PpgSnapshot::default(),
+                ppg_int,
+            },
+            Local { timer },
+        )
+    }
+
+    #[idle]
+    fn idle(_: idle::Context) -> ! {
+        loop {
+            core::hint::spin_loop();
+        }
+    }
+
+    fn now_ms() -> u64 {
+        Instant::now().duration_since_epoch().as_millis()
+    }
+
+    fn init_imu(bus: &'static Bus) -> Result<Mpu6050<BusDevice>, impl core::fmt::Debug> {
+        mpu6050_hayasen::create_default(RefCellDevice::new(bus), MPU_ADDRESS)
+    }
+
+    fn init_ppg(bus: &'static Bus) -> Result<Max30102<BusDevice>, impl core::fmt::Debug> {
+        max30102_hayasen::create_default_with_address(RefCellDevice::new(bus))
+            .and_then(|mut ppg| max30102_hayasen::setup_high_performance_mode(&mut ppg).map(|_| ppg))
+    }
+
+    /// Lets the MAX30102 pull its INT line low once the FIFO is almost full instead of
+    /// polling it, then reads the status register to release any stale interrupt.
+    fn enable_ppg_interrupt(raw: &mut BusDevice) -> Result<(), I2cError> {
+        let mut fifo_config = [0u8];
+        raw.write_read(MAX30102_ADDRESS, &[MAX30102_FIFO_CONFIG], &mut fifo_config)?;
+        raw.write(MAX30102_ADDRESS, &[MAX30102_FIFO_CONFIG, (fifo_config[0] & 0xF0) | MAX30102_FIFO_A_FULL])?;
+        raw.write(MAX30102_ADDRESS, &[MAX30102_INT_ENABLE_1, MAX30102_A_FULL_EN])?;
+        let mut status = [0u8];
+        raw.write_read(MAX30102_ADDRESS, &[MAX30102_INT_STATUS_1], &mut status)
+    }
+
+    fn report_init_failure<E: core::fmt::Debug>(name: &str, attempt: &Attempt<'_, E>) {
+        if let Some(ms) = attempt.retry_in_ms {
+            println!("{} init attempt {} failed: {:?}, retrying in {} ms", name, attempt.number, attempt.error, ms);
+        }
+    }
+
+    fn report_presence(name: &str, event: Option<PresenceEvent>) {
+        match event {
+            Some(PresenceEvent::Disconnected) => println!("{} disconnected", name),
+            Some(PresenceEvent::Reconnected) => println!("{} reconnected, re-applying configuration", name),
+            Some(PresenceEvent::Reset) => println!("{} lost its configuration, re-applying it", name),
+            None => {}
+        }
+    }
+
+    /// Highest priority: the IMU is sampled on every timer tick.
+    ///
+    /// Normally the only thing that can delay it is a FIFO task holding the bus lock,
+    /// which is bounded by the length of a single I2C transaction of at most `PPG_CHUNK`
+    /// samples. Re-creating a driver in `supervise` takes longer, but only happens
+    /// after a sensor went missing.
+    #[task(binds = TG0_T0_LEVEL, priority = 3, shared = [bus], local = [
+        timer,
+        last_tick: Option<Instant> = None,
+        ticks: u32 = 0,
+        supervise_ticks: u32 = 0,
+        errors: u32 = 0,
+        worst_latency_us: u64 = 0,
+    ])]
+    fn imu_tick(mut cx: imu_tick::Context) {
+        cx.local.timer.clear_interrupt();
+
+        let now = Instant::now();
+        if let Some(last) = cx.local.last_tick.replace(now) {
+            let period_us = Duration::from_millis(IMU_PERIOD_MS).as_micros();
+            let latency_us = (now - last).as_micros().abs_diff(period_us);
+            *cx.local.worst_latency_us = (*cx.local.worst_latency_us).max(latency_us);
+        }
+
+        *cx.local.supervise_ticks += 1;
+        if *cx.local.supervise_ticks >= SUPERVISE_EVERY {
+            // Still running from last time is fine, it catches up on the next spawn
+            let _ = supervise::spawn();
+            *cx.local.supervise_ticks = 0;
+        }
+
+        // Nothing to read while the supervisor waits for the sensor to come back
+        let Some(reading) = cx.shared.bus.lock(|bus| bus.imu.sensor().map(mpu6050_hayasen::read_all)) else {
+            return;
+        };
+
+        *cx.local.ticks += 1;
+        match reading {
+            Ok((temperature, acceleration, angular_velocity)) => {
+                if *cx.local.ticks >= REPORT_EVERY {
+                    let snapshot = ImuSnapshot {
+                        temperature,
+                        acceleration,
+                        angular_velocity,
+                        worst_latency_us: *cx.local.worst_latency_us,
+                        errors: *cx.local.errors,
+                    };
+                    // A report still being printed is simply skipped
+                    let _ = report::spawn(snapshot);
+                    *cx.local.ticks = 0;
+                    *cx.local.worst_latency_us = 0;
+                }
+            },
+            Err(_) => {
+                *cx.local.errors += 1;
+            }
+        }
+    }
+
+    /// Drains the MAX30102 FIFO whenever it signals almost full.
+    ///
+    /// The FIFO is read a few samples per bus lock rather than all at once, so the IMU
+    /// tick can slip in between the chunks. The samples are read as the red and IR
+    /// pair the SpO2 mode of `setup_high_performance_mode` queues.
+    ///
+    /// If the INT line cannot be released, e.g. because the sensor is wedged or the bus
+    /// is held by a fault, the level interrupt would fire again straight away and keep
+    /// `supervise` from ever running. After `MAX_CLEAR_FAILURES` attempts the interrupt
+    /// is switched off and the sensor handed to `supervise` to be set up again.
+    #[task(binds = GPIO, priority = 2, shared = [bus, ppg, ppg_int], local = [clear_failures: u32 = 0])]
+    fn ppg_fifo(mut cx: ppg_fifo::Context) {
+        cx.shared.ppg_int.lock(|pin| pin.clear_interrupt());
+
+        // FIFO_WR_PTR, OVF_COUNTER and FIFO_RD_PTR follow each other. While the sensor
+        // is being set up again only the interrupt is released
+        let pointers = cx.shared.bus.lock(|bus| {
+            let mut pointers = [0u8; 3];
+            if bus.ppg.is_ready() {
+                bus.raw.write_read(MAX30102_ADDRESS, &[MAX30102_FIFO_WR_PTR], &mut pointers)?;
+            }
+            Ok::<_, I2cError>(pointers)
+        });
+        let mut pending = match pointers {
+            // Equal pointers mean empty, unless the FIFO overflowed and is full
+            Ok([write, overflow, read]) => match write.wrapping_sub(read) % MAX30102_FIFO_DEPTH {
+                0 if overflow > 0 => usize::from(MAX30102_FIFO_DEPTH),
+                pending => usize::from(pending),
+            },
+            Err(_) => 0,
+        };
+        let mut result = pointers.map(|_| ());
+
+        let mut drained = 0;
+        let mut last = None;
+        while pending > 0 && result.is_ok() {
+            let count = pending.min(PPG_CHUNK);
+            let mut bytes = [0u8; PPG_CHUNK * MAX30102_SAMPLE_BYTES];
+            let bytes = &mut bytes[..count * MAX30102_SAMPLE_BYTES];
+            // One lock per chunk
+            result = cx.shared.bus.lock(|bus| bus.raw.write_read(MAX30102_ADDRESS, &[MAX30102_FIFO_DATA], bytes));
+            if result.is_ok() {
+                let (samples, _) = bytes.as_chunks::<MAX30102_SAMPLE_BYTES>();
+                last = samples.last().map(|sample| (fifo_value(&sample[..3]), fifo_value(&sample[3..])));
+                drained += count;
+                pending -= count;
+            }
+        }
+
+        // Reading INT_STATUS releases the INT line
+        let cleared = cx.shared.bus.lock(|bus| {
+            let mut status = [0u8];
+            bus.raw.write_read(MAX30102_ADDRESS, &[MAX30102_INT_STATUS_1], &mut status)
+        });
+
+        if cleared.is_ok() {
+            *cx.local.clear_failures = 0;
+        } else {
+            *cx.local.clear_failures += 1;
+            if *cx.local.clear_failures >= MAX_CLEAR_FAILURES {
+                println!("MAX30102 keeps its INT line low, disabling the interrupt until it is set up again");
+                cx.shared.ppg_int.lock(|pin| pin.unlisten());
+                cx.shared.bus.lock(|bus| bus.ppg.mark_failed(now_ms()));
+                *cx.local.clear_failures = 0;
+            }
+        }
+
+        cx.shared.ppg.lock(|ppg| {
+            ppg.samples += drained as u32;
+            if last.is_some() {
+                ppg.last = last;
+            }
+            if result.is_err() || cleared.is_err() {
+                ppg.errors += 1;
+            }
+        });
+    }
+
+    /// An 18-bit FIFO value, stored in three big-endian bytes.
+    fn fifo_value(bytes: &[u8]) -> u32 {
+        (u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2])) & 0x3_FFFF
+    }
+
+    /// Verifies both sensors every `PRESENCE_CHECK_MS` and re-creates the drivers of the
+    /// ones that went missing or lost their configuration, on the backoff schedule.
+    ///
+    /// Each sensor is handled under its own lock, so the IMU tick gets in between. A
+    /// re-initialisation does hold the bus for its whole length, which delays the tick
+    /// once. The MAX30102 interrupt is armed again after every re-initialisation, in
+    /// case `ppg_fifo` switched it off.
+    #[task(priority = 1, shared = [bus, ppg_int], local = [last_check_ms: u64 = 0])]
+    async fn supervise(mut cx: supervise::Context) {
+        let now = now_ms();
+        let check = now - *cx.local.last_check_ms >= PRESENCE_CHECK_MS;
+        if check {
+            *cx.local.last_check_ms = now;
+        }
+
+        cx.shared.bus.lock(|bus| {
+            if check {
+                let event = bus.imu_monitor.check(&mut bus.raw);
+                report_presence("MPU6050", event);
+                if event.is_some() {
+                    bus.imu.mark_failed(now);
+                }
+            }
+
+            let cell = bus.bus;
+            let was_ready = bus.imu.is_ready();
+            let ready = bus.imu.poll(now, || init_imu(cell), |attempt| report_init_failure("MPU6050", attempt)).is_some();
+            if ready && !was_ready {
+                println!("MPU6050 initialized");
+                let _ = bus.imu_monitor.capture(&mut bus.raw);
+            }
+        });
+
+        let ppg_back = cx.shared.bus.lock(|bus| {
+            if check {
+                let event = bus.ppg_monitor.check(&mut bus.raw);
+                report_presence("MAX30102", event);
+                if event.is_some() {
+                    bus.ppg.mark_failed(now);
+                }
+            }
+
+            let cell = bus.bus;
+            let was_ready = bus.ppg.is_ready();
+            let ready = bus.ppg.poll(now, || init_ppg(cell), |attempt| report_init_failure("MAX30102", attempt)).is_some();
+            if ready && !was_ready {
+                // A reset MAX30102 comes back with its interrupt disabled
+                match enable_ppg_interrupt(&mut bus.raw) {
+                    Ok(()) => {
+                        println!("MAX30102 initialized");
+                        let _ = bus.ppg_monitor.capture(&mut bus.raw);
+                        return true;
+                    },
+                    Err(e) => {
+                        println!("Failed to enable the MAX30102 interrupt: {:?}", e);
+                        bus.ppg.mark_failed(now);
+                    }
+                }
+            }
+            false
+        });
+
+        if ppg_back {
+            cx.shared.ppg_int.lock(|pin| {
+                pin.clear_interrupt();
+                pin.listen(Event::LowLevel);
+            });
+        }
+    }
+
+    /// Lowest priority: formatting and printing never delays sampling.
+    #[task(priority = 1, shared = [ppg])]
+    async fn report(mut cx: report::Context, imu: ImuSnapshot) {
+        let ppg = cx.shared.ppg.lock(|ppg| *ppg);
+
+        println!("Temperature : {:.2} C", imu.temperature);
+        println!("Acceleration [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g", imu.acceleration[0], imu.acceleration[1], imu.acceleration[2]);
+        println!("Angular Velocity [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", imu.angular_velocity[0], imu.angular_velocity[1], imu.angular_velocity[2]);
+        println!("IMU timing : worst tick latency {} us, {} read errors", imu.worst_latency_us, imu.errors);
+        match ppg.last {
+            Some((red, ir)) => println!("PPG : {} samples drained, last red {} ir {}, {} read errors", ppg.samples, red, ir, ppg.errors),
+            None => println!("PPG : waiting for the first FIFO interrupt..."),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::cell::RefCell;
+
+use esp_backtrace as _;
+use esp_hal::{
+    i2c::master::{
+        I2c, Config
+    },
+    clock::CpuClock,
+    delay::Delay,
+    time::{Instant, Rate},
+    Blocking,
+    main
+};
+use esp_println::println;
+use example_support::init::{init_with_retry, Attempt, Backoff, SensorSupervisor};
+use hayasen::max30102::FifoSample;
+use hayasen::{max30102_hayasen, mpu6050_hayasen};
+
+esp_bootloader_esp_idf::esp_app_desc!();
+
+/// The MPU6050 is read at 50 Hz.
+const IMU_PERIOD_MS: u64 = 20;
+/// The MAX30102 buffers 32 samples, draining it every 100 ms keeps well clear of overflow.
+const PPG_PERIOD_MS: u64 = 100;
+const REPORT_PERIOD_MS: u64 = 1_000;
+
+#[cfg(not(feature = "critical-section-bus"))]
+mod shared {
+    use super::*;
+
+    pub type SharedBus = RefCell<I2c<'static, Blocking>>;
+    pub type Device<'a> = embedded_hal_bus::i2c::RefCellDevice<'a, I2c<'static, Blocking>>;
+
+    pub fn new_bus(i2c: I2c<'static, Blocking>) -> SharedBus {
+        RefCell::new(i2c)
+    }
+
+    pub fn device(bus: &SharedBus) -> Device<'_> {
+        Device::new(bus)
+    }
+}
+
+#[cfg(feature = "critical-section-bus")]
+mod shared {
+    use super::*;
+
+    pub type SharedBus = critical_section::Mutex<RefCell<I2c<'static, Blocking>>>;
+    pub type Device<'a> = embedded_hal_bus::i2c::CriticalSectionDevice<'a, I2c<'static, Blocking>>;
+
+    pub fn new_bus(i2c: I2c<'static, Blocking>) -> SharedBus {
+        critical_section::Mutex::new(RefCell::new(i2c))
+    }
+
+    pub fn device(bus: &SharedBus) -> Device<'_> {
+        Device::new(bus)
+    }
+}
+
+/// Fixed-rate schedule on top of the system timer.
+struct Periodic {
+    period_ms: u64,
+    next_ms: u64,
+}
+
+impl Periodic {
+    fn new(period_ms: u64, now_ms: u64) -> Self {
+        Self { period_ms, next_ms: now_ms }
+    }
+
+    fn due(&mut self, now_ms: u64) -> bool {
+        if now_ms < self.next_ms {
+            return false;
+        }
+        self.next_ms += self.period_ms;
+        // Skip missed slots instead of bursting to catch up
+        if self.next_ms <= now_ms {
+            self.next_ms = now_ms + self.period_ms;
+        }
+        true
+    }
+}
+
+fn now_ms() -> u64 {
+    Instant::now().duration_since_epoch().as_millis()
+}
+
+fn report_init_failure<E: core::fmt::Debug>(name: &str, attempt: &Attempt<'_, E>) {
+    match attempt.retry_in_ms {
+        Some(ms) => println!("{} init attempt {} failed: {:?}, retrying in {} ms", name, attempt.number, attempt.error, ms),
+        None => println!("{} init attempt {} failed: {:?}, giving up", name, attempt.number, attempt.error),
+    }
+}
+
+#[main]
+fn main() -> ! {
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    let mut delay = Delay::new();
+
+    let sda = peripherals.GPIO4;
+    let scl = peripherals.GPIO5;
+
+    let mpu_address: u8 = 0x68;
+
+    let i2c = I2c::new(peripherals.I2C0, Config::default().with_frequency(Rate::from_khz(400)))
+        .unwrap()
+        .with_sda(sda)
+        .with_scl(scl);
+
+    // One bus, one device handle per driver
+    let bus = shared::new_bus(i2c);
+
+    let init_imu = || mpu6050_hayasen::create_default(shared::device(&bus), mpu_address);
+    let init_ppg = || {
+        max30102_hayasen::create_default_with_address(shared::device(&bus))
+            .and_then(|mut ppg| max30102_hayasen::setup_high_performance_mode(&mut ppg).map(|_| ppg))
+    };
+
+    // A sensor that is still missing after the retries is retried in the background,
+    // while the other one is sampled
+    let backoff = Backoff::default();
+    let initial = init_with_retry(&backoff, &mut delay, init_imu, |attempt| report_init_failure("MPU6050", attempt));
+    if initial.is_err() {
+        println!("MPU6050 unavailable, running degraded and retrying in the background");
+    }
+    let mut imu = SensorSupervisor::new(initial, now_ms(), backoff);
+    let initial = init_with_retry(&backoff, &mut delay, init_ppg, |attempt| report_init_failure("MAX30102", attempt));
+    if initial.is_err() {
+        println!("MAX30102 unavailable, running degraded and retrying in the background");
+    }
+    let mut ppg = SensorSupervisor::new(initial, now_ms(), backoff);
+
+    println!("MPU6050 every {} ms and MAX30102 every {} ms on one I2C bus", IMU_PERIOD_MS, PPG_PERIOD_MS);
+
+    let mut imu_schedule = Periodic::new(IMU_PERIOD_MS, now_ms());
+    let mut ppg_schedule = Periodic::new(PPG_PERIOD_MS, now_ms());
+    let mut report_schedule = Periodic::new(REPORT_PERIOD_MS, now_ms() + REPORT_PERIOD_MS);
+
+    let mut sample_buffer: [FifoSample; 32] = core::array::from_fn(|_| FifoSample { red: 0, ir: 0 });
+    let mut last_imu = None;
+    let mut last_ppg = None;
+    let mut imu_reads: u32 = 0;
+    let mut ppg_samples: u32 = 0;
+    let mut imu_errors: u32 = 0;
+    let mut ppg_errors: u32 = 0;
+
+    loop {
+        let now = now_ms();
+
+        if imu_schedule.due(now) {
+            let was_ready = imu.is_ready();
+            if let Some(sensor) = imu.poll(now, init_imu, |attempt| report_init_failure("MPU6050", attempt)) {
+                if !was_ready {
+                    println!("MPU6050 initialized");
+                }
+                match mpu6050_hayasen::read_all(sensor) {
+                    Ok(reading) => {
+                        last_imu = Some(reading);
+                        imu_reads += 1;
+                    },
+                    Err(_) => imu_errors += 1,
+                }
+            }
+        }
+
+        if ppg_schedule.due(now) {
+            let was_ready = ppg.is_ready();
+            if let Some(sensor) = ppg.poll(now, init_ppg, |attempt| report_init_failure("MAX30102", attempt)) {
+                if !was_ready {
+                    println!("MAX30102 initialized");
+                }
+                match max30102_hayasen::read_fifo_batch(sensor, &mut sample_buffer) {
+                    Ok(count) => {
+                        if count > 0 {
+                            last_ppg = Some((sample_buffer[count - 1].red, sample_buffer[count - 1].ir));
+                        }
+                        ppg_samples += count as u32;
+                    },
+                    Err(_) => ppg_errors += 1,
+                }
+            }
+        }
+
+        if report_schedule.due(now) {
+            if !imu.is_ready() {
+                println!("MPU6050 : not connected");
+            } else if let Some((temperature, acceleration, angular_velocity)) = last_imu {
+                println!("Temperature : {:.2} C", temperature);
+                println!("Acceleration [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g", acceleration[0], acceleration[1], acceleration[2]);
+                println!("Angular Velocity [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", angular_velocity[0], angular_velocity[1], angular_velocity[2]);
+            }
+            if !ppg.is_ready() {
+                println!("MAX30102 : not connected");
+            } else if let Some((red, ir)) = last_ppg {
+                println!("PPG : red {} ir {}", red, ir);
+            }
+            println!("Last second : {} IMU reads ({} errors), {} PPG samples ({} errors)", imu_reads, imu_errors, ppg_samples, ppg_errors);
+            imu_reads = 0;
+            ppg_samples = 0;
+        }
+
+        delay.delay_millis(1);
+    }
+}
@@ -0,0 +1,184 @@
+//! Six-position accelerometer calibration: bias, scale factor and misalignment.
+//!
+//! Subtracting an offset (see [`calibration`](crate::calibration)) leaves two errors
+//! that matter for tilt: each axis has its own gain, and the axes are not quite
+//! orthogonal, so some of X leaks into Y and Z. Both are captured by the model
+//!
+//! ```text
+//! corrected = M · (raw - bias)
+//! ```
+//!
+//! with `M` a full 3x3 matrix: its diagonal holds the scale factors and its
+//! off-diagonal terms the cross-axis misalignment. Averaging a still reading with each
+//! axis pointing up and down gives six readings of a known gravity vector, from which
+//! [`fit`] solves for `M` and `bias` by linear least squares. Any four or more
+//! non-coplanar positions will do, so extra positions can be added for a better fit.
+
+use crate::calibration::{Calibrator, Orientation, Progress};
+use crate::imu::ImuSample;
+use crate::linalg::{invert3, mul3, NormalEquations};
+use crate::units::{Acceleration, Vector3};
+
+/// Correction of accelerometer bias, scale factor and misalignment.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AccelCalibration {
+    /// Row-major, applied after the bias has been subtracted.
+    pub matrix: [[f32; 3]; 3],
+    pub bias: Vector3<Acceleration>,
+}
+
+impl Default for AccelCalibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl AccelCalibration {
+    /// Leaves readings unchanged.
+    pub const IDENTITY: Self = Self {
+        matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        bias: Vector3::new(Acceleration::ZERO, Acceleration::ZERO, Acceleration::ZERO),
+    };
+
+    pub fn apply(&self, acceleration: Vector3<Acceleration>) -> Vector3<Acceleration> {
+        let [x, y, z] = (acceleration - self.bias).as_mps2();
+        Vector3::from_mps2(self.matrix.map(|row| row[0] * x + row[1] * y + row[2] * z))
+    }
+
+    /// Per-axis gain, the diagonal of the matrix.
+    pub fn scale(&self) -> [f32; 3] {
+        [self.matrix[0][0], self.matrix[1][1], self.matrix[2][2]]
+    }
+}
+
+/// Why a set of positions could not be fitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitError {
+    /// At least four positions are needed for the twelve unknowns.
+    TooFewPositions,
+    /// The positions do not span all three axes, e.g. they all lie in one plane.
+    Degenerate,
+}
+
+/// One averaged still reading and the gravity vector it should have read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub measured: Vector3<Acceleration>,
+    pub reference: Vector3<Acceleration>,
+}
+
+/// Solves for the calibration that maps each measured reading closest to its reference.
+pub fn fit(positions: &[Position]) -> Result<AccelCalibration, FitError> {
+    if positions.len() < 4 {
+        return Err(FitError::TooFewPositions);
+    }
+
+    // corrected = A · [raw, 1], one independent fit of four coefficients per output axis
+    let mut rows = [NormalEquations::<4>::new(); 3];
+    for position in positions {
+        let [x, y, z] = position.measured.as_g().map(f64::from);
+        let reference = position.reference.as_g().map(f64::from);
+        for (equations, target) in rows.iter_mut().zip(reference) {
+            equations.add([x, y, z, 1.0], target);
+        }
+    }
+
+    let mut matrix = [[0.0f64; 3]; 3];
+    let mut offset = [0.0f64; 3];
+    for (axis, equations) in rows.iter().enumerate() {
+        let [a, b, c, d] = equations.solve().ok_or(FitError::Degenerate)?;
+        matrix[axis] = [a, b, c];
+        offset[axis] = d;
+    }
+
+    // A · [raw, 1] = M · raw + offset = M · (raw - bias) with bias = -M⁻¹ · offset
+    let inverse = invert3(matrix).ok_or(FitError::Degenerate)?;
+    let bias = mul3(&inverse, offset).map(|b| -b as f32);
+
+    Ok(AccelCalibration {
+        matrix: matrix.map(|row| row.map(|v| v as f32)),
+        bias: Vector3::from_g(bias),
+    })
+}
+
+/// Where a six-position run stands after a sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    /// The sensor is not (yet) in the requested orientation.
+    Waiting { orientation: Orientation },
+    Collecting {
+        orientation: Orientation,
+        collected: u32,
+        required: u32,
+    },
+    /// The sensor moved, collection for this orientation starts over.
+    Restarted { orientation: Orientation },
+    /// This orientation is done, the sensor should now be turned to `next`.
+    PositionDone { orientation: Orientation, next: Orientation },
+    Done(Result<AccelCalibration, FitError>),
+}
+
+/// Guides the sensor through all six orientations and fits the result.
+#[derive(Debug, Clone)]
+pub struct SixPosition {
+    samples: u32,
+    index: usize,
+    calibrator: Calibrator,
+    positions: [Position; 6],
+}
+
+impl SixPosition {
+    /// Averages `samples` still readings in each orientation.
+    pub fn new(samples: u32) -> Self {
+        let first = Orientation::ALL[0];
+        Self {
+            samples,
+            index: 0,
+            calibrator: Calibrator::new(first, samples),
+            positions: [Position {
+                measured: Vector3::default(),
+                reference: Vector3::default(),
+            }; 6],
+        }
+    }
+
+    /// The orientation the sensor should be held in now, `None` once done.
+    pub fn orientation(&self) -> Option<Orientation> {
+        Orientation::ALL.get(self.index).copied()
+    }
+
+    pub fn add(&mut self, sample: &ImuSample) -> Step {
+        let Some(orientation) = self.orientation() else {
+            return Step::Done(fit(&self.positions));
+        };
+
+        // Readings taken while turning the sensor, or in the wrong orientation, are skipped
+        if Orientation::from_gravity(sample.acceleration) != orientation {
+            self.calibrator.reset();
+            return Step::Waiting { orientation };
+        }
+
+        match self.calibrator.add(sample) {
+            Progress::Collecting { collected, required } => Step::Collecting {
+                orientation,
+                collected,
+                required,
+            },
+            Progress::Restarted => Step::Restarted { orientation },
+            Progress::Done(calibration) => {
+                self.positions[self.index] = Position {
+                    measured: calibration.accel_offset + orientation.gravity(),
+                    reference: orientation.gravity(),
+                };
+                self.index += 1;
+                match self.orientation() {
+                    Some(next) => {
+                        self.calibrator = Calibrator::new(next, self.samples);
+                        Step::PositionDone { orientation, next }
+                    }
+                    None => Step::Done(fit(&self.positions)),
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! Accelerometer readings through the FIFO of an MPU6050 or MPU9250, at the full 1 kHz.
+//!
+//! Polling the data registers cannot keep up with the accelerometer's own rate, and
+//! every late poll drops or repeats a reading, which smears a spectrum. The FIFO
+//! queues readings inside the sensor, so they arrive evenly spaced however irregularly
+//! they are fetched. Only the accelerometer is queued, six bytes per reading. The
+//! MPU6050's FIFO holds 1024 bytes, 170 readings, and has to be drained at least every
+//! 170 ms; the MPU9250's holds 512 bytes, 85 readings or 85 ms.
+//!
+//! [`AccelFifo`] takes over the sensor's configuration. It wakes it, sets the range, a
+//! 1 kHz sample rate and the digital low-pass filter, which keeps most of what lies
+//! above the 500 Hz Nyquist frequency out. On the MPU6050 that filter is set in
+//! CONFIG, shared with the gyroscope, at 184 Hz. The MPU9250 has a separate one for
+//! the accelerometer in ACCEL_CONFIG2, set to 218 Hz.
+
+use embedded_hal::i2c::I2c;
+
+use crate::units::{Acceleration, Vector3};
+
+/// Readings per second.
+pub const SAMPLE_RATE_HZ: f32 = 1_000.0;
+/// Bytes per reading, X, Y and Z big-endian.
+const READING_BYTES: usize = 6;
+/// Readings fetched per I2C transfer.
+const CHUNK: usize = 16;
+
+const SMPLRT_DIV: u8 = 0x19;
+const CONFIG: u8 = 0x1A;
+const ACCEL_CONFIG: u8 = 0x1C;
+/// MPU9250 only.
+const ACCEL_CONFIG2: u8 = 0x1D;
+const FIFO_EN: u8 = 0x23;
+const INT_STATUS: u8 = 0x3A;
+const USER_CTRL: u8 = 0x6A;
+const PWR_MGMT_1: u8 = 0x6B;
+const FIFO_COUNTH: u8 = 0x72;
+const FIFO_R_W: u8 = 0x74;
+const WHO_AM_I: u8 = 0x75;
+
+const MPU6050_ID: u8 = 0x68;
+const MPU9250_ID: u8 = 0x71;
+/// The MPU9255, which the bus scan also takes for an MPU9250.
+const MPU9255_ID: u8 = 0x73;
+/// PWR_MGMT_1: awake, clocked from the X gyro's PLL.
+const CLOCK_PLL_X: u8 = 0x01;
+/// CONFIG: gyro output at 1 kHz, and on the MPU6050 accelerometer bandwidth 184 Hz.
+const DLPF_184_HZ: u8 = 0x01;
+/// ACCEL_CONFIG2 of the MPU9250: accelerometer bandwidth 218 Hz, output at 1 kHz.
+const ACCEL_DLPF_218_HZ: u8 = 0x01;
+/// FIFO_EN: queue the accelerometer.
+const ACCEL_FIFO_EN: u8 = 0x08;
+/// INT_STATUS: the FIFO overflowed, cleared by reading INT_STATUS.
+const FIFO_OFLOW_INT: u8 = 0x10;
+/// USER_CTRL: enable the FIFO.
+const USER_FIFO_EN: u8 = 0x40;
+/// USER_CTRL: empty the FIFO, clears itself.
+const USER_FIFO_RESET: u8 = 0x04;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Mpu6050,
+    Mpu9250,
+}
+
+impl Model {
+    /// Size of the FIFO in bytes.
+    pub fn fifo_bytes(self) -> usize {
+        match self {
+            Model::Mpu6050 => 1_024,
+            Model::Mpu9250 => 512,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccelRange {
+    G2,
+    G4,
+    G8,
+    G16,
+}
+
+impl AccelRange {
+    fn bits(self) -> u8 {
+        match self {
+            AccelRange::G2 => 0x00,
+            AccelRange::G4 => 0x08,
+            AccelRange::G8 => 0x10,
+            AccelRange::G16 => 0x18,
+        }
+    }
+
+    fn g_per_count(self) -> f32 {
+        let full_scale = match self {
+            AccelRange::G2 => 2.0,
+            AccelRange::G4 => 4.0,
+            AccelRange::G8 => 8.0,
+            AccelRange::G16 => 16.0,
+        };
+        full_scale / 32768.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    /// Something other than an MPU6050 or MPU9250 answered, with this WHO_AM_I value.
+    WrongDevice(u8),
+    /// The FIFO filled up before it was drained and readings were lost. It has been
+    /// emptied, so the next readings are contiguous again.
+    Overflow,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Error::I2c(error)
+    }
+}
+
+#[derive(Debug)]
+pub struct AccelFifo<I2C> {
+    i2c: I2C,
+    address: u8,
+    model: Model,
+    range: AccelRange,
+}
+
+impl<I2C: I2c> AccelFifo<I2C> {
+    /// Checks the identity, configures the sensor and starts queueing readings.
+    pub fn new(mut i2c: I2C, address: u8, range: AccelRange) -> Result<Self, Error<I2C::Error>> {
+        let mut id = [0u8];
+        i2c.write_read(address, &[WHO_AM_I], &mut id)?;
+        let model = match id[0] {
+            MPU6050_ID => Model::Mpu6050,
+            MPU9250_ID | MPU9255_ID => Model::Mpu9250,
+            other => return Err(Error::WrongDevice(other)),
+        };
+
+        i2c.write(address, &[PWR_MGMT_1, CLOCK_PLL_X])?;
+        // 1 kHz divided by one
+        i2c.write(address, &[SMPLRT_DIV, 0])?;
+        i2c.write(address, &[CONFIG, DLPF_184_HZ])?;
+        i2c.write(address, &[ACCEL_CONFIG, range.bits()])?;
+        if model == Model::Mpu9250 {
+            i2c.write(address, &[ACCEL_CONFIG2, ACCEL_DLPF_218_HZ])?;
+        }
+        i2c.write(address, &[FIFO_EN, ACCEL_FIFO_EN])?;
+
+        let mut fifo = Self { i2c, address, model, range };
+        fifo.clear()?;
+        Ok(fifo)
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    /// Readings the FIFO holds before it overflows.
+    pub fn capacity(&self) -> usize {
+        self.model.fifo_bytes() / READING_BYTES
+    }
+
+    pub fn range(&self) -> AccelRange {
+        self.range
+    }
+
+    /// Throws away everything queued, so that the next reading is a fresh one.
+    pub fn clear(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.i2c.write(self.address, &[USER_CTRL, USER_FIFO_RESET])?;
+        // Reading INT_STATUS clears an overflow from before
+        let mut status = [0u8];
+        self.i2c.write_read(self.address, &[INT_STATUS], &mut status)?;
+        self.i2c.write(self.address, &[USER_CTRL, USER_FIFO_EN])?;
+        Ok(())
+    }
+
+    /// Readings waiting in the FIFO.
+    pub fn available(&mut self) -> Result<usize, Error<I2C::Error>> {
+        let mut count = [0u8; 2];
+        self.i2c.write_read(self.address, &[FIFO_COUNTH], &mut count)?;
+        Ok(usize::from(u16::from_be_bytes(count)) / READING_BYTES)
+    }
+
+    /// Moves as many queued readings into `readings` as fit, oldest first, and returns
+    /// how many that were.
+    pub fn read(&mut self, readings: &mut [Vector3<Acceleration>]) -> Result<usize, Error<I2C::Error>> {
+        let mut status = [0u8];
+        self.i2c.write_read(self.address, &[INT_STATUS], &mut status)?;
+        if status[0] & FIFO_OFLOW_INT != 0 {
+            self.clear()?;
+            return Err(Error::Overflow);
+        }
+
+        let count = self.available()?.min(readings.len());
+        let scale = self.range.g_per_count();
+        let mut bytes = [0u8; CHUNK * READING_BYTES];
+        for chunk in readings[..count].chunks_mut(CHUNK) {
+            let bytes = &mut bytes[..chunk.len() * READING_BYTES];
+            self.i2c.write_read(self.address, &[FIFO_R_W], bytes)?;
+            let (raw, _) = bytes.as_chunks::<READING_BYTES>();
+            for (reading, raw) in chunk.iter_mut().zip(raw) {
+                let (counts, _) = raw.as_chunks::<2>();
+                *reading = Vector3::from_g(core::array::from_fn(|axis| f32::from(i16::from_be_bytes(counts[axis])) * scale));
+            }
+        }
+        Ok(count)
+    }
+
+    /// Stops queueing and hands back the bus.
+    pub fn release(mut self) -> Result<I2C, Error<I2C::Error>> {
+        self.i2c.write(self.address, &[USER_CTRL, 0])?;
+        self.i2c.write(self.address, &[FIFO_EN, 0])?;
+        Ok(self.i2c)
+    }
+}
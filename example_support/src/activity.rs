@@ -0,0 +1,270 @@
+//! Activity classification from the accelerometer: still, walking, running, cycling or
+//! riding in a vehicle.
+//!
+//! [`FeatureExtractor`] condenses the last few seconds of readings into a handful of
+//! [`Features`], and a [`DecisionTree`] maps those to an [`Activity`]. The readings
+//! are first averaged into bins at a fixed [`BIN_RATE_HZ`], so the features, and the
+//! tree's thresholds, do not depend on how fast the sensor is read.
+//!
+//! The built-in tree in `activity_tree.rs` is generated by `tools/train_activity.py`
+//! from synthetic recordings. It is a starting point: retrain it on recordings from
+//! the real sensor, worn where it will be worn, and pass the generated table to
+//! [`DecisionTree::new`]. The trainer computes the features the same way as
+//! [`FeatureExtractor`]; a change to one has to be made to the other.
+
+use core::f32::consts::PI;
+
+use libm::{cosf, sinf, sqrtf};
+
+use crate::imu::ImuSample;
+
+/// Rate of the bins the readings are averaged into.
+pub const BIN_RATE_HZ: f32 = 25.0;
+/// Bins the features are computed over, 5.12 s.
+pub const WINDOW: usize = 128;
+/// Bins between two sets of features, so the windows overlap by half.
+pub const HOP: usize = 64;
+
+const BIN_US: u64 = 40_000;
+/// Longest gap between samples that is bridged by repeating the last bin. After a
+/// longer one the window starts over.
+const MAX_GAP_US: u64 = 500_000;
+/// Range of the dominant frequency, in DFT bins: about 0.4 to 5 Hz.
+const MIN_FREQUENCY_BIN: usize = 2;
+const MAX_FREQUENCY_BIN: usize = 26;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activity {
+    Still,
+    Walking,
+    Running,
+    Cycling,
+    Vehicle,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feature {
+    Variance,
+    Energy,
+    DominantFrequency,
+    Periodicity,
+    Jerk,
+}
+
+/// What a window of readings looks like.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Features {
+    /// Variance of the magnitude of the acceleration, in g².
+    pub variance: f32,
+    /// Mean squared deviation of the acceleration vector from its mean, in g². Unlike
+    /// the variance this includes changes of direction.
+    pub energy: f32,
+    /// Strongest frequency in the magnitude, in Hz.
+    pub dominant_frequency: f32,
+    /// Share of the magnitude's power at and right around the dominant frequency, from
+    /// near 0 for noise to 1 for a pure sine.
+    pub periodicity: f32,
+    /// Mean rate of change of the acceleration vector, in g/s.
+    pub jerk: f32,
+}
+
+impl Features {
+    pub fn get(&self, feature: Feature) -> f32 {
+        match feature {
+            Feature::Variance => self.variance,
+            Feature::Energy => self.energy,
+            Feature::DominantFrequency => self.dominant_frequency,
+            Feature::Periodicity => self.periodicity,
+            Feature::Jerk => self.jerk,
+        }
+    }
+
+    /// Computes the features of a window of bins, oldest first.
+    fn compute(bins: &[[f32; 3]; WINDOW]) -> Self {
+        let n = WINDOW as f32;
+        let magnitudes = bins.map(|a| sqrtf(a.iter().map(|a| a * a).sum()));
+        let mean_magnitude = magnitudes.iter().sum::<f32>() / n;
+        let mut mean = [0.0; 3];
+        for a in bins {
+            for (mean, a) in mean.iter_mut().zip(a) {
+                *mean += a / n;
+            }
+        }
+
+        let deviations = magnitudes.map(|m| m - mean_magnitude);
+        let power = deviations.iter().map(|d| d * d).sum::<f32>();
+        let energy = bins
+            .iter()
+            .map(|a| a.iter().zip(mean).map(|(a, mean)| (a - mean) * (a - mean)).sum::<f32>())
+            .sum::<f32>();
+        let jerk = bins
+            .windows(2)
+            .map(|pair| sqrtf(pair[1].iter().zip(pair[0]).map(|(b, a)| (b - a) * (b - a)).sum()))
+            .sum::<f32>()
+            * BIN_RATE_HZ
+            / (n - 1.0);
+
+        // A plain DFT over the few bins of interest is cheap enough every 2.5 s. The
+        // bins either side are needed too: a frequency between two bins spreads over
+        // its neighbours
+        let mut powers = [0.0; MAX_FREQUENCY_BIN + 2];
+        for (k, bin_power) in powers.iter_mut().enumerate().skip(MIN_FREQUENCY_BIN - 1) {
+            let (mut re, mut im) = (0.0, 0.0);
+            for (i, d) in deviations.iter().enumerate() {
+                let angle = 2.0 * PI * ((k * i) % WINDOW) as f32 / n;
+                re += d * cosf(angle);
+                im += d * sinf(angle);
+            }
+            *bin_power = re * re + im * im;
+        }
+        let best_bin = (MIN_FREQUENCY_BIN..=MAX_FREQUENCY_BIN).fold(MIN_FREQUENCY_BIN, |best, k| {
+            if powers[k] > powers[best] {
+                k
+            } else {
+                best
+            }
+        });
+        let peak_power = powers[best_bin - 1] + powers[best_bin] + powers[best_bin + 1];
+        // Parseval: the one-sided spectrum of a zero-mean signal holds N·Σx²/2
+        let periodicity = if power > 0.0 { (peak_power / (n * power / 2.0)).min(1.0) } else { 0.0 };
+
+        Self {
+            variance: power / n,
+            energy: energy / n,
+            dominant_frequency: best_bin as f32 * BIN_RATE_HZ / n,
+            periodicity,
+            jerk,
+        }
+    }
+}
+
+/// Averages readings into bins and computes the [`Features`] of the last [`WINDOW`]
+/// bins every [`HOP`] bins.
+#[derive(Debug, Clone)]
+pub struct FeatureExtractor {
+    /// Ring of the bin averages, in g.
+    bins: [[f32; 3]; WINDOW],
+    next: usize,
+    /// Bins still to go until the next features.
+    countdown: usize,
+    bin_start_us: Option<u64>,
+    last_us: u64,
+    sum: [f32; 3],
+    count: u32,
+}
+
+impl Default for FeatureExtractor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FeatureExtractor {
+    pub fn new() -> Self {
+        Self {
+            bins: [[0.0; 3]; WINDOW],
+            next: 0,
+            countdown: WINDOW,
+            bin_start_us: None,
+            last_us: 0,
+            sum: [0.0; 3],
+            count: 0,
+        }
+    }
+
+    /// Forgets the readings so far; the next features come a full window later.
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Feeds one sample, returning the features of the window it completes.
+    pub fn update(&mut self, sample: &ImuSample) -> Option<Features> {
+        let now = sample.timestamp_us;
+        let gap = now.checked_sub(self.last_us).is_none_or(|gap| gap > MAX_GAP_US);
+        if self.bin_start_us.is_some() && gap {
+            self.reset();
+        }
+        self.last_us = now;
+        let bin_start = *self.bin_start_us.get_or_insert(now);
+
+        let mut features = None;
+        let mut start = bin_start;
+        while now >= start + BIN_US {
+            let bin = if self.count > 0 {
+                self.sum.map(|sum| sum / self.count as f32)
+            } else {
+                // Nothing arrived during this bin, so carry the previous one over
+                self.bins[(self.next + WINDOW - 1) % WINDOW]
+            };
+            features = features.or(self.push(bin));
+            self.sum = [0.0; 3];
+            self.count = 0;
+            start += BIN_US;
+        }
+        self.bin_start_us = Some(start);
+
+        for (sum, a) in self.sum.iter_mut().zip(sample.acceleration.as_g()) {
+            *sum += a;
+        }
+        self.count += 1;
+        features
+    }
+
+    fn push(&mut self, bin: [f32; 3]) -> Option<Features> {
+        self.bins[self.next] = bin;
+        self.next = (self.next + 1) % WINDOW;
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return None;
+        }
+        self.countdown = HOP;
+        // `next` is now the oldest bin
+        let window = core::array::from_fn(|i| self.bins[(self.next + i) % WINDOW]);
+        Some(Features::compute(&window))
+    }
+}
+
+/// A node of a [`DecisionTree`], as generated by `tools/train_activity.py`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Node {
+    /// Continues at `below` if the feature is below the threshold, at `above`
+    /// otherwise. Both are indices into the tree.
+    Split {
+        feature: Feature,
+        threshold: f32,
+        below: u16,
+        above: u16,
+    },
+    Leaf(Activity),
+}
+
+/// A decision tree over [`Features`], its root at index 0.
+#[derive(Debug, Clone, Copy)]
+pub struct DecisionTree {
+    nodes: &'static [Node],
+}
+
+impl Default for DecisionTree {
+    /// The tree trained on synthetic recordings, see `tools/train_activity.py`.
+    fn default() -> Self {
+        Self::new(crate::activity_tree::NODES)
+    }
+}
+
+impl DecisionTree {
+    pub const fn new(nodes: &'static [Node]) -> Self {
+        Self { nodes }
+    }
+
+    pub fn classify(&self, features: &Features) -> Activity {
+        let mut index = 0;
+        loop {
+            match self.nodes[index] {
+                Node::Split { feature, threshold, below, above } => {
+                    index = usize::from(if features.get(feature) < threshold { below } else { above });
+                },
+                Node::Leaf(activity) => return activity,
+            }
+        }
+    }
+}
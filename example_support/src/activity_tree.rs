@@ -0,0 +1,23 @@
+//! Decision tree for activity classification.
+//!
+//! Generated by `tools/train_activity.py`, do not edit.
+
+use crate::activity::{Activity, Feature, Node};
+
+pub static NODES: &[Node] = &[
+    Node::Split { feature: Feature::Variance, threshold: 0.000380027, below: 1, above: 2 },
+    Node::Leaf(Activity::Still),
+    Node::Split { feature: Feature::Variance, threshold: 0.100433, below: 3, above: 14 },
+    Node::Split { feature: Feature::Periodicity, threshold: 0.549215, below: 4, above: 5 },
+    Node::Leaf(Activity::Vehicle),
+    Node::Split { feature: Feature::DominantFrequency, threshold: 2.05078, below: 6, above: 11 },
+    Node::Split { feature: Feature::Periodicity, threshold: 0.862207, below: 7, above: 10 },
+    Node::Split { feature: Feature::Variance, threshold: 0.00617264, below: 8, above: 9 },
+    Node::Leaf(Activity::Cycling),
+    Node::Leaf(Activity::Walking),
+    Node::Leaf(Activity::Cycling),
+    Node::Split { feature: Feature::Variance, threshold: 0.0205809, below: 12, above: 13 },
+    Node::Leaf(Activity::Cycling),
+    Node::Leaf(Activity::Walking),
+    Node::Leaf(Activity::Running),
+];
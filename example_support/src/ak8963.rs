@@ -0,0 +1,197 @@
+//! Driver for the AK8963 magnetometer inside the MPU9250.
+//!
+//! The AK8963 sits on the MPU9250's auxiliary I2C bus. With bypass enabled
+//! ([`enable_mpu9250_bypass`]) it answers on the main bus at [`ADDRESS`] and is read
+//! directly. Every chip has its own sensitivity trim in fuse ROM, which
+//! [`Ak8963::new`] reads once and applies to every reading. Readings are returned in
+//! µT and already rotated into the MPU9250's accelerometer and gyroscope axes, whose X
+//! and Y are swapped and Z flipped relative to the magnetometer's own.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::I2c;
+
+pub use crate::scan::enable_mpu9250_bypass;
+use crate::units::{MagneticField, Vector3};
+
+/// Address of the AK8963 inside an MPU9250.
+pub const ADDRESS: u8 = 0x0C;
+
+const WIA: u8 = 0x00;
+const ST1: u8 = 0x02;
+const HXL: u8 = 0x03;
+const CNTL1: u8 = 0x0A;
+const ASAX: u8 = 0x10;
+
+const DEVICE_ID: u8 = 0x48;
+/// ST1: a new measurement is waiting.
+const ST1_DRDY: u8 = 0x01;
+/// ST2: the field exceeded the measurement range, the reading is invalid.
+const ST2_HOFL: u8 = 0x08;
+/// CNTL1: 16-bit output instead of 14-bit.
+const CNTL1_BIT: u8 = 0x10;
+const MODE_POWER_DOWN: u8 = 0x00;
+const MODE_FUSE_ROM: u8 = 0x0F;
+
+/// Time the AK8963 needs after leaving power-down mode, 100 µs in the datasheet.
+const MODE_CHANGE_US: u32 = 100;
+
+/// Full scale of ±4912 µT, over the output range of each resolution.
+const FULL_SCALE_UT: f32 = 4912.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    /// 0.6 µT per count.
+    Bits14,
+    /// 0.15 µT per count.
+    Bits16,
+}
+
+impl Resolution {
+    fn microtesla_per_count(self) -> f32 {
+        match self {
+            Resolution::Bits14 => FULL_SCALE_UT / 8190.0,
+            Resolution::Bits16 => FULL_SCALE_UT / 32760.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// A measurement is taken on every [`Ak8963::trigger`].
+    Single,
+    Continuous8Hz,
+    Continuous100Hz,
+}
+
+impl Mode {
+    fn bits(self) -> u8 {
+        match self {
+            Mode::Single => 0x01,
+            Mode::Continuous8Hz => 0x02,
+            Mode::Continuous100Hz => 0x06,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error<E> {
+    I2c(E),
+    /// Something other than an AK8963 answered, with this WIA value.
+    WrongDevice(u8),
+    /// The field was stronger than the sensor can measure, usually a magnet or a
+    /// motor close by. That reading is discarded.
+    Overflow,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(error: E) -> Self {
+        Error::I2c(error)
+    }
+}
+
+#[derive(Debug)]
+pub struct Ak8963<I2C> {
+    i2c: I2C,
+    address: u8,
+    resolution: Resolution,
+    mode: Mode,
+    /// Factory sensitivity adjustment per magnetometer axis.
+    adjustment: [f32; 3],
+}
+
+impl<I2C: I2c> Ak8963<I2C> {
+    /// Checks the identity, reads the sensitivity adjustment from fuse ROM and starts
+    /// measuring. Bypass must already be enabled on the MPU9250.
+    pub fn new<D: DelayNs>(
+        mut i2c: I2C,
+        address: u8,
+        resolution: Resolution,
+        mode: Mode,
+        delay: &mut D,
+    ) -> Result<Self, Error<I2C::Error>> {
+        let mut id = [0u8];
+        i2c.write_read(address, &[WIA], &mut id)?;
+        if id[0] != DEVICE_ID {
+            return Err(Error::WrongDevice(id[0]));
+        }
+
+        // Every mode change has to pass through power-down
+        i2c.write(address, &[CNTL1, MODE_POWER_DOWN])?;
+        delay.delay_us(MODE_CHANGE_US);
+        i2c.write(address, &[CNTL1, MODE_FUSE_ROM])?;
+        delay.delay_us(MODE_CHANGE_US);
+        let mut asa = [0u8; 3];
+        i2c.write_read(address, &[ASAX], &mut asa)?;
+        i2c.write(address, &[CNTL1, MODE_POWER_DOWN])?;
+        delay.delay_us(MODE_CHANGE_US);
+
+        let mut sensor = Self {
+            i2c,
+            address,
+            resolution,
+            mode,
+            adjustment: asa.map(|asa| (f32::from(asa) - 128.0) * 0.5 / 128.0 + 1.0),
+        };
+        sensor.set_mode(mode)?;
+        delay.delay_us(MODE_CHANGE_US);
+        Ok(sensor)
+    }
+
+    /// Factory sensitivity adjustment of the magnetometer's X, Y and Z axes.
+    pub fn adjustment(&self) -> [f32; 3] {
+        self.adjustment
+    }
+
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Starts one measurement, ready about 8 ms later. Only for [`Mode::Single`], where
+    /// the sensor powers down again after each measurement.
+    pub fn trigger(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.set_mode(Mode::Single)
+    }
+
+    /// The newest measurement in the MPU9250's axes, `None` if there has been none
+    /// since the last read.
+    pub fn read(&mut self) -> Result<Option<Vector3<MagneticField>>, Error<I2C::Error>> {
+        let mut st1 = [0u8];
+        self.i2c.write_read(self.address, &[ST1], &mut st1)?;
+        if st1[0] & ST1_DRDY == 0 {
+            return Ok(None);
+        }
+
+        // HXL..HZH then ST2, whose read releases the data registers for the next
+        // measurement and must be part of every read
+        let mut data = [0u8; 7];
+        self.i2c.write_read(self.address, &[HXL], &mut data)?;
+        if data[6] & ST2_HOFL != 0 {
+            return Err(Error::Overflow);
+        }
+
+        let (counts, _) = data.as_chunks::<2>();
+        let scale = self.resolution.microtesla_per_count();
+        let [x, y, z]: [f32; 3] =
+            core::array::from_fn(|axis| f32::from(i16::from_le_bytes(counts[axis])) * scale * self.adjustment[axis]);
+        Ok(Some(Vector3::from_microtesla([y, x, -z])))
+    }
+
+    /// Powers the magnetometer down and hands back the bus.
+    pub fn release(mut self) -> Result<I2C, Error<I2C::Error>> {
+        self.i2c.write(self.address, &[CNTL1, MODE_POWER_DOWN])?;
+        Ok(self.i2c)
+    }
+
+    fn set_mode(&mut self, mode: Mode) -> Result<(), Error<I2C::Error>> {
+        let bit = match self.resolution {
+            Resolution::Bits14 => 0,
+            Resolution::Bits16 => CNTL1_BIT,
+        };
+        self.i2c.write(self.address, &[CNTL1, bit | mode.bits()])?;
+        Ok(())
+    }
+}
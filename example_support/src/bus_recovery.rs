@@ -0,0 +1,155 @@
+//! I2C bus recovery.
+//!
+//! A slave that was reset or lost power in the middle of a read can be left driving
+//! SDA low, waiting for clock pulses that never come. The master then sees every
+//! transaction fail with an arbitration or timeout error, forever. The cure (I2C
+//! specification, section 3.1.16) is to take the pins away from the I2C peripheral,
+//! clock SCL by hand until the slave lets go of SDA (at most nine pulses), and finish
+//! with a STOP condition before handing the pins back.
+
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{ErrorKind, InputPin, OutputPin};
+use embedded_hal::i2c::{self, I2c};
+
+/// Half of an SCL period, 100 kHz bus speed.
+const HALF_PERIOD_US: u32 = 5;
+
+/// Maximum number of clock pulses needed to finish any byte a slave may be sending.
+pub const MAX_CLOCK_PULSES: u8 = 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryError {
+    /// Driving or reading one of the pins failed.
+    Pin(ErrorKind),
+    /// SDA was still low after [`MAX_CLOCK_PULSES`] clock pulses.
+    SdaStuckLow,
+}
+
+/// Clocks SCL until SDA is released, then issues a STOP condition.
+///
+/// Both pins must be configured as open-drain outputs with their input enabled, so
+/// that setting them high releases the line and SDA can be read back. Returns the
+/// number of clock pulses it took to free the bus.
+pub fn recover_bus<SCL, SDA, D>(scl: &mut SCL, sda: &mut SDA, delay: &mut D) -> Result<u8, RecoveryError>
+where
+    SCL: OutputPin,
+    SDA: InputPin + OutputPin,
+    D: DelayNs,
+{
+    sda.set_high().map_err(pin_error)?;
+    scl.set_high().map_err(pin_error)?;
+    delay.delay_us(HALF_PERIOD_US);
+
+    let mut pulses = 0;
+    while sda.is_low().map_err(pin_error)? {
+        if pulses == MAX_CLOCK_PULSES {
+            return Err(RecoveryError::SdaStuckLow);
+        }
+        scl.set_low().map_err(pin_error)?;
+        delay.delay_us(HALF_PERIOD_US);
+        scl.set_high().map_err(pin_error)?;
+        delay.delay_us(HALF_PERIOD_US);
+        pulses += 1;
+    }
+
+    // STOP: SDA rises while SCL is high
+    scl.set_low().map_err(pin_error)?;
+    delay.delay_us(HALF_PERIOD_US);
+    sda.set_low().map_err(pin_error)?;
+    delay.delay_us(HALF_PERIOD_US);
+    scl.set_high().map_err(pin_error)?;
+    delay.delay_us(HALF_PERIOD_US);
+    sda.set_high().map_err(pin_error)?;
+    delay.delay_us(HALF_PERIOD_US);
+
+    Ok(pulses)
+}
+
+/// What addressing a device once says about it and the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Probe {
+    /// The device acknowledged, the bus works.
+    Present,
+    /// Nothing acknowledged the address: the device is unplugged or unpowered, but
+    /// the bus works.
+    Missing,
+    /// Lost arbitration, a bus error or a timeout: the bus itself looks stuck.
+    Stuck,
+}
+
+/// Addresses `address` once, see [`Probe`].
+pub fn probe<I: I2c>(i2c: &mut I, address: u8) -> Probe {
+    let mut byte = [0u8];
+    match i2c.read(address, &mut byte) {
+        Ok(()) => Probe::Present,
+        Err(error) => match i2c::Error::kind(&error) {
+            i2c::ErrorKind::NoAcknowledge(_) => Probe::Missing,
+            _ => Probe::Stuck,
+        },
+    }
+}
+
+/// Addresses `address` once and tells whether the bus itself looks stuck.
+///
+/// A device that is unplugged simply does not acknowledge its address, which a bus
+/// recovery cannot fix. A bus held low shows up as lost arbitration, bus errors or
+/// timeouts instead, so only those call for [`recover_bus`].
+pub fn bus_is_stuck<I: I2c>(i2c: &mut I, address: u8) -> bool {
+    probe(i2c, address) == Probe::Stuck
+}
+
+fn pin_error<E: embedded_hal::digital::Error>(error: E) -> RecoveryError {
+    RecoveryError::Pin(error.kind())
+}
+
+/// Read error counters for a sensor on the bus.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ErrorCounters {
+    /// Failures since the last successful read.
+    pub consecutive: u32,
+    /// Failures since boot.
+    pub total: u32,
+    /// Bus recoveries performed since boot.
+    pub recoveries: u32,
+}
+
+/// Decides when repeated read errors warrant a bus recovery.
+#[derive(Debug, Clone)]
+pub struct BusHealth {
+    threshold: u32,
+    counters: ErrorCounters,
+}
+
+impl BusHealth {
+    /// Requests a recovery after `threshold` consecutive failures.
+    pub const fn new(threshold: u32) -> Self {
+        Self {
+            threshold,
+            counters: ErrorCounters {
+                consecutive: 0,
+                total: 0,
+                recoveries: 0,
+            },
+        }
+    }
+
+    pub fn counters(&self) -> ErrorCounters {
+        self.counters
+    }
+
+    pub fn record_success(&mut self) {
+        self.counters.consecutive = 0;
+    }
+
+    /// Records a failed read, returns `true` once a bus recovery is due.
+    pub fn record_failure(&mut self) -> bool {
+        self.counters.consecutive += 1;
+        self.counters.total += 1;
+        self.counters.consecutive >= self.threshold
+    }
+
+    pub fn record_recovery(&mut self) {
+        self.counters.consecutive = 0;
+        self.counters.recoveries += 1;
+    }
+}
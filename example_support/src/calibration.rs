@@ -0,0 +1,333 @@
+//! Gyroscope bias and accelerometer offset calibration.
+//!
+//! At rest a gyroscope should read zero and an accelerometer should read exactly 1 g
+//! along the axis pointing up. Neither does: both have a constant offset that differs
+//! from part to part. [`Calibrator`] averages a run of samples taken while the sensor
+//! sits still in a known [`Orientation`] and turns the difference from the ideal
+//! reading into a [`Calibration`], restarting whenever it sees the sensor move.
+//!
+//! The result is applied in software, by wrapping the IMU in [`Calibrated`], or once
+//! and for all in the offset registers of the MPU6050 and MPU9250 with
+//! [`HardwareOffsets`].
+
+use embedded_hal::i2c::I2c;
+use libm::{fabsf, roundf};
+
+use crate::imu::{Imu, ImuSample};
+use crate::units::{Acceleration, AngularRate, MagneticField, Temperature, Vector3};
+
+/// Which sensor axis points up while calibrating.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    XUp,
+    XDown,
+    YUp,
+    YDown,
+    /// Flat on the table, component side up.
+    ZUp,
+    ZDown,
+}
+
+impl Orientation {
+    pub const ALL: [Orientation; 6] = [
+        Orientation::XUp,
+        Orientation::XDown,
+        Orientation::YUp,
+        Orientation::YDown,
+        Orientation::ZUp,
+        Orientation::ZDown,
+    ];
+
+    /// What an ideal accelerometer reads at rest in this orientation.
+    pub fn gravity(self) -> Vector3<Acceleration> {
+        let g = match self {
+            Orientation::XUp => [1.0, 0.0, 0.0],
+            Orientation::XDown => [-1.0, 0.0, 0.0],
+            Orientation::YUp => [0.0, 1.0, 0.0],
+            Orientation::YDown => [0.0, -1.0, 0.0],
+            Orientation::ZUp => [0.0, 0.0, 1.0],
+            Orientation::ZDown => [0.0, 0.0, -1.0],
+        };
+        Vector3::from_g(g)
+    }
+
+    /// The orientation whose up axis is closest to a reading taken at rest.
+    pub fn from_gravity(acceleration: Vector3<Acceleration>) -> Self {
+        let [x, y, z] = acceleration.as_g();
+        let (ax, ay, az) = (fabsf(x), fabsf(y), fabsf(z));
+        if ax >= ay && ax >= az {
+            if x >= 0.0 { Orientation::XUp } else { Orientation::XDown }
+        } else if ay >= az {
+            if y >= 0.0 { Orientation::YUp } else { Orientation::YDown }
+        } else if z >= 0.0 {
+            Orientation::ZUp
+        } else {
+            Orientation::ZDown
+        }
+    }
+}
+
+/// Constant errors to subtract from every reading.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub gyro_bias: Vector3<AngularRate>,
+    pub accel_offset: Vector3<Acceleration>,
+}
+
+impl Calibration {
+    /// The gyro bias only, for when the accelerometer is corrected by a six-position
+    /// [`AccelCalibration`](crate::accel_calibration::AccelCalibration) instead.
+    pub fn gyro_only(self) -> Self {
+        Self {
+            accel_offset: Vector3::from_g([0.0; 3]),
+            ..self
+        }
+    }
+
+    pub fn apply(&self, mut sample: ImuSample) -> ImuSample {
+        sample.acceleration = sample.acceleration - self.accel_offset;
+        sample.angular_velocity = sample.angular_velocity - self.gyro_bias;
+        sample
+    }
+}
+
+/// Where a calibration run stands after a sample.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Progress {
+    Collecting { collected: u32, required: u32 },
+    /// The sensor moved, the samples collected so far were discarded.
+    Restarted,
+    Done(Calibration),
+}
+
+/// Estimates gyroscope bias and accelerometer offsets from samples taken at rest.
+#[derive(Debug, Clone)]
+pub struct Calibrator {
+    orientation: Orientation,
+    required: u32,
+    gyro_tolerance: AngularRate,
+    accel_tolerance: Acceleration,
+    collected: u32,
+    gyro_sum: Vector3<AngularRate>,
+    accel_sum: Vector3<Acceleration>,
+}
+
+impl Calibrator {
+    /// Averages `samples` readings (at least one) taken in `orientation`.
+    pub fn new(orientation: Orientation, samples: u32) -> Self {
+        Self {
+            orientation,
+            required: samples.max(1),
+            gyro_tolerance: AngularRate::from_dps(2.0),
+            accel_tolerance: Acceleration::from_g(0.05),
+            collected: 0,
+            gyro_sum: Vector3::default(),
+            accel_sum: Vector3::default(),
+        }
+    }
+
+    /// How far a reading may stray from the average so far before it counts as
+    /// movement. Defaults to 2 °/s and 0.05 g on any axis.
+    pub fn with_tolerances(mut self, gyro: AngularRate, accel: Acceleration) -> Self {
+        self.gyro_tolerance = gyro;
+        self.accel_tolerance = accel;
+        self
+    }
+
+    pub fn orientation(&self) -> Orientation {
+        self.orientation
+    }
+
+    /// Discards the samples collected so far.
+    pub fn reset(&mut self) {
+        self.collected = 0;
+        self.gyro_sum = Vector3::default();
+        self.accel_sum = Vector3::default();
+    }
+
+    pub fn add(&mut self, sample: &ImuSample) -> Progress {
+        if self.collected > 0 && self.moved(sample) {
+            self.reset();
+            return Progress::Restarted;
+        }
+
+        self.collected += 1;
+        self.gyro_sum = self.gyro_sum + sample.angular_velocity;
+        self.accel_sum = self.accel_sum + sample.acceleration;
+
+        if self.collected < self.required {
+            return Progress::Collecting {
+                collected: self.collected,
+                required: self.required,
+            };
+        }
+
+        let count = self.collected as f32;
+        let calibration = Calibration {
+            gyro_bias: self.gyro_sum / count,
+            accel_offset: self.accel_sum / count - self.orientation.gravity(),
+        };
+        self.reset();
+        Progress::Done(calibration)
+    }
+
+    fn moved(&self, sample: &ImuSample) -> bool {
+        let count = self.collected as f32;
+        let gyro = (sample.angular_velocity - self.gyro_sum / count).to_array();
+        let accel = (sample.acceleration - self.accel_sum / count).to_array();
+        gyro.iter().any(|d| d.abs() > self.gyro_tolerance) || accel.iter().any(|d| d.abs() > self.accel_tolerance)
+    }
+}
+
+/// An [`Imu`] whose readings are corrected by a [`Calibration`].
+#[derive(Debug, Clone)]
+pub struct Calibrated<I> {
+    imu: I,
+    calibration: Calibration,
+}
+
+impl<I: Imu> Calibrated<I> {
+    pub fn new(imu: I, calibration: Calibration) -> Self {
+        Self { imu, calibration }
+    }
+
+    pub fn calibration(&self) -> &Calibration {
+        &self.calibration
+    }
+
+    pub fn set_calibration(&mut self, calibration: Calibration) {
+        self.calibration = calibration;
+    }
+
+    pub fn imu(&mut self) -> &mut I {
+        &mut self.imu
+    }
+
+    pub fn into_inner(self) -> I {
+        self.imu
+    }
+}
+
+impl<I: Imu> Imu for Calibrated<I> {
+    type Error = I::Error;
+
+    fn read_all(&mut self) -> Result<(f32, [f32; 3], [f32; 3]), Self::Error> {
+        let (temperature, acceleration, angular_velocity) = self.imu.read_all()?;
+        let acceleration = Vector3::from_g(acceleration) - self.calibration.accel_offset;
+        let angular_velocity = Vector3::from_dps(angular_velocity) - self.calibration.gyro_bias;
+        Ok((temperature, acceleration.as_g(), angular_velocity.as_dps()))
+    }
+
+    fn read_temperature(&mut self) -> Result<Temperature, Self::Error> {
+        self.imu.read_temperature()
+    }
+
+    fn read_magnetic_field(&mut self) -> Option<Result<Vector3<MagneticField>, Self::Error>> {
+        self.imu.read_magnetic_field()
+    }
+}
+
+/// Location of the user offset registers, which differs between the MPU models.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffsetRegisters {
+    /// High byte of the X, Y and Z accelerometer offsets.
+    pub accel: [u8; 3],
+    /// High byte of the X gyroscope offset, followed by Y and Z.
+    pub gyro: u8,
+}
+
+/// XA_OFFSET_H and XG_OFFSET_H are not in the published register map of the MPU6050,
+/// but the silicon has them.
+pub const MPU6050_OFFSETS: OffsetRegisters = OffsetRegisters {
+    accel: [0x06, 0x08, 0x0A],
+    gyro: 0x13,
+};
+
+pub const MPU9250_OFFSETS: OffsetRegisters = OffsetRegisters {
+    accel: [0x77, 0x7A, 0x7D],
+    gyro: 0x13,
+};
+
+/// The gyro offset registers count in steps of the ±1000 °/s range.
+const GYRO_OFFSET_LSB_PER_DPS: f32 = 32.8;
+/// The accel offset registers count in steps of the ±16 g range.
+const ACCEL_OFFSET_LSB_PER_G: f32 = 2048.0;
+
+/// A [`Calibration`] converted into values for the sensor's own offset registers.
+///
+/// With the offsets in hardware the sensor reports corrected readings directly, but
+/// they are lost when it powers down, so write them again after every init.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HardwareOffsets {
+    registers: OffsetRegisters,
+    accel: [i16; 3],
+    gyro: [i16; 3],
+}
+
+impl HardwareOffsets {
+    /// Reads the factory trim from the accelerometer offset registers.
+    ///
+    /// The registers only hold the factory values until something is written to them,
+    /// and a reset of the microcontroller alone does not restore them. Read them once
+    /// after power-up and keep them with the calibration.
+    pub fn read_factory_accel<I: I2c>(
+        i2c: &mut I,
+        address: u8,
+        registers: OffsetRegisters,
+    ) -> Result<[i16; 3], I::Error> {
+        let mut factory = [0i16; 3];
+        for (value, &register) in factory.iter_mut().zip(&registers.accel) {
+            let mut bytes = [0u8; 2];
+            i2c.write_read(address, &[register], &mut bytes)?;
+            *value = i16::from_be_bytes(bytes);
+        }
+        Ok(factory)
+    }
+
+    /// The accelerometer correction is added to the factory trim, so the calibration
+    /// has to be measured against the factory values.
+    pub fn new(registers: OffsetRegisters, factory_accel: [i16; 3], calibration: &Calibration) -> Self {
+        let mut accel = factory_accel;
+        for (value, offset) in accel.iter_mut().zip(calibration.accel_offset.as_g()) {
+            // Bit 0 enables temperature compensation and must be kept as it is
+            let correction = roundf(offset * ACCEL_OFFSET_LSB_PER_G) as i16 & !1;
+            *value = value.wrapping_sub(correction);
+        }
+
+        let gyro = calibration
+            .gyro_bias
+            .as_dps()
+            .map(|bias| roundf(-bias * GYRO_OFFSET_LSB_PER_DPS) as i16);
+
+        Self { registers, accel, gyro }
+    }
+
+    /// [`read_factory_accel`](Self::read_factory_accel) followed by [`new`](Self::new).
+    /// Only correct while no offsets have been written since the sensor powered up.
+    pub fn compute<I: I2c>(
+        i2c: &mut I,
+        address: u8,
+        registers: OffsetRegisters,
+        calibration: &Calibration,
+    ) -> Result<Self, I::Error> {
+        let factory_accel = Self::read_factory_accel(i2c, address, registers)?;
+        Ok(Self::new(registers, factory_accel, calibration))
+    }
+
+    pub fn accel(&self) -> [i16; 3] {
+        self.accel
+    }
+
+    pub fn gyro(&self) -> [i16; 3] {
+        self.gyro
+    }
+
+    pub fn write<I: I2c>(&self, i2c: &mut I, address: u8) -> Result<(), I::Error> {
+        for (&register, value) in self.registers.accel.iter().zip(self.accel) {
+            let [high, low] = value.to_be_bytes();
+            i2c.write(address, &[register, high, low])?;
+        }
+        let [x, y, z] = self.gyro.map(i16::to_be_bytes);
+        i2c.write(address, &[self.registers.gyro, x[0], x[1], y[0], y[1], z[0], z[1]])
+    }
+}
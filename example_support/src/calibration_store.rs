@@ -0,0 +1,333 @@
+//! Calibration kept in flash across resets.
+//!
+//! Records are written to a ring of fixed-size slots spread over two or more flash
+//! sectors. Each save goes to the slot after the newest record and a sector is only
+//! erased when the ring wraps into it, so a sector is erased once per
+//! `ERASE_SIZE / SLOT_SIZE` saves, and the previous record in the other sector survives
+//! a power loss in the middle of a save. Loading scans every slot and returns the
+//! newest record whose CRC checks out.
+//!
+//! A record is a header followed by tagged sections, one per kind of calibration.
+//! Adding a kind does not change the format: older firmware skips sections it does not
+//! know, and newer firmware reads a section missing from an older record as not
+//! calibrated. [`FORMAT_VERSION`] only goes up when an existing section changes
+//! meaning, and records of a newer version than the firmware understands are ignored.
+//!
+//! The region has to be kept free of anything else, in particular of the application
+//! image. espflash's default partition table gives the app everything up to the end
+//! of flash, so the examples that store calibration flash a `partitions.csv` with a
+//! dedicated `calibration` data partition, at [`PARTITION_OFFSET`], instead.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embedded_storage::nor_flash::NorFlash;
+
+use crate::accel_calibration::AccelCalibration;
+use crate::calibration::Calibration;
+use crate::mag_calibration::MagCalibration;
+use crate::ppg::Spo2Curve;
+use crate::units::Vector3;
+
+/// Version of the record format written by this firmware.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// Offset of the `calibration` partition in the examples' `partitions.csv`. Keep the
+/// two in step.
+pub const PARTITION_OFFSET: u32 = 0x3F_0000;
+/// Size of the `calibration` partition in the examples' `partitions.csv`.
+pub const PARTITION_SIZE: u32 = 0x1_0000;
+
+/// Space taken by one record in flash.
+pub const SLOT_SIZE: usize = 256;
+
+const MAGIC: u32 = u32::from_le_bytes(*b"HCAL");
+/// Magic, version, payload length, sequence number and CRC.
+const HEADER_SIZE: usize = 16;
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+const TAG_IMU: u8 = 1;
+const TAG_ACCEL: u8 = 2;
+const TAG_MAGNETOMETER: u8 = 3;
+const TAG_SPO2: u8 = 4;
+const TAG_FACTORY_ACCEL_OFFSETS: u8 = 5;
+
+/// Everything that can be calibrated, `None` for what has not been yet.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct StoredCalibration {
+    pub imu: Option<Calibration>,
+    pub accel: Option<AccelCalibration>,
+    pub magnetometer: Option<MagCalibration>,
+    pub spo2: Option<Spo2Curve>,
+    /// What the accelerometer offset registers held when the sensor was first seen,
+    /// see [`HardwareOffsets::read_factory_accel`](crate::calibration::HardwareOffsets::read_factory_accel).
+    pub factory_accel_offsets: Option<[i16; 3]>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StoreError<E> {
+    Flash(E),
+    /// The region is not made of at least two whole sectors inside the flash.
+    Layout,
+}
+
+impl<E> From<E> for StoreError<E> {
+    fn from(error: E) -> Self {
+        StoreError::Flash(error)
+    }
+}
+
+/// The newest valid record found in flash.
+struct Newest {
+    slot: u32,
+    sequence: u32,
+    calibration: StoredCalibration,
+}
+
+/// Saves and loads [`StoredCalibration`] records in a region of NOR flash.
+pub struct CalibrationStore<F> {
+    flash: F,
+    base: u32,
+    sectors: u32,
+}
+
+impl<F: NorFlash> CalibrationStore<F> {
+    /// Uses `sectors` erase sectors (at least two) starting at `base`, which must be
+    /// sector aligned. Nothing else may use that region.
+    pub fn new(flash: F, base: u32, sectors: u32) -> Result<Self, StoreError<F::Error>> {
+        let aligned = (base as usize).is_multiple_of(F::ERASE_SIZE)
+            && F::ERASE_SIZE.is_multiple_of(SLOT_SIZE)
+            && SLOT_SIZE.is_multiple_of(F::WRITE_SIZE)
+            && SLOT_SIZE.is_multiple_of(F::READ_SIZE);
+        let fits = base as usize + sectors as usize * F::ERASE_SIZE <= flash.capacity();
+        if !aligned || !fits || sectors < 2 {
+            return Err(StoreError::Layout);
+        }
+        Ok(Self { flash, base, sectors })
+    }
+
+    /// Uses the data partition at `offset`, `size` bytes long, as read from the
+    /// partition table. It must span at least two whole sectors.
+    pub fn in_partition(flash: F, offset: u32, size: u32) -> Result<Self, StoreError<F::Error>> {
+        if !(size as usize).is_multiple_of(F::ERASE_SIZE) {
+            return Err(StoreError::Layout);
+        }
+        Self::new(flash, offset, size / F::ERASE_SIZE as u32)
+    }
+
+    /// Uses the last two sectors of the flash. Nothing protects them from the
+    /// application image: only use this with a partition table that leaves the end of
+    /// flash to data, or on flash that holds no program.
+    pub fn at_end(flash: F) -> Result<Self, StoreError<F::Error>> {
+        let size = 2 * F::ERASE_SIZE;
+        let base = flash.capacity().checked_sub(size).ok_or(StoreError::Layout)?;
+        Self::new(flash, base as u32, 2)
+    }
+
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// The newest valid record, `None` if there is none (e.g. on first boot).
+    pub fn load(&mut self) -> Result<Option<StoredCalibration>, StoreError<F::Error>> {
+        Ok(self.newest()?.map(|newest| newest.calibration))
+    }
+
+    /// Writes `calibration` as the newest record. Nothing is written if it is the same
+    /// as the newest record already stored.
+    pub fn save(&mut self, calibration: &StoredCalibration) -> Result<(), StoreError<F::Error>> {
+        let newest = self.newest()?;
+        if newest.as_ref().is_some_and(|newest| newest.calibration == *calibration) {
+            return Ok(());
+        }
+
+        let sequence = newest.as_ref().map_or(0, |newest| newest.sequence.wrapping_add(1));
+        let mut slot = newest.map_or(0, |newest| newest.slot + 1) % self.slots();
+        let mut buffer = [0u8; SLOT_SIZE];
+
+        // Skip slots left dirty by an interrupted save, up to the next sector
+        loop {
+            if slot.is_multiple_of(self.slots_per_sector()) {
+                let start = self.slot_offset(slot);
+                self.flash.erase(start, start + F::ERASE_SIZE as u32)?;
+                break;
+            }
+            self.read_slot(slot, &mut buffer)?;
+            if buffer.iter().all(|&byte| byte == 0xFF) {
+                break;
+            }
+            slot = (slot + 1) % self.slots();
+        }
+
+        let length = encode(calibration, sequence, &mut buffer);
+        let length = length.next_multiple_of(F::WRITE_SIZE);
+        self.flash.write(self.slot_offset(slot), &buffer[..length])?;
+        Ok(())
+    }
+
+    fn slots_per_sector(&self) -> u32 {
+        (F::ERASE_SIZE / SLOT_SIZE) as u32
+    }
+
+    fn slots(&self) -> u32 {
+        self.sectors * self.slots_per_sector()
+    }
+
+    fn slot_offset(&self, slot: u32) -> u32 {
+        self.base + slot * SLOT_SIZE as u32
+    }
+
+    fn read_slot(&mut self, slot: u32, buffer: &mut [u8; SLOT_SIZE]) -> Result<(), F::Error> {
+        self.flash.read(self.slot_offset(slot), buffer)
+    }
+
+    fn newest(&mut self) -> Result<Option<Newest>, F::Error> {
+        let mut newest: Option<Newest> = None;
+        let mut buffer = [0u8; SLOT_SIZE];
+        for slot in 0..self.slots() {
+            self.read_slot(slot, &mut buffer)?;
+            if let Some((sequence, calibration)) = decode(&buffer) {
+                if newest.as_ref().is_none_or(|newest| sequence > newest.sequence) {
+                    newest = Some(Newest {
+                        slot,
+                        sequence,
+                        calibration,
+                    });
+                }
+            }
+        }
+        Ok(newest)
+    }
+}
+
+/// Builds a record in `buffer`, returning its length.
+fn encode(calibration: &StoredCalibration, sequence: u32, buffer: &mut [u8; SLOT_SIZE]) -> usize {
+    buffer.fill(0xFF);
+    let mut length = HEADER_SIZE;
+    let mut section = |tag: u8, values: &mut dyn Iterator<Item = f32>| {
+        let start = length;
+        length += 2;
+        for value in values {
+            buffer[length..length + 4].copy_from_slice(&value.to_le_bytes());
+            length += 4;
+        }
+        buffer[start] = tag;
+        buffer[start + 1] = (length - start - 2) as u8;
+    };
+
+    if let Some(imu) = &calibration.imu {
+        section(TAG_IMU, &mut imu.gyro_bias.as_rad_per_s().into_iter().chain(imu.accel_offset.as_mps2()));
+    }
+    if let Some(accel) = &calibration.accel {
+        section(TAG_ACCEL, &mut accel.matrix.into_iter().flatten().chain(accel.bias.as_mps2()));
+    }
+    if let Some(magnetometer) = &calibration.magnetometer {
+        let hard_iron = magnetometer.hard_iron.as_microtesla();
+        section(TAG_MAGNETOMETER, &mut hard_iron.into_iter().chain(magnetometer.soft_iron.into_iter().flatten()));
+    }
+    if let Some(spo2) = &calibration.spo2 {
+        section(TAG_SPO2, &mut [spo2.a, spo2.b, spo2.c].into_iter());
+    }
+    if let Some(offsets) = calibration.factory_accel_offsets {
+        section(TAG_FACTORY_ACCEL_OFFSETS, &mut offsets.into_iter().map(f32::from));
+    }
+
+    let payload_length = (length - HEADER_SIZE) as u16;
+    buffer[0..4].copy_from_slice(&MAGIC.to_le_bytes());
+    buffer[4..6].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    buffer[6..8].copy_from_slice(&payload_length.to_le_bytes());
+    buffer[8..12].copy_from_slice(&sequence.to_le_bytes());
+    let crc = checksum(&buffer[4..12], &buffer[HEADER_SIZE..length]);
+    buffer[12..16].copy_from_slice(&crc.to_le_bytes());
+    length
+}
+
+/// The sequence number and contents of a valid record.
+fn decode(buffer: &[u8; SLOT_SIZE]) -> Option<(u32, StoredCalibration)> {
+    let word = |at: usize| u32::from_le_bytes([buffer[at], buffer[at + 1], buffer[at + 2], buffer[at + 3]]);
+    let half = |at: usize| u16::from_le_bytes([buffer[at], buffer[at + 1]]);
+
+    if word(0) != MAGIC {
+        return None;
+    }
+    let version = half(4);
+    let length = HEADER_SIZE + usize::from(half(6));
+    if version > FORMAT_VERSION || length > SLOT_SIZE {
+        return None;
+    }
+    if checksum(&buffer[4..12], &buffer[HEADER_SIZE..length]) != word(12) {
+        return None;
+    }
+
+    let mut calibration = StoredCalibration::default();
+    let mut sections = &buffer[HEADER_SIZE..length];
+    while let [tag, size, rest @ ..] = sections {
+        let (data, remaining) = rest.split_at_checked(usize::from(*size))?;
+        sections = remaining;
+        match *tag {
+            TAG_IMU => {
+                if let Some([bx, by, bz, ox, oy, oz]) = floats(data) {
+                    calibration.imu = Some(Calibration {
+                        gyro_bias: Vector3::from_rad_per_s([bx, by, bz]),
+                        accel_offset: Vector3::from_mps2([ox, oy, oz]),
+                    });
+                }
+            }
+            TAG_ACCEL => {
+                if let Some([m @ .., bx, by, bz]) = floats::<12>(data) {
+                    calibration.accel = Some(AccelCalibration {
+                        matrix: matrix(m),
+                        bias: Vector3::from_mps2([bx, by, bz]),
+                    });
+                }
+            }
+            TAG_MAGNETOMETER => {
+                if let Some([hx, hy, hz, m @ ..]) = floats::<12>(data) {
+                    calibration.magnetometer = Some(MagCalibration {
+                        hard_iron: Vector3::from_microtesla([hx, hy, hz]),
+                        soft_iron: matrix(m),
+                    });
+                }
+            }
+            TAG_SPO2 => {
+                if let Some([a, b, c]) = floats(data) {
+                    calibration.spo2 = Some(Spo2Curve { a, b, c });
+                }
+            }
+            TAG_FACTORY_ACCEL_OFFSETS => {
+                if let Some(offsets) = floats::<3>(data) {
+                    calibration.factory_accel_offsets = Some(offsets.map(|offset| offset as i16));
+                }
+            }
+            // Written by newer firmware
+            _ => {}
+        }
+    }
+    Some((word(8), calibration))
+}
+
+fn checksum(header: &[u8], payload: &[u8]) -> u32 {
+    let mut digest = CRC.digest();
+    digest.update(header);
+    digest.update(payload);
+    digest.finalize()
+}
+
+/// `None` if `data` does not hold exactly `N` values.
+fn floats<const N: usize>(data: &[u8]) -> Option<[f32; N]> {
+    if data.len() != N * 4 {
+        return None;
+    }
+    let (chunks, _) = data.as_chunks::<4>();
+    let mut values = [0.0; N];
+    for (value, bytes) in values.iter_mut().zip(chunks) {
+        *value = f32::from_le_bytes(*bytes);
+    }
+    Some(values)
+}
+
+fn matrix(values: [f32; 9]) -> [[f32; 3]; 3] {
+    [
+        [values[0], values[1], values[2]],
+        [values[3], values[4], values[5]],
+        [values[6], values[7], values[8]],
+    ]
+}
@@ -0,0 +1,169 @@
+//! Tilt-compensated compass heading.
+//!
+//! The horizontal part of the Earth's field points to magnetic north, but a tilted
+//! magnetometer also picks up some of the much larger vertical part. The roll and
+//! pitch measured by the accelerometer rotate the reading back to level first, so the
+//! heading holds while the board is tilted, as long as it is not accelerating.
+//!
+//! Magnetic north differs from true north by the declination, which depends on where
+//! on Earth the board is. It is either configured as a constant or looked up in a
+//! [`DeclinationGrid`] compiled into the firmware, generated offline by
+//! `tools/declination_grid.py` from the World Magnetic Model coefficients.
+//!
+//! Nearby magnets, motors and steel change the strength of the field as well as its
+//! direction, so a reading much stronger or weaker than the Earth's field is flagged
+//! as disturbed.
+
+use libm::{atan2f, cosf, floorf, sinf};
+
+use crate::tilt::Tilt;
+use crate::units::{Acceleration, MagneticField, Vector3};
+
+/// Share by which the field strength may differ from the reference before a reading
+/// counts as disturbed.
+pub const DEFAULT_DISTURBANCE_TOLERANCE: f32 = 0.15;
+
+/// Weight of each undisturbed reading in the learned reference strength, so it
+/// follows over a few hundred readings.
+const REFERENCE_WEIGHT: f32 = 0.01;
+
+/// Heading of the sensor's X axis, in degrees clockwise from north within [0, 360).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Heading {
+    pub magnetic: f32,
+    /// Magnetic heading corrected by the declination.
+    pub true_north: f32,
+    pub field_strength: MagneticField,
+    /// The field strength is too far from the reference for the heading to be trusted.
+    pub disturbed: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct Compass {
+    declination: f32,
+    tolerance: f32,
+    /// Expected field strength in µT, learned from the readings unless fixed.
+    reference: Option<f32>,
+    fixed_reference: bool,
+}
+
+impl Compass {
+    /// `declination` in degrees, east of true north positive.
+    pub fn new(declination: f32) -> Self {
+        Self {
+            declination,
+            tolerance: DEFAULT_DISTURBANCE_TOLERANCE,
+            reference: None,
+            fixed_reference: false,
+        }
+    }
+
+    /// Compares readings against this strength, e.g. the one found by the
+    /// magnetometer calibration, instead of learning it from the readings.
+    pub fn with_field_strength(mut self, strength: MagneticField) -> Self {
+        self.reference = Some(strength.as_microtesla());
+        self.fixed_reference = true;
+        self
+    }
+
+    /// Share by which the field strength may differ from the reference.
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn declination(&self) -> f32 {
+        self.declination
+    }
+
+    pub fn set_declination(&mut self, declination: f32) {
+        self.declination = declination;
+    }
+
+    /// The field strength readings are compared against, `None` before the first one.
+    pub fn reference(&self) -> Option<MagneticField> {
+        self.reference.map(MagneticField::from_microtesla)
+    }
+
+    /// Heading from a calibrated magnetometer reading and the accelerometer reading
+    /// taken with it, `None` if the field has no horizontal part to point with.
+    pub fn heading(&mut self, acceleration: Vector3<Acceleration>, field: Vector3<MagneticField>) -> Option<Heading> {
+        let level = level_field(Tilt::from_acceleration(acceleration), field.as_microtesla());
+        if level[0] == 0.0 && level[1] == 0.0 {
+            return None;
+        }
+
+        let strength = field.norm().as_microtesla();
+        let reference = *self.reference.get_or_insert(strength);
+        let disturbed = (strength - reference).abs() > self.tolerance * reference;
+        if !disturbed && !self.fixed_reference {
+            self.reference = Some(reference + REFERENCE_WEIGHT * (strength - reference));
+        }
+
+        let magnetic = wrap_heading(atan2f(level[1], level[0]).to_degrees());
+        Some(Heading {
+            magnetic,
+            true_north: wrap_heading(magnetic + self.declination),
+            field_strength: MagneticField::from_microtesla(strength),
+            disturbed,
+        })
+    }
+}
+
+/// Horizontal part of the field, in the sensor's axes rotated back to level by the
+/// roll and pitch. North lies as far counter-clockwise of X as X lies clockwise of
+/// north, so the heading is `atan2(y, x)`.
+fn level_field(tilt: Tilt, [x, y, z]: [f32; 3]) -> [f32; 2] {
+    let (sin_roll, cos_roll) = (sinf(tilt.roll.to_radians()), cosf(tilt.roll.to_radians()));
+    let (sin_pitch, cos_pitch) = (sinf(tilt.pitch.to_radians()), cosf(tilt.pitch.to_radians()));
+    let level_x = x * cos_pitch + (y * sin_roll + z * cos_roll) * sin_pitch;
+    let level_y = y * cos_roll - z * sin_roll;
+    [level_x, level_y]
+}
+
+/// Wraps an angle in degrees into [0, 360).
+fn wrap_heading(degrees: f32) -> f32 {
+    let wrapped = degrees - 360.0 * floorf(degrees / 360.0);
+    if wrapped >= 360.0 { 0.0 } else { wrapped }
+}
+
+/// Magnetic declination on a regular latitude and longitude grid, interpolated
+/// between the grid points. Tables are generated by `tools/declination_grid.py`.
+#[derive(Debug, Clone, Copy)]
+pub struct DeclinationGrid {
+    /// Latitude of the first row, in degrees north.
+    pub south: f32,
+    /// Longitude of the first column, in degrees east.
+    pub west: f32,
+    /// Spacing of rows and columns in degrees.
+    pub step: f32,
+    pub columns: usize,
+    /// Declination in hundredths of a degree, east positive, row by row from the
+    /// south-west corner.
+    pub values: &'static [i16],
+}
+
+impl DeclinationGrid {
+    /// Declination in degrees at a position, `None` outside the grid.
+    pub fn declination(&self, latitude: f32, longitude: f32) -> Option<f32> {
+        if self.columns < 2 || self.values.len() < 2 * self.columns {
+            return None;
+        }
+        let rows = self.values.len() / self.columns;
+        let row = (latitude - self.south) / self.step;
+        // Longitudes wrap, so a grid may start anywhere
+        let offset = (longitude - self.west) % 360.0;
+        let offset = if offset < 0.0 { offset + 360.0 } else { offset };
+        let column = offset / self.step;
+        if row < 0.0 || row > (rows - 1) as f32 || column > (self.columns - 1) as f32 {
+            return None;
+        }
+
+        let (r0, c0) = ((row as usize).min(rows - 2), (column as usize).min(self.columns - 2));
+        let (fr, fc) = (row - r0 as f32, column - c0 as f32);
+        let at = |r: usize, c: usize| f32::from(self.values[r * self.columns + c]) / 100.0;
+        let south = at(r0, c0) + fc * (at(r0, c0 + 1) - at(r0, c0));
+        let north = at(r0 + 1, c0) + fc * (at(r0 + 1, c0 + 1) - at(r0 + 1, c0));
+        Some(south + fr * (north - south))
+    }
+}
@@ -0,0 +1,87 @@
+//! Roll and pitch from a complementary filter.
+//!
+//! Integrating the gyroscope gives a smooth angle that drifts, while the tilt of the
+//! gravity vector is drift free but noisy and upset by any acceleration. The filter
+//! passes the integrated gyro through a high-pass and the accelerometer tilt through a
+//! low-pass with the same crossover frequency, so each covers the band where the other
+//! is weak. Below the crossover the angles follow the accelerometer, above it the
+//! gyroscope.
+
+use core::f32::consts::PI;
+
+use libm::{cosf, sinf, tanf};
+
+use crate::tilt::Tilt;
+use crate::imu::{ImuSample, SampleInterval};
+
+/// Longest gap between samples that is still integrated. After a longer gap the
+/// filter restarts from the accelerometer.
+const MAX_DT_S: f32 = 1.0;
+
+#[derive(Debug, Clone)]
+pub struct ComplementaryFilter {
+    time_constant_s: f32,
+    /// In radians.
+    roll: f32,
+    pitch: f32,
+    interval: SampleInterval,
+}
+
+impl ComplementaryFilter {
+    /// `crossover_hz` is where the gyroscope takes over from the accelerometer. A
+    /// lower crossover rejects more vibration but corrects gyro drift more slowly.
+    pub fn new(crossover_hz: f32) -> Self {
+        Self {
+            time_constant_s: 1.0 / (2.0 * PI * crossover_hz),
+            roll: 0.0,
+            pitch: 0.0,
+            interval: SampleInterval::default(),
+        }
+    }
+
+    /// Forgets the current angles, the next sample starts from its accelerometer tilt.
+    pub fn reset(&mut self) {
+        self.interval.reset();
+    }
+
+    /// Current estimate in degrees.
+    pub fn tilt(&self) -> Tilt {
+        Tilt {
+            roll: self.roll.to_degrees(),
+            pitch: self.pitch.to_degrees(),
+        }
+    }
+
+    /// Fuses one sample, using its timestamp for the time step.
+    pub fn update(&mut self, sample: &ImuSample) -> Tilt {
+        let measured = Tilt::from_acceleration(sample.acceleration);
+        let (measured_roll, measured_pitch) = (measured.roll.to_radians(), measured.pitch.to_radians());
+
+        let Some(dt) = self.interval.next(sample.timestamp_us, MAX_DT_S) else {
+            self.roll = measured_roll;
+            self.pitch = measured_pitch;
+            return self.tilt();
+        };
+
+        // Body rates to Euler angle rates
+        let [p, q, r] = sample.angular_velocity.as_rad_per_s();
+        let (sin_roll, cos_roll) = (sinf(self.roll), cosf(self.roll));
+        let roll_rate = p + (q * sin_roll + r * cos_roll) * tanf(self.pitch);
+        let pitch_rate = q * cos_roll - r * sin_roll;
+
+        let alpha = self.time_constant_s / (self.time_constant_s + dt);
+        let roll = self.roll + roll_rate * dt;
+        let pitch = self.pitch + pitch_rate * dt;
+
+        // Blend along the shorter way round, roll wraps at ±180°
+        self.roll = wrap(roll + (1.0 - alpha) * wrap(measured_roll - roll));
+        self.pitch = pitch + (1.0 - alpha) * (measured_pitch - pitch);
+        self.tilt()
+    }
+}
+
+/// Wraps an angle into [-π, π).
+fn wrap(angle: f32) -> f32 {
+    let wrapped = (angle + PI) % (2.0 * PI);
+    if wrapped < 0.0 { wrapped + PI } else { wrapped - PI }
+}
@@ -0,0 +1,124 @@
+//! Two IMUs read as a pair, e.g. one on each segment of a limb.
+//!
+//! Both units are read back-to-back, alternating which one goes first so that the
+//! skew between the two readings averages out to zero instead of always favouring the
+//! same unit. The readings are not simultaneous: each one is still a full I2C
+//! transaction, about 0.4 ms at 400 kHz, apart from the other, which is reported with
+//! every pair. Latching both at the same instant takes the FSYNC pins, driven from a
+//! common GPIO, or reading both through their FIFOs at the same sample rate.
+//!
+//! From the two gravity vectors we get each unit's tilt and the angle between them.
+//! Rotation about gravity (yaw) cannot be observed from accelerometers, so the
+//! relative orientation is limited to roll, pitch and the included angle.
+
+use libm::acosf;
+
+use crate::tilt::Tilt;
+use crate::units::{Acceleration, Vector3};
+
+/// A reading from each unit, with the time between them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PairSample<T> {
+    pub a: T,
+    pub b: T,
+    /// Time of the `b` reading minus time of the `a` reading, in microseconds.
+    pub skew_us: i64,
+}
+
+/// Which of the two units a read failed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairError<E> {
+    A(E),
+    B(E),
+}
+
+/// Reads two sensors one right after the other, see the [module documentation](self)
+/// for how far apart that is.
+#[derive(Debug, Default, Clone)]
+pub struct DualSampler {
+    b_first: bool,
+}
+
+impl DualSampler {
+    pub const fn new() -> Self {
+        Self { b_first: false }
+    }
+
+    /// Reads both sensors, `now_us` is sampled right before each read. Stops at the
+    /// first read that fails.
+    pub fn sample<T, E, A, B, C>(&mut self, mut read_a: A, mut read_b: B, mut now_us: C) -> Result<PairSample<T>, PairError<E>>
+    where
+        A: FnMut() -> Result<T, E>,
+        B: FnMut() -> Result<T, E>,
+        C: FnMut() -> u64,
+    {
+        let b_first = self.b_first;
+        self.b_first = !b_first;
+
+        let (a, a_time, b, b_time) = if b_first {
+            let b_time = now_us();
+            let b = read_b().map_err(PairError::B)?;
+            let a_time = now_us();
+            let a = read_a().map_err(PairError::A)?;
+            (a, a_time, b, b_time)
+        } else {
+            let a_time = now_us();
+            let a = read_a().map_err(PairError::A)?;
+            let b_time = now_us();
+            let b = read_b().map_err(PairError::B)?;
+            (a, a_time, b, b_time)
+        };
+
+        Ok(PairSample {
+            a,
+            b,
+            skew_us: b_time as i64 - a_time as i64,
+        })
+    }
+}
+
+/// Orientation of unit `b` relative to unit `a`, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RelativeOrientation {
+    /// Wrapped to (-180, 180].
+    pub roll: f32,
+    /// Wrapped to (-180, 180], like roll.
+    pub pitch: f32,
+    /// Angle between the two gravity vectors, e.g. the flexion of a joint between the
+    /// two segments. Unlike roll and pitch it does not depend on how the units are
+    /// rotated about their own mounting axis.
+    pub included_angle: f32,
+}
+
+impl RelativeOrientation {
+    pub fn between(acceleration_a: Vector3<Acceleration>, acceleration_b: Vector3<Acceleration>) -> Self {
+        let tilt_a = Tilt::from_acceleration(acceleration_a);
+        let tilt_b = Tilt::from_acceleration(acceleration_b);
+
+        let included_angle = match (acceleration_a.direction(), acceleration_b.direction()) {
+            (Some(a), Some(b)) => acosf(dot(a, b).clamp(-1.0, 1.0)).to_degrees(),
+            _ => 0.0,
+        };
+
+        Self {
+            roll: wrap_degrees(tilt_b.roll - tilt_a.roll),
+            pitch: wrap_degrees(tilt_b.pitch - tilt_a.pitch),
+            included_angle,
+        }
+    }
+}
+
+fn dot(a: [f32; 3], b: [f32; 3]) -> f32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
+
+/// Wraps an angle into (-180, 180].
+fn wrap_degrees(angle: f32) -> f32 {
+    let mut wrapped = angle % 360.0;
+    if wrapped > 180.0 {
+        wrapped -= 360.0;
+    } else if wrapped <= -180.0 {
+        wrapped += 360.0;
+    }
+    wrapped
+}
@@ -0,0 +1,318 @@
+//! Extended Kalman filter for orientation and gyro bias.
+//!
+//! The filter keeps a quaternion and a gyro bias estimate, and a 6x6 covariance of
+//! their errors: a small rotation of the sensor frame and an error in the bias (the
+//! "multiplicative" or error-state form, which keeps the quaternion at unit length).
+//! Every sample first integrates the bias-corrected gyroscope, then corrects the
+//! estimate with the direction of gravity and, when the sample has one, the heading of
+//! the magnetic field. Gyro drift shows up as a persistent disagreement with those
+//! references, which the filter attributes to the bias, so unlike the
+//! [`complementary`](crate::complementary) or [`Madgwick`](crate::madgwick::Madgwick)
+//! filters it learns the bias online. Without a magnetometer the bias about the
+//! vertical cannot be observed and yaw drifts with it.
+//!
+//! Each measurement axis is applied as a separate scalar update, so there are no
+//! matrix inversions and everything fits in fixed-size arrays.
+
+use libm::{atan2f, fabsf, sqrtf};
+
+use crate::tilt::Tilt;
+use crate::imu::{ImuSample, SampleInterval};
+use crate::quaternion::{EulerAngles, Quaternion};
+use crate::units::{AngularRate, Vector3};
+
+/// Longest gap between samples that is still integrated. After a longer gap the
+/// filter starts again from the next sample's accelerometer and magnetometer.
+const MAX_DT_S: f32 = 1.0;
+
+/// Rotation error and gyro bias error.
+const STATES: usize = 6;
+
+type Matrix = [[f32; STATES]; STATES];
+
+/// Uncertainty of the starting orientation, which comes from a single sample.
+const INITIAL_ATTITUDE_STD_DEG: f32 = 10.0;
+/// Uncertainty of the starting gyro bias, about what is left after a calibration.
+const INITIAL_BIAS_STD_DPS: f32 = 3.0;
+
+/// Standard deviations the filter assumes for its inputs. Larger values make it trust
+/// that input less.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Noise {
+    /// Gyroscope white noise in °/s/√Hz, including motion the filter does not model.
+    pub gyro: f32,
+    /// How quickly the gyro bias wanders, in °/s/√s.
+    pub gyro_bias_drift: f32,
+    /// Accelerometer noise in g. The filter adds how far a reading is from 1 g, so
+    /// readings taken while accelerating count for less.
+    pub accel: f32,
+    /// Error of the magnetometer heading in degrees.
+    pub heading: f32,
+}
+
+impl Default for Noise {
+    fn default() -> Self {
+        Self {
+            gyro: 0.3,
+            gyro_bias_drift: 0.01,
+            accel: 0.05,
+            heading: 5.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Ekf {
+    noise: Noise,
+    quaternion: Quaternion,
+    /// In rad/s.
+    bias: [f32; 3],
+    covariance: Matrix,
+    interval: SampleInterval,
+}
+
+impl Default for Ekf {
+    fn default() -> Self {
+        Self::new(Noise::default())
+    }
+}
+
+impl Ekf {
+    /// The first sample sets the starting orientation.
+    pub fn new(noise: Noise) -> Self {
+        Self {
+            noise,
+            quaternion: Quaternion::IDENTITY,
+            bias: [0.0; 3],
+            covariance: initial_covariance(),
+            interval: SampleInterval::default(),
+        }
+    }
+
+    pub fn noise(&self) -> Noise {
+        self.noise
+    }
+
+    /// Retunes the filter without losing the estimate.
+    pub fn set_noise(&mut self, noise: Noise) {
+        self.noise = noise;
+    }
+
+    pub fn quaternion(&self) -> Quaternion {
+        self.quaternion
+    }
+
+    pub fn euler(&self) -> EulerAngles {
+        self.quaternion.to_euler()
+    }
+
+    pub fn gyro_bias(&self) -> Vector3<AngularRate> {
+        Vector3::from_rad_per_s(self.bias)
+    }
+
+    /// Standard deviation of the rotation error about each sensor axis, in degrees.
+    pub fn attitude_uncertainty(&self) -> [f32; 3] {
+        [0, 1, 2].map(|i| sqrtf(self.covariance[i][i]).to_degrees())
+    }
+
+    pub fn bias_uncertainty(&self) -> Vector3<AngularRate> {
+        Vector3::from_rad_per_s([3, 4, 5].map(|i| sqrtf(self.covariance[i][i])))
+    }
+
+    /// Starts again from the next sample, keeping the bias estimate.
+    pub fn reset(&mut self) {
+        self.interval.reset();
+    }
+
+    /// Fuses one sample, using its magnetometer reading when it has one.
+    pub fn update(&mut self, sample: &ImuSample) -> Quaternion {
+        let magnetic_field = sample.magnetic_field.map(|m| m.as_microtesla());
+        self.step(sample, magnetic_field)
+    }
+
+    /// Fuses one sample as if it had no magnetometer reading.
+    pub fn update_imu(&mut self, sample: &ImuSample) -> Quaternion {
+        self.step(sample, None)
+    }
+
+    fn step(&mut self, sample: &ImuSample, magnetic_field: Option<[f32; 3]>) -> Quaternion {
+        let Some(dt) = self.interval.next(sample.timestamp_us, MAX_DT_S) else {
+            self.align(sample, magnetic_field);
+            return self.quaternion;
+        };
+
+        self.predict(sample.angular_velocity.as_rad_per_s(), dt);
+        self.correct_gravity(sample.acceleration.as_g());
+        if let Some(m) = magnetic_field {
+            self.correct_heading(m);
+        }
+        self.quaternion
+    }
+
+    /// Points the estimate along the measured gravity and field directly.
+    fn align(&mut self, sample: &ImuSample, magnetic_field: Option<[f32; 3]>) {
+        let tilt = Tilt::from_acceleration(sample.acceleration);
+        let level = Quaternion::from_euler(EulerAngles {
+            roll: tilt.roll,
+            pitch: tilt.pitch,
+            yaw: 0.0,
+        });
+        let yaw = match magnetic_field.and_then(|m| heading_error(level, m)) {
+            Some(error) => error.to_degrees(),
+            None => self.euler().yaw,
+        };
+        self.quaternion = Quaternion::from_euler(EulerAngles {
+            roll: tilt.roll,
+            pitch: tilt.pitch,
+            yaw,
+        });
+
+        // The bias is still known as well as it was before
+        let previous = self.covariance;
+        self.covariance = initial_covariance();
+        for (row, old) in self.covariance[3..].iter_mut().zip(&previous[3..]) {
+            row[3..].copy_from_slice(&old[3..]);
+        }
+    }
+
+    fn predict(&mut self, gyro: [f32; 3], dt: f32) {
+        let rate = [0, 1, 2].map(|i| gyro[i] - self.bias[i]);
+        self.quaternion = (self.quaternion * Quaternion::from_rotation_vector(rate.map(|w| w * dt))).normalize();
+
+        // The rotation error turns against the body rate and grows with the bias error
+        let mut transition = identity();
+        let [wx, wy, wz] = rate.map(|w| w * dt);
+        transition[0][1] = wz;
+        transition[0][2] = -wy;
+        transition[1][0] = -wz;
+        transition[1][2] = wx;
+        transition[2][0] = wy;
+        transition[2][1] = -wx;
+        for i in 0..3 {
+            transition[i][i + 3] = -dt;
+        }
+
+        let mut covariance = multiply(&multiply(&transition, &self.covariance), &transpose(&transition));
+        let gyro_noise = self.noise.gyro.to_radians();
+        let bias_drift = self.noise.gyro_bias_drift.to_radians();
+        for i in 0..3 {
+            covariance[i][i] += gyro_noise * gyro_noise * dt;
+            covariance[i + 3][i + 3] += bias_drift * bias_drift * dt;
+        }
+        self.covariance = symmetric(&covariance);
+    }
+
+    fn correct_gravity(&mut self, accel: [f32; 3]) {
+        let magnitude = sqrtf(accel.iter().map(|a| a * a).sum());
+        if magnitude == 0.0 {
+            // Free fall, nothing to correct towards
+            return;
+        }
+        let measured = accel.map(|a| a / magnitude);
+        let deviation = fabsf(magnitude - 1.0);
+        let variance = self.noise.accel * self.noise.accel + deviation * deviation;
+
+        for axis in 0..3 {
+            // Gravity as the estimate expects to see it, and how it moves with the error
+            let [ux, uy, uz] = self.quaternion.rotate_inverse([0.0, 0.0, 1.0]);
+            let skew = [[0.0, -uz, uy], [uz, 0.0, -ux], [-uy, ux, 0.0]];
+            let row = skew[axis];
+            let expected = [ux, uy, uz][axis];
+            self.scalar_update([row[0], row[1], row[2], 0.0, 0.0, 0.0], measured[axis] - expected, variance);
+        }
+    }
+
+    fn correct_heading(&mut self, field: [f32; 3]) {
+        let Some(error) = heading_error(self.quaternion, field) else {
+            return;
+        };
+        // A rotation about the world's vertical, expressed in the sensor frame
+        let [ux, uy, uz] = self.quaternion.rotate_inverse([0.0, 0.0, 1.0]);
+        let heading = self.noise.heading.to_radians();
+        self.scalar_update([ux, uy, uz, 0.0, 0.0, 0.0], error, heading * heading);
+    }
+
+    /// Kalman update for a single measurement `innovation ≈ h · error`, after which
+    /// the error is folded into the quaternion and bias.
+    fn scalar_update(&mut self, h: [f32; STATES], innovation: f32, variance: f32) {
+        let ph: [f32; STATES] = self.covariance.map(|row| dot(&row, &h));
+        let s = dot(&h, &ph) + variance;
+        if s <= 0.0 {
+            return;
+        }
+        let gain = ph.map(|p| p / s);
+
+        for (i, row) in self.covariance.iter_mut().enumerate() {
+            for (j, p) in row.iter_mut().enumerate() {
+                *p -= gain[i] * ph[j];
+            }
+        }
+
+        let correction = gain.map(|k| k * innovation);
+        self.quaternion =
+            (self.quaternion * Quaternion::from_rotation_vector([correction[0], correction[1], correction[2]])).normalize();
+        for i in 0..3 {
+            self.bias[i] += correction[i + 3];
+        }
+    }
+}
+
+/// Yaw that turns the estimate to agree with the measured field, in radians. `None`
+/// when the field is too close to vertical to have a direction.
+fn heading_error(quaternion: Quaternion, field: [f32; 3]) -> Option<f32> {
+    let [hx, hy, hz] = quaternion.rotate(field);
+    if hx * hx + hy * hy < 1e-4 * (hx * hx + hy * hy + hz * hz) {
+        return None;
+    }
+    Some(-atan2f(hy, hx))
+}
+
+fn initial_covariance() -> Matrix {
+    let attitude = INITIAL_ATTITUDE_STD_DEG.to_radians();
+    let bias = INITIAL_BIAS_STD_DPS.to_radians();
+    let mut covariance = [[0.0; STATES]; STATES];
+    for i in 0..3 {
+        covariance[i][i] = attitude * attitude;
+        covariance[i + 3][i + 3] = bias * bias;
+    }
+    covariance
+}
+
+fn identity() -> Matrix {
+    let mut m = [[0.0; STATES]; STATES];
+    for (i, row) in m.iter_mut().enumerate() {
+        row[i] = 1.0;
+    }
+    m
+}
+
+fn dot(a: &[f32; STATES], b: &[f32; STATES]) -> f32 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn transpose(m: &Matrix) -> Matrix {
+    let mut t = [[0.0; STATES]; STATES];
+    for (i, row) in m.iter().enumerate() {
+        for (j, v) in row.iter().enumerate() {
+            t[j][i] = *v;
+        }
+    }
+    t
+}
+
+fn multiply(a: &Matrix, b: &Matrix) -> Matrix {
+    let b = transpose(b);
+    a.map(|row| b.map(|column| dot(&row, &column)))
+}
+
+/// Averages out the asymmetry rounding leaves in a covariance.
+fn symmetric(m: &Matrix) -> Matrix {
+    let t = transpose(m);
+    let mut s = *m;
+    for (row, t_row) in s.iter_mut().zip(&t) {
+        for (v, t) in row.iter_mut().zip(t_row) {
+            *v = (*v + t) / 2.0;
+        }
+    }
+    s
+}
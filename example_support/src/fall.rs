@@ -0,0 +1,254 @@
+//! Fall detection from the accelerometer.
+//!
+//! A fall has a typical signature: the body drops, so the measured acceleration
+//! falls well below 1 g; it hits the ground, a sharp spike of several g; and then,
+//! unlike after a jump or sitting down hard, the person lies still in a different
+//! orientation than before. [`FallDetector`] walks through those stages and only
+//! reports a fall when all of them are seen in order, within the configured windows.
+
+use libm::{acosf, sqrtf};
+
+use crate::imu::{ImuSample, SampleInterval};
+use crate::units::{Acceleration, Vector3};
+
+/// Longest gap between samples before the detector starts over, as nothing is known
+/// about what happened in between.
+const MAX_DT_S: f32 = 0.5;
+/// Time constant of the gravity direction tracked before a fall.
+const REFERENCE_S: f32 = 1.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FallConfig {
+    /// Magnitude below which the sensor counts as falling.
+    pub free_fall: Acceleration,
+    /// Shortest drop, anything briefer is a bump.
+    pub min_free_fall_ms: u32,
+    /// Magnitude of the impact.
+    pub impact: Acceleration,
+    /// Time from the end of the drop within which the impact must come.
+    pub impact_window_ms: u32,
+    /// Time after the impact that is ignored while the body comes to rest.
+    pub settle_ms: u32,
+    /// How long the person must then lie still.
+    pub inactivity_ms: u32,
+    /// Largest spread of the magnitude that still counts as lying still.
+    pub inactivity_range: Acceleration,
+    /// Smallest change of the gravity direction between before and after, in degrees.
+    pub min_orientation_change: f32,
+}
+
+impl Default for FallConfig {
+    fn default() -> Self {
+        Self {
+            free_fall: Acceleration::from_g(0.6),
+            min_free_fall_ms: 60,
+            impact: Acceleration::from_g(2.5),
+            impact_window_ms: 500,
+            settle_ms: 1_000,
+            inactivity_ms: 2_000,
+            inactivity_range: Acceleration::from_g(0.2),
+            min_orientation_change: 45.0,
+        }
+    }
+}
+
+/// A detected fall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FallEvent {
+    /// Time of the impact, in the samples' time base.
+    pub timestamp_us: u64,
+    /// How long the drop lasted.
+    pub free_fall_ms: u32,
+    /// Peak magnitude of the impact.
+    pub impact: Acceleration,
+    /// Angle between the gravity direction before the fall and while lying afterwards,
+    /// in degrees.
+    pub orientation_change: f32,
+}
+
+/// Where the detector is in the sequence of a fall.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FallStage {
+    Monitoring,
+    FreeFall { started_us: u64 },
+    /// The drop was long enough, waiting for the impact.
+    AwaitingImpact { free_fall_ms: u32, ended_us: u64 },
+    /// Impact seen, waiting for the body to come to rest and then lie still.
+    PostImpact {
+        free_fall_ms: u32,
+        impact_us: u64,
+        impact: Acceleration,
+    },
+}
+
+/// Readings since the body came to rest after an impact.
+#[derive(Debug, Clone, Copy)]
+struct Stillness {
+    /// Range of the magnitude, in g.
+    min: f32,
+    max: f32,
+    sum: [f32; 3],
+}
+
+impl Stillness {
+    const NONE: Self = Self {
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+        sum: [0.0; 3],
+    };
+}
+
+#[derive(Debug, Clone)]
+pub struct FallDetector {
+    config: FallConfig,
+    interval: SampleInterval,
+    stage: FallStage,
+    /// Gravity direction while upright, frozen during a fall.
+    reference: Option<[f32; 3]>,
+    stillness: Stillness,
+}
+
+impl Default for FallDetector {
+    fn default() -> Self {
+        Self::new(FallConfig::default())
+    }
+}
+
+impl FallDetector {
+    pub fn new(config: FallConfig) -> Self {
+        Self {
+            config,
+            interval: SampleInterval::default(),
+            stage: FallStage::Monitoring,
+            reference: None,
+            stillness: Stillness::NONE,
+        }
+    }
+
+    pub fn config(&self) -> FallConfig {
+        self.config
+    }
+
+    pub fn stage(&self) -> FallStage {
+        self.stage
+    }
+
+    /// Abandons any fall in progress and forgets the orientation.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    /// Feeds one sample, returning the fall it completes, if any.
+    pub fn update(&mut self, sample: &ImuSample) -> Option<FallEvent> {
+        // Start over from this sample after a gap, a fall cannot be judged on the few
+        // samples either side of it
+        let Some(dt) = self.interval.next(sample.timestamp_us, MAX_DT_S) else {
+            self.stage = FallStage::Monitoring;
+            self.stillness = Stillness::NONE;
+            self.reference = None;
+            self.track_reference(sample.acceleration.as_g(), 0.0);
+            return None;
+        };
+        self.step(sample, dt)
+    }
+
+    fn step(&mut self, sample: &ImuSample, dt: f32) -> Option<FallEvent> {
+        let acceleration = sample.acceleration.as_g();
+        let magnitude = sample.acceleration.norm().as_g();
+        let now = sample.timestamp_us;
+        let config = self.config;
+        let elapsed_ms = |since: u64| (now.saturating_sub(since) / 1_000) as u32;
+
+        match &mut self.stage {
+            FallStage::Monitoring => {
+                if magnitude < config.free_fall.as_g() {
+                    self.stage = FallStage::FreeFall { started_us: now };
+                } else {
+                    self.track_reference(acceleration, dt);
+                }
+            },
+            FallStage::FreeFall { started_us } => {
+                if magnitude >= config.free_fall.as_g() {
+                    let free_fall_ms = elapsed_ms(*started_us);
+                    self.stage = if free_fall_ms >= config.min_free_fall_ms {
+                        FallStage::AwaitingImpact { free_fall_ms, ended_us: now }
+                    } else {
+                        FallStage::Monitoring
+                    };
+                    // The drop may end in the impact itself
+                    return self.step(sample, dt);
+                }
+            },
+            FallStage::AwaitingImpact { free_fall_ms, ended_us } => {
+                if magnitude >= config.impact.as_g() {
+                    self.stage = FallStage::PostImpact {
+                        free_fall_ms: *free_fall_ms,
+                        impact_us: now,
+                        impact: sample.acceleration.norm(),
+                    };
+                    self.stillness = Stillness::NONE;
+                } else if elapsed_ms(*ended_us) > config.impact_window_ms {
+                    self.stage = FallStage::Monitoring;
+                }
+            },
+            FallStage::PostImpact { free_fall_ms, impact_us, impact } => {
+                let since_impact = elapsed_ms(*impact_us);
+                if since_impact < config.settle_ms {
+                    // Bounces after the first contact may be harder still
+                    if magnitude > impact.as_g() {
+                        *impact = Acceleration::from_g(magnitude);
+                    }
+                    return None;
+                }
+
+                let still = &mut self.stillness;
+                still.min = still.min.min(magnitude);
+                still.max = still.max.max(magnitude);
+                if still.max - still.min > config.inactivity_range.as_g() {
+                    // Moving again, so it was not a fall, or the person got up
+                    self.stage = FallStage::Monitoring;
+                    return None;
+                }
+                for (sum, a) in still.sum.iter_mut().zip(acceleration) {
+                    *sum += a;
+                }
+                if since_impact < config.settle_ms + config.inactivity_ms {
+                    return None;
+                }
+
+                let lying = Vector3::from_g(still.sum).direction();
+                let orientation_change = match (self.reference, lying) {
+                    (Some(before), Some(after)) => {
+                        let cos = before.iter().zip(after).map(|(b, a)| b * a).sum::<f32>();
+                        acosf(cos.clamp(-1.0, 1.0)).to_degrees()
+                    },
+                    _ => 0.0,
+                };
+                let event = FallEvent {
+                    timestamp_us: *impact_us,
+                    free_fall_ms: *free_fall_ms,
+                    impact: *impact,
+                    orientation_change,
+                };
+                self.stage = FallStage::Monitoring;
+                // Lying down is the new normal until the person gets up
+                self.reference = lying;
+                return (orientation_change >= config.min_orientation_change).then_some(event);
+            },
+        }
+        None
+    }
+
+    fn track_reference(&mut self, acceleration: [f32; 3], dt: f32) {
+        let weight = dt / (dt + REFERENCE_S);
+        let reference = self.reference.get_or_insert(acceleration);
+        let mut blended = [0.0; 3];
+        for ((blended, reference), a) in blended.iter_mut().zip(*reference).zip(acceleration) {
+            *blended = reference + weight * (a - reference);
+        }
+        let norm = sqrtf(blended.iter().map(|b| b * b).sum());
+        if norm > 0.0 {
+            *reference = blended.map(|b| b / norm);
+        }
+    }
+}
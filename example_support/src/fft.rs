@@ -0,0 +1,132 @@
+//! A fixed-size FFT of real samples, in place and without allocation.
+//!
+//! [`real_fft`] packs the `N` real samples into `N / 2` complex ones, transforms those
+//! with an iterative radix-2 FFT and untangles the result, which takes about half the
+//! work of a complex FFT of the same length. `N` is a power of two, at least 4.
+//!
+//! The result is packed into the same array: `data[0]` is bin 0 (DC), `data[1]` the
+//! real bin `N / 2` (Nyquist), and `data[2 * k]`, `data[2 * k + 1]` are the real and
+//! imaginary parts of bin `k` for `k` in `1..N / 2`. The transform is unscaled, a
+//! constant `c` comes out as `N * c` in bin 0.
+
+use core::f32::consts::PI;
+
+use libm::{cosf, sinf};
+
+/// A window to taper a block of samples with before the transform, so that a frequency
+/// between two bins does not leak over the whole spectrum.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Window {
+    /// No window. Only for signals that repeat exactly within the block.
+    Rectangular,
+    /// A good compromise between frequency resolution and leakage.
+    Hann,
+    /// Reads the amplitude of a sine within 0.1 dB wherever it falls between bins, at
+    /// the cost of a main lobe ten bins wide.
+    FlatTop,
+}
+
+impl Window {
+    /// Weight of sample `n` of `len`. The windows are periodic, as suits an FFT.
+    pub fn weight(self, n: usize, len: usize) -> f32 {
+        let x = 2.0 * PI * n as f32 / len as f32;
+        match self {
+            Window::Rectangular => 1.0,
+            Window::Hann => 0.5 - 0.5 * cosf(x),
+            Window::FlatTop => {
+                0.215_578_95 - 0.416_631_58 * cosf(x) + 0.277_263_16 * cosf(2.0 * x) - 0.083_578_95 * cosf(3.0 * x)
+                    + 0.006_947_368 * cosf(4.0 * x)
+            },
+        }
+    }
+
+    /// Multiplies `samples` by the window. Returns the mean of the weights and the mean
+    /// of their squares, which correct amplitudes and powers read off the spectrum.
+    pub fn apply(self, samples: &mut [f32]) -> (f32, f32) {
+        let len = samples.len();
+        let (mut sum, mut sum_of_squares) = (0.0, 0.0);
+        for (n, sample) in samples.iter_mut().enumerate() {
+            let weight = self.weight(n, len);
+            *sample *= weight;
+            sum += weight;
+            sum_of_squares += weight * weight;
+        }
+        (sum / len as f32, sum_of_squares / len as f32)
+    }
+}
+
+/// Transforms `N` real samples in place, see the [module documentation](self) for the
+/// layout of the result.
+pub fn real_fft<const N: usize>(data: &mut [f32; N]) {
+    const { assert!(N.is_power_of_two() && N >= 4, "the FFT length must be a power of two, at least 4") };
+    let half = N / 2;
+
+    // Even samples as the real parts, odd ones as the imaginary parts
+    complex_fft(data);
+
+    // Bins 0 and N / 2 are real and share the first pair
+    let (re, im) = (data[0], data[1]);
+    data[0] = re + im;
+    data[1] = re - im;
+
+    // Each bin k is untangled together with bin N / 2 - k
+    for k in 1..=half / 2 {
+        let j = half - k;
+        let (zk_re, zk_im) = (data[2 * k], data[2 * k + 1]);
+        let (zj_re, zj_im) = (data[2 * j], data[2 * j + 1]);
+        // The transforms of the even and the odd samples
+        let (even_re, even_im) = (0.5 * (zk_re + zj_re), 0.5 * (zk_im - zj_im));
+        let (odd_re, odd_im) = (0.5 * (zk_im + zj_im), -0.5 * (zk_re - zj_re));
+        let angle = -2.0 * PI * k as f32 / N as f32;
+        let (w_re, w_im) = (cosf(angle), sinf(angle));
+        let (t_re, t_im) = (w_re * odd_re - w_im * odd_im, w_re * odd_im + w_im * odd_re);
+        data[2 * k] = even_re + t_re;
+        data[2 * k + 1] = even_im + t_im;
+        data[2 * j] = even_re - t_re;
+        data[2 * j + 1] = t_im - even_im;
+    }
+}
+
+/// Power of bin `k` of a [`real_fft`] result, `|X[k]|²`.
+pub fn bin_power<const N: usize>(data: &[f32; N], k: usize) -> f32 {
+    match k {
+        0 => data[0] * data[0],
+        k if k == N / 2 => data[1] * data[1],
+        k => data[2 * k] * data[2 * k] + data[2 * k + 1] * data[2 * k + 1],
+    }
+}
+
+/// In-place radix-2 FFT of the complex numbers interleaved in `data`, real part first.
+fn complex_fft(data: &mut [f32]) {
+    let points = data.len() / 2;
+    let bits = points.trailing_zeros();
+
+    for i in 0..points {
+        let j = i.reverse_bits() >> (usize::BITS - bits);
+        if j > i {
+            data.swap(2 * i, 2 * j);
+            data.swap(2 * i + 1, 2 * j + 1);
+        }
+    }
+
+    let mut span = 1;
+    while span < points {
+        // The twiddle factor advances by a fixed rotation along each group
+        let angle = -PI / span as f32;
+        let (step_re, step_im) = (cosf(angle), sinf(angle));
+        for group in data.chunks_exact_mut(4 * span) {
+            let (low, high) = group.split_at_mut(2 * span);
+            let (mut w_re, mut w_im) = (1.0f32, 0.0f32);
+            for (a, b) in low.as_chunks_mut::<2>().0.iter_mut().zip(high.as_chunks_mut::<2>().0) {
+                let t_re = w_re * b[0] - w_im * b[1];
+                let t_im = w_re * b[1] + w_im * b[0];
+                b[0] = a[0] - t_re;
+                b[1] = a[1] - t_im;
+                a[0] += t_re;
+                a[1] += t_im;
+                (w_re, w_im) = (w_re * step_re - w_im * step_im, w_re * step_im + w_im * step_re);
+            }
+        }
+        span *= 2;
+    }
+}
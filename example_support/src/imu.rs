@@ -0,0 +1,183 @@
+//! A common interface over the hayasen IMU drivers.
+//!
+//! Fusion, calibration and logging code takes an [`Imu`] (or the [`ImuSample`]s it
+//! produces) and works the same with an MPU6050 or an MPU9250. The implementations for
+//! the hayasen drivers are enabled with the `mpu6050` and `mpu9250` features.
+//!
+//! Only [`Imu::read_all`] deals in bare numbers, in the units the drivers report; every
+//! other method and [`ImuSample`] carry [typed quantities](crate::units).
+
+use core::fmt;
+
+use crate::units::{Acceleration, AngularRate, MagneticField, Temperature, Vector3};
+
+/// One reading of every axis of an IMU.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ImuSample {
+    /// Time the reading was taken, in microseconds from an arbitrary epoch.
+    pub timestamp_us: u64,
+    /// Die temperature.
+    pub temperature: Temperature,
+    pub acceleration: Vector3<Acceleration>,
+    pub angular_velocity: Vector3<AngularRate>,
+    /// Only present for IMUs with a magnetometer.
+    pub magnetic_field: Option<Vector3<MagneticField>>,
+}
+
+impl fmt::Display for ImuSample {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [ax, ay, az] = self.acceleration.as_g();
+        let [gx, gy, gz] = self.angular_velocity.as_dps();
+        writeln!(f, "Temperature : {:.2} C", self.temperature.as_celsius())?;
+        writeln!(f, "Acceleration [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g", ax, ay, az)?;
+        write!(f, "Angular Velocity [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", gx, gy, gz)?;
+        if let Some([mx, my, mz]) = self.magnetic_field.map(Vector3::as_microtesla) {
+            write!(f, "\nMagnetic Field [X, Y, Z] : [{:.2}, {:.2}, {:.2}] uT", mx, my, mz)?;
+        }
+        Ok(())
+    }
+}
+
+/// An accelerometer and gyroscope, optionally with a magnetometer.
+pub trait Imu {
+    type Error: fmt::Debug;
+
+    /// Reads temperature (°C), acceleration (g) and angular velocity (°/s) in one burst,
+    /// exactly as the driver reports them.
+    fn read_all(&mut self) -> Result<(f32, [f32; 3], [f32; 3]), Self::Error>;
+
+    fn read_acceleration(&mut self) -> Result<Vector3<Acceleration>, Self::Error> {
+        self.read_all().map(|(_, acceleration, _)| Vector3::from_g(acceleration))
+    }
+
+    fn read_angular_velocity(&mut self) -> Result<Vector3<AngularRate>, Self::Error> {
+        self.read_all().map(|(_, _, angular_velocity)| Vector3::from_dps(angular_velocity))
+    }
+
+    fn read_temperature(&mut self) -> Result<Temperature, Self::Error> {
+        self.read_all().map(|(temperature, _, _)| Temperature::from_celsius(temperature))
+    }
+
+    /// `None` if the IMU has no magnetometer.
+    fn read_magnetic_field(&mut self) -> Option<Result<Vector3<MagneticField>, Self::Error>> {
+        None
+    }
+
+    /// Reads every axis, stamping the reading with `timestamp_us`.
+    fn sample(&mut self, timestamp_us: u64) -> Result<ImuSample, Self::Error> {
+        let (temperature, acceleration, angular_velocity) = self.read_all()?;
+        let magnetic_field = self.read_magnetic_field().transpose()?;
+        Ok(ImuSample {
+            timestamp_us,
+            temperature: Temperature::from_celsius(temperature),
+            acceleration: Vector3::from_g(acceleration),
+            angular_velocity: Vector3::from_dps(angular_velocity),
+            magnetic_field,
+        })
+    }
+}
+
+impl<T: Imu + ?Sized> Imu for &mut T {
+    type Error = T::Error;
+
+    fn read_all(&mut self) -> Result<(f32, [f32; 3], [f32; 3]), Self::Error> {
+        (**self).read_all()
+    }
+
+    fn read_acceleration(&mut self) -> Result<Vector3<Acceleration>, Self::Error> {
+        (**self).read_acceleration()
+    }
+
+    fn read_angular_velocity(&mut self) -> Result<Vector3<AngularRate>, Self::Error> {
+        (**self).read_angular_velocity()
+    }
+
+    fn read_temperature(&mut self) -> Result<Temperature, Self::Error> {
+        (**self).read_temperature()
+    }
+
+    fn read_magnetic_field(&mut self) -> Option<Result<Vector3<MagneticField>, Self::Error>> {
+        (**self).read_magnetic_field()
+    }
+}
+
+/// An [`Imu`] paired with the clock used to timestamp its samples.
+pub struct Timestamped<I, C> {
+    imu: I,
+    clock: C,
+}
+
+impl<I, C> Timestamped<I, C>
+where
+    I: Imu,
+    C: FnMut() -> u64,
+{
+    /// `clock` returns the current time in microseconds.
+    pub fn new(imu: I, clock: C) -> Self {
+        Self { imu, clock }
+    }
+
+    pub fn imu(&mut self) -> &mut I {
+        &mut self.imu
+    }
+
+    pub fn into_inner(self) -> I {
+        self.imu
+    }
+
+    /// Reads every axis, timestamped with the time the read started.
+    pub fn sample(&mut self) -> Result<ImuSample, I::Error> {
+        let timestamp_us = (self.clock)();
+        self.imu.sample(timestamp_us)
+    }
+}
+
+/// Time step between the samples fed to a filter, taken from their timestamps.
+#[derive(Debug, Default, Clone)]
+pub(crate) struct SampleInterval {
+    last_us: Option<u64>,
+}
+
+impl SampleInterval {
+    /// Seconds since the previous sample. `None` for the first sample, and for one
+    /// that is out of order or more than `max_s` after the previous one.
+    pub(crate) fn next(&mut self, timestamp_us: u64, max_s: f32) -> Option<f32> {
+        let dt = self
+            .last_us
+            .and_then(|last| timestamp_us.checked_sub(last))
+            .map(|elapsed| elapsed as f32 / 1e6)
+            .filter(|&dt| dt > 0.0 && dt <= max_s);
+        self.last_us = Some(timestamp_us);
+        dt
+    }
+
+    pub(crate) fn reset(&mut self) {
+        self.last_us = None;
+    }
+}
+
+#[cfg(feature = "mpu6050")]
+impl<I2C, E> Imu for hayasen::mpu6050::Mpu6050<I2C>
+where
+    I2C: embedded_hal::i2c::I2c<Error = E>,
+    E: fmt::Debug,
+{
+    type Error = hayasen::error::Error<E>;
+
+    fn read_all(&mut self) -> Result<(f32, [f32; 3], [f32; 3]), Self::Error> {
+        hayasen::mpu6050_hayasen::read_all(self)
+    }
+}
+
+#[cfg(feature = "mpu9250")]
+impl<I2C, E> Imu for hayasen::mpu9250::Mpu9250<I2C>
+where
+    I2C: embedded_hal::i2c::I2c<Error = E>,
+    E: fmt::Debug,
+{
+    type Error = hayasen::error::Error<E>;
+
+    fn read_all(&mut self) -> Result<(f32, [f32; 3], [f32; 3]), Self::Error> {
+        hayasen::mpu9250_hayasen::read_all(self)
+    }
+}
@@ -0,0 +1,194 @@
+//! Sensor initialisation with retry and exponential backoff.
+//!
+//! The hayasen constructors consume the I2C bus they are given, so a failed attempt
+//! loses the bus. Hand them a shared bus device (e.g. `embedded_hal_bus::i2c::RefCellDevice`)
+//! instead, so that the init closure can be called as often as needed.
+
+use embedded_hal::delay::DelayNs;
+
+/// Exponential backoff schedule.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Backoff {
+    initial_ms: u32,
+    max_ms: u32,
+    multiplier: u32,
+    max_attempts: u32,
+}
+
+impl Backoff {
+    /// Starts at `initial_ms` and doubles after every failure, up to `max_ms`.
+    pub const fn new(initial_ms: u32, max_ms: u32) -> Self {
+        Self {
+            initial_ms,
+            max_ms,
+            multiplier: 2,
+            max_attempts: 5,
+        }
+    }
+
+    pub const fn with_multiplier(mut self, multiplier: u32) -> Self {
+        self.multiplier = multiplier;
+        self
+    }
+
+    /// Number of attempts made by [`init_with_retry`] before giving up.
+    pub const fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub const fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    /// Delay to wait after the given failed attempt (1-based).
+    pub fn delay_ms(&self, attempt: u32) -> u32 {
+        let mut delay = self.initial_ms.min(self.max_ms);
+        for _ in 1..attempt {
+            delay = delay.saturating_mul(self.multiplier).min(self.max_ms);
+            if delay == self.max_ms {
+                break;
+            }
+        }
+        delay
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self::new(100, 5_000)
+    }
+}
+
+/// A failed initialisation attempt, handed to the reporting callback.
+#[derive(Debug)]
+pub struct Attempt<'a, E> {
+    /// 1-based attempt number.
+    pub number: u32,
+    /// Attempt limit, `None` when retrying indefinitely in degraded mode.
+    pub max_attempts: Option<u32>,
+    pub error: &'a E,
+    /// Time until the next attempt, `None` if this was the last one.
+    pub retry_in_ms: Option<u32>,
+}
+
+/// Calls `init` until it succeeds or the backoff runs out of attempts.
+///
+/// `report` is called with the concrete error after every failed attempt. The error of
+/// the last attempt is returned if all of them fail.
+pub fn init_with_retry<T, E, D, I, R>(
+    backoff: &Backoff,
+    delay: &mut D,
+    mut init: I,
+    mut report: R,
+) -> Result<T, E>
+where
+    D: DelayNs,
+    I: FnMut() -> Result<T, E>,
+    R: FnMut(&Attempt<'_, E>),
+{
+    let max_attempts = backoff.max_attempts.max(1);
+    let mut number = 1;
+    loop {
+        let error = match init() {
+            Ok(sensor) => return Ok(sensor),
+            Err(error) => error,
+        };
+
+        let retry_in_ms = (number < max_attempts).then(|| backoff.delay_ms(number));
+        report(&Attempt {
+            number,
+            max_attempts: Some(max_attempts),
+            error: &error,
+            retry_in_ms,
+        });
+
+        match retry_in_ms {
+            Some(ms) => delay.delay_ms(ms),
+            None => return Err(error),
+        }
+        number += 1;
+    }
+}
+
+/// Keeps a sensor running, or retries it in the background when it is missing.
+///
+/// In degraded mode [`SensorSupervisor::poll`] retries the init closure on the backoff
+/// schedule (capped at its maximum delay, never giving up) without blocking, so the
+/// rest of the firmware keeps running in the meantime.
+pub struct SensorSupervisor<T> {
+    state: State<T>,
+    backoff: Backoff,
+}
+
+enum State<T> {
+    Ready(T),
+    Degraded { failures: u32, next_retry_ms: u64 },
+}
+
+impl<T> SensorSupervisor<T> {
+    /// Wraps the outcome of the boot-time initialisation.
+    pub fn new<E>(initial: Result<T, E>, now_ms: u64, backoff: Backoff) -> Self {
+        let state = match initial {
+            Ok(sensor) => State::Ready(sensor),
+            Err(_) => State::Degraded {
+                failures: backoff.max_attempts,
+                next_retry_ms: now_ms + u64::from(backoff.delay_ms(backoff.max_attempts)),
+            },
+        };
+        Self { state, backoff }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        matches!(self.state, State::Ready(_))
+    }
+
+    /// The sensor, if it is currently initialised.
+    pub fn sensor(&mut self) -> Option<&mut T> {
+        match &mut self.state {
+            State::Ready(sensor) => Some(sensor),
+            State::Degraded { .. } => None,
+        }
+    }
+
+    /// Returns the sensor, retrying `init` first if a retry is due.
+    pub fn poll<E, I, R>(&mut self, now_ms: u64, init: I, report: R) -> Option<&mut T>
+    where
+        I: FnOnce() -> Result<T, E>,
+        R: FnOnce(&Attempt<'_, E>),
+    {
+        if let State::Degraded { failures, next_retry_ms } = self.state {
+            if now_ms < next_retry_ms {
+                return None;
+            }
+
+            match init() {
+                Ok(sensor) => self.state = State::Ready(sensor),
+                Err(error) => {
+                    let number = failures + 1;
+                    let retry_in_ms = self.backoff.delay_ms(number);
+                    report(&Attempt {
+                        number,
+                        max_attempts: None,
+                        error: &error,
+                        retry_in_ms: Some(retry_in_ms),
+                    });
+                    self.state = State::Degraded {
+                        failures: number,
+                        next_retry_ms: now_ms + u64::from(retry_in_ms),
+                    };
+                }
+            }
+        }
+
+        self.sensor()
+    }
+
+    /// Drops the sensor and schedules a re-initialisation after the initial backoff delay.
+    pub fn mark_failed(&mut self, now_ms: u64) {
+        self.state = State::Degraded {
+            failures: 1,
+            next_retry_ms: now_ms + u64::from(self.backoff.delay_ms(1)),
+        };
+    }
+}
@@ -0,0 +1,38 @@
+//! Helpers shared by the Hayasen examples.
+//!
+//! Everything in here is `no_std` and hardware independent, so it can be unit tested
+//! on the host with `cargo test`.
+
+#![no_std]
+
+pub mod accel_calibration;
+pub mod accel_fifo;
+pub mod activity;
+mod activity_tree;
+pub mod ak8963;
+pub mod bus_recovery;
+pub mod calibration;
+pub mod calibration_store;
+pub mod compass;
+pub mod complementary;
+pub mod dual_imu;
+pub mod ekf;
+pub mod fall;
+pub mod fft;
+pub mod imu;
+pub mod init;
+mod linalg;
+pub mod madgwick;
+pub mod mag_calibration;
+pub mod mahony;
+pub mod motion_artifact;
+pub mod pedometer;
+pub mod ppg;
+pub mod presence;
+pub mod quaternion;
+pub mod sample_stats;
+pub mod scan;
+pub mod tap;
+pub mod tilt;
+pub mod units;
+pub mod vibration;
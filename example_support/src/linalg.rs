@@ -0,0 +1,120 @@
+//! The little dense linear algebra the calibration fits need.
+//!
+//! Normal equations of the fits mix squared and linear terms, so everything here works
+//! in `f64` even though the readings are `f32`.
+
+use libm::{fabs, sqrt};
+
+/// Pivots smaller than this are treated as zero, i.e. the system is singular.
+const SINGULAR: f64 = 1e-12;
+
+/// Solves `a * x = b` by Gaussian elimination with partial pivoting.
+pub(crate) fn solve<const N: usize>(mut a: [[f64; N]; N], mut b: [f64; N]) -> Option<[f64; N]> {
+    for column in 0..N {
+        let pivot = (column..N).max_by(|&i, &j| fabs(a[i][column]).total_cmp(&fabs(a[j][column])))?;
+        if fabs(a[pivot][column]) < SINGULAR {
+            return None;
+        }
+        a.swap(column, pivot);
+        b.swap(column, pivot);
+
+        let pivot_row = a[column];
+        for row in column + 1..N {
+            let factor = a[row][column] / pivot_row[column];
+            for (target, source) in a[row][column..].iter_mut().zip(&pivot_row[column..]) {
+                *target -= factor * source;
+            }
+            b[row] -= factor * b[column];
+        }
+    }
+
+    let mut x = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: f64 = (row + 1..N).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Accumulates the normal equations `Xᵀ X w = Xᵀ y` of a linear least-squares fit,
+/// one row of `X` at a time.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct NormalEquations<const N: usize> {
+    xtx: [[f64; N]; N],
+    xty: [f64; N],
+}
+
+impl<const N: usize> NormalEquations<N> {
+    pub(crate) const fn new() -> Self {
+        Self {
+            xtx: [[0.0; N]; N],
+            xty: [0.0; N],
+        }
+    }
+
+    pub(crate) fn add(&mut self, x: [f64; N], y: f64) {
+        for i in 0..N {
+            for j in 0..N {
+                self.xtx[i][j] += x[i] * x[j];
+            }
+            self.xty[i] += x[i] * y;
+        }
+    }
+
+    /// The least-squares solution, `None` if the rows do not determine it.
+    pub(crate) fn solve(&self) -> Option<[f64; N]> {
+        solve(self.xtx, self.xty)
+    }
+}
+
+/// Inverse of a 3x3 matrix, `None` if it is singular.
+pub(crate) fn invert3(m: [[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0];
+    let adjugate = [
+        [cofactor(1, 2, 1, 2), -cofactor(0, 2, 1, 2), cofactor(0, 1, 1, 2)],
+        [-cofactor(1, 2, 0, 2), cofactor(0, 2, 0, 2), -cofactor(0, 1, 0, 2)],
+        [cofactor(1, 2, 0, 1), -cofactor(0, 2, 0, 1), cofactor(0, 1, 0, 1)],
+    ];
+    let determinant = m[0][0] * adjugate[0][0] + m[0][1] * adjugate[1][0] + m[0][2] * adjugate[2][0];
+    if fabs(determinant) < SINGULAR {
+        return None;
+    }
+    Some(adjugate.map(|row| row.map(|v| v / determinant)))
+}
+
+pub(crate) fn mul3(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    m.map(|row| row[0] * v[0] + row[1] * v[1] + row[2] * v[2])
+}
+
+/// Eigenvalues and eigenvectors (the columns of the matrix) of a symmetric 3x3
+/// matrix, by Jacobi rotations.
+pub(crate) fn symmetric_eigen3(mut a: [[f64; 3]; 3]) -> ([f64; 3], [[f64; 3]; 3]) {
+    let mut vectors = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    for _ in 0..32 {
+        let (p, q) = [(0, 1), (0, 2), (1, 2)]
+            .into_iter()
+            .max_by(|&(i, j), &(k, l)| fabs(a[i][j]).total_cmp(&fabs(a[k][l])))
+            .unwrap_or((0, 1));
+        let scale = fabs(a[0][0]) + fabs(a[1][1]) + fabs(a[2][2]);
+        if fabs(a[p][q]) <= 1e-15 * scale {
+            break;
+        }
+
+        // Rotation in the p-q plane that zeroes a[p][q]
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+        let sign = if theta >= 0.0 { 1.0 } else { -1.0 };
+        let t = sign / (fabs(theta) + sqrt(theta * theta + 1.0));
+        let c = 1.0 / sqrt(t * t + 1.0);
+        let s = t * c;
+
+        for row in a.iter_mut().chain(vectors.iter_mut()) {
+            let (x, y) = (row[p], row[q]);
+            row[p] = c * x - s * y;
+            row[q] = s * x + c * y;
+        }
+        let (row_p, row_q) = (a[p], a[q]);
+        a[p] = core::array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+        a[q] = core::array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+    }
+    ([a[0][0], a[1][1], a[2][2]], vectors)
+}
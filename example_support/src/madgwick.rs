@@ -0,0 +1,143 @@
+//! Madgwick's gradient descent orientation filter.
+//!
+//! The gyroscope is integrated into a quaternion, and every step is nudged against the
+//! gradient of the mismatch between where the quaternion says gravity (and, with a
+//! magnetometer, north) should be and where the sensors measure it. `beta` sets the
+//! size of that nudge in rad/s: larger values converge faster and cancel more gyro
+//! drift, smaller ones are less disturbed by acceleration and magnetic interference.
+//!
+//! With only an accelerometer and gyroscope (the IMU variant, e.g. an MPU6050) yaw is
+//! integrated from the gyroscope alone and drifts. With a magnetometer as well (the
+//! MARG variant, e.g. an MPU9250) yaw is referenced to magnetic north.
+
+use libm::sqrtf;
+
+use crate::imu::{ImuSample, SampleInterval};
+use crate::quaternion::{EulerAngles, Quaternion};
+
+/// Longest gap between samples that is still integrated. After a longer gap the
+/// sample only sets the time for the next one.
+const MAX_DT_S: f32 = 1.0;
+
+#[derive(Debug, Clone)]
+pub struct Madgwick {
+    beta: f32,
+    quaternion: Quaternion,
+    interval: SampleInterval,
+}
+
+impl Default for Madgwick {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_BETA)
+    }
+}
+
+impl Madgwick {
+    /// The gain used by Madgwick's reference implementation.
+    pub const DEFAULT_BETA: f32 = 0.1;
+
+    /// Starts level and facing north, `beta` is the correction gain in rad/s.
+    pub fn new(beta: f32) -> Self {
+        Self {
+            beta,
+            quaternion: Quaternion::IDENTITY,
+            interval: SampleInterval::default(),
+        }
+    }
+
+    pub fn beta(&self) -> f32 {
+        self.beta
+    }
+
+    /// Changes the gain without losing the estimate, e.g. a high gain to converge
+    /// quickly after start-up followed by a low one.
+    pub fn set_beta(&mut self, beta: f32) {
+        self.beta = beta;
+    }
+
+    pub fn quaternion(&self) -> Quaternion {
+        self.quaternion
+    }
+
+    pub fn euler(&self) -> EulerAngles {
+        self.quaternion.to_euler()
+    }
+
+    /// Starts again from `quaternion`.
+    pub fn reset(&mut self, quaternion: Quaternion) {
+        self.quaternion = quaternion.normalize();
+        self.interval.reset();
+    }
+
+    /// Fuses one sample, using its magnetometer reading when it has one.
+    pub fn update(&mut self, sample: &ImuSample) -> Quaternion {
+        let magnetic_field = sample.magnetic_field.and_then(|m| m.direction());
+        self.step(sample, magnetic_field)
+    }
+
+    /// Fuses one sample as if it had no magnetometer reading.
+    pub fn update_imu(&mut self, sample: &ImuSample) -> Quaternion {
+        self.step(sample, None)
+    }
+
+    fn step(&mut self, sample: &ImuSample, magnetic_field: Option<[f32; 3]>) -> Quaternion {
+        let Some(dt) = self.interval.next(sample.timestamp_us, MAX_DT_S) else {
+            return self.quaternion;
+        };
+
+        let q = self.quaternion;
+        let [gx, gy, gz] = sample.angular_velocity.as_rad_per_s();
+        let mut rate = q * Quaternion::new(0.0, gx, gy, gz) * 0.5;
+
+        // A free-falling accelerometer has no direction to correct towards
+        if let Some(a) = sample.acceleration.direction() {
+            let gradient = match magnetic_field {
+                Some(m) => gravity_gradient(q, a) + magnetic_gradient(q, m),
+                None => gravity_gradient(q, a),
+            };
+            let norm = gradient.norm();
+            if norm > 0.0 {
+                rate = rate - gradient * (self.beta / norm);
+            }
+        }
+
+        self.quaternion = (q + rate * dt).normalize();
+        self.quaternion
+    }
+}
+
+/// Gradient of the squared error between gravity rotated into the sensor frame and
+/// the measured direction `a`.
+fn gravity_gradient(q: Quaternion, a: [f32; 3]) -> Quaternion {
+    let Quaternion { w, x, y, z } = q;
+    let f1 = 2.0 * (x * z - w * y) - a[0];
+    let f2 = 2.0 * (w * x + y * z) - a[1];
+    let f3 = 1.0 - 2.0 * (x * x + y * y) - a[2];
+    Quaternion::new(
+        -2.0 * y * f1 + 2.0 * x * f2,
+        2.0 * z * f1 + 2.0 * w * f2 - 4.0 * x * f3,
+        -2.0 * w * f1 + 2.0 * z * f2 - 4.0 * y * f3,
+        2.0 * x * f1 + 2.0 * y * f2,
+    )
+}
+
+/// Gradient of the squared error between the Earth's field rotated into the sensor
+/// frame and the measured direction `m`. The reference field is the measurement
+/// rotated into the world frame with its horizontal part turned to point north, so
+/// only yaw is corrected and the dip angle never has to be known.
+fn magnetic_gradient(q: Quaternion, m: [f32; 3]) -> Quaternion {
+    let Quaternion { w, x, y, z } = q;
+    let h = q.rotate(m);
+    let bx = sqrtf(h[0] * h[0] + h[1] * h[1]);
+    let bz = h[2];
+
+    let f1 = 2.0 * bx * (0.5 - y * y - z * z) + 2.0 * bz * (x * z - w * y) - m[0];
+    let f2 = 2.0 * bx * (x * y - w * z) + 2.0 * bz * (w * x + y * z) - m[1];
+    let f3 = 2.0 * bx * (w * y + x * z) + 2.0 * bz * (0.5 - x * x - y * y) - m[2];
+    Quaternion::new(
+        -2.0 * bz * y * f1 + (-2.0 * bx * z + 2.0 * bz * x) * f2 + 2.0 * bx * y * f3,
+        2.0 * bz * z * f1 + (2.0 * bx * y + 2.0 * bz * w) * f2 + (2.0 * bx * z - 4.0 * bz * x) * f3,
+        (-4.0 * bx * y - 2.0 * bz * w) * f1 + (2.0 * bx * x + 2.0 * bz * z) * f2 + (2.0 * bx * w - 4.0 * bz * y) * f3,
+        (-4.0 * bx * z + 2.0 * bz * x) * f1 + (-2.0 * bx * w + 2.0 * bz * y) * f2 + 2.0 * bx * x * f3,
+    )
+}
@@ -0,0 +1,298 @@
+//! Magnetometer hard and soft iron correction.
+//!
+//! Magnetised parts near the sensor add a constant field (hard iron), and
+//! ferromagnetic material bends the earth's field so that its strength depends on
+//! direction (soft iron). Rotated through every orientation, the readings trace an
+//! offset, squashed ellipsoid instead of a sphere centred on zero; the correction
+//! recentres it and maps it back onto a sphere.
+//!
+//! [`MagCalibrator`] collects readings while the board is turned through as many
+//! orientations as possible, keeping them spread evenly over the sphere and reporting
+//! how much of it has been covered. [`MagCalibrator::fit`] then fits a general
+//! ellipsoid to them by linear least squares.
+
+use core::f32::consts::PI;
+
+use libm::{atan2f, cbrt, floorf, sqrt, sqrtf};
+
+use crate::linalg::{invert3, mul3, symmetric_eigen3, NormalEquations};
+use crate::units::{MagneticField, Vector3};
+
+/// The sphere is divided into this many bands of latitude, of equal area...
+const BANDS: usize = 4;
+/// ...and each band into this many sectors of longitude.
+const SECTORS: usize = 8;
+/// Regions of the sphere that coverage is counted in.
+pub const REGIONS: usize = BANDS * SECTORS;
+
+/// Fewest readings the fit is attempted with, enough for its nine unknowns with
+/// plenty to spare for the noise.
+pub const MIN_FIT_SAMPLES: usize = 24;
+/// Share of the regions that must have readings before fitting. Fewer leave the
+/// ellipsoid's shape unconstrained along the directions never seen.
+pub const MIN_FIT_COVERAGE: f32 = 0.5;
+
+/// Readings closer than this to one already kept add nothing and are dropped, so
+/// holding the board still does not fill the buffer.
+const DEFAULT_MIN_SPACING_UT: f32 = 1.5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagCalibration {
+    /// Centre of the ellipsoid.
+    pub hard_iron: Vector3<MagneticField>,
+    /// Row-major, applied after the hard iron offset has been subtracted.
+    pub soft_iron: [[f32; 3]; 3],
+}
+
+impl Default for MagCalibration {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl MagCalibration {
+    /// Leaves readings unchanged.
+    pub const IDENTITY: Self = Self {
+        hard_iron: Vector3::new(MagneticField::ZERO, MagneticField::ZERO, MagneticField::ZERO),
+        soft_iron: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+    };
+
+    pub fn apply(&self, field: Vector3<MagneticField>) -> Vector3<MagneticField> {
+        let [x, y, z] = (field - self.hard_iron).as_microtesla();
+        Vector3::from_microtesla(self.soft_iron.map(|row| row[0] * x + row[1] * y + row[2] * z))
+    }
+}
+
+/// How much of the sphere the kept readings cover.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coverage {
+    pub samples: usize,
+    /// Regions of the sphere, out of [`REGIONS`], with at least one reading.
+    pub regions: usize,
+}
+
+impl Coverage {
+    /// Share of the sphere covered, from 0 to 1.
+    pub fn fraction(&self) -> f32 {
+        self.regions as f32 / REGIONS as f32
+    }
+}
+
+/// Why the readings could not be fitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FitError {
+    /// Fewer than [`MIN_FIT_SAMPLES`] readings.
+    TooFewSamples,
+    /// Less than [`MIN_FIT_COVERAGE`] of the sphere covered, e.g. the board was only
+    /// turned flat on the table.
+    InsufficientCoverage,
+    /// The readings do not lie on an ellipsoid, e.g. a magnet moved with the board.
+    NotAnEllipsoid,
+}
+
+/// The result of a fit.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MagFit {
+    pub calibration: MagCalibration,
+    /// Strength of the field after correction, the local Earth's field if nothing
+    /// else was nearby.
+    pub field_strength: MagneticField,
+    /// RMS deviation of the corrected readings from `field_strength`, as a share of
+    /// it. A few percent is typical; much more means the readings were disturbed.
+    pub residual: f32,
+}
+
+/// Collects up to `N` readings for a fit. `N` should be a few times [`REGIONS`]; each
+/// region keeps at most `N / REGIONS` readings, rounded up, so none of them dominates
+/// the fit.
+#[derive(Debug, Clone)]
+pub struct MagCalibrator<const N: usize> {
+    samples: [[f32; 3]; N],
+    len: usize,
+    min: [f32; 3],
+    max: [f32; 3],
+    min_spacing_ut: f32,
+}
+
+impl<const N: usize> Default for MagCalibrator<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> MagCalibrator<N> {
+    pub const fn new() -> Self {
+        Self {
+            samples: [[0.0; 3]; N],
+            len: 0,
+            min: [f32::INFINITY; 3],
+            max: [f32::NEG_INFINITY; 3],
+            min_spacing_ut: DEFAULT_MIN_SPACING_UT,
+        }
+    }
+
+    /// Readings within `spacing` of one already kept are dropped. The default is
+    /// 1.5 µT, about two degrees of rotation in the Earth's field.
+    pub fn with_min_spacing(mut self, spacing: MagneticField) -> Self {
+        self.min_spacing_ut = spacing.as_microtesla();
+        self
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new().with_min_spacing(MagneticField::from_microtesla(self.min_spacing_ut));
+    }
+
+    pub fn samples(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    /// Keeps the reading if it adds a new direction, and reports the coverage so far.
+    pub fn add(&mut self, field: Vector3<MagneticField>) -> Coverage {
+        let reading = field.as_microtesla();
+        if self.len < N && self.is_new(reading) {
+            self.min = [0, 1, 2].map(|i| self.min[i].min(reading[i]));
+            self.max = [0, 1, 2].map(|i| self.max[i].max(reading[i]));
+            self.samples[self.len] = reading;
+            self.len += 1;
+        }
+        self.coverage()
+    }
+
+    pub fn coverage(&self) -> Coverage {
+        let centre = self.centre();
+        let mut seen = [false; REGIONS];
+        for &sample in self.kept() {
+            if let Some(region) = region(sample, centre) {
+                seen[region] = true;
+            }
+        }
+        Coverage {
+            samples: self.len,
+            regions: seen.iter().filter(|&&seen| seen).count(),
+        }
+    }
+
+    /// Fits an ellipsoid to the kept readings.
+    pub fn fit(&self) -> Result<MagFit, FitError> {
+        if self.len < MIN_FIT_SAMPLES {
+            return Err(FitError::TooFewSamples);
+        }
+        if self.coverage().fraction() < MIN_FIT_COVERAGE {
+            return Err(FitError::InsufficientCoverage);
+        }
+
+        // Shifted and scaled to around the unit sphere, which keeps the squared terms
+        // of the normal equations well conditioned
+        let rough_centre = self.centre().map(f64::from);
+        let scale = self
+            .kept()
+            .iter()
+            .map(|s| {
+                let d = [0, 1, 2].map(|i| f64::from(s[i]) - rough_centre[i]);
+                sqrt(d[0] * d[0] + d[1] * d[1] + d[2] * d[2])
+            })
+            .sum::<f64>()
+            / self.len as f64;
+        if scale <= 0.0 {
+            return Err(FitError::NotAnEllipsoid);
+        }
+
+        // a x² + b y² + c z² + 2f yz + 2g xz + 2h xy + 2p x + 2q y + 2r z = 1
+        let mut equations = NormalEquations::<9>::new();
+        for s in self.kept() {
+            let [x, y, z] = [0, 1, 2].map(|i| (f64::from(s[i]) - rough_centre[i]) / scale);
+            equations.add([x * x, y * y, z * z, 2.0 * y * z, 2.0 * x * z, 2.0 * x * y, 2.0 * x, 2.0 * y, 2.0 * z], 1.0);
+        }
+        let [a, b, c, f, g, h, p, q, r] = equations.solve().ok_or(FitError::NotAnEllipsoid)?;
+
+        let quadric = [[a, h, g], [h, b, f], [g, f, c]];
+        let centre = mul3(&invert3(quadric).ok_or(FitError::NotAnEllipsoid)?, [p, q, r]).map(|v| -v);
+        let k = 1.0 + dot3(centre, mul3(&quadric, centre));
+        let (values, vectors) = symmetric_eigen3(quadric.map(|row| row.map(|v| v / k)));
+        if values.iter().any(|&v| v <= 0.0) || k <= 0.0 {
+            return Err(FitError::NotAnEllipsoid);
+        }
+
+        // Maps the ellipsoid onto a sphere of the same volume, the geometric mean of
+        // its semi-axes
+        let radius = cbrt(1.0 / sqrt(values[0] * values[1] * values[2]));
+        let root = values.map(sqrt);
+        let soft_iron: [[f32; 3]; 3] = core::array::from_fn(|i| {
+            core::array::from_fn(|j| (radius * (0..3).map(|k| vectors[i][k] * root[k] * vectors[j][k]).sum::<f64>()) as f32)
+        });
+        let hard_iron = [0, 1, 2].map(|i| (rough_centre[i] + centre[i] * scale) as f32);
+
+        let calibration = MagCalibration {
+            hard_iron: Vector3::from_microtesla(hard_iron),
+            soft_iron,
+        };
+        let field_strength = (radius * scale) as f32;
+        let squared_error: f32 = self
+            .kept()
+            .iter()
+            .map(|&s| {
+                let deviation = calibration.apply(Vector3::from_microtesla(s)).norm().as_microtesla() - field_strength;
+                deviation * deviation
+            })
+            .sum();
+
+        Ok(MagFit {
+            calibration,
+            field_strength: MagneticField::from_microtesla(field_strength),
+            residual: sqrtf(squared_error / self.len as f32) / field_strength,
+        })
+    }
+
+    fn kept(&self) -> &[[f32; 3]] {
+        &self.samples[..self.len]
+    }
+
+    /// Centre of the readings' bounding box, a first guess at the hard iron offset.
+    fn centre(&self) -> [f32; 3] {
+        if self.len == 0 {
+            return [0.0; 3];
+        }
+        [0, 1, 2].map(|i| (self.min[i] + self.max[i]) / 2.0)
+    }
+
+    fn is_new(&self, reading: [f32; 3]) -> bool {
+        let spacing = self.min_spacing_ut * self.min_spacing_ut;
+        let close = self.kept().iter().any(|s| distance_squared(*s, reading) < spacing);
+        if close {
+            return false;
+        }
+
+        // Until the centre is known well enough to tell regions apart, keep everything
+        let centre = self.centre();
+        let Some(target) = region(reading, centre) else {
+            return true;
+        };
+        let per_region = N.div_ceil(REGIONS);
+        self.kept().iter().filter(|&&s| region(s, centre) == Some(target)).count() < per_region
+    }
+}
+
+/// Region of the sphere that `reading` points to, seen from `centre`.
+fn region(reading: [f32; 3], centre: [f32; 3]) -> Option<usize> {
+    let d = [0, 1, 2].map(|i| reading[i] - centre[i]);
+    let norm = sqrtf(distance_squared(d, [0.0; 3]));
+    if norm <= 0.0 {
+        return None;
+    }
+    // Bands of equal height cut a sphere into equal areas
+    let band = (floorf((d[2] / norm + 1.0) / 2.0 * BANDS as f32) as usize).min(BANDS - 1);
+    let sector = (floorf((atan2f(d[1], d[0]) + PI) / (2.0 * PI) * SECTORS as f32) as usize).min(SECTORS - 1);
+    Some(band * SECTORS + sector)
+}
+
+fn distance_squared(a: [f32; 3], b: [f32; 3]) -> f32 {
+    (0..3).map(|i| (a[i] - b[i]) * (a[i] - b[i])).sum()
+}
+
+fn dot3(a: [f64; 3], b: [f64; 3]) -> f64 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
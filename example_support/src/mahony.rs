@@ -0,0 +1,137 @@
+//! Mahony's nonlinear complementary filter on the rotation group.
+//!
+//! The cross product between the measured gravity (and north) directions and the ones
+//! predicted by the current quaternion is an error rotation. It is fed back into the
+//! gyroscope rate through a PI controller before integrating: `kp` pulls the estimate
+//! towards the measurements, `ki` integrates the error into an estimate of the gyro
+//! bias. Compared with [`Madgwick`](crate::madgwick::Madgwick) the integral term lets
+//! it cancel a constant bias completely rather than trading it against a steady error.
+
+use libm::sqrtf;
+
+use crate::imu::{ImuSample, SampleInterval};
+use crate::quaternion::{EulerAngles, Quaternion};
+use crate::units::{AngularRate, Vector3};
+
+/// Longest gap between samples that is still integrated. After a longer gap the
+/// sample only sets the time for the next one.
+const MAX_DT_S: f32 = 1.0;
+
+#[derive(Debug, Clone)]
+pub struct Mahony {
+    kp: f32,
+    ki: f32,
+    quaternion: Quaternion,
+    /// Integral feedback in rad/s, the negated gyro bias once settled.
+    integral: [f32; 3],
+    interval: SampleInterval,
+}
+
+impl Default for Mahony {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_KP, Self::DEFAULT_KI)
+    }
+}
+
+impl Mahony {
+    /// The gains used by Mahony's reference implementation.
+    pub const DEFAULT_KP: f32 = 0.5;
+    pub const DEFAULT_KI: f32 = 0.0;
+
+    /// Starts level and facing north. `kp` is in rad/s per unit of error, `ki` in
+    /// rad/s² per unit of error; zero disables bias estimation.
+    ///
+    /// The Earth's field dips steeply away from the equator, so only its small
+    /// horizontal part corrects yaw and yaw converges several times slower than tilt.
+    /// Raise `kp` if heading takes too long to settle.
+    pub fn new(kp: f32, ki: f32) -> Self {
+        Self {
+            kp,
+            ki,
+            quaternion: Quaternion::IDENTITY,
+            integral: [0.0; 3],
+            interval: SampleInterval::default(),
+        }
+    }
+
+    pub fn gains(&self) -> (f32, f32) {
+        (self.kp, self.ki)
+    }
+
+    /// Changes the gains without losing the estimate. Setting `ki` to zero also
+    /// forgets the bias estimate.
+    pub fn set_gains(&mut self, kp: f32, ki: f32) {
+        self.kp = kp;
+        self.ki = ki;
+        if ki == 0.0 {
+            self.integral = [0.0; 3];
+        }
+    }
+
+    pub fn quaternion(&self) -> Quaternion {
+        self.quaternion
+    }
+
+    pub fn euler(&self) -> EulerAngles {
+        self.quaternion.to_euler()
+    }
+
+    /// Gyro bias estimated by the integral term, which is subtracted from every
+    /// reading.
+    pub fn gyro_bias(&self) -> Vector3<AngularRate> {
+        Vector3::from_rad_per_s(self.integral.map(|i| -i))
+    }
+
+    /// Starts again from `quaternion`, keeping the bias estimate.
+    pub fn reset(&mut self, quaternion: Quaternion) {
+        self.quaternion = quaternion.normalize();
+        self.interval.reset();
+    }
+
+    /// Fuses one sample, using its magnetometer reading when it has one.
+    pub fn update(&mut self, sample: &ImuSample) -> Quaternion {
+        let magnetic_field = sample.magnetic_field.and_then(|m| m.direction());
+        self.step(sample, magnetic_field)
+    }
+
+    /// Fuses one sample as if it had no magnetometer reading.
+    pub fn update_imu(&mut self, sample: &ImuSample) -> Quaternion {
+        self.step(sample, None)
+    }
+
+    fn step(&mut self, sample: &ImuSample, magnetic_field: Option<[f32; 3]>) -> Quaternion {
+        let Some(dt) = self.interval.next(sample.timestamp_us, MAX_DT_S) else {
+            return self.quaternion;
+        };
+
+        let q = self.quaternion;
+        let mut rate = sample.angular_velocity.as_rad_per_s();
+
+        if let Some(a) = sample.acceleration.direction() {
+            let mut error = cross(a, q.rotate_inverse([0.0, 0.0, 1.0]));
+            if let Some(m) = magnetic_field {
+                // Expected field: the measurement turned in the world frame to point north
+                let h = q.rotate(m);
+                let expected = q.rotate_inverse([sqrtf(h[0] * h[0] + h[1] * h[1]), 0.0, h[2]]);
+                let magnetic = cross(m, expected);
+                for (e, m) in error.iter_mut().zip(magnetic) {
+                    *e += m;
+                }
+            }
+
+            for axis in 0..3 {
+                if self.ki > 0.0 {
+                    self.integral[axis] += self.ki * error[axis] * dt;
+                }
+                rate[axis] += self.kp * error[axis] + self.integral[axis];
+            }
+        }
+
+        self.quaternion = (q * Quaternion::from_rotation_vector(rate.map(|r| r * dt))).normalize();
+        self.quaternion
+    }
+}
+
+fn cross(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[1] * b[2] - a[2] * b[1], a[2] * b[0] - a[0] * b[2], a[0] * b[1] - a[1] * b[0]]
+}
@@ -0,0 +1,257 @@
+//! Motion artifact rejection for PPG, with an accelerometer worn next to the sensor.
+//!
+//! When the wearer moves, the sensor shifts on the skin and blood sloshes in the
+//! tissue, and the PPG picks up a disturbance often larger than the pulse itself. The
+//! accelerometer sees the same motion, which helps in two ways:
+//!
+//! - [`MotionGate`] tracks how much the wearer is moving. While that is above a
+//!   threshold, and for a while after, pulse rate and SpO2 should not be updated.
+//! - [`NlmsCanceller`] learns how the acceleration shows up in a PPG channel and
+//!   subtracts that, an adaptive noise canceller with the acceleration as the noise
+//!   reference. It uses the normalised LMS algorithm, which adapts more slowly than
+//!   RLS but costs a few multiplications per tap instead of a matrix update.
+//!
+//! The canceller expects PPG and acceleration samples in pairs, taken at the same
+//! time. The MAX30102 delivers its samples in batches from its FIFO, so
+//! [`ReferenceHistory`] keeps the recent accelerometer readings and interpolates one
+//! for the time of each PPG sample.
+
+use libm::sqrtf;
+
+use crate::imu::{ImuSample, SampleInterval};
+use crate::units::Acceleration;
+
+/// Longest gap between samples that is bridged. After a longer one the gate starts over.
+const MAX_DT_S: f32 = 0.5;
+/// Time constant of the gravity estimate that is subtracted from the readings.
+const GRAVITY_S: f32 = 1.0;
+/// Time constant of the motion energy.
+const ENERGY_S: f32 = 0.25;
+/// Added to the reference power before normalising, in g², so that the canceller does
+/// not chase sensor noise while the wearer is still.
+const REFERENCE_FLOOR: f32 = 1e-2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MotionGateConfig {
+    /// RMS of the acceleration, gravity removed, above which the wearer is moving.
+    pub threshold: Acceleration,
+    /// How long after the motion the PPG is still disturbed.
+    pub hold_ms: u32,
+}
+
+impl Default for MotionGateConfig {
+    fn default() -> Self {
+        Self {
+            threshold: Acceleration::from_g(0.05),
+            hold_ms: 2_000,
+        }
+    }
+}
+
+/// Decides from the accelerometer whether the PPG can be trusted.
+#[derive(Debug, Clone)]
+pub struct MotionGate {
+    config: MotionGateConfig,
+    interval: SampleInterval,
+    gravity: [f32; 3],
+    linear: [f32; 3],
+    energy: f32,
+    moving_until_us: Option<u64>,
+    steady: bool,
+}
+
+impl Default for MotionGate {
+    fn default() -> Self {
+        Self::new(MotionGateConfig::default())
+    }
+}
+
+impl MotionGate {
+    pub fn new(config: MotionGateConfig) -> Self {
+        Self {
+            config,
+            interval: SampleInterval::default(),
+            gravity: [0.0; 3],
+            linear: [0.0; 3],
+            energy: 0.0,
+            moving_until_us: None,
+            steady: false,
+        }
+    }
+
+    pub fn config(&self) -> MotionGateConfig {
+        self.config
+    }
+
+    /// Forgets the motion so far. Until the hold time has passed again the PPG is not
+    /// trusted, as after the first sample.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    /// Feeds one sample, returning whether the wearer has been still long enough for
+    /// the PPG to be trusted.
+    pub fn update(&mut self, sample: &ImuSample) -> bool {
+        let acceleration = sample.acceleration.as_g();
+        let now = sample.timestamp_us;
+        let Some(dt) = self.interval.next(now, MAX_DT_S) else {
+            // Until the gravity estimate settles, assume the worst
+            self.gravity = acceleration;
+            self.linear = [0.0; 3];
+            self.energy = 0.0;
+            self.moving_until_us = Some(now + u64::from(self.config.hold_ms) * 1_000);
+            self.steady = false;
+            return false;
+        };
+
+        let weight = dt / (dt + GRAVITY_S);
+        for ((gravity, linear), a) in self.gravity.iter_mut().zip(&mut self.linear).zip(acceleration) {
+            *gravity += weight * (a - *gravity);
+            *linear = a - *gravity;
+        }
+        let power = self.linear.iter().map(|a| a * a).sum::<f32>();
+        self.energy += dt / (dt + ENERGY_S) * (power - self.energy);
+
+        if self.motion().as_g() > self.config.threshold.as_g() {
+            self.moving_until_us = Some(now + u64::from(self.config.hold_ms) * 1_000);
+        }
+        self.steady = self.moving_until_us.is_none_or(|until| now >= until);
+        self.steady
+    }
+
+    /// Whether the PPG can be trusted, as of the last sample.
+    pub fn is_steady(&self) -> bool {
+        self.steady
+    }
+
+    /// RMS of the recent acceleration with gravity removed.
+    pub fn motion(&self) -> Acceleration {
+        Acceleration::from_g(sqrtf(self.energy))
+    }
+
+    /// The last acceleration with gravity removed, in g. This is the reference for an
+    /// [`NlmsCanceller`].
+    pub fn linear_acceleration(&self) -> [f32; 3] {
+        self.linear
+    }
+}
+
+/// Recent linear accelerations with their timestamps, to look up the motion at the
+/// time of a PPG sample. Holds the last `N` readings.
+#[derive(Debug, Clone)]
+pub struct ReferenceHistory<const N: usize> {
+    /// Oldest first.
+    readings: [(u64, [f32; 3]); N],
+    len: usize,
+}
+
+impl<const N: usize> Default for ReferenceHistory<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ReferenceHistory<N> {
+    pub fn new() -> Self {
+        Self {
+            readings: [(0, [0.0; 3]); N],
+            len: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Adds a reading, dropping the oldest one when full. A reading older than the
+    /// newest one starts the history over.
+    pub fn push(&mut self, timestamp_us: u64, linear: [f32; 3]) {
+        if N == 0 {
+            return;
+        }
+        if self.readings[..self.len].last().is_some_and(|&(last, _)| timestamp_us < last) {
+            self.clear();
+        }
+        if self.len == N {
+            self.readings.copy_within(1.., 0);
+            self.len -= 1;
+        }
+        self.readings[self.len] = (timestamp_us, linear);
+        self.len += 1;
+    }
+
+    /// The acceleration at `timestamp_us`, interpolated between the readings either
+    /// side of it. Before the oldest or after the newest reading, that reading is used.
+    /// `None` if the history is empty.
+    pub fn at(&self, timestamp_us: u64) -> Option<[f32; 3]> {
+        let readings = &self.readings[..self.len];
+        let after = readings.partition_point(|&(t, _)| t < timestamp_us);
+        match (after.checked_sub(1).map(|i| readings[i]), readings.get(after)) {
+            (Some((t0, a0)), Some(&(t1, a1))) => {
+                let weight = (timestamp_us - t0) as f32 / (t1 - t0) as f32;
+                Some(core::array::from_fn(|axis| a0[axis] + weight * (a1[axis] - a0[axis])))
+            },
+            (Some((_, a)), None) | (None, Some(&(_, a))) => Some(a),
+            (None, None) => None,
+        }
+    }
+}
+
+/// Adaptive noise canceller with the three axes of the acceleration as reference, each
+/// through an FIR filter of `TAPS` samples.
+#[derive(Debug, Clone)]
+pub struct NlmsCanceller<const TAPS: usize> {
+    step: f32,
+    /// Recent references, newest first.
+    history: [[f32; 3]; TAPS],
+    weights: [[f32; 3]; TAPS],
+}
+
+impl<const TAPS: usize> Default for NlmsCanceller<TAPS> {
+    fn default() -> Self {
+        Self::new(0.02)
+    }
+}
+
+impl<const TAPS: usize> NlmsCanceller<TAPS> {
+    /// `step` trades how fast the canceller adapts against how much noise it adds,
+    /// between 0 and 2; around 0.02 works well.
+    pub fn new(step: f32) -> Self {
+        Self {
+            step,
+            history: [[0.0; 3]; TAPS],
+            weights: [[0.0; 3]; TAPS],
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.step);
+    }
+
+    /// Feeds the AC part of a PPG sample and the linear acceleration taken with it.
+    /// Returns the PPG with the part explained by the motion taken out.
+    pub fn update(&mut self, primary: f32, reference: [f32; 3]) -> f32 {
+        self.history.copy_within(..TAPS.saturating_sub(1), 1);
+        if let Some(newest) = self.history.first_mut() {
+            *newest = reference;
+        }
+
+        let mut estimate = 0.0;
+        let mut power = REFERENCE_FLOOR;
+        for (weights, inputs) in self.weights.iter().zip(&self.history) {
+            for (w, x) in weights.iter().zip(inputs) {
+                estimate += w * x;
+                power += x * x;
+            }
+        }
+        let error = primary - estimate;
+
+        let gain = self.step * error / power;
+        for (weights, inputs) in self.weights.iter_mut().zip(&self.history) {
+            for (w, x) in weights.iter_mut().zip(inputs) {
+                *w += gain * x;
+            }
+        }
+        error
+    }
+}
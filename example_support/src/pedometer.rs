@@ -0,0 +1,234 @@
+//! Step counting and cadence from the accelerometer.
+//!
+//! Each step shows up as a bump in the magnitude of the acceleration, whichever way
+//! the sensor is worn. The magnitude is smoothed, its slowly moving baseline (gravity)
+//! is subtracted, and every excursion above an adaptive threshold is a candidate step,
+//! timed at its peak. The threshold follows the recent peak heights, so both a stroll
+//! and a run are picked up without triggering on the smaller bumps in between.
+//!
+//! Shaking the sensor also produces peaks, but not at a steady rhythm. Candidates only
+//! count once [`PedometerConfig::regular_steps`] of them in a row have come at
+//! similar intervals; they are then all counted at once, and every further step counts
+//! as it happens for as long as the rhythm holds.
+
+use core::f32::consts::PI;
+
+use crate::imu::{ImuSample, SampleInterval};
+use crate::units::Acceleration;
+
+/// Longest gap between samples the filters bridge. After a longer one they restart.
+const MAX_DT_S: f32 = 1.0;
+
+/// Steps used to average the cadence.
+const CADENCE_STEPS: usize = 4;
+
+/// Crossover of the smoothing low-pass. Running cadence is below 4 Hz.
+const SMOOTHING_HZ: f32 = 5.0;
+/// Time constant of the baseline, long against a step.
+const BASELINE_S: f32 = 1.5;
+/// The threshold is this share of the average recent peak height.
+const PEAK_FRACTION: f32 = 0.4;
+/// Weight of each candidate in the average peak height.
+const PEAK_WEIGHT: f32 = 0.25;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PedometerConfig {
+    /// Smallest peak above the baseline that can be a step, whatever the recent ones.
+    pub min_peak: Acceleration,
+    /// Shortest time between steps, 250 ms is a sprint at 240 steps per minute.
+    pub min_step_interval_ms: u32,
+    /// Longest time between steps. After a longer pause the rhythm has to be found again.
+    pub max_step_interval_ms: u32,
+    /// Candidates that must come at a steady rhythm before any of them count.
+    pub regular_steps: u32,
+    /// How much an interval may differ from the average of the ones before it, as a
+    /// share of that average.
+    pub regularity: f32,
+}
+
+impl Default for PedometerConfig {
+    fn default() -> Self {
+        Self {
+            min_peak: Acceleration::from_g(0.1),
+            min_step_interval_ms: 250,
+            max_step_interval_ms: 2_000,
+            regular_steps: 7,
+            regularity: 0.2,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Pedometer {
+    config: PedometerConfig,
+    interval: SampleInterval,
+    /// Magnitude after the two smoothing stages, and its baseline, in g.
+    smoothed: [f32; 2],
+    baseline: f32,
+    /// Highest point of the current excursion above the baseline, and when it was.
+    excursion: Option<(f32, u64)>,
+    peak_average: f32,
+    last_candidate_us: Option<u64>,
+    /// Candidates in the current steady run, counted or not.
+    run: u32,
+    walking: bool,
+    recent_intervals_us: [u64; CADENCE_STEPS],
+    recent: usize,
+    steps: u32,
+}
+
+impl Default for Pedometer {
+    fn default() -> Self {
+        Self::new(PedometerConfig::default())
+    }
+}
+
+impl Pedometer {
+    pub fn new(config: PedometerConfig) -> Self {
+        Self {
+            config,
+            interval: SampleInterval::default(),
+            smoothed: [0.0; 2],
+            baseline: 0.0,
+            excursion: None,
+            peak_average: 0.0,
+            last_candidate_us: None,
+            run: 0,
+            walking: false,
+            recent_intervals_us: [0; CADENCE_STEPS],
+            recent: 0,
+            steps: 0,
+        }
+    }
+
+    pub fn config(&self) -> PedometerConfig {
+        self.config
+    }
+
+    /// Steps counted since start or the last reset.
+    pub fn steps(&self) -> u32 {
+        self.steps
+    }
+
+    /// Whether the last steps came at a steady rhythm.
+    pub fn is_walking(&self) -> bool {
+        self.walking
+    }
+
+    /// Steps per minute over the last few steps, 0 when not walking.
+    pub fn cadence(&self) -> f32 {
+        match self.average_interval() {
+            Some(average) if self.walking => 60e6 / average,
+            _ => 0.0,
+        }
+    }
+
+    /// Clears the count and starts looking for a rhythm again.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.config);
+    }
+
+    /// Feeds one sample and returns the number of steps it added to the count.
+    pub fn update(&mut self, sample: &ImuSample) -> u32 {
+        let magnitude = sample.acceleration.norm().as_g();
+        let now = sample.timestamp_us;
+
+        // Restart the filters from this sample after a gap
+        let Some(dt) = self.interval.next(now, MAX_DT_S) else {
+            self.smoothed = [magnitude; 2];
+            self.baseline = magnitude;
+            self.excursion = None;
+            self.end_walk();
+            return 0;
+        };
+
+        let smoothing = dt / (dt + 1.0 / (2.0 * PI * SMOOTHING_HZ));
+        self.smoothed[0] += smoothing * (magnitude - self.smoothed[0]);
+        self.smoothed[1] += smoothing * (self.smoothed[0] - self.smoothed[1]);
+        self.baseline += dt / (dt + BASELINE_S) * (self.smoothed[1] - self.baseline);
+        let signal = self.smoothed[1] - self.baseline;
+
+        if self.walking && self.last_candidate_us.is_some_and(|last| now.saturating_sub(last) > self.max_interval_us()) {
+            self.end_walk();
+        }
+
+        if signal > 0.0 {
+            if self.excursion.is_none_or(|(peak, _)| signal > peak) {
+                self.excursion = Some((signal, now));
+            }
+            return 0;
+        }
+
+        // The excursion ends when the signal is back down at the baseline. Every peak
+        // above the noise adjusts the threshold, so it comes down again after a run
+        let Some((peak, at)) = self.excursion.take() else {
+            return 0;
+        };
+        let min_peak = self.config.min_peak.as_g();
+        let threshold = min_peak.max(PEAK_FRACTION * self.peak_average);
+        if peak > min_peak {
+            self.peak_average += PEAK_WEIGHT * (peak - self.peak_average);
+        }
+        if peak > threshold { self.candidate(at) } else { 0 }
+    }
+
+    fn candidate(&mut self, at: u64) -> u32 {
+        let interval = self.last_candidate_us.map(|last| at.saturating_sub(last));
+        if interval.is_some_and(|interval| interval < u64::from(self.config.min_step_interval_ms) * 1_000) {
+            // Part of the same footfall
+            return 0;
+        }
+        self.last_candidate_us = Some(at);
+
+        let Some(interval) = interval.filter(|&interval| interval <= self.max_interval_us()) else {
+            self.end_walk();
+            self.run = 1;
+            return 0;
+        };
+        let steady = self
+            .average_interval()
+            .is_none_or(|average| (interval as f32 - average).abs() <= self.config.regularity * average);
+        if !steady {
+            // This interval may be the start of a new rhythm
+            self.walking = false;
+            self.run = 2;
+            self.recent = 0;
+            self.push_interval(interval);
+            return 0;
+        }
+
+        self.run += 1;
+        self.push_interval(interval);
+        let added = if self.walking {
+            1
+        } else if self.run >= self.config.regular_steps {
+            self.walking = true;
+            self.run
+        } else {
+            0
+        };
+        self.steps += added;
+        added
+    }
+
+    /// Average of the intervals in the current run.
+    fn average_interval(&self) -> Option<f32> {
+        let intervals = &self.recent_intervals_us[..self.recent.min(CADENCE_STEPS)];
+        (!intervals.is_empty()).then(|| intervals.iter().sum::<u64>() as f32 / intervals.len() as f32)
+    }
+
+    fn push_interval(&mut self, interval: u64) {
+        self.recent_intervals_us[self.recent % CADENCE_STEPS] = interval;
+        self.recent += 1;
+    }
+
+    fn end_walk(&mut self) {
+        self.walking = false;
+        self.run = 0;
+        self.recent = 0;
+    }
+
+    fn max_interval_us(&self) -> u64 {
+        u64::from(self.config.max_step_interval_ms) * 1_000
+    }
+}
@@ -0,0 +1,280 @@
+//! Photoplethysmography (PPG) helpers for the MAX30102.
+
+use core::f32::consts::PI;
+
+use libm::{expf, sqrtf};
+
+use crate::imu::SampleInterval;
+use crate::linalg::NormalEquations;
+
+/// Maps the ratio of ratios `R = (AC_red / DC_red) / (AC_ir / DC_ir)` to SpO2 in
+/// percent, `SpO2 = a + b·R + c·R²`.
+///
+/// The coefficients depend on the LEDs and the optics around the sensor, so they are
+/// determined against a reference oximeter for each build.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Spo2Curve {
+    pub a: f32,
+    pub b: f32,
+    pub c: f32,
+}
+
+impl Default for Spo2Curve {
+    /// The linear approximation `SpO2 = 104 - 17·R` commonly used for the MAX30102.
+    fn default() -> Self {
+        Self { a: 104.0, b: -17.0, c: 0.0 }
+    }
+}
+
+impl Spo2Curve {
+    /// Fits the curve to `(R, SpO2)` pairs, each an R measured by the MAX30102 and the
+    /// SpO2 a reference oximeter showed at the same time. Three or more pairs give a
+    /// quadratic, two a straight line. `None` with fewer pairs, or when they do not
+    /// determine the curve (e.g. all at the same R).
+    pub fn fit(points: &[(f32, f32)]) -> Option<Self> {
+        match points.len() {
+            0 | 1 => None,
+            2 => {
+                let mut equations = NormalEquations::<2>::new();
+                for &(ratio, spo2) in points {
+                    equations.add([1.0, f64::from(ratio)], f64::from(spo2));
+                }
+                let [a, b] = equations.solve()?;
+                Some(Self { a: a as f32, b: b as f32, c: 0.0 })
+            },
+            _ => {
+                let mut equations = NormalEquations::<3>::new();
+                for &(ratio, spo2) in points {
+                    let ratio = f64::from(ratio);
+                    equations.add([1.0, ratio, ratio * ratio], f64::from(spo2));
+                }
+                let [a, b, c] = equations.solve()?;
+                Some(Self { a: a as f32, b: b as f32, c: c as f32 })
+            },
+        }
+    }
+
+    /// SpO2 in percent, clamped to 70..=100 where the curve is meaningful.
+    pub fn spo2(&self, ratio: f32) -> f32 {
+        (self.a + self.b * ratio + self.c * ratio * ratio).clamp(70.0, 100.0)
+    }
+}
+
+/// Separates a PPG channel into its slowly moving DC level and the pulsatile AC part.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DcTracker {
+    weight: f32,
+    level: Option<f32>,
+}
+
+impl DcTracker {
+    /// `weight` is the share of each sample in the level; 0.01 gives a time constant
+    /// of about a second at 100 samples per second.
+    pub fn new(weight: f32) -> Self {
+        Self { weight, level: None }
+    }
+
+    /// Returns the AC part of the sample.
+    pub fn update(&mut self, sample: f32) -> f32 {
+        let level = self.level.get_or_insert(sample);
+        *level += self.weight * (sample - *level);
+        sample - *level
+    }
+
+    pub fn level(&self) -> Option<f32> {
+        self.level
+    }
+
+    pub fn reset(&mut self) {
+        self.level = None;
+    }
+}
+
+/// Intervals the pulse rate is the median of.
+const PULSE_INTERVALS: usize = 5;
+/// Crossover of the low-pass in front of the peak detector, well above 180 bpm.
+const PULSE_SMOOTHING_HZ: f32 = 5.0;
+/// Peaks must rise above the valley before them by this share of the average recent
+/// rise.
+const PULSE_RISE_FRACTION: f32 = 0.5;
+/// Weight of each beat in the average rise.
+const PULSE_RISE_WEIGHT: f32 = 0.2;
+/// After a beat, peaks are ignored for this share of the current beat interval.
+const PULSE_REFRACTORY: f32 = 0.6;
+/// Beat intervals outside 40 to 180 bpm are ignored.
+const MIN_BEAT_INTERVAL_US: u64 = 333_000;
+const MAX_BEAT_INTERVAL_US: u64 = 1_500_000;
+/// Without a beat for this long the pulse rate is unknown again.
+const PULSE_TIMEOUT_US: u64 = 3_000_000;
+
+/// Pulse rate from the AC part of a PPG channel, as the median of the last few
+/// intervals between beats.
+#[derive(Debug, Clone)]
+pub struct PulseRate {
+    interval: SampleInterval,
+    smoothed: [f32; 2],
+    previous: f32,
+    rising: bool,
+    /// Lowest point since the last peak.
+    valley: f32,
+    rise_average: f32,
+    last_beat_us: Option<u64>,
+    intervals_us: [u64; PULSE_INTERVALS],
+    intervals: usize,
+}
+
+impl Default for PulseRate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PulseRate {
+    pub fn new() -> Self {
+        Self {
+            interval: SampleInterval::default(),
+            smoothed: [0.0; 2],
+            previous: 0.0,
+            rising: false,
+            valley: 0.0,
+            rise_average: 0.0,
+            last_beat_us: None,
+            intervals_us: [0; PULSE_INTERVALS],
+            intervals: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+
+    /// Feeds one AC sample, returning the pulse rate in beats per minute when the
+    /// sample completes a beat.
+    pub fn update(&mut self, timestamp_us: u64, ac: f32) -> Option<f32> {
+        let Some(dt) = self.interval.next(timestamp_us, 0.5) else {
+            self.smoothed = [ac; 2];
+            self.previous = ac;
+            self.valley = ac;
+            return None;
+        };
+        if self.last_beat_us.is_some_and(|last| timestamp_us.saturating_sub(last) > PULSE_TIMEOUT_US) {
+            // The signal may have changed as well, so the rise is learned anew
+            self.last_beat_us = None;
+            self.intervals = 0;
+            self.rise_average = 0.0;
+        }
+
+        let alpha = 1.0 - expf(-2.0 * PI * PULSE_SMOOTHING_HZ * dt);
+        self.smoothed[0] += alpha * (ac - self.smoothed[0]);
+        self.smoothed[1] += alpha * (self.smoothed[0] - self.smoothed[1]);
+        let value = self.smoothed[1];
+        let was_rising = self.rising;
+        self.rising = value > self.previous;
+        let peak = self.previous;
+        self.previous = value;
+        self.valley = self.valley.min(value);
+        if !was_rising || self.rising {
+            return None;
+        }
+        // Measured from the valley, so the breathing wander underneath does not matter
+        let rise = peak - core::mem::replace(&mut self.valley, value);
+        if rise <= PULSE_RISE_FRACTION * self.rise_average {
+            return None;
+        }
+
+        // The previous sample was a peak, high enough to be a beat
+        let since_last = self.last_beat_us.map(|last| timestamp_us.saturating_sub(last));
+        let refractory_us = self.median_interval_us().map_or(0, |median| (PULSE_REFRACTORY * median as f32) as u64);
+        if since_last.is_some_and(|since| since < MIN_BEAT_INTERVAL_US.max(refractory_us)) {
+            // The dicrotic notch, or noise on the way down
+            return None;
+        }
+        self.rise_average = if self.rise_average > 0.0 {
+            self.rise_average + PULSE_RISE_WEIGHT * (rise - self.rise_average)
+        } else {
+            rise
+        };
+        self.last_beat_us = Some(timestamp_us);
+        let since_last = since_last.filter(|&since| since <= MAX_BEAT_INTERVAL_US)?;
+        self.intervals_us.copy_within(..PULSE_INTERVALS - 1, 1);
+        self.intervals_us[0] = since_last;
+        self.intervals = (self.intervals + 1).min(PULSE_INTERVALS);
+        self.bpm()
+    }
+
+    /// The current pulse rate, once two beats have been seen in a row.
+    pub fn bpm(&self) -> Option<f32> {
+        self.median_interval_us().map(|median| 60e6 / median as f32)
+    }
+
+    fn median_interval_us(&self) -> Option<u64> {
+        let mut sorted = self.intervals_us;
+        let recent = &mut sorted[..self.intervals];
+        recent.sort_unstable();
+        recent.get(recent.len() / 2).copied()
+    }
+}
+
+/// SpO2 from the ratio of ratios over windows of raw red and IR samples.
+#[derive(Debug, Clone)]
+pub struct Spo2Estimator {
+    curve: Spo2Curve,
+    window: u32,
+    red: DcTracker,
+    ir: DcTracker,
+    /// Sums of the squared AC parts and of the DC levels over the window.
+    red_ac: f32,
+    ir_ac: f32,
+    red_dc: f32,
+    ir_dc: f32,
+    samples: u32,
+    settled: bool,
+}
+
+impl Spo2Estimator {
+    /// `window` samples go into each estimate, 100 is a second at 100 samples per second.
+    pub fn new(curve: Spo2Curve, window: u32) -> Self {
+        Self {
+            curve,
+            window,
+            red: DcTracker::new(0.01),
+            ir: DcTracker::new(0.01),
+            red_ac: 0.0,
+            ir_ac: 0.0,
+            red_dc: 0.0,
+            ir_dc: 0.0,
+            samples: 0,
+            settled: false,
+        }
+    }
+
+    /// Forgets the current window and lets the DC levels settle again.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.curve, self.window);
+    }
+
+    /// Feeds one pair of raw samples, returning SpO2 in percent at the end of a window.
+    pub fn update(&mut self, red: f32, ir: f32) -> Option<f32> {
+        let red_ac = self.red.update(red);
+        let ir_ac = self.ir.update(ir);
+        self.red_ac += red_ac * red_ac;
+        self.ir_ac += ir_ac * ir_ac;
+        self.red_dc += self.red.level().unwrap_or(red);
+        self.ir_dc += self.ir.level().unwrap_or(ir);
+        self.samples += 1;
+        if self.samples < self.window {
+            return None;
+        }
+
+        let ratio = (sqrtf(self.red_ac) / self.red_dc) / (sqrtf(self.ir_ac) / self.ir_dc);
+        let (red_ac, ir_ac) = (self.red_ac, self.ir_ac);
+        self.red_ac = 0.0;
+        self.ir_ac = 0.0;
+        self.red_dc = 0.0;
+        self.ir_dc = 0.0;
+        self.samples = 0;
+        // The first window only lets the DC levels settle
+        let settled = core::mem::replace(&mut self.settled, true);
+        (settled && red_ac > 0.0 && ir_ac > 0.0 && ratio.is_finite()).then(|| self.curve.spo2(ratio))
+    }
+}
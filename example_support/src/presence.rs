@@ -0,0 +1,160 @@
+//! Detecting a sensor that was unplugged, power cycled or reset.
+//!
+//! A disconnected sensor is easy to spot: it stops acknowledging its address, or
+//! something else answers with the wrong identity. A sensor that lost power for a
+//! moment is harder, as it answers normally but comes back with its registers at their
+//! power-on defaults, so the readings silently use the wrong ranges and rates. To catch
+//! that, [`PresenceMonitor`] takes a snapshot of the configuration registers once the
+//! driver has set them up and compares against it on every check.
+
+use embedded_hal::i2c::I2c;
+
+/// Maximum number of configuration registers in a snapshot.
+pub const MAX_CONFIG_REGISTERS: usize = 8;
+
+/// How to recognise a device and what configuration it is expected to keep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceIdentity {
+    pub name: &'static str,
+    pub address: u8,
+    pub id_register: u8,
+    /// Any of these values in `id_register` identifies the device.
+    pub expected_ids: &'static [u8],
+    /// Registers written by the driver during initialisation.
+    pub config_registers: &'static [u8],
+}
+
+impl DeviceIdentity {
+    /// The same device at an alternate address (e.g. an MPU with AD0 pulled high).
+    pub const fn with_address(mut self, address: u8) -> Self {
+        self.address = address;
+        self
+    }
+}
+
+/// SMPLRT_DIV, CONFIG, GYRO_CONFIG, ACCEL_CONFIG and PWR_MGMT_1.
+const MPU_CONFIG_REGISTERS: &[u8] = &[0x19, 0x1A, 0x1B, 0x1C, 0x6B];
+
+pub const MPU6050: DeviceIdentity = DeviceIdentity {
+    name: "MPU6050",
+    address: 0x68,
+    id_register: 0x75,
+    expected_ids: &[0x68],
+    config_registers: MPU_CONFIG_REGISTERS,
+};
+
+/// Also accepts the MPU9255, which only differs in its WHO_AM_I value.
+pub const MPU9250: DeviceIdentity = DeviceIdentity {
+    name: "MPU9250",
+    address: 0x68,
+    id_register: 0x75,
+    expected_ids: &[0x71, 0x73],
+    config_registers: MPU_CONFIG_REGISTERS,
+};
+
+/// FIFO_CONFIG, MODE_CONFIG, SPO2_CONFIG and both LED pulse amplitudes.
+pub const MAX30102: DeviceIdentity = DeviceIdentity {
+    name: "MAX30102",
+    address: 0x57,
+    id_register: 0xFF,
+    expected_ids: &[0x15],
+    config_registers: &[0x08, 0x09, 0x0A, 0x0C, 0x0D],
+};
+
+/// A change in the state of the monitored device.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PresenceEvent {
+    /// The device stopped answering, or the wrong device answers at its address.
+    Disconnected,
+    /// The device answers again. Its configuration must be re-applied.
+    Reconnected,
+    /// The device never went away but lost its configuration, e.g. after a brown-out.
+    Reset,
+}
+
+/// Periodically verifies the identity and configuration of a device.
+#[derive(Debug, Clone)]
+pub struct PresenceMonitor {
+    identity: DeviceIdentity,
+    snapshot: [u8; MAX_CONFIG_REGISTERS],
+    configured: bool,
+    connected: bool,
+}
+
+impl PresenceMonitor {
+    pub const fn new(identity: DeviceIdentity) -> Self {
+        Self {
+            identity,
+            snapshot: [0; MAX_CONFIG_REGISTERS],
+            configured: false,
+            connected: false,
+        }
+    }
+
+    pub fn identity(&self) -> &DeviceIdentity {
+        &self.identity
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected
+    }
+
+    /// Records the configuration to compare against. Call this after every
+    /// (re-)initialisation of the driver.
+    pub fn capture<I: I2c>(&mut self, i2c: &mut I) -> Result<(), I::Error> {
+        self.snapshot = self.read_config(i2c)?;
+        self.configured = true;
+        self.connected = true;
+        Ok(())
+    }
+
+    /// Checks the device, returning an event only when its state changed.
+    ///
+    /// After [`PresenceEvent::Reconnected`] or [`PresenceEvent::Reset`] the caller is
+    /// expected to re-initialise the driver and call [`PresenceMonitor::capture`];
+    /// until then, `Reset` is reported again on every check.
+    pub fn check<I: I2c>(&mut self, i2c: &mut I) -> Option<PresenceEvent> {
+        let identified = self.read_id(i2c).is_ok_and(|id| self.identity.expected_ids.contains(&id));
+
+        if !identified {
+            let was_connected = core::mem::replace(&mut self.connected, false);
+            self.configured = false;
+            return was_connected.then_some(PresenceEvent::Disconnected);
+        }
+
+        if !self.connected {
+            self.connected = true;
+            return Some(PresenceEvent::Reconnected);
+        }
+
+        match self.read_config(i2c) {
+            Ok(config) if self.configured && config != self.snapshot => {
+                self.configured = false;
+                Some(PresenceEvent::Reset)
+            },
+            Ok(_) if !self.configured => Some(PresenceEvent::Reset),
+            Ok(_) => None,
+            Err(_) => {
+                self.connected = false;
+                self.configured = false;
+                Some(PresenceEvent::Disconnected)
+            }
+        }
+    }
+
+    fn read_id<I: I2c>(&self, i2c: &mut I) -> Result<u8, I::Error> {
+        let mut id = [0u8];
+        i2c.write_read(self.identity.address, &[self.identity.id_register], &mut id)?;
+        Ok(id[0])
+    }
+
+    fn read_config<I: I2c>(&self, i2c: &mut I) -> Result<[u8; MAX_CONFIG_REGISTERS], I::Error> {
+        let mut config = [0u8; MAX_CONFIG_REGISTERS];
+        for (value, &register) in config.iter_mut().zip(self.identity.config_registers) {
+            let mut byte = [0u8];
+            i2c.write_read(self.identity.address, &[register], &mut byte)?;
+            *value = byte[0];
+        }
+        Ok(config)
+    }
+}
@@ -0,0 +1,149 @@
+//! Orientation as a unit quaternion.
+//!
+//! A [`Quaternion`] here rotates vectors from the sensor (body) frame into the world
+//! frame, which has Z pointing up and X pointing to magnetic north. [`EulerAngles`]
+//! decompose it as yaw about Z, then pitch about Y, then roll about X, the same
+//! convention as [`Tilt`](crate::tilt::Tilt).
+
+use core::ops::{Add, Mul, Sub};
+
+use libm::{asinf, atan2f, cosf, fabsf, sinf, sqrtf};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quaternion {
+    pub w: f32,
+    pub x: f32,
+    pub y: f32,
+    pub z: f32,
+}
+
+/// Orientation in degrees. Yaw is counter-clockwise from north seen from above.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct EulerAngles {
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quaternion {
+    /// Sensor frame aligned with the world frame: flat, X pointing north.
+    pub const IDENTITY: Self = Self::new(1.0, 0.0, 0.0, 0.0);
+
+    pub const fn new(w: f32, x: f32, y: f32, z: f32) -> Self {
+        Self { w, x, y, z }
+    }
+
+    pub fn from_euler(angles: EulerAngles) -> Self {
+        let (sr, cr) = (sinf(angles.roll.to_radians() / 2.0), cosf(angles.roll.to_radians() / 2.0));
+        let (sp, cp) = (sinf(angles.pitch.to_radians() / 2.0), cosf(angles.pitch.to_radians() / 2.0));
+        let (sy, cy) = (sinf(angles.yaw.to_radians() / 2.0), cosf(angles.yaw.to_radians() / 2.0));
+        Self::new(
+            cr * cp * cy + sr * sp * sy,
+            sr * cp * cy - cr * sp * sy,
+            cr * sp * cy + sr * cp * sy,
+            cr * cp * sy - sr * sp * cy,
+        )
+    }
+
+    /// Rotation by `|v|` radians about the axis `v`.
+    pub fn from_rotation_vector(v: [f32; 3]) -> Self {
+        let angle = sqrtf(v[0] * v[0] + v[1] * v[1] + v[2] * v[2]);
+        if angle < 1e-9 {
+            // First order, avoids dividing by a vanishing angle
+            return Self::new(1.0, v[0] / 2.0, v[1] / 2.0, v[2] / 2.0).normalize();
+        }
+        let s = sinf(angle / 2.0) / angle;
+        Self::new(cosf(angle / 2.0), v[0] * s, v[1] * s, v[2] * s)
+    }
+
+    pub fn to_euler(self) -> EulerAngles {
+        let Self { w, x, y, z } = self;
+        EulerAngles {
+            roll: atan2f(2.0 * (w * x + y * z), 1.0 - 2.0 * (x * x + y * y)).to_degrees(),
+            pitch: asinf((2.0 * (w * y - z * x)).clamp(-1.0, 1.0)).to_degrees(),
+            yaw: atan2f(2.0 * (w * z + x * y), 1.0 - 2.0 * (y * y + z * z)).to_degrees(),
+        }
+    }
+
+    pub fn conjugate(self) -> Self {
+        Self::new(self.w, -self.x, -self.y, -self.z)
+    }
+
+    pub fn norm(self) -> f32 {
+        sqrtf(self.dot(self))
+    }
+
+    /// Scaled back to unit length, the identity if it has collapsed to zero.
+    pub fn normalize(self) -> Self {
+        let norm = self.norm();
+        if norm > 0.0 {
+            self * (1.0 / norm)
+        } else {
+            Self::IDENTITY
+        }
+    }
+
+    pub fn dot(self, other: Self) -> f32 {
+        self.w * other.w + self.x * other.x + self.y * other.y + self.z * other.z
+    }
+
+    /// Angle of the rotation from `self` to `other`, in degrees.
+    pub fn angle_to(self, other: Self) -> f32 {
+        // atan2 of the difference rotation stays accurate near zero, where acos of the
+        // dot product loses most of its precision in f32
+        let d = self.conjugate() * other;
+        (2.0 * atan2f(sqrtf(d.x * d.x + d.y * d.y + d.z * d.z), fabsf(d.w))).to_degrees()
+    }
+
+    /// Rotates a sensor frame vector into the world frame.
+    pub fn rotate(self, v: [f32; 3]) -> [f32; 3] {
+        let rotated = self * Self::new(0.0, v[0], v[1], v[2]) * self.conjugate();
+        [rotated.x, rotated.y, rotated.z]
+    }
+
+    /// Rotates a world frame vector into the sensor frame.
+    pub fn rotate_inverse(self, v: [f32; 3]) -> [f32; 3] {
+        self.conjugate().rotate(v)
+    }
+}
+
+/// Hamilton product, `a * b` applies `b` first.
+impl Mul for Quaternion {
+    type Output = Self;
+    fn mul(self, b: Self) -> Self {
+        let a = self;
+        Self::new(
+            a.w * b.w - a.x * b.x - a.y * b.y - a.z * b.z,
+            a.w * b.x + a.x * b.w + a.y * b.z - a.z * b.y,
+            a.w * b.y - a.x * b.z + a.y * b.w + a.z * b.x,
+            a.w * b.z + a.x * b.y - a.y * b.x + a.z * b.w,
+        )
+    }
+}
+
+impl Mul<f32> for Quaternion {
+    type Output = Self;
+    fn mul(self, s: f32) -> Self {
+        Self::new(self.w * s, self.x * s, self.y * s, self.z * s)
+    }
+}
+
+impl Add for Quaternion {
+    type Output = Self;
+    fn add(self, b: Self) -> Self {
+        Self::new(self.w + b.w, self.x + b.x, self.y + b.y, self.z + b.z)
+    }
+}
+
+impl Sub for Quaternion {
+    type Output = Self;
+    fn sub(self, b: Self) -> Self {
+        Self::new(self.w - b.w, self.x - b.x, self.y - b.y, self.z - b.z)
+    }
+}
@@ -0,0 +1,119 @@
+//! Averages IMU readings into periodic summaries and tracks the timing jitter of the
+//! loop that takes them.
+//!
+//! The async examples sample on a ticker and log one summary per so many readings.
+//! [`SampleAverager`] is the part of that pipeline that does not depend on the executor.
+
+use core::fmt;
+
+use crate::imu::ImuSample;
+use crate::units::{Acceleration, AngularRate, Temperature, Vector3};
+
+/// Means over the readings since the last summary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub samples: u32,
+    pub temperature: Temperature,
+    pub acceleration: Vector3<Acceleration>,
+    /// Mean of the magnitude of each reading, which unlike the magnitude of the mean
+    /// acceleration also grows with vibration.
+    pub acceleration_magnitude: Acceleration,
+    pub angular_velocity: Vector3<AngularRate>,
+    /// Intended time between two readings.
+    pub period_us: u64,
+    /// Largest deviation of the time between two readings from `period_us`.
+    pub max_jitter_us: u64,
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [ax, ay, az] = self.acceleration.as_g();
+        let [gx, gy, gz] = self.angular_velocity.as_dps();
+        writeln!(f, "Temperature : {:.2} C", self.temperature.as_celsius())?;
+        writeln!(
+            f,
+            "Acceleration [X, Y, Z] : [{:.3}, {:.3}, {:.3}] g (|a| = {:.3} g)",
+            ax,
+            ay,
+            az,
+            self.acceleration_magnitude.as_g()
+        )?;
+        writeln!(f, "Angular Velocity [X, Y, Z] : [{:.3}, {:.3}, {:.3}] dps", gx, gy, gz)?;
+        write!(
+            f,
+            "Timing : {} samples every {} us, max jitter {} us",
+            self.samples, self.period_us, self.max_jitter_us
+        )
+    }
+}
+
+/// Accumulates readings and turns every so many of them into a [`Summary`].
+#[derive(Debug, Clone)]
+pub struct SampleAverager {
+    period_us: u64,
+    decimation: u32,
+    last_timestamp_us: Option<u64>,
+    count: u32,
+    temperature: f32,
+    acceleration: [f32; 3],
+    magnitude: f32,
+    angular_velocity: [f32; 3],
+    max_jitter_us: u64,
+}
+
+impl SampleAverager {
+    /// Summarises every `decimation` readings, taken every `period_us`.
+    pub fn new(period_us: u64, decimation: u32) -> Self {
+        Self {
+            period_us,
+            decimation: decimation.max(1),
+            last_timestamp_us: None,
+            count: 0,
+            temperature: 0.0,
+            acceleration: [0.0; 3],
+            magnitude: 0.0,
+            angular_velocity: [0.0; 3],
+            max_jitter_us: 0,
+        }
+    }
+
+    /// Adds a reading, returning the summary when it completes one.
+    pub fn update(&mut self, sample: &ImuSample) -> Option<Summary> {
+        // The interval is measured across summaries too, only the very first reading
+        // has none
+        if let Some(last) = self.last_timestamp_us {
+            let interval_us = sample.timestamp_us.saturating_sub(last);
+            self.max_jitter_us = self.max_jitter_us.max(interval_us.abs_diff(self.period_us));
+        }
+        self.last_timestamp_us = Some(sample.timestamp_us);
+
+        self.temperature += sample.temperature.as_celsius();
+        self.magnitude += sample.acceleration.norm().as_g();
+        for (sum, a) in self.acceleration.iter_mut().zip(sample.acceleration.as_g()) {
+            *sum += a;
+        }
+        for (sum, w) in self.angular_velocity.iter_mut().zip(sample.angular_velocity.as_dps()) {
+            *sum += w;
+        }
+        self.count += 1;
+        if self.count < self.decimation {
+            return None;
+        }
+
+        let n = self.count as f32;
+        let summary = Summary {
+            samples: self.count,
+            temperature: Temperature::from_celsius(self.temperature / n),
+            acceleration: Vector3::from_g(self.acceleration.map(|a| a / n)),
+            acceleration_magnitude: Acceleration::from_g(self.magnitude / n),
+            angular_velocity: Vector3::from_dps(self.angular_velocity.map(|w| w / n)),
+            period_us: self.period_us,
+            max_jitter_us: self.max_jitter_us,
+        };
+        *self = Self {
+            last_timestamp_us: self.last_timestamp_us,
+            ..Self::new(self.period_us, self.decimation)
+        };
+        Some(summary)
+    }
+}
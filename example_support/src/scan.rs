@@ -0,0 +1,208 @@
+//! I2C bus scanning and sensor identification.
+//!
+//! Every 7-bit address outside the reserved ranges is probed with a one byte read.
+//! Devices that answer are identified by reading the identity register that the
+//! known sensors keep at their usual addresses. A scan only reads, except for
+//! [`BusInventory::scan_with_bypass`], which reconfigures any MPU9250 it finds.
+
+use core::fmt;
+
+use embedded_hal::i2c::I2c;
+
+/// First and last address that is not reserved by the I2C specification.
+pub const FIRST_ADDRESS: u8 = 0x08;
+pub const LAST_ADDRESS: u8 = 0x77;
+
+/// Maximum number of devices kept in a [`BusInventory`].
+pub const MAX_DEVICES: usize = 16;
+
+/// INT_PIN_CFG register of the MPU9250 and its BYPASS_EN bit.
+const MPU_INT_PIN_CFG: u8 = 0x37;
+const MPU_BYPASS_EN: u8 = 0x02;
+/// USER_CTRL register of the MPU9250 and its I2C_MST_EN bit.
+const MPU_USER_CTRL: u8 = 0x6A;
+const MPU_I2C_MST_EN: u8 = 0x20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SensorKind {
+    Mpu6050,
+    /// MPU9250, or the otherwise identical MPU9255.
+    Mpu9250,
+    /// The magnetometer inside the MPU9250, only visible once I2C bypass is enabled.
+    Ak8963,
+    Max30102,
+    /// Something answered but did not identify as any known sensor.
+    Unknown,
+}
+
+impl fmt::Display for SensorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            SensorKind::Mpu6050 => "MPU6050",
+            SensorKind::Mpu9250 => "MPU9250",
+            SensorKind::Ak8963 => "AK8963",
+            SensorKind::Max30102 => "MAX30102",
+            SensorKind::Unknown => "unknown device",
+        })
+    }
+}
+
+struct Signature {
+    kind: SensorKind,
+    addresses: &'static [u8],
+    id_register: u8,
+    ids: &'static [u8],
+}
+
+const SIGNATURES: &[Signature] = &[
+    Signature { kind: SensorKind::Mpu6050, addresses: &[0x68, 0x69], id_register: 0x75, ids: &[0x68] },
+    Signature { kind: SensorKind::Mpu9250, addresses: &[0x68, 0x69], id_register: 0x75, ids: &[0x71, 0x73] },
+    Signature { kind: SensorKind::Ak8963, addresses: &[0x0C, 0x0D, 0x0E, 0x0F], id_register: 0x00, ids: &[0x48] },
+    Signature { kind: SensorKind::Max30102, addresses: &[0x57], id_register: 0xFF, ids: &[0x15] },
+];
+
+/// A device that answered during a scan.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detected {
+    pub address: u8,
+    pub kind: SensorKind,
+    /// Content of the identity register, if the address has a known signature.
+    pub id: Option<u8>,
+}
+
+impl fmt::Display for Detected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0x{:02X} {}", self.address, self.kind)?;
+        if let Some(id) = self.id {
+            write!(f, " (id 0x{:02X})", id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Returns `true` if a device acknowledges `address`.
+pub fn probe<I: I2c>(i2c: &mut I, address: u8) -> bool {
+    let mut byte = [0u8];
+    i2c.read(address, &mut byte).is_ok()
+}
+
+/// Identifies the device at `address`, assuming it acknowledged a probe.
+pub fn identify<I: I2c>(i2c: &mut I, address: u8) -> Detected {
+    let mut last_id = None;
+    for signature in SIGNATURES.iter().filter(|s| s.addresses.contains(&address)) {
+        let mut id = [0u8];
+        if i2c.write_read(address, &[signature.id_register], &mut id).is_err() {
+            continue;
+        }
+        if signature.ids.contains(&id[0]) {
+            return Detected { address, kind: signature.kind, id: Some(id[0]) };
+        }
+        last_id = Some(id[0]);
+    }
+    Detected { address, kind: SensorKind::Unknown, id: last_id }
+}
+
+/// Enables I2C bypass on an MPU9250 so that its AK8963 appears on the main bus.
+///
+/// The MPU9250's own I2C master is switched off first, as it would otherwise keep
+/// driving the auxiliary bus the bypass connects to.
+pub fn enable_mpu9250_bypass<I: I2c>(i2c: &mut I, mpu_address: u8) -> Result<(), I::Error> {
+    let mut user_ctrl = [0u8];
+    i2c.write_read(mpu_address, &[MPU_USER_CTRL], &mut user_ctrl)?;
+    if user_ctrl[0] & MPU_I2C_MST_EN != 0 {
+        i2c.write(mpu_address, &[MPU_USER_CTRL, user_ctrl[0] & !MPU_I2C_MST_EN])?;
+    }
+
+    let mut int_pin_cfg = [0u8];
+    i2c.write_read(mpu_address, &[MPU_INT_PIN_CFG], &mut int_pin_cfg)?;
+    i2c.write(mpu_address, &[MPU_INT_PIN_CFG, int_pin_cfg[0] | MPU_BYPASS_EN])
+}
+
+/// The devices found on a bus, in address order.
+#[derive(Debug, Clone)]
+pub struct BusInventory {
+    devices: [Option<Detected>; MAX_DEVICES],
+    len: usize,
+    overflowed: bool,
+}
+
+impl BusInventory {
+    /// Probes every address and identifies the devices that answer, without writing to
+    /// any of them.
+    pub fn scan<I: I2c>(i2c: &mut I) -> Self {
+        Self::scan_devices(i2c, false)
+    }
+
+    /// Like [`BusInventory::scan`], but also lists the magnetometer of an MPU9250.
+    ///
+    /// This changes the configuration of every MPU9250 found: its I2C master is
+    /// switched off and bypass is enabled ([`enable_mpu9250_bypass`]), and both stay
+    /// that way after the scan.
+    pub fn scan_with_bypass<I: I2c>(i2c: &mut I) -> Self {
+        Self::scan_devices(i2c, true)
+    }
+
+    fn scan_devices<I: I2c>(i2c: &mut I, bypass: bool) -> Self {
+        let mut inventory = Self {
+            devices: [None; MAX_DEVICES],
+            len: 0,
+            overflowed: false,
+        };
+
+        for address in FIRST_ADDRESS..=LAST_ADDRESS {
+            if !probe(i2c, address) {
+                continue;
+            }
+            let detected = identify(i2c, address);
+            inventory.push(detected);
+
+            if bypass && detected.kind == SensorKind::Mpu9250 && enable_mpu9250_bypass(i2c, address).is_ok() {
+                // The magnetometer sits below the MPU addresses, go back for it
+                for ak_address in 0x0C..=0x0F {
+                    if !inventory.contains(ak_address) && probe(i2c, ak_address) {
+                        inventory.push(identify(i2c, ak_address));
+                    }
+                }
+            }
+        }
+
+        inventory.devices[..inventory.len].sort_unstable_by_key(|d| d.map(|d| d.address));
+        inventory
+    }
+
+    fn push(&mut self, detected: Detected) {
+        match self.devices.get_mut(self.len) {
+            Some(slot) => {
+                *slot = Some(detected);
+                self.len += 1;
+            },
+            None => self.overflowed = true,
+        }
+    }
+
+    fn contains(&self, address: u8) -> bool {
+        self.iter().any(|d| d.address == address)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Detected> {
+        self.devices[..self.len].iter().flatten()
+    }
+
+    /// Address of the first device of the given kind.
+    pub fn find(&self, kind: SensorKind) -> Option<u8> {
+        self.iter().find(|d| d.kind == kind).map(|d| d.address)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// `true` if more than [`MAX_DEVICES`] devices answered and some were left out.
+    pub fn overflowed(&self) -> bool {
+        self.overflowed
+    }
+}
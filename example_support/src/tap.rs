@@ -0,0 +1,239 @@
+//! Tap and double-tap detection on the accelerometer.
+//!
+//! This is what accelerometers like the ADXL345 or LIS3DH do in hardware, for IMUs
+//! that do not. A tap is a short, sharp shock: the acceleration on some axis, with
+//! the slowly changing part (gravity, turning the device) removed, jumps above the
+//! threshold and falls back below it within [`TapConfig::max_duration_ms`]. It must
+//! then stay quiet for [`TapConfig::quiet_ms`], which rejects vibration and knocks
+//! that ring on. A second tap that starts after [`TapConfig::latency_ms`] and within
+//! [`TapConfig::window_ms`] after that makes a double tap; one that comes sooner is
+//! taken as a bounce and discards both.
+//!
+//! The shock lasts only milliseconds, so sample at 200 Hz or more if possible; at
+//! 100 Hz a tap is one or two samples long, and weak taps are missed.
+
+use libm::fabsf;
+
+use crate::imu::{ImuSample, SampleInterval};
+use crate::units::Acceleration;
+
+/// Longest gap between samples that is bridged. After a longer one the detector
+/// starts over.
+const MAX_DT_S: f32 = 0.5;
+/// Time constant of the baseline that is subtracted from each axis.
+const BASELINE_S: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TapConfig {
+    /// Shock an axis must exceed, gravity and slow motion removed.
+    pub threshold: Acceleration,
+    /// Longest time above the threshold. Anything longer is a push, not a tap.
+    pub max_duration_ms: u32,
+    /// Time after a tap during which nothing may exceed the threshold.
+    pub quiet_ms: u32,
+    /// Time after the first tap before a second one may start.
+    pub latency_ms: u32,
+    /// Time after the latency within which the second tap must start.
+    pub window_ms: u32,
+}
+
+impl Default for TapConfig {
+    fn default() -> Self {
+        Self {
+            threshold: Acceleration::from_g(1.0),
+            max_duration_ms: 50,
+            quiet_ms: 30,
+            latency_ms: 80,
+            window_ms: 300,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    X,
+    Y,
+    Z,
+}
+
+/// Axis of the largest shock and its sign. A tap on a face pushes the sensor away
+/// from it: tapping the top of a board lying flat reads negative Z.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapDirection {
+    pub axis: Axis,
+    pub positive: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TapKind {
+    Single,
+    Double,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TapEvent {
+    pub kind: TapKind,
+    /// Start of the (first) tap, in the samples' time base.
+    pub timestamp_us: u64,
+    /// Direction of the (first) tap.
+    pub direction: TapDirection,
+    /// Largest shock of the (first) tap.
+    pub strength: Acceleration,
+}
+
+/// A shock that may turn out to be a tap.
+#[derive(Debug, Clone, Copy)]
+struct Tap {
+    started_us: u64,
+    /// Signed shock on each axis at the peak, in g.
+    peak: [f32; 3],
+}
+
+impl Tap {
+    fn strength(&self) -> f32 {
+        self.peak.iter().fold(0.0, |max, &a| f32::max(max, fabsf(a)))
+    }
+
+    fn direction(&self) -> TapDirection {
+        let (index, value) = self
+            .peak
+            .iter()
+            .enumerate()
+            .fold((0, 0.0f32), |best, (i, &a)| if fabsf(a) > fabsf(best.1) { (i, a) } else { best });
+        let axis = [Axis::X, Axis::Y, Axis::Z][index];
+        TapDirection { axis, positive: value > 0.0 }
+    }
+
+    fn event(&self, kind: TapKind) -> TapEvent {
+        TapEvent {
+            kind,
+            timestamp_us: self.started_us,
+            direction: self.direction(),
+            strength: Acceleration::from_g(self.strength()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+enum State {
+    Idle,
+    /// Above the threshold, `first` is set if this is the second tap.
+    Shock { tap: Tap, first: Option<Tap> },
+    /// Back below the threshold since `since`, which it must stay for the quiet time.
+    Quiet { tap: Tap, first: Option<Tap>, since: u64 },
+    /// A tap is confirmed, waiting to see whether a second one follows.
+    Waiting { first: Tap, ended: u64 },
+    /// Something that was not a tap, waiting for it to calm down.
+    Settling { since: u64 },
+}
+
+#[derive(Debug, Clone)]
+pub struct TapDetector {
+    config: TapConfig,
+    interval: SampleInterval,
+    baseline: [f32; 3],
+    state: State,
+}
+
+impl Default for TapDetector {
+    fn default() -> Self {
+        Self::new(TapConfig::default())
+    }
+}
+
+impl TapDetector {
+    pub fn new(config: TapConfig) -> Self {
+        Self {
+            config,
+            interval: SampleInterval::default(),
+            baseline: [0.0; 3],
+            state: State::Idle,
+        }
+    }
+
+    pub fn config(&self) -> TapConfig {
+        self.config
+    }
+
+    /// Feeds one sample and returns a tap it completes. A single tap is reported once
+    /// the window for a second one has passed.
+    pub fn update(&mut self, sample: &ImuSample) -> Option<TapEvent> {
+        let acceleration = sample.acceleration.as_g();
+        let now = sample.timestamp_us;
+        let Some(dt) = self.interval.next(now, MAX_DT_S) else {
+            self.baseline = acceleration;
+            self.state = State::Idle;
+            return None;
+        };
+
+        let mut shock = [0.0; 3];
+        for ((shock, baseline), a) in shock.iter_mut().zip(self.baseline).zip(acceleration) {
+            *shock = a - baseline;
+        }
+        let above = shock.iter().any(|&s| fabsf(s) > self.config.threshold.as_g());
+        let config = self.config;
+        let ms = |since: u64| now.saturating_sub(since) / 1_000;
+
+        let (state, event) = match self.state {
+            State::Idle if above => (State::Shock { tap: Tap { started_us: now, peak: shock }, first: None }, None),
+            State::Idle => (State::Idle, None),
+            State::Shock { mut tap, first } => {
+                if ms(tap.started_us) > u64::from(config.max_duration_ms) {
+                    (State::Settling { since: now }, None)
+                } else if above {
+                    let strongest = shock.iter().fold(0.0, |max, &a| f32::max(max, fabsf(a)));
+                    if strongest > tap.strength() {
+                        tap.peak = shock;
+                    }
+                    (State::Shock { tap, first }, None)
+                } else {
+                    (State::Quiet { tap, first, since: now }, None)
+                }
+            },
+            State::Quiet { tap, first, since } => {
+                if above {
+                    (State::Settling { since: now }, None)
+                } else if ms(since) < u64::from(config.quiet_ms) {
+                    (State::Quiet { tap, first, since }, None)
+                } else {
+                    match first {
+                        Some(first) => (State::Idle, Some(first.event(TapKind::Double))),
+                        None => (State::Waiting { first: tap, ended: since }, None),
+                    }
+                }
+            },
+            State::Waiting { first, ended } => {
+                let elapsed = ms(ended);
+                if above && elapsed < u64::from(config.latency_ms) {
+                    // Too soon for a second tap, the first one bounced
+                    (State::Settling { since: now }, None)
+                } else if above {
+                    (State::Shock { tap: Tap { started_us: now, peak: shock }, first: Some(first) }, None)
+                } else if elapsed > u64::from(config.latency_ms + config.window_ms) {
+                    (State::Idle, Some(first.event(TapKind::Single)))
+                } else {
+                    (State::Waiting { first, ended }, None)
+                }
+            },
+            State::Settling { since } => {
+                if above {
+                    (State::Settling { since: now }, None)
+                } else if ms(since) >= u64::from(config.quiet_ms) {
+                    (State::Idle, None)
+                } else {
+                    (State::Settling { since }, None)
+                }
+            },
+        };
+        self.state = state;
+
+        // The baseline follows gravity and slow motion, but not the shocks themselves
+        if !above {
+            let weight = dt / (dt + BASELINE_S);
+            for (baseline, a) in self.baseline.iter_mut().zip(acceleration) {
+                *baseline += weight * (a - *baseline);
+            }
+        }
+        event
+    }
+}
@@ -0,0 +1,28 @@
+//! Roll and pitch, the orientation angles an accelerometer can observe.
+//!
+//! Roll is the rotation about X, in (-180, 180], and pitch the rotation about Y, in
+//! [-90, 90], in the ZYX convention of [`EulerAngles`](crate::quaternion::EulerAngles)
+//! without the yaw, which gravity does not reveal. A unit lying flat reads +1 g on Z
+//! and has zero roll and pitch.
+
+use libm::{atan2f, sqrtf};
+
+use crate::units::{Acceleration, Vector3};
+
+/// Tilt of a single unit, in degrees.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tilt {
+    pub roll: f32,
+    pub pitch: f32,
+}
+
+impl Tilt {
+    /// Tilt from an accelerometer reading taken at rest.
+    pub fn from_acceleration(acceleration: Vector3<Acceleration>) -> Self {
+        let [x, y, z] = acceleration.as_mps2();
+        Self {
+            roll: atan2f(y, z).to_degrees(),
+            pitch: atan2f(-x, sqrtf(y * y + z * z)).to_degrees(),
+        }
+    }
+}
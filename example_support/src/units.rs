@@ -0,0 +1,332 @@
+//! Typed physical quantities for sensor readings.
+//!
+//! The drivers return bare `f32`s whose unit is only a convention (g, °/s, °C). Each
+//! quantity here stores its value in one fixed unit and converts explicitly on the
+//! way in and out, so passing an angular rate where an acceleration is expected, or
+//! degrees where radians are expected, no longer compiles.
+
+use core::fmt;
+use core::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+use libm::sqrtf;
+
+/// Standard gravity in m/s².
+pub const STANDARD_GRAVITY: f32 = 9.806_65;
+
+/// A scalar quantity stored in its SI (or conventional base) unit.
+pub trait Quantity: Copy {
+    /// Wraps a value already in the base unit.
+    fn from_base(value: f32) -> Self;
+    /// The value in the base unit.
+    fn base(self) -> f32;
+}
+
+macro_rules! quantity {
+    ($(#[$doc:meta])* $name:ident, $unit:literal) => {
+        $(#[$doc])*
+        #[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+        pub struct $name(f32);
+
+        impl Quantity for $name {
+            fn from_base(value: f32) -> Self {
+                Self(value)
+            }
+
+            fn base(self) -> f32 {
+                self.0
+            }
+        }
+
+        impl $name {
+            pub const ZERO: Self = Self(0.0);
+
+            pub fn abs(self) -> Self {
+                Self(libm::fabsf(self.0))
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+            fn add(self, rhs: Self) -> Self {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl Sub for $name {
+            type Output = Self;
+            fn sub(self, rhs: Self) -> Self {
+                Self(self.0 - rhs.0)
+            }
+        }
+
+        impl SubAssign for $name {
+            fn sub_assign(&mut self, rhs: Self) {
+                self.0 -= rhs.0;
+            }
+        }
+
+        impl Neg for $name {
+            type Output = Self;
+            fn neg(self) -> Self {
+                Self(-self.0)
+            }
+        }
+
+        impl Mul<f32> for $name {
+            type Output = Self;
+            fn mul(self, rhs: f32) -> Self {
+                Self(self.0 * rhs)
+            }
+        }
+
+        impl Div<f32> for $name {
+            type Output = Self;
+            fn div(self, rhs: f32) -> Self {
+                Self(self.0 / rhs)
+            }
+        }
+
+        /// Ratio of two quantities of the same kind.
+        impl Div for $name {
+            type Output = f32;
+            fn div(self, rhs: Self) -> f32 {
+                self.0 / rhs.0
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                fmt::Display::fmt(&self.0, f)?;
+                f.write_str(concat!(" ", $unit))
+            }
+        }
+    };
+}
+
+quantity!(
+    /// Acceleration, stored in m/s².
+    Acceleration,
+    "m/s^2"
+);
+
+quantity!(
+    /// Angular rate, stored in rad/s.
+    AngularRate,
+    "rad/s"
+);
+
+quantity!(
+    /// Magnetic flux density, stored in µT.
+    MagneticField,
+    "uT"
+);
+
+impl Acceleration {
+    pub fn from_mps2(value: f32) -> Self {
+        Self(value)
+    }
+
+    pub fn from_g(value: f32) -> Self {
+        Self(value * STANDARD_GRAVITY)
+    }
+
+    pub fn as_mps2(self) -> f32 {
+        self.0
+    }
+
+    pub fn as_g(self) -> f32 {
+        self.0 / STANDARD_GRAVITY
+    }
+}
+
+impl AngularRate {
+    pub fn from_rad_per_s(value: f32) -> Self {
+        Self(value)
+    }
+
+    pub fn from_dps(value: f32) -> Self {
+        Self(value.to_radians())
+    }
+
+    pub fn as_rad_per_s(self) -> f32 {
+        self.0
+    }
+
+    pub fn as_dps(self) -> f32 {
+        self.0.to_degrees()
+    }
+}
+
+impl MagneticField {
+    pub fn from_microtesla(value: f32) -> Self {
+        Self(value)
+    }
+
+    pub fn from_gauss(value: f32) -> Self {
+        Self(value * 100.0)
+    }
+
+    pub fn as_microtesla(self) -> f32 {
+        self.0
+    }
+
+    pub fn as_gauss(self) -> f32 {
+        self.0 / 100.0
+    }
+}
+
+/// An absolute temperature, stored in °C.
+///
+/// Unlike the other quantities it has no arithmetic: adding two temperatures is
+/// meaningless, and their difference is a different kind of quantity.
+#[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
+pub struct Temperature(f32);
+
+impl Temperature {
+    pub fn from_celsius(value: f32) -> Self {
+        Self(value)
+    }
+
+    pub fn from_kelvin(value: f32) -> Self {
+        Self(value - 273.15)
+    }
+
+    pub fn as_celsius(self) -> f32 {
+        self.0
+    }
+
+    pub fn as_kelvin(self) -> f32 {
+        self.0 + 273.15
+    }
+}
+
+impl fmt::Display for Temperature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(&self.0, f)?;
+        f.write_str(" C")
+    }
+}
+
+/// A three-axis reading of one quantity, in sensor axes.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Vector3<Q> {
+    pub x: Q,
+    pub y: Q,
+    pub z: Q,
+}
+
+impl<Q: Quantity> Vector3<Q> {
+    pub const fn new(x: Q, y: Q, z: Q) -> Self {
+        Self { x, y, z }
+    }
+
+    pub fn map<R>(self, mut f: impl FnMut(Q) -> R) -> Vector3<R> {
+        Vector3 {
+            x: f(self.x),
+            y: f(self.y),
+            z: f(self.z),
+        }
+    }
+
+    pub fn to_array(self) -> [Q; 3] {
+        [self.x, self.y, self.z]
+    }
+
+    /// Length of the vector.
+    pub fn norm(self) -> Q {
+        let [x, y, z] = self.to_array().map(Q::base);
+        Q::from_base(sqrtf(x * x + y * y + z * z))
+    }
+
+    /// Unit vector pointing the same way (dimensionless), `None` for a zero vector.
+    pub fn direction(self) -> Option<[f32; 3]> {
+        let norm = self.norm().base();
+        (norm > 0.0).then(|| self.to_array().map(|q| q.base() / norm))
+    }
+}
+
+impl<Q: Quantity> From<[Q; 3]> for Vector3<Q> {
+    fn from([x, y, z]: [Q; 3]) -> Self {
+        Self { x, y, z }
+    }
+}
+
+impl<Q: Quantity + Add<Output = Q>> Add for Vector3<Q> {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.x + rhs.x, self.y + rhs.y, self.z + rhs.z)
+    }
+}
+
+impl<Q: Quantity + Sub<Output = Q>> Sub for Vector3<Q> {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.x - rhs.x, self.y - rhs.y, self.z - rhs.z)
+    }
+}
+
+impl<Q: Quantity + Mul<f32, Output = Q>> Mul<f32> for Vector3<Q> {
+    type Output = Self;
+    fn mul(self, rhs: f32) -> Self {
+        Self::new(self.x * rhs, self.y * rhs, self.z * rhs)
+    }
+}
+
+impl<Q: Quantity + Div<f32, Output = Q>> Div<f32> for Vector3<Q> {
+    type Output = Self;
+    fn div(self, rhs: f32) -> Self {
+        Self::new(self.x / rhs, self.y / rhs, self.z / rhs)
+    }
+}
+
+impl Vector3<Acceleration> {
+    pub fn from_g(values: [f32; 3]) -> Self {
+        values.map(Acceleration::from_g).into()
+    }
+
+    pub fn from_mps2(values: [f32; 3]) -> Self {
+        values.map(Acceleration::from_mps2).into()
+    }
+
+    pub fn as_g(self) -> [f32; 3] {
+        self.to_array().map(Acceleration::as_g)
+    }
+
+    pub fn as_mps2(self) -> [f32; 3] {
+        self.to_array().map(Acceleration::as_mps2)
+    }
+}
+
+impl Vector3<AngularRate> {
+    pub fn from_dps(values: [f32; 3]) -> Self {
+        values.map(AngularRate::from_dps).into()
+    }
+
+    pub fn from_rad_per_s(values: [f32; 3]) -> Self {
+        values.map(AngularRate::from_rad_per_s).into()
+    }
+
+    pub fn as_dps(self) -> [f32; 3] {
+        self.to_array().map(AngularRate::as_dps)
+    }
+
+    pub fn as_rad_per_s(self) -> [f32; 3] {
+        self.to_array().map(AngularRate::as_rad_per_s)
+    }
+}
+
+impl Vector3<MagneticField> {
+    pub fn from_microtesla(values: [f32; 3]) -> Self {
+        values.map(MagneticField::from_microtesla).into()
+    }
+
+    pub fn as_microtesla(self) -> [f32; 3] {
+        self.to_array().map(MagneticField::as_microtesla)
+    }
+}
@@ -0,0 +1,384 @@
+//! Vibration analysis of machinery from blocks of accelerometer readings.
+//!
+//! [`VibrationAnalyzer`] collects `N` readings, a power of two, and transforms each axis
+//! with a windowed [`real_fft`]. From the [`Spectrum`] it reports, per axis, the RMS
+//! acceleration and velocity over the band of interest, the strongest peaks and the
+//! RMS in a few configurable bands. The readings have to be evenly spaced, which is
+//! what [`crate::accel_fifo`] is for.
+//!
+//! With a [`MachineClass`] set, the velocity RMS is also placed in one of the zones of
+//! ISO 10816-1, the classic broadband severity chart: A for new machines, B for
+//! unrestricted long-term operation, C for a machine that should be looked at soon,
+//! D for vibration that can cause damage. The limits there apply to measurements on
+//! the bearing housings between 10 and 1000 Hz, with a proper transducer; an MPU6050
+//! glued to a motor gives a fair idea, not a certified reading.
+
+use core::f32::consts::PI;
+
+use libm::{ceilf, logf, sqrtf};
+
+use crate::fft::{bin_power, real_fft, Window};
+use crate::units::{Acceleration, Vector3};
+
+/// Peaks reported per axis.
+pub const MAX_PEAKS: usize = 5;
+/// Bands reported per axis, further bands in the configuration are ignored.
+pub const MAX_BANDS: usize = 8;
+
+/// Standard gravity in mm/s², to turn g into a velocity.
+const G_MM_PER_S2: f32 = 9_806.65;
+
+/// A frequency range, `low_hz` included and `high_hz` excluded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    pub low_hz: f32,
+    pub high_hz: f32,
+}
+
+impl Band {
+    pub const fn new(low_hz: f32, high_hz: f32) -> Self {
+        Self { low_hz, high_hz }
+    }
+}
+
+/// Bands of the default configuration, roughly unbalance and misalignment, looseness,
+/// and bearings and gears at the speeds of small motors.
+pub const DEFAULT_BANDS: &[Band] = &[
+    Band::new(10.0, 50.0),
+    Band::new(50.0, 100.0),
+    Band::new(100.0, 200.0),
+    Band::new(200.0, 500.0),
+];
+
+/// Machine classes of ISO 10816-1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MachineClass {
+    /// Small machines, such as motors up to 15 kW.
+    I,
+    /// Medium machines, 15 to 75 kW, or up to 300 kW on special foundations.
+    II,
+    /// Large machines on rigid, heavy foundations.
+    III,
+    /// Large machines on soft foundations.
+    IV,
+}
+
+impl MachineClass {
+    /// Velocity RMS in mm/s at the A/B, B/C and C/D boundaries.
+    pub fn limits(self) -> [f32; 3] {
+        match self {
+            MachineClass::I => [0.71, 1.8, 4.5],
+            MachineClass::II => [1.12, 2.8, 7.1],
+            MachineClass::III => [1.8, 4.5, 11.2],
+            MachineClass::IV => [2.8, 7.1, 18.0],
+        }
+    }
+
+    pub fn zone(self, velocity_rms_mm_s: f32) -> Zone {
+        match self.limits().iter().filter(|&&limit| velocity_rms_mm_s >= limit).count() {
+            0 => Zone::A,
+            1 => Zone::B,
+            2 => Zone::C,
+            _ => Zone::D,
+        }
+    }
+}
+
+/// Evaluation zones of ISO 10816-1, from good to bad.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Zone {
+    /// Typical of newly commissioned machines.
+    A,
+    /// Acceptable for unrestricted long-term operation.
+    B,
+    /// Only acceptable for a limited time, until maintenance.
+    C,
+    /// Severe enough to cause damage.
+    D,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VibrationConfig {
+    /// Rate the readings were taken at.
+    pub sample_rate_hz: f32,
+    pub window: Window,
+    /// Band the RMS values and peaks are taken over. Below it are gravity, tilt and
+    /// drift; above the Nyquist frequency there is nothing, so keep the high end at or
+    /// below half of `sample_rate_hz`.
+    pub band: Band,
+    pub bands: &'static [Band],
+    /// Classify the velocity RMS by ISO 10816-1 for this kind of machine.
+    pub machine_class: Option<MachineClass>,
+}
+
+impl Default for VibrationConfig {
+    fn default() -> Self {
+        Self {
+            sample_rate_hz: 1_000.0,
+            window: Window::Hann,
+            // Up to the Nyquist frequency of the 1 kHz above
+            band: Band::new(10.0, 500.0),
+            bands: DEFAULT_BANDS,
+            machine_class: None,
+        }
+    }
+}
+
+/// A spectral peak.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Peak {
+    /// Interpolated between the bins, so finer than the spectrum's resolution.
+    pub frequency_hz: f32,
+    /// Amplitude of the sine making up the peak, half its peak-to-peak swing.
+    pub amplitude: Acceleration,
+}
+
+/// Power spectrum of one block of readings along one axis.
+#[derive(Debug, Clone)]
+pub struct Spectrum<const N: usize> {
+    data: [f32; N],
+    sample_rate_hz: f32,
+    window: Window,
+    /// Turns `|X[k]|²` into mean square in g².
+    power_scale: f32,
+}
+
+impl<const N: usize> Spectrum<N> {
+    /// Transforms `samples`, in g, after removing their mean and applying `window`.
+    pub fn new(samples: &[f32; N], sample_rate_hz: f32, window: Window) -> Self {
+        let mean = samples.iter().sum::<f32>() / N as f32;
+        let mut data = samples.map(|sample| sample - mean);
+        let (_, mean_square_weight) = window.apply(&mut data);
+        real_fft(&mut data);
+        Self {
+            data,
+            sample_rate_hz,
+            window,
+            power_scale: 1.0 / ((N * N) as f32 * mean_square_weight),
+        }
+    }
+
+    /// Number of bins, from 0 Hz to the Nyquist frequency.
+    pub fn bins(&self) -> usize {
+        N / 2 + 1
+    }
+
+    /// Spacing of the bins.
+    pub fn resolution_hz(&self) -> f32 {
+        self.sample_rate_hz / N as f32
+    }
+
+    pub fn frequency(&self, bin: usize) -> f32 {
+        bin as f32 * self.resolution_hz()
+    }
+
+    /// Mean square in bin `bin`, in g². Over all bins these add up to the variance of
+    /// the readings.
+    pub fn power(&self, bin: usize) -> f32 {
+        let one_sided = if bin == 0 || bin == N / 2 { 1.0 } else { 2.0 };
+        one_sided * bin_power(&self.data, bin) * self.power_scale
+    }
+
+    /// RMS acceleration within `band`.
+    pub fn rms(&self, band: Band) -> Acceleration {
+        Acceleration::from_g(sqrtf(self.bins_in(band).map(|bin| self.power(bin)).sum()))
+    }
+
+    /// RMS velocity within `band` in mm/s, the acceleration integrated bin by bin.
+    pub fn velocity_rms(&self, band: Band) -> f32 {
+        let mean_square = self
+            .bins_in(band)
+            .filter(|&bin| bin > 0)
+            .map(|bin| {
+                let per_g = G_MM_PER_S2 / (2.0 * PI * self.frequency(bin));
+                self.power(bin) * per_g * per_g
+            })
+            .sum::<f32>();
+        sqrtf(mean_square)
+    }
+
+    /// Fills `peaks` with the strongest peaks within `band`, strongest first, and
+    /// returns how many were found.
+    pub fn peaks(&self, band: Band, peaks: &mut [Peak]) -> usize {
+        let mut count = 0;
+        for bin in self.bins_in(band) {
+            let power = self.power(bin);
+            let is_peak = power > 0.0
+                && (bin == 0 || power > self.power(bin - 1))
+                && (bin + 1 == self.bins() || power >= self.power(bin + 1));
+            if !is_peak {
+                continue;
+            }
+            let peak = self.peak_at(bin);
+            let Some(position) = peaks[..count]
+                .iter()
+                .position(|other| peak.amplitude.as_g() > other.amplitude.as_g())
+                .or((count < peaks.len()).then_some(count))
+            else {
+                continue;
+            };
+            count = (count + 1).min(peaks.len());
+            peaks[position..count].rotate_right(1);
+            peaks[position] = peak;
+        }
+        count
+    }
+
+    fn peak_at(&self, bin: usize) -> Peak {
+        // Bins either side that hold most of the main lobe of the window
+        let width = match self.window {
+            Window::Rectangular => 1,
+            Window::Hann => 2,
+            Window::FlatTop => 4,
+        };
+        let lobe = bin.saturating_sub(width)..(bin + width + 1).min(self.bins());
+        // The power of the whole lobe gives the amplitude, wherever between two bins
+        // the frequency falls
+        let amplitude = sqrtf(2.0 * lobe.map(|bin| self.power(bin)).sum::<f32>());
+
+        // The top of a lobe is close to a parabola on a log scale
+        let mut offset = 0.0;
+        if bin > 0 && bin + 1 < self.bins() {
+            let [left, centre, right] = [bin - 1, bin, bin + 1].map(|bin| logf(self.power(bin).max(f32::MIN_POSITIVE)));
+            let curvature = left - 2.0 * centre + right;
+            if curvature < 0.0 {
+                offset = (0.5 * (left - right) / curvature).clamp(-0.5, 0.5);
+            }
+        }
+        Peak {
+            frequency_hz: (bin as f32 + offset) * self.resolution_hz(),
+            amplitude: Acceleration::from_g(amplitude),
+        }
+    }
+
+    fn bins_in(&self, band: Band) -> impl Iterator<Item = usize> {
+        let resolution = self.resolution_hz();
+        let low = ceilf(band.low_hz / resolution).max(0.0) as usize;
+        let high = ceilf(band.high_hz / resolution).max(0.0) as usize;
+        low..high.min(self.bins())
+    }
+}
+
+/// What a block says about the vibration along one axis.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AxisReport {
+    /// RMS acceleration within the configured band.
+    pub rms: Acceleration,
+    /// RMS velocity within the configured band, in mm/s.
+    pub velocity_rms_mm_s: f32,
+    /// Only with a [`MachineClass`] configured.
+    pub zone: Option<Zone>,
+    peaks: [Peak; MAX_PEAKS],
+    peak_count: usize,
+    bands: [Acceleration; MAX_BANDS],
+    band_count: usize,
+}
+
+impl AxisReport {
+    /// The strongest peaks, strongest first.
+    pub fn peaks(&self) -> &[Peak] {
+        &self.peaks[..self.peak_count]
+    }
+
+    /// RMS acceleration in each configured band, in order.
+    pub fn bands(&self) -> &[Acceleration] {
+        &self.bands[..self.band_count]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VibrationReport {
+    /// X, Y and Z.
+    pub axes: [AxisReport; 3],
+}
+
+impl VibrationReport {
+    /// The axis vibrating the most by velocity, which is what severity is judged by.
+    pub fn worst_axis(&self) -> usize {
+        (1..3).fold(0, |worst, axis| {
+            if self.axes[axis].velocity_rms_mm_s > self.axes[worst].velocity_rms_mm_s {
+                axis
+            } else {
+                worst
+            }
+        })
+    }
+}
+
+/// Collects blocks of `N` readings and analyses each one once it is full. The blocks
+/// do not overlap.
+#[derive(Debug, Clone)]
+pub struct VibrationAnalyzer<const N: usize> {
+    config: VibrationConfig,
+    block: [[f32; N]; 3],
+    filled: usize,
+}
+
+impl<const N: usize> Default for VibrationAnalyzer<N> {
+    fn default() -> Self {
+        Self::new(VibrationConfig::default())
+    }
+}
+
+impl<const N: usize> VibrationAnalyzer<N> {
+    pub fn new(config: VibrationConfig) -> Self {
+        Self {
+            config,
+            block: [[0.0; N]; 3],
+            filled: 0,
+        }
+    }
+
+    pub fn config(&self) -> VibrationConfig {
+        self.config
+    }
+
+    /// Throws away a partly collected block, after readings were lost.
+    pub fn reset(&mut self) {
+        self.filled = 0;
+    }
+
+    /// Adds one reading, returning the analysis when it completes a block.
+    pub fn update(&mut self, acceleration: Vector3<Acceleration>) -> Option<VibrationReport> {
+        for (axis, a) in self.block.iter_mut().zip(acceleration.as_g()) {
+            axis[self.filled] = a;
+        }
+        self.filled += 1;
+        if self.filled < N {
+            return None;
+        }
+        self.filled = 0;
+        Some(VibrationReport {
+            axes: self.block.each_ref().map(|samples| self.analyze(samples)),
+        })
+    }
+
+    /// Analyses one axis of a complete block.
+    pub fn analyze(&self, samples: &[f32; N]) -> AxisReport {
+        let config = &self.config;
+        let spectrum = Spectrum::new(samples, config.sample_rate_hz, config.window);
+        let velocity_rms_mm_s = spectrum.velocity_rms(config.band);
+
+        let mut peaks = [Peak {
+            frequency_hz: 0.0,
+            amplitude: Acceleration::from_g(0.0),
+        }; MAX_PEAKS];
+        let peak_count = spectrum.peaks(config.band, &mut peaks);
+
+        let mut bands = [Acceleration::from_g(0.0); MAX_BANDS];
+        let band_count = config.bands.len().min(MAX_BANDS);
+        for (rms, &band) in bands.iter_mut().zip(config.bands) {
+            *rms = spectrum.rms(band);
+        }
+
+        AxisReport {
+            rms: spectrum.rms(config.band),
+            velocity_rms_mm_s,
+            zone: config.machine_class.map(|class| class.zone(velocity_rms_mm_s)),
+            peaks,
+            peak_count,
+            bands,
+            band_count,
+        }
+    }
+}
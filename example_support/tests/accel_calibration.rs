@@ -0,0 +1,130 @@
+use example_support::accel_calibration::{fit, AccelCalibration, FitError, Position, SixPosition, Step};
+use example_support::calibration::Orientation;
+use example_support::imu::ImuSample;
+use example_support::units::{Temperature, Vector3};
+
+/// How the simulated accelerometer distorts the true acceleration: gains of 1.03, 0.97
+/// and 1.01, a few percent of cross-axis coupling, and a constant bias.
+const DISTORTION: [[f32; 3]; 3] = [[1.03, 0.02, -0.01], [0.015, 0.97, 0.02], [-0.02, 0.01, 1.01]];
+const BIAS: [f32; 3] = [0.05, -0.03, 0.08];
+
+/// What the distorted sensor reads for a true acceleration `g` (in g).
+fn raw(g: [f32; 3]) -> [f32; 3] {
+    let mut out = BIAS;
+    for (axis, row) in DISTORTION.iter().enumerate() {
+        out[axis] += row[0] * g[0] + row[1] * g[1] + row[2] * g[2];
+    }
+    out
+}
+
+fn assert_close(actual: [f32; 3], expected: [f32; 3], tolerance: f32) {
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < tolerance, "{actual:?} != {expected:?}");
+    }
+}
+
+fn six_positions() -> Vec<Position> {
+    Orientation::ALL
+        .iter()
+        .map(|orientation| Position {
+            measured: Vector3::from_g(raw(orientation.gravity().as_g())),
+            reference: orientation.gravity(),
+        })
+        .collect()
+}
+
+#[test]
+fn fit_recovers_bias_scale_and_misalignment() {
+    let calibration = fit(&six_positions()).unwrap();
+
+    assert_close(calibration.bias.as_g(), BIAS, 1e-4);
+    for orientation in Orientation::ALL {
+        let corrected = calibration.apply(Vector3::from_g(raw(orientation.gravity().as_g())));
+        assert_close(corrected.as_g(), orientation.gravity().as_g(), 1e-4);
+    }
+
+    // An orientation that was not part of the fit is corrected too
+    let tilted = [0.5, -0.5, core::f32::consts::FRAC_1_SQRT_2];
+    assert_close(calibration.apply(Vector3::from_g(raw(tilted))).as_g(), tilted, 1e-4);
+    // The gains are roughly the inverse of the distortion
+    assert_close(calibration.scale(), [1.0 / 1.03, 1.0 / 0.97, 1.0 / 1.01], 0.01);
+}
+
+#[test]
+fn fit_needs_positions_on_every_axis() {
+    assert_eq!(fit(&six_positions()[..3]), Err(FitError::TooFewPositions));
+
+    // X and Y up and down never excite Z
+    let flat: Vec<Position> = six_positions().into_iter().take(4).collect();
+    assert_eq!(fit(&flat), Err(FitError::Degenerate));
+}
+
+#[test]
+fn identity_leaves_readings_alone() {
+    let reading = Vector3::from_g([0.1, -0.2, 0.98]);
+    assert_eq!(AccelCalibration::default().apply(reading), reading);
+}
+
+fn sample(acceleration: [f32; 3], noise: f32) -> ImuSample {
+    ImuSample {
+        timestamp_us: 0,
+        temperature: Temperature::from_celsius(25.0),
+        acceleration: Vector3::from_g(acceleration.map(|a| a + noise)),
+        angular_velocity: Vector3::from_dps([0.3 + noise, -0.2, 0.1]),
+        magnetic_field: None,
+    }
+}
+
+#[test]
+fn guided_run_walks_through_all_orientations() {
+    const SAMPLES: u32 = 20;
+    let mut run = SixPosition::new(SAMPLES);
+    let mut result = None;
+
+    for (index, orientation) in Orientation::ALL.into_iter().enumerate() {
+        assert_eq!(run.orientation(), Some(orientation));
+
+        // Still held in the previous orientation: nothing is collected
+        if let Some(previous) = index.checked_sub(1).map(|i| Orientation::ALL[i]) {
+            let step = run.add(&sample(raw(previous.gravity().as_g()), 0.0));
+            assert_eq!(step, Step::Waiting { orientation });
+        }
+
+        for n in 0..SAMPLES {
+            let noise = if n % 2 == 0 { 0.002 } else { -0.002 };
+            match run.add(&sample(raw(orientation.gravity().as_g()), noise)) {
+                Step::Collecting { collected, .. } => assert_eq!(collected, n + 1),
+                Step::PositionDone { orientation: done, .. } => assert_eq!(done, orientation),
+                Step::Done(fitted) => result = Some(fitted),
+                step => panic!("unexpected {step:?}"),
+            }
+        }
+    }
+
+    let calibration = result.unwrap().unwrap();
+    assert_eq!(run.orientation(), None);
+    assert_close(calibration.bias.as_g(), BIAS, 1e-3);
+}
+
+#[test]
+fn guided_run_restarts_a_position_on_movement() {
+    let mut run = SixPosition::new(5);
+    let up = raw(Orientation::XUp.gravity().as_g());
+
+    run.add(&sample(up, 0.0));
+    run.add(&sample(up, 0.0));
+    assert_eq!(
+        run.add(&sample(up, 0.1)),
+        Step::Restarted {
+            orientation: Orientation::XUp
+        }
+    );
+    assert_eq!(
+        run.add(&sample(up, 0.0)),
+        Step::Collecting {
+            orientation: Orientation::XUp,
+            collected: 1,
+            required: 5
+        }
+    );
+}
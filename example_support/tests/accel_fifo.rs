@@ -0,0 +1,121 @@
+mod common;
+
+use core::cell::RefCell;
+
+use common::{SimBus, SimDevice};
+use embedded_hal_bus::i2c::RefCellDevice;
+use example_support::accel_fifo::{AccelFifo, AccelRange, Error, Model};
+use example_support::units::{Acceleration, Vector3};
+
+const MPU: u8 = 0x68;
+
+fn bus() -> RefCell<SimBus> {
+    RefCell::new(SimBus::new(vec![SimDevice::mpu_with_fifo(MPU, 0x68)]))
+}
+
+fn push(bus: &RefCell<SimBus>, counts: [i16; 3]) {
+    bus.borrow_mut().device(MPU).push_mpu_fifo_reading(counts);
+}
+
+fn empty<const N: usize>() -> [Vector3<Acceleration>; N] {
+    [Vector3::from_g([0.0; 3]); N]
+}
+
+#[test]
+fn configures_the_sensor_for_1_khz() {
+    let bus = bus();
+    AccelFifo::new(RefCellDevice::new(&bus), MPU, AccelRange::G8).unwrap();
+    let registers = bus.borrow_mut().device(MPU).registers;
+    // Awake, sample rate divider of one, 184 Hz low-pass, ±8 g, accelerometer into the FIFO
+    assert_eq!(registers[0x6B], 0x01);
+    assert_eq!(registers[0x19], 0x00);
+    assert_eq!(registers[0x1A], 0x01);
+    assert_eq!(registers[0x1C], 0x10);
+    assert_eq!(registers[0x23], 0x08);
+    assert_eq!(registers[0x6A], 0x40);
+    // ACCEL_CONFIG2 only exists on the MPU9250
+    assert_eq!(registers[0x1D], 0x00);
+}
+
+#[test]
+fn sets_the_mpu9250_accelerometer_filter_and_capacity() {
+    let bus = RefCell::new(SimBus::new(vec![SimDevice::mpu_with_fifo(MPU, 0x71)]));
+    let mut fifo = AccelFifo::new(RefCellDevice::new(&bus), MPU, AccelRange::G2).unwrap();
+    assert_eq!((fifo.model(), fifo.capacity()), (Model::Mpu9250, 85));
+    assert_eq!(bus.borrow_mut().device(MPU).registers[0x1D], 0x01);
+
+    // 512 bytes hold 85 readings
+    for _ in 0..85 {
+        push(&bus, [0, 0, 16384]);
+    }
+    assert_eq!(fifo.available().unwrap(), 85);
+    push(&bus, [0, 0, 16384]);
+    assert_eq!(fifo.read(&mut empty::<32>()), Err(Error::Overflow));
+}
+
+#[test]
+fn takes_an_mpu9255_for_an_mpu9250() {
+    let bus = RefCell::new(SimBus::new(vec![SimDevice::mpu_with_fifo(MPU, 0x73)]));
+    let fifo = AccelFifo::new(RefCellDevice::new(&bus), MPU, AccelRange::G2).unwrap();
+    assert_eq!(fifo.model(), Model::Mpu9250);
+}
+
+#[test]
+fn rejects_another_device() {
+    let bus = RefCell::new(SimBus::new(vec![SimDevice::mpu_with_fifo(MPU, 0x70)]));
+    let result = AccelFifo::new(RefCellDevice::new(&bus), MPU, AccelRange::G2);
+    assert_eq!(result.err(), Some(Error::WrongDevice(0x70)));
+}
+
+#[test]
+fn reads_queued_readings_in_order() {
+    let bus = bus();
+    // Whatever was queued before the start is thrown away
+    push(&bus, [1, 2, 3]);
+    let mut fifo = AccelFifo::new(RefCellDevice::new(&bus), MPU, AccelRange::G4).unwrap();
+    assert_eq!(fifo.available().unwrap(), 0);
+
+    for n in 0..40 {
+        push(&bus, [8192, -100 * n, 16384]);
+    }
+    assert_eq!(fifo.available().unwrap(), 40);
+
+    // More than one transfer's worth, in two reads
+    let mut readings = empty::<25>();
+    assert_eq!(fifo.read(&mut readings).unwrap(), 25);
+    let mut rest = empty::<25>();
+    assert_eq!(fifo.read(&mut rest).unwrap(), 15);
+    for (n, reading) in readings.iter().chain(&rest[..15]).enumerate() {
+        let [x, y, z] = reading.as_g();
+        let expected_y = -100.0 * n as f32 / 8192.0;
+        assert!((x - 1.0).abs() < 1e-6 && (y - expected_y).abs() < 1e-6 && (z - 2.0).abs() < 1e-6, "{n}: {reading:?}");
+    }
+    assert_eq!(fifo.read(&mut rest).unwrap(), 0);
+}
+
+#[test]
+fn reports_an_overflow_and_starts_over() {
+    let bus = bus();
+    let mut fifo = AccelFifo::new(RefCellDevice::new(&bus), MPU, AccelRange::G2).unwrap();
+    assert_eq!((fifo.model(), fifo.capacity()), (Model::Mpu6050, 170));
+    // 1024 bytes hold 170 readings
+    for _ in 0..171 {
+        push(&bus, [0, 0, 16384]);
+    }
+    let mut readings = empty::<32>();
+    assert_eq!(fifo.read(&mut readings), Err(Error::Overflow));
+    assert_eq!(fifo.available().unwrap(), 0);
+
+    push(&bus, [0, 0, 16384]);
+    assert_eq!(fifo.read(&mut readings).unwrap(), 1);
+    assert!((readings[0].as_g()[2] - 1.0).abs() < 1e-6);
+}
+
+#[test]
+fn release_stops_the_fifo() {
+    let bus = bus();
+    let fifo = AccelFifo::new(RefCellDevice::new(&bus), MPU, AccelRange::G2).unwrap();
+    fifo.release().unwrap();
+    let registers = bus.borrow_mut().device(MPU).registers;
+    assert_eq!((registers[0x23], registers[0x6A]), (0, 0));
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::activity::Trace;
+use example_support::activity::{Activity, DecisionTree, Feature, FeatureExtractor, Features, Node};
+
+fn features(trace: &Trace) -> Vec<Features> {
+    let mut extractor = FeatureExtractor::new();
+    trace.samples().iter().filter_map(|sample| extractor.update(sample)).collect()
+}
+
+fn classify(trace: &Trace) -> Vec<Activity> {
+    let tree = DecisionTree::default();
+    features(trace).iter().map(|features| tree.classify(features)).collect()
+}
+
+/// Share of the windows labelled `activity`.
+fn share(labels: &[Activity], activity: Activity) -> f64 {
+    labels.iter().filter(|&&label| label == activity).count() as f64 / labels.len() as f64
+}
+
+#[test]
+fn classifies_labelled_activities() {
+    type Session = fn(Trace, f64) -> Trace;
+    let sessions: [(Activity, Session); 5] = [
+        (Activity::Still, |trace, _| trace.still(60.0)),
+        (Activity::Walking, |trace, x| trace.walk(60.0, 95.0 + 25.0 * x, 0.2 + 0.2 * x)),
+        (Activity::Running, |trace, x| trace.run(60.0, 155.0 + 25.0 * x, 0.9 + x)),
+        (Activity::Cycling, |trace, x| trace.cycle(60.0, 60.0 + 30.0 * x, 0.07 + 0.1 * x)),
+        (Activity::Vehicle, |trace, x| trace.drive(60.0, 0.035 + 0.04 * x)),
+    ];
+    let mut correct = 0;
+    let mut total = 0;
+    for (activity, session) in sessions {
+        let mut labels = Vec::new();
+        for (seed, x) in [(1, 0.0), (2, 0.3), (3, 0.6), (4, 1.0)] {
+            for rate_hz in [50.0, 100.0] {
+                labels.extend(classify(&session(Trace::new(rate_hz).with_seed(seed).with_noise(0.01), x)));
+            }
+        }
+        let share = share(&labels, activity);
+        assert!(share > 0.8, "{activity:?}: {:.0}% of {labels:?}", share * 100.0);
+        correct += labels.iter().filter(|&&label| label == activity).count();
+        total += labels.len();
+    }
+    assert!(correct as f64 > 0.9 * total as f64, "{correct} of {total}");
+}
+
+#[test]
+fn works_however_the_sensor_is_worn() {
+    let trace = Trace::new(100.0).tilted(50.0, -70.0).walk(60.0, 110.0, 0.3);
+    assert!(share(&classify(&trace), Activity::Walking) > 0.9);
+
+    let trace = Trace::new(100.0).tilted(-30.0, 20.0).drive(60.0, 0.05);
+    assert!(share(&classify(&trace), Activity::Vehicle) > 0.9);
+}
+
+#[test]
+fn follows_a_change_of_activity() {
+    let trace = Trace::new(100.0).still(30.0).walk(30.0, 110.0, 0.3).run(30.0, 170.0, 1.2).still(30.0);
+    let labels = classify(&trace);
+    // Each window is labelled at its end, and covers about 5 s before
+    let at = |s: f64| labels[((s - 5.12) / 2.56) as usize];
+    assert_eq!([at(25.0), at(55.0), at(85.0), at(115.0)], [Activity::Still, Activity::Walking, Activity::Running, Activity::Still]);
+}
+
+#[test]
+fn features_of_a_steady_rhythm() {
+    // A pure 2 Hz bounce of 0.3 g
+    let trace = Trace::new(100.0)
+        .with_noise(0.0)
+        .custom(30.0, |t| [0.0, 0.0, 0.3 * (2.0 * std::f64::consts::PI * 2.0 * t).sin()]);
+    for features in features(&trace) {
+        assert!((features.dominant_frequency - 2.0).abs() <= 0.1, "{features:?}");
+        assert!(features.periodicity > 0.9, "{features:?}");
+        // The bins average away a little of the swing
+        assert!((features.variance - 0.045).abs() < 0.005, "{features:?}");
+        assert!((features.energy - features.variance).abs() < 0.002, "{features:?}");
+        // Mean slope of a sine is 4·A·f
+        assert!((features.jerk - 2.4).abs() < 0.2, "{features:?}");
+        assert_eq!(features.get(Feature::Periodicity), features.periodicity);
+    }
+
+    for features in features(&Trace::new(100.0).with_noise(0.01).still(30.0)) {
+        assert!(features.variance < 1e-4 && features.energy < 1e-3, "{features:?}");
+    }
+}
+
+#[test]
+fn features_do_not_depend_on_the_sample_rate() {
+    let walk = |rate_hz| features(&Trace::new(rate_hz).with_noise(0.0).walk(30.0, 110.0, 0.3));
+    for (slow, fast) in walk(50.0).iter().zip(walk(200.0)) {
+        assert!((slow.variance - fast.variance).abs() < 0.1 * fast.variance, "{slow:?} vs {fast:?}");
+        assert!((slow.jerk - fast.jerk).abs() < 0.1 * fast.jerk, "{slow:?} vs {fast:?}");
+        assert_eq!(slow.dominant_frequency, fast.dominant_frequency);
+    }
+}
+
+#[test]
+fn windows_overlap_by_half() {
+    // 30 s are 750 bins, the first window after 128 and then one every 64
+    let trace = Trace::new(100.0).still(30.0);
+    assert_eq!(features(&trace).len(), 10);
+
+    // A gap in the readings starts the window over, leaving two stretches of 15 s
+    // with four windows each
+    let mut extractor = FeatureExtractor::new();
+    let mut count = 0;
+    for sample in trace.samples() {
+        let mut sample = *sample;
+        if sample.timestamp_us >= 15_000_000 {
+            sample.timestamp_us += 1_000_000;
+        }
+        count += usize::from(extractor.update(&sample).is_some());
+    }
+    assert_eq!(count, 8);
+}
+
+#[test]
+fn takes_a_tree_of_its_own() {
+    static NODES: &[Node] = &[
+        Node::Split { feature: Feature::Energy, threshold: 0.01, below: 1, above: 2 },
+        Node::Leaf(Activity::Still),
+        Node::Leaf(Activity::Running),
+    ];
+    let tree = DecisionTree::new(NODES);
+    let labels: Vec<_> = features(&Trace::new(100.0).walk(20.0, 110.0, 0.3)).iter().map(|f| tree.classify(f)).collect();
+    assert!(labels.iter().all(|&label| label == Activity::Running), "{labels:?}");
+}
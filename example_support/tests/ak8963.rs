@@ -0,0 +1,113 @@
+mod common;
+
+use common::{SimBus, SimDevice};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource};
+use example_support::ak8963::{self, enable_mpu9250_bypass, Ak8963, Error, Mode, Resolution};
+
+const MPU: u8 = 0x68;
+
+struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _: u32) {}
+}
+
+/// An MPU9250 with bypass enabled and a magnetometer with the given trim.
+fn bus_with_magnetometer(asa: [u8; 3]) -> SimBus {
+    let magnetometer = SimDevice::ak8963(MPU).with_register(0x10, asa[0]).with_register(0x11, asa[1]).with_register(0x12, asa[2]);
+    let mut bus = SimBus::new(vec![SimDevice::mpu(MPU, 0x71), magnetometer]);
+    enable_mpu9250_bypass(&mut bus, MPU).unwrap();
+    bus
+}
+
+/// Stores a measurement the way the AK8963 presents it: little-endian, then ST2.
+fn measure(bus: &mut SimBus, counts: [i16; 3], st2: u8) {
+    let device = bus.device(ak8963::ADDRESS);
+    device.registers[0x02] = 0x01;
+    for (axis, count) in counts.iter().enumerate() {
+        let [low, high] = count.to_le_bytes();
+        device.registers[0x03 + 2 * axis] = low;
+        device.registers[0x04 + 2 * axis] = high;
+    }
+    device.registers[0x09] = st2;
+}
+
+fn assert_close(a: [f32; 3], b: [f32; 3]) {
+    for (a, b) in a.iter().zip(b) {
+        assert!((a - b).abs() < 1e-3, "{a:?} != {b:?}");
+    }
+}
+
+#[test]
+fn only_answers_once_bypass_is_enabled() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(MPU, 0x71).with_register(0x6A, 0x20), SimDevice::ak8963(MPU)]);
+    let result = Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut NoDelay);
+    assert!(matches!(result, Err(Error::I2c(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address)))));
+
+    enable_mpu9250_bypass(&mut bus, MPU).unwrap();
+    // Bypass only works with the MPU9250's own I2C master switched off
+    assert_eq!(bus.device(MPU).registers[0x6A] & 0x20, 0);
+    assert_ne!(bus.device(MPU).registers[0x37] & 0x02, 0);
+    assert!(Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut NoDelay).is_ok());
+}
+
+#[test]
+fn rejects_another_device() {
+    let mut bus = bus_with_magnetometer([128; 3]);
+    bus.device(ak8963::ADDRESS).registers[0x00] = 0x71;
+    let result = Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut NoDelay);
+    assert_eq!(result.err(), Some(Error::WrongDevice(0x71)));
+}
+
+#[test]
+fn starts_in_the_requested_mode_and_resolution() {
+    let mut bus = bus_with_magnetometer([128; 3]);
+    Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut NoDelay).unwrap();
+    assert_eq!(bus.device(ak8963::ADDRESS).registers[0x0A], 0x16);
+
+    Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits14, Mode::Continuous8Hz, &mut NoDelay).unwrap();
+    assert_eq!(bus.device(ak8963::ADDRESS).registers[0x0A], 0x02);
+}
+
+#[test]
+fn applies_the_factory_adjustment_in_the_mpu_axes() {
+    let mut bus = bus_with_magnetometer([128, 176, 80]);
+    measure(&mut bus, [1000, -2000, 400], 0x10);
+    let mut sensor = Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut NoDelay).unwrap();
+    assert_eq!(sensor.adjustment(), [1.0, 1.1875, 0.8125]);
+
+    // 0.15 µT per count, magnetometer X and Y swapped and Z flipped
+    let lsb = 4912.0 / 32760.0;
+    let field = sensor.read().unwrap().unwrap();
+    assert_close(field.as_microtesla(), [-2000.0 * lsb * 1.1875, 1000.0 * lsb, -400.0 * lsb * 0.8125]);
+}
+
+#[test]
+fn full_scale_is_the_same_at_both_resolutions() {
+    let mut bus = bus_with_magnetometer([128; 3]);
+    measure(&mut bus, [8190, 0, 0], 0x00);
+    let mut sensor = Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits14, Mode::Continuous8Hz, &mut NoDelay).unwrap();
+    assert_close(sensor.read().unwrap().unwrap().as_microtesla(), [0.0, 4912.0, 0.0]);
+
+    // Powered down when released
+    let bus = sensor.release().unwrap();
+    assert_eq!(bus.device(ak8963::ADDRESS).registers[0x0A], 0x00);
+}
+
+#[test]
+fn no_new_measurement_reads_nothing() {
+    let mut bus = bus_with_magnetometer([128; 3]);
+    measure(&mut bus, [100, 100, 100], 0x10);
+    bus.device(ak8963::ADDRESS).registers[0x02] = 0x00;
+    let mut sensor = Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut NoDelay).unwrap();
+    assert_eq!(sensor.read(), Ok(None));
+}
+
+#[test]
+fn overflowed_measurement_is_discarded() {
+    let mut bus = bus_with_magnetometer([128; 3]);
+    measure(&mut bus, [32000, 0, 0], 0x18);
+    let mut sensor = Ak8963::new(&mut bus, ak8963::ADDRESS, Resolution::Bits16, Mode::Continuous100Hz, &mut NoDelay).unwrap();
+    assert_eq!(sensor.read(), Err(Error::Overflow));
+}
@@ -0,0 +1,149 @@
+mod common;
+
+use std::cell::RefCell;
+use std::convert::Infallible;
+use std::rc::Rc;
+
+use common::{SimBus, SimDevice};
+use embedded_hal::delay::DelayNs;
+use embedded_hal::digital::{ErrorType, InputPin, OutputPin};
+use example_support::bus_recovery::{bus_is_stuck, probe, recover_bus, Probe, BusHealth, RecoveryError, MAX_CLOCK_PULSES};
+
+/// Open-drain bus with a slave that holds SDA low for a number of SCL pulses.
+#[derive(Default)]
+struct Bus {
+    scl_high: bool,
+    sda_released_by_master: bool,
+    slave_pulses_left: u32,
+    stop_seen: bool,
+}
+
+impl Bus {
+    fn sda_high(&self) -> bool {
+        self.sda_released_by_master && self.slave_pulses_left == 0
+    }
+}
+
+struct Scl(Rc<RefCell<Bus>>);
+struct Sda(Rc<RefCell<Bus>>);
+
+impl ErrorType for Scl {
+    type Error = Infallible;
+}
+
+impl ErrorType for Sda {
+    type Error = Infallible;
+}
+
+impl OutputPin for Scl {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        let mut bus = self.0.borrow_mut();
+        if bus.scl_high && bus.slave_pulses_left > 0 {
+            bus.slave_pulses_left -= 1;
+        }
+        bus.scl_high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        self.0.borrow_mut().scl_high = true;
+        Ok(())
+    }
+}
+
+impl OutputPin for Sda {
+    fn set_low(&mut self) -> Result<(), Infallible> {
+        self.0.borrow_mut().sda_released_by_master = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Infallible> {
+        let mut bus = self.0.borrow_mut();
+        if bus.scl_high && !bus.sda_released_by_master {
+            bus.stop_seen = true;
+        }
+        bus.sda_released_by_master = true;
+        Ok(())
+    }
+}
+
+impl InputPin for Sda {
+    fn is_high(&mut self) -> Result<bool, Infallible> {
+        Ok(self.0.borrow().sda_high())
+    }
+
+    fn is_low(&mut self) -> Result<bool, Infallible> {
+        Ok(!self.0.borrow().sda_high())
+    }
+}
+
+struct NoDelay;
+
+impl DelayNs for NoDelay {
+    fn delay_ns(&mut self, _: u32) {}
+}
+
+fn recover(slave_pulses: u32) -> (Result<u8, RecoveryError>, Rc<RefCell<Bus>>) {
+    let bus = Rc::new(RefCell::new(Bus {
+        slave_pulses_left: slave_pulses,
+        ..Default::default()
+    }));
+    let result = recover_bus(&mut Scl(bus.clone()), &mut Sda(bus.clone()), &mut NoDelay);
+    (result, bus)
+}
+
+#[test]
+fn idle_bus_only_gets_a_stop() {
+    let (result, bus) = recover(0);
+    assert_eq!(result, Ok(0));
+    assert!(bus.borrow().stop_seen);
+}
+
+#[test]
+fn clocks_until_the_slave_releases_sda() {
+    let (result, bus) = recover(4);
+    assert_eq!(result, Ok(4));
+    let bus = bus.borrow();
+    assert!(bus.stop_seen);
+    assert!(bus.scl_high && bus.sda_high());
+}
+
+#[test]
+fn gives_up_after_nine_pulses() {
+    let (result, _) = recover(u32::from(MAX_CLOCK_PULSES) + 1);
+    assert_eq!(result, Err(RecoveryError::SdaStuckLow));
+}
+
+#[test]
+fn recovery_is_due_after_consecutive_failures() {
+    let mut health = BusHealth::new(3);
+    assert!(!health.record_failure());
+    health.record_success();
+    assert!(!health.record_failure());
+    assert!(!health.record_failure());
+    assert!(health.record_failure());
+
+    health.record_recovery();
+    let counters = health.counters();
+    assert_eq!((counters.consecutive, counters.total, counters.recoveries), (0, 4, 1));
+}
+
+#[test]
+fn a_missing_device_is_not_a_stuck_bus() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x68)]);
+    assert!(!bus_is_stuck(&mut bus, 0x68));
+    assert!(!bus_is_stuck(&mut bus, 0x69));
+
+    bus.stuck = true;
+    assert!(bus_is_stuck(&mut bus, 0x68));
+}
+
+#[test]
+fn probe_tells_a_missing_device_from_a_stuck_bus() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x68)]);
+    assert_eq!(probe(&mut bus, 0x68), Probe::Present);
+    assert_eq!(probe(&mut bus, 0x69), Probe::Missing);
+
+    bus.stuck = true;
+    assert_eq!(probe(&mut bus, 0x68), Probe::Stuck);
+}
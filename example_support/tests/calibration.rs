@@ -0,0 +1,144 @@
+mod common;
+
+use common::{SimBus, SimDevice};
+use example_support::calibration::{
+    Calibrated, Calibration, Calibrator, HardwareOffsets, Orientation, Progress, MPU6050_OFFSETS, MPU9250_OFFSETS,
+};
+use example_support::imu::{Imu, ImuSample};
+use example_support::units::{Temperature, Vector3};
+
+fn assert_close(actual: [f32; 3], expected: [f32; 3]) {
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < 1e-3, "{actual:?} != {expected:?}");
+    }
+}
+
+fn reading(acceleration: [f32; 3], angular_velocity: [f32; 3]) -> ImuSample {
+    ImuSample {
+        timestamp_us: 0,
+        temperature: Temperature::from_celsius(25.0),
+        acceleration: Vector3::from_g(acceleration),
+        angular_velocity: Vector3::from_dps(angular_velocity),
+        magnetic_field: None,
+    }
+}
+
+fn calibrate(calibrator: &mut Calibrator, samples: impl IntoIterator<Item = ImuSample>) -> Option<Calibration> {
+    samples.into_iter().find_map(|sample| match calibrator.add(&sample) {
+        Progress::Done(calibration) => Some(calibration),
+        _ => None,
+    })
+}
+
+#[test]
+fn bias_is_the_average_at_rest() {
+    let mut calibrator = Calibrator::new(Orientation::ZUp, 4);
+    let noise = [-0.1, 0.1, -0.05, 0.05];
+    let samples = noise
+        .iter()
+        .map(|n| reading([0.02 + n / 100.0, -0.01, 1.03], [1.5 + n, -0.7, 0.2 - n]));
+
+    let calibration = calibrate(&mut calibrator, samples).unwrap();
+    assert_close(calibration.gyro_bias.as_dps(), [1.5, -0.7, 0.2]);
+    assert_close(calibration.accel_offset.as_g(), [0.02, -0.01, 0.03]);
+}
+
+#[test]
+fn offsets_account_for_the_orientation() {
+    let mut calibrator = Calibrator::new(Orientation::XDown, 2);
+    let samples = [reading([-0.95, 0.0, 0.01], [0.0; 3]); 2];
+
+    let calibration = calibrate(&mut calibrator, samples).unwrap();
+    assert_close(calibration.accel_offset.as_g(), [0.05, 0.0, 0.01]);
+}
+
+#[test]
+fn movement_restarts_collection() {
+    let mut calibrator = Calibrator::new(Orientation::ZUp, 3);
+    let still = reading([0.0, 0.0, 1.0], [1.0, 1.0, 1.0]);
+
+    assert_eq!(calibrator.add(&still), Progress::Collecting { collected: 1, required: 3 });
+    assert_eq!(calibrator.add(&still), Progress::Collecting { collected: 2, required: 3 });
+    assert_eq!(calibrator.add(&reading([0.0, 0.0, 1.0], [15.0, 1.0, 1.0])), Progress::Restarted);
+    assert_eq!(calibrator.add(&still), Progress::Collecting { collected: 1, required: 3 });
+    assert_eq!(calibrator.add(&reading([0.2, 0.0, 1.0], [1.0, 1.0, 1.0])), Progress::Restarted);
+}
+
+/// An IMU with a constant error on every axis.
+struct BiasedImu;
+
+impl Imu for BiasedImu {
+    type Error = ();
+
+    fn read_all(&mut self) -> Result<(f32, [f32; 3], [f32; 3]), ()> {
+        Ok((30.0, [0.1, -0.1, 1.1], [2.0, -3.0, 0.5]))
+    }
+}
+
+#[test]
+fn calibrated_imu_reads_ideal_values_at_rest() {
+    let mut calibrator = Calibrator::new(Orientation::ZUp, 10);
+    let samples = core::iter::repeat_with(|| BiasedImu.sample(0).unwrap());
+    let calibration = calibrate(&mut calibrator, samples).unwrap();
+
+    let mut imu = Calibrated::new(BiasedImu, calibration);
+    let sample = imu.sample(0).unwrap();
+    assert_close(sample.acceleration.as_g(), [0.0, 0.0, 1.0]);
+    assert_close(sample.angular_velocity.as_dps(), [0.0; 3]);
+    assert_eq!(sample.temperature, Temperature::from_celsius(30.0));
+    assert_eq!(calibration.apply(BiasedImu.sample(0).unwrap()), sample);
+}
+
+#[test]
+fn hardware_offsets_keep_the_factory_trim() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x68)]);
+    // Factory trim of 100 on X with temperature compensation enabled
+    bus.device(0x68).set_i16(0x06, 101);
+
+    let calibration = Calibration {
+        gyro_bias: Vector3::from_dps([1.0, -2.0, 0.0]),
+        accel_offset: Vector3::from_g([0.05, 0.0, -0.01]),
+    };
+    let offsets = HardwareOffsets::compute(&mut bus, 0x68, MPU6050_OFFSETS, &calibration).unwrap();
+    assert_eq!(offsets.accel(), [101 - 102, 0, 20]);
+    assert_eq!(offsets.gyro(), [-33, 66, 0]);
+
+    offsets.write(&mut bus, 0x68).unwrap();
+    let registers = &bus.device(0x68).registers;
+    assert_eq!(&registers[0x06..0x0C], &[0xFF, 0xFF, 0x00, 0x00, 0x00, 0x14]);
+    assert_eq!(&registers[0x13..0x19], &[0xFF, 0xDF, 0x00, 0x42, 0x00, 0x00]);
+}
+
+#[test]
+fn stored_factory_trim_survives_offsets_already_written() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x68)]);
+    bus.device(0x68).set_i16(0x06, 101);
+    let calibration = Calibration {
+        gyro_bias: Vector3::default(),
+        accel_offset: Vector3::from_g([0.05, 0.0, 0.0]),
+    };
+
+    let factory = HardwareOffsets::read_factory_accel(&mut bus, 0x68, MPU6050_OFFSETS).unwrap();
+    let offsets = HardwareOffsets::new(MPU6050_OFFSETS, factory, &calibration);
+    offsets.write(&mut bus, 0x68).unwrap();
+
+    // Only the microcontroller restarted, so the registers still hold the corrected
+    // values; going from the stored factory trim gives the same offsets again
+    assert_eq!(HardwareOffsets::new(MPU6050_OFFSETS, factory, &calibration), offsets);
+    assert_ne!(HardwareOffsets::compute(&mut bus, 0x68, MPU6050_OFFSETS, &calibration).unwrap(), offsets);
+}
+
+#[test]
+fn mpu9250_accel_offsets_are_not_contiguous() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x71)]);
+    let calibration = Calibration {
+        gyro_bias: Vector3::default(),
+        accel_offset: Vector3::from_g([0.0, 0.0, 0.5]),
+    };
+    let offsets = HardwareOffsets::compute(&mut bus, 0x68, MPU9250_OFFSETS, &calibration).unwrap();
+    offsets.write(&mut bus, 0x68).unwrap();
+
+    let registers = &bus.device(0x68).registers;
+    assert_eq!(&registers[0x7D..0x7F], &(-1024i16).to_be_bytes());
+    assert_eq!(registers[0x79], 0);
+}
@@ -0,0 +1,280 @@
+use crc::{Crc, CRC_32_ISO_HDLC};
+use embedded_storage::nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash};
+use example_support::accel_calibration::AccelCalibration;
+use example_support::calibration::Calibration;
+use example_support::calibration_store::{CalibrationStore, StoreError, StoredCalibration, SLOT_SIZE};
+use example_support::mag_calibration::MagCalibration;
+use example_support::ppg::Spo2Curve;
+use example_support::units::Vector3;
+
+const SECTOR: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct FlashError(NorFlashErrorKind);
+
+impl NorFlashError for FlashError {
+    fn kind(&self) -> NorFlashErrorKind {
+        self.0
+    }
+}
+
+/// NOR flash in memory: erasing sets bytes to 0xFF, writing can only clear bits.
+struct MockFlash {
+    data: Vec<u8>,
+    erases: Vec<u32>,
+    /// Bytes left before the next write is cut short, as if power was lost.
+    power_budget: Option<usize>,
+}
+
+impl MockFlash {
+    fn new(sectors: usize) -> Self {
+        Self {
+            data: vec![0xFF; sectors * SECTOR],
+            erases: vec![0; sectors],
+            power_budget: None,
+        }
+    }
+}
+
+impl ErrorType for MockFlash {
+    type Error = FlashError;
+}
+
+impl ReadNorFlash for MockFlash {
+    const READ_SIZE: usize = 4;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let offset = offset as usize;
+        let data = self.data.get(offset..offset + bytes.len()).ok_or(FlashError(NorFlashErrorKind::OutOfBounds))?;
+        bytes.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.data.len()
+    }
+}
+
+impl NorFlash for MockFlash {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = SECTOR;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), FlashError> {
+        let (from, to) = (from as usize, to as usize);
+        if !from.is_multiple_of(SECTOR) || !to.is_multiple_of(SECTOR) {
+            return Err(FlashError(NorFlashErrorKind::NotAligned));
+        }
+        self.data[from..to].fill(0xFF);
+        for sector in from / SECTOR..to / SECTOR {
+            self.erases[sector] += 1;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let offset = offset as usize;
+        if !offset.is_multiple_of(4) || !bytes.len().is_multiple_of(4) {
+            return Err(FlashError(NorFlashErrorKind::NotAligned));
+        }
+        let length = match self.power_budget.take() {
+            Some(budget) => budget.min(bytes.len()),
+            None => bytes.len(),
+        };
+        for (cell, byte) in self.data[offset..offset + length].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+}
+
+fn imu_calibration(seed: f32) -> Calibration {
+    Calibration {
+        gyro_bias: Vector3::from_dps([seed, -seed, 0.5]),
+        accel_offset: Vector3::from_g([0.01, 0.02, -seed / 100.0]),
+    }
+}
+
+fn full_calibration() -> StoredCalibration {
+    StoredCalibration {
+        imu: Some(imu_calibration(1.0)),
+        accel: Some(AccelCalibration {
+            matrix: [[1.01, 0.02, 0.0], [0.0, 0.99, -0.01], [0.03, 0.0, 1.02]],
+            bias: Vector3::from_g([0.05, -0.02, 0.01]),
+        }),
+        magnetometer: Some(MagCalibration {
+            hard_iron: Vector3::from_microtesla([12.0, -30.0, 4.5]),
+            soft_iron: [[1.1, 0.05, 0.0], [0.05, 0.95, 0.0], [0.0, 0.0, 1.0]],
+        }),
+        spo2: Some(Spo2Curve { a: 110.0, b: -25.0, c: 1.5 }),
+        factory_accel_offsets: Some([-1234, 2047, -32768]),
+    }
+}
+
+#[test]
+fn empty_flash_has_no_calibration() {
+    let mut store = CalibrationStore::new(MockFlash::new(2), 0, 2).unwrap();
+    assert_eq!(store.load(), Ok(None));
+}
+
+#[test]
+fn saved_calibration_survives_a_reset() {
+    let mut store = CalibrationStore::new(MockFlash::new(4), SECTOR as u32, 2).unwrap();
+    store.save(&full_calibration()).unwrap();
+
+    let mut store = CalibrationStore::new(store.into_inner(), SECTOR as u32, 2).unwrap();
+    assert_eq!(store.load(), Ok(Some(full_calibration())));
+}
+
+#[test]
+fn store_must_cover_two_whole_sectors() {
+    assert!(matches!(CalibrationStore::new(MockFlash::new(2), 0, 1), Err(StoreError::Layout)));
+    assert!(matches!(CalibrationStore::new(MockFlash::new(2), 256, 2), Err(StoreError::Layout)));
+    assert!(matches!(CalibrationStore::new(MockFlash::new(2), SECTOR as u32, 2), Err(StoreError::Layout)));
+    assert!(CalibrationStore::at_end(MockFlash::new(3)).is_ok());
+    assert!(CalibrationStore::in_partition(MockFlash::new(4), SECTOR as u32, 3 * SECTOR as u32).is_ok());
+    assert!(matches!(CalibrationStore::in_partition(MockFlash::new(4), SECTOR as u32, SECTOR as u32 + 256), Err(StoreError::Layout)));
+    assert!(matches!(CalibrationStore::in_partition(MockFlash::new(4), SECTOR as u32, 4 * SECTOR as u32), Err(StoreError::Layout)));
+}
+
+#[test]
+fn saves_rotate_through_sectors_evenly() {
+    let mut store = CalibrationStore::new(MockFlash::new(2), 0, 2).unwrap();
+    let saves = 10 * SECTOR / SLOT_SIZE;
+    for n in 0..saves {
+        let calibration = StoredCalibration {
+            imu: Some(imu_calibration(n as f32)),
+            ..Default::default()
+        };
+        store.save(&calibration).unwrap();
+        assert_eq!(store.load().unwrap(), Some(calibration));
+    }
+
+    // One erase per sector's worth of saves, shared between the two sectors
+    let erases = store.into_inner().erases;
+    assert_eq!(erases, vec![5, 5]);
+}
+
+#[test]
+fn saving_the_same_calibration_does_not_write() {
+    let mut store = CalibrationStore::new(MockFlash::new(2), 0, 2).unwrap();
+    store.save(&full_calibration()).unwrap();
+    let before = store.into_inner();
+    let snapshot = before.data.clone();
+
+    let mut store = CalibrationStore::new(before, 0, 2).unwrap();
+    store.save(&full_calibration()).unwrap();
+    assert_eq!(store.into_inner().data, snapshot);
+}
+
+#[test]
+fn corrupted_record_falls_back_to_the_previous_one() {
+    let mut store = CalibrationStore::new(MockFlash::new(2), 0, 2).unwrap();
+    let old = StoredCalibration {
+        imu: Some(imu_calibration(1.0)),
+        ..Default::default()
+    };
+    store.save(&old).unwrap();
+    store.save(&full_calibration()).unwrap();
+
+    // Flip a bit in the payload of the newest record, which sits in the second slot
+    let mut flash = store.into_inner();
+    flash.data[SLOT_SIZE + 40] ^= 0x01;
+
+    let mut store = CalibrationStore::new(flash, 0, 2).unwrap();
+    assert_eq!(store.load(), Ok(Some(old)));
+}
+
+#[test]
+fn interrupted_save_keeps_the_previous_record() {
+    let mut store = CalibrationStore::new(MockFlash::new(2), 0, 2).unwrap();
+    let old = StoredCalibration {
+        spo2: Some(Spo2Curve::default()),
+        ..Default::default()
+    };
+    store.save(&old).unwrap();
+
+    let mut flash = store.into_inner();
+    flash.power_budget = Some(20);
+    let mut store = CalibrationStore::new(flash, 0, 2).unwrap();
+    store.save(&full_calibration()).unwrap();
+    assert_eq!(store.load(), Ok(Some(old)));
+
+    // The next save skips the half-written slot
+    store.save(&full_calibration()).unwrap();
+    assert_eq!(store.load(), Ok(Some(full_calibration())));
+    let flash = store.into_inner();
+    assert!(flash.data[2 * SLOT_SIZE..2 * SLOT_SIZE + 4] != [0xFF; 4]);
+}
+
+/// Hand-builds a record, to stand in for one written by other firmware.
+fn raw_record(version: u16, sequence: u32, sections: &[(u8, &[f32])]) -> Vec<u8> {
+    let mut payload = Vec::new();
+    for (tag, values) in sections {
+        payload.push(*tag);
+        payload.push((values.len() * 4) as u8);
+        for value in *values {
+            payload.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&version.to_le_bytes());
+    header.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+    header.extend_from_slice(&sequence.to_le_bytes());
+
+    let crc = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+    let mut digest = crc.digest();
+    digest.update(&header);
+    digest.update(&payload);
+
+    let mut record = b"HCAL".to_vec();
+    record.extend_from_slice(&header);
+    record.extend_from_slice(&digest.finalize().to_le_bytes());
+    record.extend_from_slice(&payload);
+    record.resize(record.len().next_multiple_of(4), 0xFF);
+    record
+}
+
+fn flash_with(records: &[Vec<u8>]) -> MockFlash {
+    let mut flash = MockFlash::new(2);
+    for (slot, record) in records.iter().enumerate() {
+        flash.data[slot * SLOT_SIZE..slot * SLOT_SIZE + record.len()].copy_from_slice(record);
+    }
+    flash
+}
+
+#[test]
+fn records_from_older_firmware_leave_newer_sections_empty() {
+    // Written before the magnetometer and SpO2 sections existed
+    let record = raw_record(1, 7, &[(1, &[0.01, 0.02, 0.03, 0.1, 0.2, 0.3])]);
+    let mut store = CalibrationStore::new(flash_with(&[record]), 0, 2).unwrap();
+
+    let loaded = store.load().unwrap().unwrap();
+    let imu = loaded.imu.unwrap();
+    assert_eq!(imu.gyro_bias, Vector3::from_rad_per_s([0.01, 0.02, 0.03]));
+    assert_eq!(imu.accel_offset, Vector3::from_mps2([0.1, 0.2, 0.3]));
+    assert_eq!((loaded.accel, loaded.magnetometer, loaded.spo2), (None, None, None));
+}
+
+#[test]
+fn unknown_sections_from_newer_firmware_are_skipped() {
+    let record = raw_record(1, 0, &[(42, &[1.0, 2.0]), (4, &[100.0, -20.0, 0.0])]);
+    let mut store = CalibrationStore::new(flash_with(&[record]), 0, 2).unwrap();
+
+    let loaded = store.load().unwrap().unwrap();
+    assert_eq!(loaded.spo2, Some(Spo2Curve { a: 100.0, b: -20.0, c: 0.0 }));
+}
+
+#[test]
+fn records_of_a_newer_format_are_ignored() {
+    let current = raw_record(1, 3, &[(4, &[100.0, -20.0, 0.0])]);
+    let newer = raw_record(2, 4, &[(4, &[99.0, -19.0, 0.0])]);
+    let mut store = CalibrationStore::new(flash_with(&[current, newer]), 0, 2).unwrap();
+
+    let loaded = store.load().unwrap().unwrap();
+    assert_eq!(loaded.spo2, Some(Spo2Curve { a: 100.0, b: -20.0, c: 0.0 }));
+
+    // Saving never overwrites the unreadable record with a stale sequence number
+    store.save(&full_calibration()).unwrap();
+    assert_eq!(store.load(), Ok(Some(full_calibration())));
+}
@@ -0,0 +1,219 @@
+//! Synthetic accelerometer traces of everyday activity.
+//!
+//! Gait is modelled as a periodic vertical bounce with a second harmonic for the heel
+//! strike, a forward surge at the step rate and a sideways sway at half of it (one
+//! sway per stride). Step lengths vary a little from step to step, as they do. Motion
+//! is given in the world frame, Z up; the sensor sits level unless a tilt is given.
+//!
+//! Cycling and riding in a vehicle follow the simulation in `tools/train_activity.py`,
+//! with which the built-in activity classifier was trained.
+
+use std::f64::consts::PI;
+
+use example_support::imu::ImuSample;
+use example_support::units::{Temperature, Vector3};
+
+#[derive(Debug, Clone)]
+pub struct Trace {
+    rate_hz: f64,
+    noise_g: f64,
+    /// World to sensor rotation, rows are the sensor axes in the world frame.
+    orientation: [[f64; 3]; 3],
+    samples: Vec<ImuSample>,
+    time_s: f64,
+    rng: Rng,
+}
+
+impl Trace {
+    pub fn new(rate_hz: f64) -> Self {
+        Self {
+            rate_hz,
+            noise_g: 0.02,
+            orientation: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+            samples: Vec::new(),
+            time_s: 0.0,
+            rng: Rng(0x5eed),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng(seed);
+        self
+    }
+
+    pub fn with_noise(mut self, noise_g: f64) -> Self {
+        self.noise_g = noise_g;
+        self
+    }
+
+    /// Wears the sensor tilted by `roll` and `pitch` degrees, for the parts that follow.
+    pub fn tilted(mut self, roll: f64, pitch: f64) -> Self {
+        let (sin_roll, cos_roll) = roll.to_radians().sin_cos();
+        let (sin_pitch, cos_pitch) = pitch.to_radians().sin_cos();
+        // Transpose of the pitch-then-roll body to world rotation
+        self.orientation = [
+            [cos_pitch, 0.0, -sin_pitch],
+            [sin_roll * sin_pitch, cos_roll, sin_roll * cos_pitch],
+            [cos_roll * sin_pitch, -sin_roll, cos_roll * cos_pitch],
+        ];
+        self
+    }
+
+    pub fn still(self, duration_s: f64) -> Self {
+        self.generate(duration_s, |_| [0.0; 3])
+    }
+
+    /// Walking at `cadence` steps per minute, bouncing by up to `peak_g`.
+    pub fn walk(self, duration_s: f64, cadence: f64, peak_g: f64) -> Self {
+        self.gait(duration_s, cadence, peak_g, 0.3)
+    }
+
+    /// Running, whose landings are sharper than walking steps.
+    pub fn run(self, duration_s: f64, cadence: f64, peak_g: f64) -> Self {
+        self.gait(duration_s, cadence, peak_g, 0.5)
+    }
+
+    /// Shaking by hand: jolts in random directions at irregular intervals.
+    pub fn shake(mut self, duration_s: f64, peak_g: f64) -> Self {
+        let end = self.time_s + duration_s;
+        let mut jolts = Vec::new();
+        let mut at = self.time_s;
+        while at < end {
+            at += 0.1 + 0.6 * self.rng.uniform();
+            let direction = [self.rng.gaussian(), self.rng.gaussian(), self.rng.gaussian()];
+            let norm = direction.iter().map(|d| d * d).sum::<f64>().sqrt();
+            let size = peak_g * (0.3 + 0.7 * self.rng.uniform()) / norm;
+            jolts.push((at, direction.map(|d| d * size), 0.04 + 0.08 * self.rng.uniform()));
+        }
+        self.generate(duration_s, move |t| {
+            let mut total = [0.0; 3];
+            for &(at, jolt, width) in &jolts {
+                let pulse = (-((t - at) / width).powi(2)).exp();
+                for (total, jolt) in total.iter_mut().zip(jolt) {
+                    *total += jolt * pulse;
+                }
+            }
+            total
+        })
+    }
+
+    /// Cycling at `cadence` pedal revolutions per minute, the hips rocking by up to
+    /// `amplitude_g` with each stroke, on a slightly bumpy road.
+    pub fn cycle(mut self, duration_s: f64, cadence: f64, amplitude_g: f64) -> Self {
+        let road = self.lowpass(duration_s, 0.02, 3.0);
+        let (start, rate_hz) = (self.time_s, self.rate_hz);
+        let phase = 2.0 * PI * self.rng.uniform();
+        self.generate(duration_s, move |t| {
+            let angle = 2.0 * PI * cadence / 60.0 * t + phase;
+            let bump = road[(((t - start) * rate_hz).round() as usize).min(road.len() - 1)];
+            [
+                0.3 * amplitude_g * (2.0 * angle + 1.0).sin(),
+                0.6 * amplitude_g * angle.sin(),
+                amplitude_g * (2.0 * angle).sin() + bump,
+            ]
+        })
+    }
+
+    /// Riding in a car or bus: suspension bumps of about `bumps_g`, engine vibration,
+    /// and gentle braking and turning.
+    pub fn drive(mut self, duration_s: f64, bumps_g: f64) -> Self {
+        let bumps = self.lowpass(duration_s, bumps_g, 2.0);
+        let vibration: Vec<f64> = (0..3 * bumps.len()).map(|_| 0.02 * self.rng.gaussian()).collect();
+        let (start, rate_hz) = (self.time_s, self.rate_hz);
+        let braking = 0.05 + 0.2 * self.rng.uniform();
+        let turning = 0.05 + 0.2 * self.rng.uniform();
+        self.generate(duration_s, move |t| {
+            let index = (((t - start) * rate_hz).round() as usize).min(bumps.len() - 1);
+            let bump = bumps[index];
+            [
+                braking * (2.0 * PI * t / 13.0).sin() + vibration[3 * index],
+                turning * (2.0 * PI * t / 9.0).sin() + 0.3 * bump + vibration[3 * index + 1],
+                bump + vibration[3 * index + 2],
+            ]
+        })
+    }
+
+    /// Any other motion, `acceleration(t)` in g on top of gravity in the world frame,
+    /// `t` in seconds since the trace started.
+    pub fn custom(self, duration_s: f64, acceleration: impl Fn(f64) -> [f64; 3]) -> Self {
+        self.generate(duration_s, acceleration)
+    }
+
+    pub fn samples(&self) -> &[ImuSample] {
+        &self.samples
+    }
+
+    pub fn duration_s(&self) -> f64 {
+        self.time_s
+    }
+
+    fn gait(mut self, duration_s: f64, cadence: f64, peak_g: f64, harmonic: f64) -> Self {
+        // Step boundaries, each step within 5% of the nominal period
+        let period = 60.0 / cadence;
+        let mut boundaries = vec![self.time_s];
+        while *boundaries.last().unwrap() < self.time_s + duration_s + period {
+            let step = period * (1.0 + 0.05 * (2.0 * self.rng.uniform() - 1.0));
+            boundaries.push(boundaries.last().unwrap() + step);
+        }
+        self.generate(duration_s, move |t| {
+            let index = boundaries.partition_point(|&b| b <= t).max(1) - 1;
+            let phase = (t - boundaries[index]) / (boundaries[index + 1] - boundaries[index]);
+            let angle = 2.0 * PI * phase;
+            let vertical = peak_g * ((1.0 - harmonic) * angle.cos() + harmonic * (2.0 * angle + 0.5).cos());
+            let surge = 0.3 * peak_g * angle.sin();
+            let sway = 0.1 * peak_g * ((index as f64 + phase) * PI).sin();
+            [surge, sway, vertical]
+        })
+    }
+
+    /// Gaussian noise of the given deviation through a first-order low-pass, one value
+    /// per sample for `duration_s`.
+    fn lowpass(&mut self, duration_s: f64, deviation: f64, cutoff_hz: f64) -> Vec<f64> {
+        let alpha = 1.0 - (-2.0 * PI * cutoff_hz / self.rate_hz).exp();
+        let drive = deviation * ((2.0 - alpha) / alpha).sqrt();
+        let mut value = deviation * self.rng.gaussian();
+        (0..=(duration_s * self.rate_hz).ceil() as usize)
+            .map(|_| {
+                value += alpha * (drive * self.rng.gaussian() - value);
+                value
+            })
+            .collect()
+    }
+
+    fn generate(mut self, duration_s: f64, linear_g: impl Fn(f64) -> [f64; 3]) -> Self {
+        let period = 1.0 / self.rate_hz;
+        let end = self.time_s + duration_s;
+        while self.time_s < end - 1e-9 {
+            let [x, y, z] = linear_g(self.time_s);
+            // The accelerometer reads gravity as 1 g upwards, plus the motion
+            let world = [x, y, z + 1.0];
+            let acceleration: [f32; 3] = std::array::from_fn(|axis| {
+                let row = self.orientation[axis];
+                (row[0] * world[0] + row[1] * world[1] + row[2] * world[2] + self.noise_g * self.rng.gaussian()) as f32
+            });
+            self.samples.push(ImuSample {
+                timestamp_us: (self.time_s * 1e6).round() as u64,
+                temperature: Temperature::from_celsius(25.0),
+                acceleration: Vector3::from_g(acceleration),
+                angular_velocity: Vector3::from_dps([0.0; 3]),
+                magnetic_field: None,
+            });
+            self.time_s += period;
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn uniform(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn gaussian(&mut self) -> f64 {
+        (0..12).map(|_| self.uniform()).sum::<f64>() - 6.0
+    }
+}
@@ -0,0 +1,227 @@
+//! A simulated I2C bus with register-map devices, for testing bus level code,
+//! scripted IMU motion for testing the fusion filters, synthetic activity traces and
+//! synthetic PPG.
+
+#![allow(dead_code)]
+
+pub mod activity;
+pub mod motion;
+pub mod ppg;
+
+use std::collections::VecDeque;
+
+use embedded_hal::i2c::{ErrorKind, ErrorType, I2c, NoAcknowledgeSource, Operation};
+
+/// MAX30102 FIFO registers.
+const FIFO_WR_PTR: u8 = 0x04;
+const FIFO_RD_PTR: u8 = 0x06;
+const FIFO_DATA: u8 = 0x07;
+/// MPU FIFO registers.
+const MPU_INT_STATUS: u8 = 0x3A;
+const MPU_USER_CTRL: u8 = 0x6A;
+const MPU_FIFO_COUNTH: u8 = 0x72;
+const MPU_FIFO_R_W: u8 = 0x74;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FifoKind {
+    Max30102,
+    Mpu,
+}
+
+/// A device with 256 byte-wide registers and an auto-incrementing register pointer.
+pub struct SimDevice {
+    pub address: u8,
+    pub registers: [u8; 256],
+    pub connected: bool,
+    /// Address of an MPU9250 whose I2C bypass must be enabled for this device to answer.
+    pub behind_bypass_of: Option<u8>,
+    /// Bytes waiting in a MAX30102 or MPU style FIFO, read through a non-incrementing data register.
+    pub fifo: Option<VecDeque<u8>>,
+    fifo_kind: FifoKind,
+    pointer: u8,
+}
+
+impl SimDevice {
+    pub fn new(address: u8) -> Self {
+        Self {
+            address,
+            registers: [0; 256],
+            connected: true,
+            behind_bypass_of: None,
+            fifo: None,
+            fifo_kind: FifoKind::Max30102,
+            pointer: 0,
+        }
+    }
+
+    pub fn with_register(mut self, register: u8, value: u8) -> Self {
+        self.registers[usize::from(register)] = value;
+        self
+    }
+
+    /// An MPU6050 or MPU9250 right after power-on, with the given WHO_AM_I value.
+    pub fn mpu(address: u8, who_am_i: u8) -> Self {
+        Self::new(address).with_register(0x75, who_am_i).with_register(0x6B, 0x40)
+    }
+
+    /// An MPU6050 or MPU9250 whose FIFO is filled with [`Self::push_mpu_fifo_reading`]. It
+    /// holds 512 bytes on the MPU9250 and 1024 on the MPU6050.
+    pub fn mpu_with_fifo(address: u8, who_am_i: u8) -> Self {
+        let mut device = Self::mpu(address, who_am_i);
+        device.fifo = Some(VecDeque::new());
+        device.fifo_kind = FifoKind::Mpu;
+        device
+    }
+
+    /// The AK8963 magnetometer of an MPU9250 at `mpu_address`.
+    pub fn ak8963(mpu_address: u8) -> Self {
+        let mut device = Self::new(0x0C).with_register(0x00, 0x48);
+        device.behind_bypass_of = Some(mpu_address);
+        device
+    }
+
+    /// A MAX30102 right after power-on.
+    pub fn max30102() -> Self {
+        let mut device = Self::new(0x57).with_register(0xFE, 0x03).with_register(0xFF, 0x15);
+        device.fifo = Some(VecDeque::new());
+        device
+    }
+
+    /// Queues a red/IR sample in the MAX30102 FIFO and advances its write pointer.
+    pub fn push_fifo_sample(&mut self, red: u32, ir: u32) {
+        let fifo = self.fifo.as_mut().expect("device has no FIFO");
+        for value in [red, ir] {
+            fifo.extend([(value >> 16) as u8 & 0x03, (value >> 8) as u8, value as u8]);
+        }
+        let write_pointer = &mut self.registers[usize::from(FIFO_WR_PTR)];
+        *write_pointer = (*write_pointer + 1) % 32;
+    }
+
+    /// Queues an accelerometer reading in the MPU FIFO. Once full, the oldest reading
+    /// makes way and the overflow flag in INT_STATUS is raised.
+    pub fn push_mpu_fifo_reading(&mut self, counts: [i16; 3]) {
+        let fifo = self.fifo.as_mut().expect("device has no FIFO");
+        let size = if self.registers[0x75] == 0x71 { 512 } else { 1024 };
+        if fifo.len() + 6 > size {
+            fifo.drain(..6);
+            self.registers[usize::from(MPU_INT_STATUS)] |= 0x10;
+        }
+        fifo.extend(counts.iter().flat_map(|count| count.to_be_bytes()));
+        self.update_mpu_fifo_count();
+    }
+
+    fn update_mpu_fifo_count(&mut self) {
+        let count = self.fifo.as_ref().map_or(0, VecDeque::len) as u16;
+        self.set_i16(MPU_FIFO_COUNTH, count as i16);
+    }
+
+    /// Stores a big-endian 16-bit value, as the MPU data registers hold them.
+    pub fn set_i16(&mut self, register: u8, value: i16) {
+        let [high, low] = value.to_be_bytes();
+        self.registers[usize::from(register)] = high;
+        self.registers[usize::from(register) + 1] = low;
+    }
+
+    /// Power cycles the device, clearing everything but its identity.
+    pub fn power_cycle(&mut self, identity: &[(u8, u8)]) {
+        self.registers = [0; 256];
+        for &(register, value) in identity {
+            self.registers[usize::from(register)] = value;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct SimBus {
+    pub devices: Vec<SimDevice>,
+    pub transactions: usize,
+    /// A slave holds SDA low, every transaction loses arbitration.
+    pub stuck: bool,
+}
+
+impl SimBus {
+    pub fn new(devices: Vec<SimDevice>) -> Self {
+        Self { devices, transactions: 0, stuck: false }
+    }
+
+    pub fn device(&mut self, address: u8) -> &mut SimDevice {
+        self.devices.iter_mut().find(|d| d.address == address).expect("no such device")
+    }
+}
+
+impl ErrorType for SimBus {
+    type Error = ErrorKind;
+}
+
+impl I2c for SimBus {
+    fn transaction(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+        self.transactions += 1;
+        if self.stuck {
+            return Err(ErrorKind::ArbitrationLoss);
+        }
+        let bypass_enabled = |devices: &[SimDevice], mpu_address: u8| {
+            devices.iter().any(|d| d.address == mpu_address && d.connected && d.registers[0x37] & 0x02 != 0)
+        };
+        let index = self
+            .devices
+            .iter()
+            .position(|d| {
+                d.address == address
+                    && d.connected
+                    && d.behind_bypass_of.is_none_or(|mpu| bypass_enabled(&self.devices, mpu))
+            })
+            .ok_or(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))?;
+        let device = &mut self.devices[index];
+
+        for operation in operations {
+            match operation {
+                Operation::Write(bytes) => {
+                    if let Some((&register, data)) = bytes.split_first() {
+                        device.pointer = register;
+                        for &byte in data {
+                            device.registers[usize::from(device.pointer)] = byte;
+                            if device.fifo_kind == FifoKind::Mpu && device.pointer == MPU_USER_CTRL && byte & 0x04 != 0 {
+                                // FIFO_RESET empties the FIFO and clears itself
+                                device.registers[usize::from(MPU_USER_CTRL)] &= !0x04;
+                                if let Some(fifo) = device.fifo.as_mut() {
+                                    fifo.clear();
+                                }
+                                device.update_mpu_fifo_count();
+                            }
+                            device.pointer = device.pointer.wrapping_add(1);
+                        }
+                    }
+                },
+                Operation::Read(buffer) => {
+                    for byte in buffer.iter_mut() {
+                        match device.fifo.as_mut() {
+                            Some(fifo) if device.fifo_kind == FifoKind::Mpu && device.pointer == MPU_FIFO_R_W => {
+                                *byte = fifo.pop_front().unwrap_or(0);
+                                device.update_mpu_fifo_count();
+                            },
+                            Some(_) if device.fifo_kind == FifoKind::Mpu && device.pointer == MPU_INT_STATUS => {
+                                // Reading INT_STATUS clears it
+                                *byte = device.registers[usize::from(MPU_INT_STATUS)];
+                                device.registers[usize::from(MPU_INT_STATUS)] = 0;
+                                device.pointer = device.pointer.wrapping_add(1);
+                            },
+                            Some(fifo) if device.fifo_kind == FifoKind::Max30102 && device.pointer == FIFO_DATA => {
+                                *byte = fifo.pop_front().unwrap_or(0);
+                                // One sample is two LEDs of three bytes each
+                                if fifo.len() % 6 == 0 {
+                                    let read_pointer = &mut device.registers[usize::from(FIFO_RD_PTR)];
+                                    *read_pointer = (*read_pointer + 1) % 32;
+                                }
+                            },
+                            _ => {
+                                *byte = device.registers[usize::from(device.pointer)];
+                                device.pointer = device.pointer.wrapping_add(1);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
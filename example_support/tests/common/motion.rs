@@ -0,0 +1,273 @@
+//! Scripted motion of a simulated IMU, with the true orientation alongside every sample.
+//!
+//! The world frame has Z pointing up and X pointing to magnetic north, and
+//! orientations are ZYX Euler angles (yaw, then pitch, then roll), which is the
+//! convention of `Tilt::from_acceleration`. At rest the accelerometer reads +1 g on
+//! whichever body axis points up.
+
+use example_support::imu::ImuSample;
+use example_support::quaternion::Quaternion;
+use example_support::units::{Temperature, Vector3};
+
+/// Earth's field in the world frame, in µT: 20 µT north, dipping 65° downwards.
+pub const EARTH_FIELD: [f64; 3] = [20.0, 0.0, -42.9];
+
+/// Step of the true motion, finer than any sample period used in the tests.
+const TRUTH_STEP_S: f64 = 0.000_5;
+
+#[derive(Debug, Clone, Copy)]
+struct Segment {
+    duration_s: f64,
+    /// Body angular rate in °/s.
+    rate_dps: [f64; 3],
+    /// Acceleration on top of gravity, in the world frame, in g.
+    linear_g: [f64; 3],
+}
+
+/// A sample together with the orientation it was taken at.
+#[derive(Debug, Clone, Copy)]
+pub struct TruthSample {
+    pub sample: ImuSample,
+    /// Body to world rotation, `[w, x, y, z]`.
+    pub quaternion: [f64; 4],
+    pub roll: f32,
+    pub pitch: f32,
+    pub yaw: f32,
+    /// Body angular rate without bias or noise, in °/s.
+    pub rate_dps: [f32; 3],
+}
+
+#[derive(Debug, Clone)]
+pub struct MotionProfile {
+    sample_period_s: f64,
+    /// Sample periods cycle through `sample_period_s` times these factors.
+    jitter: Vec<f64>,
+    initial: [f64; 4],
+    segments: Vec<Segment>,
+    gyro_bias_dps: [f64; 3],
+    accel_noise_g: f64,
+    gyro_noise_dps: f64,
+    magnetometer: bool,
+    seed: u64,
+}
+
+impl MotionProfile {
+    pub fn new(sample_rate_hz: f64) -> Self {
+        Self {
+            sample_period_s: 1.0 / sample_rate_hz,
+            jitter: vec![1.0],
+            initial: [1.0, 0.0, 0.0, 0.0],
+            segments: Vec::new(),
+            gyro_bias_dps: [0.0; 3],
+            accel_noise_g: 0.0,
+            gyro_noise_dps: 0.0,
+            magnetometer: false,
+            seed: 1,
+        }
+    }
+
+    /// Starting orientation as ZYX Euler angles in degrees.
+    pub fn starting_at(mut self, roll: f64, pitch: f64, yaw: f64) -> Self {
+        self.initial = from_euler(roll.to_radians(), pitch.to_radians(), yaw.to_radians());
+        self
+    }
+
+    pub fn hold(self, duration_s: f64) -> Self {
+        self.rotate(duration_s, [0.0; 3])
+    }
+
+    /// Turns at a constant body rate in °/s.
+    pub fn rotate(mut self, duration_s: f64, rate_dps: [f64; 3]) -> Self {
+        self.segments.push(Segment {
+            duration_s,
+            rate_dps,
+            linear_g: [0.0; 3],
+        });
+        self
+    }
+
+    /// Holds the orientation while accelerating, e.g. being carried or shaken.
+    pub fn accelerate(mut self, duration_s: f64, linear_g: [f64; 3]) -> Self {
+        self.segments.push(Segment {
+            duration_s,
+            rate_dps: [0.0; 3],
+            linear_g,
+        });
+        self
+    }
+
+    pub fn with_gyro_bias(mut self, bias_dps: [f64; 3]) -> Self {
+        self.gyro_bias_dps = bias_dps;
+        self
+    }
+
+    /// Standard deviations of the white noise on every axis.
+    pub fn with_noise(mut self, accel_g: f64, gyro_dps: f64) -> Self {
+        self.accel_noise_g = accel_g;
+        self.gyro_noise_dps = gyro_dps;
+        self
+    }
+
+    pub fn with_jitter(mut self, factors: &[f64]) -> Self {
+        self.jitter = factors.to_vec();
+        self
+    }
+
+    pub fn with_magnetometer(mut self) -> Self {
+        self.magnetometer = true;
+        self
+    }
+
+    pub fn duration_s(&self) -> f64 {
+        self.segments.iter().map(|s| s.duration_s).sum()
+    }
+
+    pub fn simulate(&self) -> Vec<TruthSample> {
+        let mut rng = Rng(self.seed);
+        let mut samples = Vec::new();
+        let mut q = self.initial;
+        let mut time = 0.0;
+        let mut next_sample = 0.0;
+        let mut period = self.jitter.iter().cycle();
+        let end = self.duration_s();
+
+        for segment in self.segments.iter().chain(std::iter::once(&Segment {
+            duration_s: 0.0,
+            rate_dps: [0.0; 3],
+            linear_g: [0.0; 3],
+        })) {
+            let segment_end = time + segment.duration_s;
+            loop {
+                if next_sample <= time + 1e-9 && next_sample <= end + 1e-9 {
+                    samples.push(self.measure(&mut rng, q, segment, next_sample));
+                    next_sample += self.sample_period_s * period.next().unwrap();
+                }
+                if time >= segment_end - 1e-9 {
+                    break;
+                }
+                let step = TRUTH_STEP_S.min(segment_end - time).min((next_sample - time).max(1e-9));
+                let rate = segment.rate_dps.map(|r| r.to_radians() * step);
+                q = normalize(multiply(q, from_rotation_vector(rate)));
+                time += step;
+            }
+        }
+        samples
+    }
+
+    fn measure(&self, rng: &mut Rng, q: [f64; 4], segment: &Segment, time: f64) -> TruthSample {
+        let specific_force = add([0.0, 0.0, 1.0], segment.linear_g);
+        let acceleration = rotate_inverse(q, specific_force).map(|a| a + rng.gaussian() * self.accel_noise_g);
+        let mut angular_velocity = segment.rate_dps;
+        for (axis, rate) in angular_velocity.iter_mut().enumerate() {
+            *rate += self.gyro_bias_dps[axis] + rng.gaussian() * self.gyro_noise_dps;
+        }
+        let magnetic_field = self
+            .magnetometer
+            .then(|| Vector3::from_microtesla(rotate_inverse(q, EARTH_FIELD).map(|m| m as f32)));
+
+        let (roll, pitch, yaw) = to_euler(q);
+        TruthSample {
+            sample: ImuSample {
+                timestamp_us: (time * 1e6).round() as u64,
+                temperature: Temperature::from_celsius(25.0),
+                acceleration: Vector3::from_g(acceleration.map(|a| a as f32)),
+                angular_velocity: Vector3::from_dps(angular_velocity.map(|w| w as f32)),
+                magnetic_field,
+            },
+            quaternion: q,
+            roll: roll.to_degrees() as f32,
+            pitch: pitch.to_degrees() as f32,
+            yaw: yaw.to_degrees() as f32,
+            rate_dps: segment.rate_dps.map(|r| r as f32),
+        }
+    }
+}
+
+impl TruthSample {
+    /// The true orientation, in the type the filters produce.
+    pub fn orientation(&self) -> Quaternion {
+        let [w, x, y, z] = self.quaternion.map(|c| c as f32);
+        Quaternion::new(w, x, y, z)
+    }
+}
+
+/// Angle between two orientations in degrees.
+pub fn angle_between(a: [f64; 4], b: [f64; 4]) -> f64 {
+    let dot = (a[0] * b[0] + a[1] * b[1] + a[2] * b[2] + a[3] * b[3]).abs().min(1.0);
+    (2.0 * dot.acos()).to_degrees()
+}
+
+/// Difference of two angles in degrees, wrapped into [-180, 180].
+pub fn angle_difference(a: f32, b: f32) -> f32 {
+    let d = (a - b).rem_euclid(360.0);
+    if d > 180.0 { d - 360.0 } else { d }
+}
+
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn multiply(a: [f64; 4], b: [f64; 4]) -> [f64; 4] {
+    [
+        a[0] * b[0] - a[1] * b[1] - a[2] * b[2] - a[3] * b[3],
+        a[0] * b[1] + a[1] * b[0] + a[2] * b[3] - a[3] * b[2],
+        a[0] * b[2] - a[1] * b[3] + a[2] * b[0] + a[3] * b[1],
+        a[0] * b[3] + a[1] * b[2] - a[2] * b[1] + a[3] * b[0],
+    ]
+}
+
+fn normalize(q: [f64; 4]) -> [f64; 4] {
+    let norm = q.iter().map(|c| c * c).sum::<f64>().sqrt();
+    q.map(|c| c / norm)
+}
+
+fn from_rotation_vector(v: [f64; 3]) -> [f64; 4] {
+    let angle = (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+    if angle < 1e-12 {
+        return [1.0, 0.0, 0.0, 0.0];
+    }
+    let s = (angle / 2.0).sin() / angle;
+    [(angle / 2.0).cos(), v[0] * s, v[1] * s, v[2] * s]
+}
+
+/// Expresses a world frame vector in the body frame.
+fn rotate_inverse(q: [f64; 4], v: [f64; 3]) -> [f64; 3] {
+    let conjugate = [q[0], -q[1], -q[2], -q[3]];
+    let rotated = multiply(multiply(conjugate, [0.0, v[0], v[1], v[2]]), q);
+    [rotated[1], rotated[2], rotated[3]]
+}
+
+pub fn from_euler(roll: f64, pitch: f64, yaw: f64) -> [f64; 4] {
+    let (sr, cr) = (roll / 2.0).sin_cos();
+    let (sp, cp) = (pitch / 2.0).sin_cos();
+    let (sy, cy) = (yaw / 2.0).sin_cos();
+    [
+        cr * cp * cy + sr * sp * sy,
+        sr * cp * cy - cr * sp * sy,
+        cr * sp * cy + sr * cp * sy,
+        cr * cp * sy - sr * sp * cy,
+    ]
+}
+
+fn to_euler(q: [f64; 4]) -> (f64, f64, f64) {
+    let [w, x, y, z] = q;
+    let roll = (2.0 * (w * x + y * z)).atan2(1.0 - 2.0 * (x * x + y * y));
+    let pitch = (2.0 * (w * y - z * x)).clamp(-1.0, 1.0).asin();
+    let yaw = (2.0 * (w * z + x * y)).atan2(1.0 - 2.0 * (y * y + z * z));
+    (roll, pitch, yaw)
+}
+
+/// Deterministic noise, so failures reproduce.
+struct Rng(u64);
+
+impl Rng {
+    fn uniform(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Approximately standard normal: the sum of twelve uniforms, minus six.
+    fn gaussian(&mut self) -> f64 {
+        (0..12).map(|_| self.uniform()).sum::<f64>() - 6.0
+    }
+}
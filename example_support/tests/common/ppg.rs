@@ -0,0 +1,148 @@
+//! Synthetic PPG from a wrist or finger sensor, with an accelerometer next to it.
+//!
+//! Each beat is a systolic peak followed by a smaller dicrotic wave, the beat length
+//! varying by a few percent, on top of a slow breathing wander. Motion shows up in
+//! both LED channels as a delayed, filtered copy of the linear acceleration, which is
+//! what an adaptive noise canceller can learn.
+
+use std::collections::VecDeque;
+use std::f64::consts::PI;
+
+use example_support::imu::ImuSample;
+use example_support::units::{Temperature, Vector3};
+
+/// Samples per second of both sensors.
+pub const RATE_HZ: f64 = 100.0;
+const IR_DC: f64 = 100_000.0;
+const RED_DC: f64 = 60_000.0;
+/// Height of a beat in the IR channel.
+const IR_AC: f64 = 300.0;
+/// IR counts per g of linear acceleration along each axis, 30 ms and 60 ms later.
+const ARTIFACT: [[f64; 3]; 2] = [[1500.0, -600.0, 900.0], [-500.0, 400.0, -700.0]];
+/// The red channel sees the motion a little weaker.
+const RED_ARTIFACT: f64 = 0.6;
+
+/// One reading of both sensors.
+#[derive(Debug, Clone, Copy)]
+pub struct Reading {
+    pub imu: ImuSample,
+    pub red: f32,
+    pub ir: f32,
+    /// The IR reading without the motion artifact.
+    pub clean_ir: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Ppg {
+    bpm: f64,
+    /// Ratio of ratios of the red and IR pulses.
+    ratio: f64,
+    readings: Vec<Reading>,
+    time_s: f64,
+    beat_start_s: f64,
+    beat_s: f64,
+    /// Recent linear accelerations, newest first.
+    recent: VecDeque<[f64; 3]>,
+    rng: Rng,
+}
+
+impl Ppg {
+    pub fn new(bpm: f64) -> Self {
+        Self {
+            bpm,
+            ratio: 0.5,
+            readings: Vec::new(),
+            time_s: 0.0,
+            beat_start_s: 0.0,
+            beat_s: 60.0 / bpm,
+            recent: VecDeque::from(vec![[0.0; 3]; 7]),
+            rng: Rng(0x5eed),
+        }
+    }
+
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.rng = Rng(seed);
+        self
+    }
+
+    /// SpO2 in percent, under the default `Spo2Curve`.
+    pub fn with_spo2(mut self, spo2: f64) -> Self {
+        self.ratio = (104.0 - spo2) / 17.0;
+        self
+    }
+
+    pub fn still(self, duration_s: f64) -> Self {
+        self.generate(duration_s, |_| [0.0; 3])
+    }
+
+    /// Swinging the arm at `swing_hz` by up to `amplitude_g`, with a jolt now and then.
+    pub fn moving(self, duration_s: f64, swing_hz: f64, amplitude_g: f64) -> Self {
+        self.generate(duration_s, move |t| {
+            let angle = 2.0 * PI * swing_hz * t;
+            let jolt = amplitude_g * (6.0 * angle).sin().powi(8);
+            [amplitude_g * angle.sin(), 0.5 * amplitude_g * (angle + 1.0).sin() + jolt, 0.7 * amplitude_g * (2.0 * angle).cos()]
+        })
+    }
+
+    pub fn readings(&self) -> &[Reading] {
+        &self.readings
+    }
+
+    fn generate(mut self, duration_s: f64, linear_g: impl Fn(f64) -> [f64; 3]) -> Self {
+        let period = 1.0 / RATE_HZ;
+        let end = self.time_s + duration_s;
+        while self.time_s < end - 1e-9 {
+            let t = self.time_s;
+            while t >= self.beat_start_s + self.beat_s {
+                self.beat_start_s += self.beat_s;
+                self.beat_s = 60.0 / self.bpm * (1.0 + 0.03 * (2.0 * self.rng.uniform() - 1.0));
+            }
+            let phase = (t - self.beat_start_s) / self.beat_s;
+            let pulse = (-((phase - 0.2) / 0.08).powi(2)).exp() + 0.4 * (-((phase - 0.55) / 0.1).powi(2)).exp();
+            let breathing = 0.3 * (2.0 * PI * 0.25 * t).sin();
+            let ir = IR_DC + IR_AC * (pulse + breathing);
+            let red = RED_DC + RED_DC / IR_DC * self.ratio * IR_AC * (pulse + breathing);
+
+            let linear = linear_g(t);
+            self.recent.pop_back();
+            self.recent.push_front(linear);
+            // 30 ms and 60 ms ago
+            let delayed = [self.recent[3], self.recent[6]];
+            let artifact: f64 = ARTIFACT.iter().zip(delayed).flat_map(|(gains, a)| gains.iter().zip(a).map(|(g, a)| g * a)).sum();
+
+            let noise = 0.01;
+            let acceleration: [f32; 3] = std::array::from_fn(|axis| {
+                let gravity = if axis == 2 { 1.0 } else { 0.0 };
+                (gravity + linear[axis] + noise * self.rng.gaussian()) as f32
+            });
+            self.readings.push(Reading {
+                imu: ImuSample {
+                    timestamp_us: (t * 1e6).round() as u64,
+                    temperature: Temperature::from_celsius(30.0),
+                    acceleration: Vector3::from_g(acceleration),
+                    angular_velocity: Vector3::from_dps([0.0; 3]),
+                    magnetic_field: None,
+                },
+                red: (red + RED_ARTIFACT * artifact + 5.0 * self.rng.gaussian()) as f32,
+                ir: (ir + artifact + 5.0 * self.rng.gaussian()) as f32,
+                clean_ir: ir as f32,
+            });
+            self.time_s += period;
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn uniform(&mut self) -> f64 {
+        self.0 = self.0.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn gaussian(&mut self) -> f64 {
+        (0..12).map(|_| self.uniform()).sum::<f64>() - 6.0
+    }
+}
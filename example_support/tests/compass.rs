@@ -0,0 +1,104 @@
+mod common;
+
+use common::motion::{angle_difference, MotionProfile};
+use example_support::compass::{Compass, DeclinationGrid};
+use example_support::imu::ImuSample;
+use example_support::units::{MagneticField, Vector3};
+
+/// A reading held still at these angles.
+fn still_at(roll: f64, pitch: f64, yaw: f64) -> ImuSample {
+    MotionProfile::new(100.0).starting_at(roll, pitch, yaw).hold(0.01).with_magnetometer().simulate()[0].sample
+}
+
+#[test]
+fn heading_holds_while_tilted() {
+    let mut compass = Compass::new(0.0);
+    for yaw in [0.0, 30.0, 95.0, 180.0, -60.0, -135.0] {
+        for (roll, pitch) in [(0.0, 0.0), (25.0, 0.0), (0.0, -30.0), (-40.0, 35.0), (60.0, 50.0)] {
+            let sample = still_at(roll, pitch, yaw);
+            let heading = compass.heading(sample.acceleration, sample.magnetic_field.unwrap()).unwrap();
+            // Yaw is counter-clockwise, compass headings are clockwise
+            let expected = (-yaw as f32).rem_euclid(360.0);
+            assert!(
+                angle_difference(heading.magnetic, expected).abs() < 0.1,
+                "{roll} {pitch} {yaw}: {heading:?}"
+            );
+            assert!((0.0..360.0).contains(&heading.magnetic));
+            assert!(!heading.disturbed);
+        }
+    }
+}
+
+#[test]
+fn declination_turns_magnetic_into_true_north() {
+    // Heading 355° magnetic, where magnetic north is 10° east of true north
+    let sample = still_at(10.0, -5.0, 5.0);
+    let mut compass = Compass::new(10.0);
+    let heading = compass.heading(sample.acceleration, sample.magnetic_field.unwrap()).unwrap();
+    assert!((heading.magnetic - 355.0).abs() < 0.1, "{heading:?}");
+    assert!((heading.true_north - 5.0).abs() < 0.1, "{heading:?}");
+
+    compass.set_declination(-3.5);
+    assert_eq!(compass.declination(), -3.5);
+    let heading = compass.heading(sample.acceleration, sample.magnetic_field.unwrap()).unwrap();
+    assert!((heading.true_north - 351.5).abs() < 0.1, "{heading:?}");
+}
+
+#[test]
+fn flags_a_field_of_the_wrong_strength() {
+    let sample = still_at(0.0, 0.0, 40.0);
+    let field = sample.magnetic_field.unwrap();
+    let mut compass = Compass::new(0.0);
+    for _ in 0..100 {
+        assert!(!compass.heading(sample.acceleration, field).unwrap().disturbed);
+    }
+    let reference = compass.reference().unwrap().as_microtesla();
+    assert!((reference - field.norm().as_microtesla()).abs() < 0.01);
+
+    // A magnet close by, and the heading it gives is not to be trusted
+    let disturbed = compass.heading(sample.acceleration, field * 1.4).unwrap();
+    assert!(disturbed.disturbed);
+    assert_eq!(compass.reference().unwrap().as_microtesla(), reference);
+
+    // Within the tolerance, the reference follows slowly
+    assert!(!compass.heading(sample.acceleration, field * 1.1).unwrap().disturbed);
+    assert!(compass.reference().unwrap().as_microtesla() > reference);
+}
+
+#[test]
+fn fixed_field_strength_is_not_learned() {
+    let sample = still_at(0.0, 0.0, 0.0);
+    let field = sample.magnetic_field.unwrap();
+    let mut compass = Compass::new(0.0).with_field_strength(MagneticField::from_microtesla(60.0)).with_tolerance(0.1);
+    // The Earth's field in the simulation is 47 µT, more than 10% weaker
+    for _ in 0..100 {
+        assert!(compass.heading(sample.acceleration, field).unwrap().disturbed);
+    }
+    assert_eq!(compass.reference(), Some(MagneticField::from_microtesla(60.0)));
+}
+
+#[test]
+fn a_vertical_field_has_no_heading() {
+    let sample = still_at(0.0, 0.0, 0.0);
+    let field = Vector3::from_microtesla([0.0, 0.0, -45.0]);
+    assert_eq!(Compass::new(0.0).heading(sample.acceleration, field), None);
+}
+
+#[test]
+fn declination_grid_interpolates_between_points() {
+    // 2 rows and 3 columns, 10° apart, from 40° N 10° W
+    static VALUES: [i16; 6] = [100, 200, 400, 300, 400, 800];
+    let grid = DeclinationGrid { south: 40.0, west: -10.0, step: 10.0, columns: 3, values: &VALUES };
+
+    assert_eq!(grid.declination(40.0, -10.0), Some(1.0));
+    assert_eq!(grid.declination(50.0, 10.0), Some(8.0));
+    assert!((grid.declination(45.0, -5.0).unwrap() - 2.5).abs() < 1e-4);
+    assert!((grid.declination(42.0, 5.0).unwrap() - 3.6).abs() < 1e-4);
+    // The same longitude written the other way round
+    assert_eq!(grid.declination(40.0, 350.0), Some(1.0));
+
+    assert_eq!(grid.declination(39.0, 0.0), None);
+    assert_eq!(grid.declination(51.0, 0.0), None);
+    assert_eq!(grid.declination(45.0, 15.0), None);
+    assert_eq!(grid.declination(45.0, -15.0), None);
+}
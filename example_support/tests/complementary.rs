@@ -0,0 +1,108 @@
+mod common;
+
+use common::motion::{angle_difference, MotionProfile, TruthSample};
+use example_support::complementary::ComplementaryFilter;
+
+/// Largest roll or pitch error over the samples, skipping the first `settle_s`.
+fn worst_error(filter: &mut ComplementaryFilter, samples: &[TruthSample], settle_s: f64) -> f32 {
+    let mut worst: f32 = 0.0;
+    for truth in samples {
+        let tilt = filter.update(&truth.sample);
+        if truth.sample.timestamp_us as f64 >= settle_s * 1e6 {
+            worst = worst
+                .max(angle_difference(tilt.roll, truth.roll).abs())
+                .max(angle_difference(tilt.pitch, truth.pitch).abs());
+        }
+    }
+    worst
+}
+
+#[test]
+fn starts_from_the_accelerometer_tilt() {
+    let samples = MotionProfile::new(100.0).starting_at(20.0, -10.0, 0.0).hold(0.1).simulate();
+    let mut filter = ComplementaryFilter::new(0.5);
+
+    let tilt = filter.update(&samples[0].sample);
+    assert!((tilt.roll - 20.0).abs() < 0.01, "{tilt:?}");
+    assert!((tilt.pitch + 10.0).abs() < 0.01, "{tilt:?}");
+}
+
+#[test]
+fn follows_a_scripted_motion() {
+    let samples = MotionProfile::new(200.0)
+        .hold(1.0)
+        .rotate(1.0, [45.0, 0.0, 0.0])
+        .hold(1.0)
+        .rotate(1.0, [0.0, -30.0, 0.0])
+        .hold(1.0)
+        .rotate(2.0, [-20.0, 10.0, 30.0])
+        .hold(1.0)
+        .with_noise(0.01, 0.2)
+        .simulate();
+
+    let mut filter = ComplementaryFilter::new(0.5);
+    assert!(worst_error(&mut filter, &samples, 0.0) < 1.5);
+}
+
+#[test]
+fn gyro_bias_does_not_accumulate() {
+    let samples = MotionProfile::new(100.0)
+        .rotate(1.0, [30.0, 0.0, 0.0])
+        .hold(20.0)
+        .with_gyro_bias([2.0, -2.0, 1.0])
+        .simulate();
+
+    // Integrating alone would be off by 40 degrees after 20 s
+    let mut filter = ComplementaryFilter::new(0.5);
+    worst_error(&mut filter, &samples, 0.0);
+    let tilt = filter.tilt();
+    let truth = samples.last().unwrap();
+    assert!(angle_difference(tilt.roll, truth.roll).abs() < 1.0, "{tilt:?}");
+    assert!(angle_difference(tilt.pitch, truth.pitch).abs() < 1.0, "{tilt:?}");
+}
+
+#[test]
+fn uses_the_real_time_between_samples() {
+    let samples = MotionProfile::new(100.0)
+        .rotate(1.5, [0.0, 40.0, 0.0])
+        .hold(0.5)
+        .rotate(1.5, [60.0, 0.0, 0.0])
+        .with_jitter(&[0.5, 1.5, 1.0, 2.5, 0.5])
+        .simulate();
+
+    let mut filter = ComplementaryFilter::new(0.2);
+    assert!(worst_error(&mut filter, &samples, 0.0) < 1.0);
+}
+
+#[test]
+fn rolls_through_upside_down() {
+    let samples = MotionProfile::new(200.0).rotate(4.0, [90.0, 0.0, 0.0]).simulate();
+    let mut filter = ComplementaryFilter::new(1.0);
+    assert!(worst_error(&mut filter, &samples, 0.0) < 1.0);
+}
+
+#[test]
+fn lower_crossover_rejects_more_acceleration() {
+    // Being pushed sideways at 0.5 g looks like a 27 degree roll to the accelerometer
+    let samples = MotionProfile::new(100.0).hold(1.0).accelerate(0.3, [0.0, 0.5, 0.0]).simulate();
+
+    let slow = worst_error(&mut ComplementaryFilter::new(0.1), &samples, 0.0);
+    let fast = worst_error(&mut ComplementaryFilter::new(2.0), &samples, 0.0);
+    assert!(slow < 5.0, "{slow}");
+    assert!(fast > 15.0, "{fast}");
+}
+
+#[test]
+fn restarts_after_a_long_gap() {
+    let samples = MotionProfile::new(100.0).starting_at(0.0, 0.0, 0.0).hold(0.5).simulate();
+    let later = MotionProfile::new(100.0).starting_at(45.0, 0.0, 0.0).hold(0.1).simulate();
+    let mut filter = ComplementaryFilter::new(0.1);
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+
+    let mut resumed = later[0].sample;
+    resumed.timestamp_us = samples.last().unwrap().sample.timestamp_us + 5_000_000;
+    let tilt = filter.update(&resumed);
+    assert!((tilt.roll - 45.0).abs() < 0.01, "{tilt:?}");
+}
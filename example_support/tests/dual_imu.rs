@@ -0,0 +1,63 @@
+use core::cell::Cell;
+
+use example_support::dual_imu::{DualSampler, PairError, RelativeOrientation};
+use example_support::units::{Acceleration, Vector3};
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 0.01, "{actual} != {expected}");
+}
+
+/// Gravity as seen by a unit pitched nose-up by `degrees`.
+fn pitched(degrees: f32) -> Vector3<Acceleration> {
+    let radians = degrees.to_radians();
+    Vector3::from_g([-radians.sin(), 0.0, radians.cos()])
+}
+
+#[test]
+fn relative_pitch_and_included_angle_of_a_bent_joint() {
+    let relative = RelativeOrientation::between(pitched(10.0), pitched(55.0));
+    assert_close(relative.pitch, 45.0);
+    assert_close(relative.roll, 0.0);
+    assert_close(relative.included_angle, 45.0);
+}
+
+#[test]
+fn relative_roll_wraps_around() {
+    // Roll of +170 on one unit and -170 on the other is only 20 degrees apart
+    let roll = |degrees: f32| {
+        let radians = degrees.to_radians();
+        Vector3::from_g([0.0, radians.sin(), radians.cos()])
+    };
+    let relative = RelativeOrientation::between(roll(170.0), roll(-170.0));
+    assert_close(relative.roll, 20.0);
+    assert_close(relative.included_angle, 20.0);
+}
+
+#[test]
+fn sampler_alternates_read_order() {
+    let clock = Cell::new(0u64);
+    let now_us = || {
+        clock.set(clock.get() + 100);
+        clock.get()
+    };
+    let mut sampler = DualSampler::new();
+
+    let first = sampler.sample(|| Ok::<_, ()>('a'), || Ok('b'), now_us).unwrap();
+    let second = sampler.sample(|| Ok::<_, ()>('a'), || Ok('b'), now_us).unwrap();
+
+    assert_eq!((first.a, first.b), ('a', 'b'));
+    assert_eq!(first.skew_us, 100);
+    assert_eq!(second.skew_us, -100);
+}
+
+#[test]
+fn sampler_reports_the_first_error() {
+    let mut sampler = DualSampler::new();
+    let result = sampler.sample(|| Ok(1), || Err("unit b missing"), || 0);
+    assert_eq!(result, Err(PairError::B("unit b missing")));
+    // B is read first this time, and A is never reached
+    let result = sampler.sample(|| Ok(1), || Err("unit b missing"), || 0);
+    assert_eq!(result, Err(PairError::B("unit b missing")));
+    let result = sampler.sample(|| Err("unit a missing"), || Ok(2), || 0);
+    assert_eq!(result, Err(PairError::A("unit a missing")));
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::motion::{angle_difference, MotionProfile, TruthSample};
+use example_support::ekf::{Ekf, Noise};
+use example_support::madgwick::Madgwick;
+
+/// Feeds every sample and returns the largest orientation error after `settle_s`.
+fn worst_error(filter: &mut Ekf, samples: &[TruthSample], settle_s: f64) -> f32 {
+    let mut worst: f32 = 0.0;
+    for truth in samples {
+        let q = filter.update(&truth.sample);
+        if truth.sample.timestamp_us as f64 >= settle_s * 1e6 {
+            worst = worst.max(q.angle_to(truth.orientation()));
+        }
+    }
+    worst
+}
+
+fn assert_bias(filter: &Ekf, bias: [f64; 3], axes: &[usize], tolerance: f32) {
+    let estimate = filter.gyro_bias().as_dps();
+    for &axis in axes {
+        assert!(
+            (estimate[axis] - bias[axis] as f32).abs() < tolerance,
+            "axis {axis}: {estimate:?} vs {bias:?}"
+        );
+    }
+}
+
+#[test]
+fn starts_from_the_first_sample() {
+    let samples = MotionProfile::new(100.0).starting_at(20.0, -35.0, 150.0).hold(0.1).with_magnetometer().simulate();
+    let mut filter = Ekf::default();
+    let q = filter.update(&samples[0].sample);
+    assert!(q.angle_to(samples[0].orientation()) < 0.1);
+}
+
+#[test]
+fn follows_an_mpu9250_motion_profile() {
+    let samples = MotionProfile::new(200.0)
+        .hold(1.0)
+        .rotate(2.0, [45.0, 0.0, 0.0])
+        .rotate(2.0, [0.0, -30.0, 60.0])
+        .hold(1.0)
+        .rotate(3.0, [-20.0, 10.0, -90.0])
+        .hold(1.0)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Ekf::default();
+    let worst = worst_error(&mut filter, &samples, 0.0);
+    assert!(worst < 2.0, "{worst}");
+}
+
+#[test]
+fn estimates_the_gyro_bias_with_a_magnetometer() {
+    let bias = [1.5, -2.0, 1.0];
+    let samples = MotionProfile::new(100.0)
+        .starting_at(10.0, -10.0, 30.0)
+        .hold(5.0)
+        .rotate(4.0, [20.0, 0.0, 30.0])
+        .hold(5.0)
+        .rotate(4.0, [0.0, -25.0, -30.0])
+        .hold(20.0)
+        .with_gyro_bias(bias)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Ekf::default();
+    let worst = worst_error(&mut filter, &samples, 20.0);
+    assert!(worst < 1.5, "{worst}");
+    assert_bias(&filter, bias, &[0, 1, 2], 0.2);
+}
+
+#[test]
+fn estimates_the_level_gyro_bias_of_an_mpu6050() {
+    // Without a magnetometer only the bias about the horizontal axes shows up as
+    // disagreement with gravity
+    let bias = [2.0, -1.5, 0.0];
+    let samples = MotionProfile::new(100.0)
+        .starting_at(5.0, 5.0, 0.0)
+        .hold(30.0)
+        .with_gyro_bias(bias)
+        .with_noise(0.01, 0.2)
+        .simulate();
+
+    let mut filter = Ekf::default();
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+    assert_bias(&filter, bias, &[0, 1], 0.2);
+
+    let angles = filter.euler();
+    let truth = samples.last().unwrap();
+    assert!(angle_difference(angles.roll, truth.roll).abs() < 0.5, "{angles:?}");
+    assert!(angle_difference(angles.pitch, truth.pitch).abs() < 0.5, "{angles:?}");
+}
+
+#[test]
+fn bias_estimate_removes_drift_that_madgwick_keeps() {
+    let samples = MotionProfile::new(100.0)
+        .starting_at(-15.0, 10.0, 0.0)
+        .hold(60.0)
+        .with_gyro_bias([3.0, 3.0, 0.0])
+        .simulate();
+
+    let mut ekf = Ekf::default();
+    let mut madgwick = Madgwick::default();
+    madgwick.reset(samples[0].orientation());
+    for truth in &samples {
+        ekf.update(&truth.sample);
+        madgwick.update(&truth.sample);
+    }
+
+    let truth = samples.last().unwrap();
+    let tilt_error = |roll: f32, pitch: f32| {
+        angle_difference(roll, truth.roll).abs().max(angle_difference(pitch, truth.pitch).abs())
+    };
+    let ekf_error = tilt_error(ekf.euler().roll, ekf.euler().pitch);
+    let madgwick_error = tilt_error(madgwick.euler().roll, madgwick.euler().pitch);
+    assert!(ekf_error < 0.3, "{ekf_error}");
+    assert!(madgwick_error > 3.0 * ekf_error, "{madgwick_error} vs {ekf_error}");
+}
+
+#[test]
+fn uncertainty_shrinks_as_evidence_accumulates() {
+    let samples = MotionProfile::new(100.0)
+        .hold(2.0)
+        .rotate(3.0, [30.0, 20.0, 0.0])
+        .hold(10.0)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Ekf::default();
+    filter.update(&samples[0].sample);
+    let before = filter.bias_uncertainty().as_dps();
+    for truth in &samples[1..] {
+        filter.update(&truth.sample);
+    }
+    let after = filter.bias_uncertainty().as_dps();
+    for (before, after) in before.iter().zip(after) {
+        assert!(after < before / 3.0, "{after} vs {before}");
+    }
+    assert!(filter.attitude_uncertainty().iter().all(|&std| std < 2.0));
+}
+
+#[test]
+fn accel_noise_trades_tracking_for_acceleration_rejection() {
+    // A push sideways and slightly down that keeps the magnitude at 1 g, so only the
+    // configured noise protects the estimate, and that looks like a 30 degree roll.
+    // The filters settle first, so the push is not mistaken for a gyro bias they are
+    // still unsure about.
+    let samples = MotionProfile::new(100.0)
+        .hold(20.0)
+        .accelerate(0.5, [0.0, 0.5, -0.134])
+        .hold(0.5)
+        .with_noise(0.01, 0.2)
+        .simulate();
+
+    let worst_roll = |noise: Noise| {
+        let mut filter = Ekf::new(noise);
+        let mut worst: f32 = 0.0;
+        for truth in &samples {
+            filter.update(&truth.sample);
+            if truth.sample.timestamp_us >= 20_000_000 {
+                worst = worst.max(angle_difference(filter.euler().roll, truth.roll).abs());
+            }
+        }
+        worst
+    };
+    let trusting = Noise { accel: 0.005, ..Noise::default() };
+    let skeptical = Noise { accel: 0.5, ..Noise::default() };
+    let trusting_error = worst_roll(trusting);
+    let skeptical_error = worst_roll(skeptical);
+    assert!(skeptical_error < 5.0, "{skeptical_error}");
+    assert!(trusting_error > 5.0 * skeptical_error, "{trusting_error} vs {skeptical_error}");
+
+    let mut filter = Ekf::new(trusting);
+    filter.set_noise(skeptical);
+    assert_eq!(filter.noise(), skeptical);
+}
+
+#[test]
+fn realigns_after_a_long_gap() {
+    let samples = MotionProfile::new(100.0).hold(0.5).with_magnetometer().simulate();
+    let later = MotionProfile::new(100.0).starting_at(30.0, 0.0, 90.0).hold(0.1).with_magnetometer().simulate();
+    let mut filter = Ekf::default();
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+
+    let mut resumed = later[0].sample;
+    resumed.timestamp_us = samples.last().unwrap().sample.timestamp_us + 5_000_000;
+    let q = filter.update(&resumed);
+    assert!(q.angle_to(later[0].orientation()) < 0.1);
+}
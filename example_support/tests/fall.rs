@@ -0,0 +1,152 @@
+mod common;
+
+use common::activity::Trace;
+use example_support::fall::{FallConfig, FallDetector, FallEvent, FallStage};
+use example_support::units::Acceleration;
+
+const RATE_HZ: f64 = 100.0;
+
+fn events(detector: &mut FallDetector, trace: &Trace) -> Vec<FallEvent> {
+    trace.samples().iter().filter_map(|sample| detector.update(sample)).collect()
+}
+
+/// A sharp jolt centred `after_s` into the part, as the body hits the ground.
+fn impact(trace: Trace, peak_g: f64, after_s: f64) -> Trace {
+    let at = trace.duration_s() + after_s;
+    trace.custom(0.2, move |t| [0.0, 0.0, (peak_g - 1.0) * (-((t - at) / 0.015).powi(2)).exp()])
+}
+
+/// Drops for `drop_s`, lands in the given orientation and lies there. Returns the
+/// trace and the time of the impact.
+fn fall(trace: Trace, drop_s: f64, peak_g: f64, roll: f64, pitch: f64) -> (Trace, f64) {
+    let falling = trace.custom(drop_s, |_| [0.0, 0.0, -0.92]).tilted(roll, pitch);
+    let at = falling.duration_s() + 0.05;
+    (impact(falling, peak_g, 0.05).still(6.0), at)
+}
+
+#[test]
+fn detects_a_forward_fall() {
+    let (trace, impact_s) = fall(Trace::new(RATE_HZ).walk(6.0, 105.0, 0.3), 0.4, 5.0, 0.0, 80.0);
+    let mut detector = FallDetector::default();
+    let events = events(&mut detector, &trace.still(10.0));
+
+    assert_eq!(events.len(), 1, "{events:?}");
+    let event = events[0];
+    assert!((event.timestamp_us as f64 / 1e6 - impact_s).abs() < 0.03, "{event:?}");
+    assert!(event.free_fall_ms.abs_diff(400) <= 30, "{event:?}");
+    assert!((event.impact.as_g() - 5.0).abs() < 0.3, "{event:?}");
+    assert!((event.orientation_change - 80.0).abs() < 5.0, "{event:?}");
+    assert_eq!(detector.stage(), FallStage::Monitoring);
+}
+
+#[test]
+fn detects_a_fall_to_the_side() {
+    let (trace, _) = fall(Trace::new(RATE_HZ).still(3.0), 0.3, 3.5, -90.0, 0.0);
+    let events = events(&mut FallDetector::default(), &trace);
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert!((events[0].orientation_change - 90.0).abs() < 5.0, "{events:?}");
+}
+
+#[test]
+fn does_not_depend_on_the_sample_rate() {
+    for rate_hz in [50.0, 200.0] {
+        let (trace, _) = fall(Trace::new(rate_hz).walk(6.0, 105.0, 0.3), 0.4, 5.0, 0.0, 80.0);
+        let events = events(&mut FallDetector::default(), &trace.still(4.0));
+        assert_eq!(events.len(), 1, "{rate_hz} Hz: {events:?}");
+        assert!((events[0].orientation_change - 80.0).abs() < 5.0, "{rate_hz} Hz: {events:?}");
+    }
+}
+
+#[test]
+fn a_read_outage_after_the_impact_abandons_the_fall() {
+    let (trace, impact_s) = fall(Trace::new(RATE_HZ).still(3.0), 0.4, 5.0, 0.0, 80.0);
+    // Nothing arrives from just after the body comes to rest until the end of the
+    // inactivity window, so the few samples either side of the gap prove nothing
+    let lost = (impact_s + 1.1)..(impact_s + 3.0);
+    let mut detector = FallDetector::default();
+    let events: Vec<_> = trace
+        .samples()
+        .iter()
+        .filter(|sample| !lost.contains(&(sample.timestamp_us as f64 / 1e6)))
+        .filter_map(|sample| detector.update(sample))
+        .collect();
+    assert_eq!(events, []);
+    assert_eq!(detector.stage(), FallStage::Monitoring);
+}
+
+#[test]
+fn sitting_down_hard_is_not_a_fall() {
+    // A quick drop onto a chair, leaning back a little
+    let dropping = Trace::new(RATE_HZ).walk(5.0, 100.0, 0.3).still(1.0).custom(0.35, |_| [0.0, 0.0, -0.55]).tilted(0.0, 25.0);
+    let trace = impact(dropping, 1.8, 0.05).still(6.0);
+    assert_eq!(events(&mut FallDetector::default(), &trace), []);
+
+    // Even if the landing is as hard as a fall, the orientation barely changes
+    let dropping = Trace::new(RATE_HZ).still(3.0).custom(0.35, |_| [0.0, 0.0, -0.55]).tilted(0.0, 25.0);
+    let trace = impact(dropping, 3.0, 0.05).still(6.0);
+    assert_eq!(events(&mut FallDetector::default(), &trace), []);
+}
+
+#[test]
+fn jumping_is_not_a_fall() {
+    // Push off, fly, land hard, and walk on
+    let flying = Trace::new(RATE_HZ).still(3.0).custom(0.25, |_| [0.0, 0.0, 1.0]).custom(0.45, |_| [0.0, 0.0, -1.0]);
+    let trace = impact(flying, 4.0, 0.05).walk(6.0, 110.0, 0.3);
+    assert_eq!(events(&mut FallDetector::default(), &trace), []);
+
+    // Or stand still after landing, upright as before
+    let flying = Trace::new(RATE_HZ).still(3.0).custom(0.25, |_| [0.0, 0.0, 1.0]).custom(0.45, |_| [0.0, 0.0, -1.0]);
+    let trace = impact(flying, 4.0, 0.05).still(6.0);
+    assert_eq!(events(&mut FallDetector::default(), &trace), []);
+}
+
+#[test]
+fn lying_down_is_not_a_fall() {
+    let trace = Trace::new(RATE_HZ).still(3.0).custom(1.5, |_| [0.0, 0.0, -0.2]).tilted(0.0, 90.0).still(10.0);
+    assert_eq!(events(&mut FallDetector::default(), &trace), []);
+}
+
+#[test]
+fn getting_up_right_away_cancels_the_fall() {
+    let falling = Trace::new(RATE_HZ).still(3.0).custom(0.4, |_| [0.0, 0.0, -0.92]).tilted(0.0, 80.0);
+    let trace = impact(falling, 5.0, 0.05).still(1.5).tilted(0.0, 0.0).walk(6.0, 100.0, 0.3);
+    assert_eq!(events(&mut FallDetector::default(), &trace), []);
+}
+
+#[test]
+fn thresholds_are_configurable() {
+    let (trace, _) = fall(Trace::new(RATE_HZ).still(3.0), 0.4, 3.0, 0.0, 80.0);
+
+    let hard = FallConfig { impact: Acceleration::from_g(4.0), ..FallConfig::default() };
+    assert_eq!(events(&mut FallDetector::new(hard), &trace), []);
+
+    let sideways_only = FallConfig { min_orientation_change: 85.0, ..FallConfig::default() };
+    assert_eq!(events(&mut FallDetector::new(sideways_only), &trace), []);
+
+    let mut detector = FallDetector::default();
+    assert_eq!(events(&mut detector, &trace).len(), 1);
+    assert_eq!(detector.config(), FallConfig::default());
+}
+
+#[test]
+fn walks_through_the_stages() {
+    let (trace, _) = fall(Trace::new(RATE_HZ).with_noise(0.0).still(2.0), 0.4, 5.0, 0.0, 80.0);
+    let mut detector = FallDetector::default();
+    let mut stages = Vec::new();
+    for sample in trace.samples() {
+        detector.update(sample);
+        let stage = std::mem::discriminant(&detector.stage());
+        if stages.last() != Some(&stage) {
+            stages.push(stage);
+        }
+    }
+    let expected = [
+        FallStage::Monitoring,
+        FallStage::FreeFall { started_us: 0 },
+        FallStage::AwaitingImpact { free_fall_ms: 0, ended_us: 0 },
+        FallStage::PostImpact { free_fall_ms: 0, impact_us: 0, impact: Acceleration::ZERO },
+        FallStage::Monitoring,
+    ]
+    .map(|stage| std::mem::discriminant(&stage));
+    assert_eq!(stages, expected);
+}
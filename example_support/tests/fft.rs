@@ -0,0 +1,81 @@
+use std::f64::consts::PI;
+
+use example_support::fft::{bin_power, real_fft, Window};
+
+/// A DFT straight from the definition, `(re, im)` of bins `0..=N / 2`.
+fn dft(samples: &[f32]) -> Vec<(f64, f64)> {
+    let n = samples.len();
+    (0..=n / 2)
+        .map(|k| {
+            samples.iter().enumerate().fold((0.0, 0.0), |(re, im), (i, &x)| {
+                let angle = -2.0 * PI * (k * i) as f64 / n as f64;
+                (re + f64::from(x) * angle.cos(), im + f64::from(x) * angle.sin())
+            })
+        })
+        .collect()
+}
+
+fn check<const N: usize>(samples: [f32; N]) {
+    let expected = dft(&samples);
+    let mut data = samples;
+    real_fft(&mut data);
+    let scale = expected.iter().map(|(re, im)| re.hypot(*im)).fold(1.0, f64::max);
+    let actual = |k: usize| match k {
+        0 => (data[0], 0.0),
+        k if k == N / 2 => (data[1], 0.0),
+        k => (data[2 * k], data[2 * k + 1]),
+    };
+    for (k, &(re, im)) in expected.iter().enumerate() {
+        let (actual_re, actual_im) = actual(k);
+        assert!(
+            (f64::from(actual_re) - re).abs() < 1e-4 * scale && (f64::from(actual_im) - im).abs() < 1e-4 * scale,
+            "N = {N}, bin {k}: ({actual_re}, {actual_im}) != ({re}, {im})"
+        );
+        assert!((f64::from(bin_power(&data, k)) - (re * re + im * im)).abs() < 1e-3 * scale * scale);
+    }
+}
+
+#[test]
+fn matches_the_dft() {
+    check([1.0, 2.0, -3.0, 0.5]);
+    check(std::array::from_fn::<f32, 16, _>(|i| i as f32));
+    // Pseudo-random samples at a few sizes
+    let mut state = 0x1234_5678u32;
+    let mut random = || {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as f32 / u32::MAX as f32 - 0.5
+    };
+    check(std::array::from_fn::<f32, 64, _>(|_| random()));
+    check(std::array::from_fn::<f32, 1024, _>(|_| random()));
+}
+
+#[test]
+fn places_a_sine_in_its_bin() {
+    let mut data: [f32; 256] = std::array::from_fn(|i| (2.0 * PI * 10.0 * i as f64 / 256.0).sin() as f32);
+    real_fft(&mut data);
+    // A sine of amplitude A puts A·N/2 into its bin, nothing anywhere else
+    assert!((bin_power(&data, 10).sqrt() - 128.0).abs() < 1e-2);
+    for k in (0..=128).filter(|&k| k != 10) {
+        assert!(bin_power(&data, k).sqrt() < 1e-2, "bin {k}");
+    }
+}
+
+#[test]
+fn window_gains() {
+    for (window, mean, mean_square) in [
+        (Window::Rectangular, 1.0, 1.0),
+        (Window::Hann, 0.5, 0.375),
+        (Window::FlatTop, 0.2156, 0.1752),
+    ] {
+        let mut samples = [1.0f32; 512];
+        let (actual_mean, actual_mean_square) = window.apply(&mut samples);
+        assert!((actual_mean - mean).abs() < 1e-3, "{window:?}: {actual_mean}");
+        assert!((actual_mean_square - mean_square).abs() < 1e-3, "{window:?}: {actual_mean_square}");
+        assert_eq!(samples[0], window.weight(0, 512));
+    }
+    // Tapered to zero at the ends
+    assert!(Window::Hann.weight(0, 512).abs() < 1e-6);
+    assert!((Window::Hann.weight(256, 512) - 1.0).abs() < 1e-6);
+}
@@ -0,0 +1,80 @@
+use example_support::imu::{Imu, ImuSample, Timestamped};
+use example_support::units::{MagneticField, Temperature, Vector3};
+
+/// An IMU that returns a fixed reading and counts how often it was read.
+struct FakeImu {
+    reads: usize,
+    magnetometer: bool,
+}
+
+impl Imu for FakeImu {
+    type Error = ();
+
+    fn read_all(&mut self) -> Result<(f32, [f32; 3], [f32; 3]), ()> {
+        self.reads += 1;
+        Ok((25.0, [0.0, 0.0, 1.0], [0.5, -0.5, 0.0]))
+    }
+
+    fn read_magnetic_field(&mut self) -> Option<Result<Vector3<MagneticField>, ()>> {
+        self.magnetometer.then_some(Ok(Vector3::from_microtesla([20.0, 0.0, -40.0])))
+    }
+}
+
+#[test]
+fn sample_combines_all_axes() {
+    let mut imu = FakeImu { reads: 0, magnetometer: true };
+    let sample = imu.sample(1_000).unwrap();
+
+    assert_eq!(
+        sample,
+        ImuSample {
+            timestamp_us: 1_000,
+            temperature: Temperature::from_celsius(25.0),
+            acceleration: Vector3::from_g([0.0, 0.0, 1.0]),
+            angular_velocity: Vector3::from_dps([0.5, -0.5, 0.0]),
+            magnetic_field: Some(Vector3::from_microtesla([20.0, 0.0, -40.0])),
+        }
+    );
+    assert_eq!(imu.reads, 1);
+}
+
+#[test]
+fn single_axis_reads_use_read_all() {
+    let mut imu = FakeImu { reads: 0, magnetometer: false };
+    assert_eq!(imu.read_acceleration(), Ok(Vector3::from_g([0.0, 0.0, 1.0])));
+    assert_eq!(imu.read_angular_velocity(), Ok(Vector3::from_dps([0.5, -0.5, 0.0])));
+    assert_eq!(imu.read_temperature(), Ok(Temperature::from_celsius(25.0)));
+    assert_eq!(imu.read_magnetic_field(), None);
+}
+
+#[test]
+fn timestamped_imu_stamps_every_sample() {
+    let mut now = 0;
+    let mut imu = Timestamped::new(FakeImu { reads: 0, magnetometer: false }, move || {
+        now += 10_000;
+        now
+    });
+
+    assert_eq!(imu.sample().unwrap().timestamp_us, 10_000);
+    assert_eq!(imu.sample().unwrap().timestamp_us, 20_000);
+    assert_eq!(imu.into_inner().reads, 2);
+}
+
+#[test]
+fn display_matches_the_example_output() {
+    let mut imu = FakeImu { reads: 0, magnetometer: false };
+    let text = imu.sample(0).unwrap().to_string();
+    assert_eq!(
+        text,
+        "Temperature : 25.00 C\n\
+         Acceleration [X, Y, Z] : [0.000, 0.000, 1.000] g\n\
+         Angular Velocity [X, Y, Z] : [0.500, -0.500, 0.000] dps"
+    );
+}
+
+#[test]
+fn display_includes_the_magnetometer_in_microtesla() {
+    let mut imu = FakeImu { reads: 0, magnetometer: true };
+    let text = imu.sample(0).unwrap().to_string();
+    assert!(text.ends_with("\nMagnetic Field [X, Y, Z] : [20.00, 0.00, -40.00] uT"), "{text}");
+}
@@ -0,0 +1,52 @@
+//! The `Imu` implementations of the hayasen drivers, on a simulated bus. Run with
+//! `cargo test --all-features`.
+
+#![cfg(all(feature = "mpu6050", feature = "mpu9250"))]
+
+mod common;
+
+use core::cell::RefCell;
+
+use common::{SimBus, SimDevice};
+use embedded_hal_bus::i2c::RefCellDevice;
+use example_support::imu::Imu;
+use example_support::units::Vector3;
+use hayasen::{mpu6050_hayasen, mpu9250_hayasen};
+
+const ACCEL_XOUT_H: u8 = 0x3B;
+const ACCEL_CONFIG: u8 = 0x1C;
+
+/// Puts the MPU flat on the table: 1 g on Z, in whatever range the driver selected.
+fn place_flat(bus: &RefCell<SimBus>) {
+    let mut bus = bus.borrow_mut();
+    let mpu = bus.device(0x68);
+    let range = (mpu.registers[usize::from(ACCEL_CONFIG)] >> 3) & 0x03;
+    mpu.set_i16(ACCEL_XOUT_H + 4, (16384 >> range) as i16);
+}
+
+/// Written once, used with either sensor.
+fn gravity<I: Imu>(imu: &mut I) -> f32 {
+    let sample = imu.sample(0).unwrap();
+    assert!(sample.magnetic_field.is_none());
+    sample.acceleration.norm().as_g()
+}
+
+#[test]
+fn mpu6050_is_an_imu() {
+    let bus = RefCell::new(SimBus::new(vec![SimDevice::mpu(0x68, 0x68)]));
+    let mut imu = mpu6050_hayasen::create_default(RefCellDevice::new(&bus), 0x68).unwrap();
+    place_flat(&bus);
+
+    assert!((gravity(&mut imu) - 1.0).abs() < 0.01);
+    let raw = mpu6050_hayasen::read_all(&mut imu).unwrap().1;
+    assert_eq!(imu.read_acceleration().unwrap(), Vector3::from_g(raw));
+}
+
+#[test]
+fn mpu9250_is_an_imu() {
+    let bus = RefCell::new(SimBus::new(vec![SimDevice::mpu(0x68, 0x71)]));
+    let mut imu = mpu9250_hayasen::create_default(RefCellDevice::new(&bus), 0x68).unwrap();
+    place_flat(&bus);
+
+    assert!((gravity(&mut imu) - 1.0).abs() < 0.01);
+}
@@ -0,0 +1,84 @@
+use embedded_hal::delay::DelayNs;
+use example_support::init::{init_with_retry, Backoff, SensorSupervisor};
+
+#[derive(Default)]
+struct RecordingDelay {
+    waited_ms: Vec<u32>,
+}
+
+impl DelayNs for RecordingDelay {
+    fn delay_ns(&mut self, ns: u32) {
+        self.waited_ms.push(ns / 1_000_000);
+    }
+
+    fn delay_ms(&mut self, ms: u32) {
+        self.waited_ms.push(ms);
+    }
+}
+
+#[test]
+fn backoff_doubles_up_to_the_cap() {
+    let backoff = Backoff::new(100, 1_000);
+    let delays: Vec<u32> = (1..=6).map(|attempt| backoff.delay_ms(attempt)).collect();
+    assert_eq!(delays, [100, 200, 400, 800, 1_000, 1_000]);
+}
+
+#[test]
+fn retries_until_init_succeeds() {
+    let mut delay = RecordingDelay::default();
+    let mut calls = 0;
+    let mut reported = Vec::new();
+
+    let result = init_with_retry(
+        &Backoff::new(50, 1_000),
+        &mut delay,
+        || {
+            calls += 1;
+            if calls < 3 { Err(calls) } else { Ok("sensor") }
+        },
+        |attempt| reported.push((attempt.number, *attempt.error, attempt.retry_in_ms)),
+    );
+
+    assert_eq!(result, Ok("sensor"));
+    assert_eq!(reported, [(1, 1, Some(50)), (2, 2, Some(100))]);
+    assert_eq!(delay.waited_ms, [50, 100]);
+}
+
+#[test]
+fn gives_up_with_the_last_error() {
+    let mut delay = RecordingDelay::default();
+    let mut last_retry = Some(0);
+
+    let result: Result<(), &str> = init_with_retry(
+        &Backoff::new(10, 1_000).with_max_attempts(3),
+        &mut delay,
+        || Err("not detected"),
+        |attempt| last_retry = attempt.retry_in_ms,
+    );
+
+    assert_eq!(result, Err("not detected"));
+    assert_eq!(last_retry, None);
+    assert_eq!(delay.waited_ms, [10, 20]);
+}
+
+#[test]
+fn degraded_sensor_is_retried_on_schedule() {
+    let backoff = Backoff::new(100, 400).with_max_attempts(1);
+    let mut supervisor = SensorSupervisor::new(Err::<u8, _>(()), 0, backoff);
+    assert!(!supervisor.is_ready());
+
+    // Not due yet, init must not be called
+    assert!(supervisor.poll(50, || -> Result<u8, ()> { unreachable!() }, |_| {}).is_none());
+
+    let mut next_retry = None;
+    assert!(supervisor.poll(100, || Err(()), |attempt| next_retry = attempt.retry_in_ms).is_none());
+    assert_eq!(next_retry, Some(200));
+
+    assert!(supervisor.poll(299, || -> Result<u8, ()> { unreachable!() }, |_| {}).is_none());
+    assert_eq!(supervisor.poll(300, || Ok::<_, ()>(7), |_| {}), Some(&mut 7));
+    assert!(supervisor.is_ready());
+
+    supervisor.mark_failed(1_000);
+    assert!(supervisor.sensor().is_none());
+    assert_eq!(supervisor.poll(1_100, || Ok::<_, ()>(8), |_| {}), Some(&mut 8));
+}
@@ -0,0 +1,150 @@
+mod common;
+
+use common::motion::{angle_difference, MotionProfile, TruthSample};
+use example_support::madgwick::Madgwick;
+use example_support::quaternion::Quaternion;
+
+/// Seconds until the estimate stays within `tolerance` degrees of the truth.
+fn settling_time(filter: &mut Madgwick, samples: &[TruthSample], tolerance: f32) -> f64 {
+    let mut settled_at = None;
+    for truth in samples {
+        let error = filter.update(&truth.sample).angle_to(truth.orientation());
+        if error > tolerance {
+            settled_at = None;
+        } else if settled_at.is_none() {
+            settled_at = Some(truth.sample.timestamp_us as f64 / 1e6);
+        }
+    }
+    settled_at.unwrap_or(f64::INFINITY)
+}
+
+#[test]
+fn imu_variant_finds_the_tilt() {
+    let samples = MotionProfile::new(100.0)
+        .starting_at(30.0, -20.0, 0.0)
+        .hold(15.0)
+        .with_noise(0.01, 0.2)
+        .simulate();
+
+    let mut filter = Madgwick::default();
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+    let angles = filter.euler();
+    assert!((angles.roll - 30.0).abs() < 1.0, "{angles:?}");
+    assert!((angles.pitch + 20.0).abs() < 1.0, "{angles:?}");
+}
+
+#[test]
+fn imu_variant_integrates_yaw() {
+    let samples = MotionProfile::new(200.0).hold(0.5).rotate(3.0, [0.0, 0.0, 30.0]).hold(0.5).simulate();
+
+    let mut filter = Madgwick::default();
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+    let angles = filter.euler();
+    assert!((angles.yaw - 90.0).abs() < 1.0, "{angles:?}");
+}
+
+#[test]
+fn marg_variant_finds_the_full_orientation() {
+    let samples = MotionProfile::new(100.0)
+        .starting_at(30.0, -20.0, 120.0)
+        .hold(20.0)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Madgwick::new(0.5);
+    let settled = settling_time(&mut filter, &samples, 2.0);
+    assert!(settled < 10.0, "{settled}");
+}
+
+#[test]
+fn marg_variant_follows_a_scripted_motion() {
+    let samples = MotionProfile::new(200.0)
+        .hold(1.0)
+        .rotate(2.0, [45.0, 0.0, 0.0])
+        .rotate(2.0, [0.0, -30.0, 60.0])
+        .hold(1.0)
+        .rotate(3.0, [-20.0, 10.0, -90.0])
+        .hold(1.0)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Madgwick::default();
+    for truth in &samples {
+        let error = filter.update(&truth.sample).angle_to(truth.orientation());
+        assert!(error < 2.0, "{error} at {} us", truth.sample.timestamp_us);
+    }
+}
+
+#[test]
+fn magnetometer_removes_yaw_drift() {
+    let profile = MotionProfile::new(100.0)
+        .starting_at(10.0, 5.0, 45.0)
+        .hold(30.0)
+        .with_gyro_bias([0.0, 0.0, 1.0]);
+    let without = profile.clone().simulate();
+    let with = profile.with_magnetometer().simulate();
+
+    let mut imu = Madgwick::default();
+    let mut marg = Madgwick::default();
+    imu.reset(with[0].orientation());
+    marg.reset(with[0].orientation());
+    for (a, b) in without.iter().zip(&with) {
+        imu.update(&a.sample);
+        marg.update(&b.sample);
+    }
+
+    let truth = with.last().unwrap();
+    let imu_yaw = angle_difference(imu.euler().yaw, truth.yaw).abs();
+    let marg_yaw = angle_difference(marg.euler().yaw, truth.yaw).abs();
+    assert!(imu_yaw > 20.0, "{imu_yaw}");
+    assert!(marg_yaw < 1.0, "{marg_yaw}");
+}
+
+#[test]
+fn update_imu_ignores_the_magnetometer() {
+    let samples = MotionProfile::new(100.0).starting_at(0.0, 0.0, 90.0).hold(5.0).with_magnetometer().simulate();
+
+    let mut filter = Madgwick::new(0.5);
+    for truth in &samples {
+        filter.update_imu(&truth.sample);
+    }
+    assert!(filter.euler().yaw.abs() < 0.5, "{:?}", filter.euler());
+}
+
+#[test]
+fn higher_gain_converges_faster() {
+    let samples = MotionProfile::new(100.0)
+        .starting_at(-40.0, 25.0, 60.0)
+        .hold(30.0)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let slow = settling_time(&mut Madgwick::new(0.05), &samples, 2.0);
+    let fast = settling_time(&mut Madgwick::new(0.5), &samples, 2.0);
+    assert!(fast < slow / 4.0, "{fast} vs {slow}");
+
+    // The gain can be lowered once settled without losing the estimate
+    let mut filter = Madgwick::new(0.5);
+    settling_time(&mut filter, &samples, 2.0);
+    filter.set_beta(0.05);
+    assert!(filter.quaternion().angle_to(samples.last().unwrap().orientation()) < 2.0);
+    assert_eq!(filter.beta(), 0.05);
+}
+
+#[test]
+fn skips_the_step_after_a_long_gap() {
+    let samples = MotionProfile::new(100.0).rotate(1.0, [0.0, 0.0, 90.0]).simulate();
+    let mut filter = Madgwick::default();
+    filter.update(&samples[0].sample);
+
+    let mut late = samples[50].sample;
+    late.timestamp_us += 5_000_000;
+    assert_eq!(filter.update(&late), Quaternion::IDENTITY);
+}
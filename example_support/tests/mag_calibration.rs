@@ -0,0 +1,164 @@
+use example_support::mag_calibration::{FitError, MagCalibration, MagCalibrator, REGIONS};
+use example_support::units::{MagneticField, Vector3};
+
+const FIELD_UT: f32 = 48.0;
+const HARD_IRON: [f32; 3] = [12.0, -30.0, 4.5];
+/// Symmetric, as soft iron distortion is.
+const SOFT_IRON: [[f32; 3]; 3] = [[1.15, 0.06, -0.03], [0.06, 0.88, 0.04], [-0.03, 0.04, 1.02]];
+
+/// Deterministic noise, so failures reproduce.
+struct Rng(u64);
+
+impl Rng {
+    fn uniform(&mut self) -> f32 {
+        self.0 = self.0.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1_442_695_040_888_963_407);
+        (self.0 >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    fn gaussian(&mut self) -> f32 {
+        (0..12).map(|_| self.uniform()).sum::<f32>() - 6.0
+    }
+}
+
+/// `count` directions spread evenly over the sphere, visited in a shuffled order.
+fn sphere(count: usize, rng: &mut Rng) -> Vec<[f32; 3]> {
+    let golden = std::f32::consts::PI * (3.0 - 5f32.sqrt());
+    let mut points: Vec<[f32; 3]> = (0..count)
+        .map(|i| {
+            let z = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let r = (1.0 - z * z).sqrt();
+            let (sin, cos) = (golden * i as f32).sin_cos();
+            [r * cos, r * sin, z]
+        })
+        .collect();
+    for i in (1..points.len()).rev() {
+        let j = (rng.uniform() * (i + 1) as f32) as usize % (i + 1);
+        points.swap(i, j);
+    }
+    points
+}
+
+/// What a distorted magnetometer reads for the Earth's field along `direction`.
+fn distorted(direction: [f32; 3], rng: &mut Rng, noise_ut: f32) -> Vector3<MagneticField> {
+    let field = direction.map(|d| d * FIELD_UT);
+    let reading: [f32; 3] = std::array::from_fn(|i| {
+        (0..3).map(|j| SOFT_IRON[i][j] * field[j]).sum::<f32>() + HARD_IRON[i] + rng.gaussian() * noise_ut
+    });
+    Vector3::from_microtesla(reading)
+}
+
+fn angle(a: [f32; 3], b: [f32; 3]) -> f32 {
+    let dot: f32 = (0..3).map(|i| a[i] * b[i]).sum();
+    let norms = (0..3).map(|i| a[i] * a[i]).sum::<f32>().sqrt() * (0..3).map(|i| b[i] * b[i]).sum::<f32>().sqrt();
+    (dot / norms).clamp(-1.0, 1.0).acos().to_degrees()
+}
+
+#[test]
+fn recovers_hard_and_soft_iron_distortion() {
+    let mut rng = Rng(7);
+    let directions = sphere(600, &mut rng);
+    let mut calibrator = MagCalibrator::<256>::new();
+    for &direction in &directions {
+        calibrator.add(distorted(direction, &mut rng, 0.3));
+    }
+
+    let fit = calibrator.fit().unwrap();
+    let hard_iron = fit.calibration.hard_iron.as_microtesla();
+    for (fitted, truth) in hard_iron.iter().zip(HARD_IRON) {
+        assert!((fitted - truth).abs() < 0.5, "{hard_iron:?}");
+    }
+    assert!((fit.field_strength.as_microtesla() - FIELD_UT).abs() < 0.05 * FIELD_UT, "{fit:?}");
+    assert!(fit.residual < 0.02, "{}", fit.residual);
+
+    // Corrected readings lie on a sphere, and the angles between them are the true ones
+    let probes = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0], [0.6, -0.64, 0.48]];
+    let corrected: Vec<[f32; 3]> =
+        probes.iter().map(|&p| fit.calibration.apply(distorted(p, &mut rng, 0.0)).as_microtesla()).collect();
+    for c in &corrected {
+        let strength = (c[0] * c[0] + c[1] * c[1] + c[2] * c[2]).sqrt();
+        assert!((strength - fit.field_strength.as_microtesla()).abs() < 0.5, "{strength}");
+    }
+    for i in 0..probes.len() {
+        for j in i + 1..probes.len() {
+            let error = angle(corrected[i], corrected[j]) - angle(probes[i], probes[j]);
+            assert!(error.abs() < 1.0, "{i} {j}: {error}");
+        }
+    }
+}
+
+#[test]
+fn coverage_grows_as_the_board_turns() {
+    let mut rng = Rng(3);
+    let mut calibrator = MagCalibrator::<256>::new();
+    let directions = sphere(400, &mut rng);
+    let mut progress = Vec::new();
+    for (n, &direction) in directions.iter().enumerate() {
+        let coverage = calibrator.add(distorted(direction, &mut rng, 0.3));
+        if n % 100 == 99 {
+            progress.push(coverage.regions);
+        }
+    }
+    assert!(progress.windows(2).all(|pair| pair[0] <= pair[1]), "{progress:?}");
+    let coverage = calibrator.coverage();
+    assert_eq!(coverage.regions, REGIONS);
+    assert_eq!(coverage.fraction(), 1.0);
+    assert!(coverage.samples <= 256);
+}
+
+#[test]
+fn turning_flat_on_the_table_is_not_enough() {
+    let mut rng = Rng(5);
+    let mut calibrator = MagCalibrator::<256>::new();
+    // A full turn about the vertical, the field dipping 65° below the horizon
+    for step in 0..360 {
+        let heading = (step as f32).to_radians();
+        let (dip_sin, dip_cos) = (-65f32).to_radians().sin_cos();
+        calibrator.add(distorted([dip_cos * heading.cos(), dip_cos * heading.sin(), dip_sin], &mut rng, 0.3));
+    }
+    assert!(calibrator.coverage().fraction() < 0.5, "{:?}", calibrator.coverage());
+    assert_eq!(calibrator.fit(), Err(FitError::InsufficientCoverage));
+}
+
+#[test]
+fn holding_still_adds_nothing() {
+    let mut rng = Rng(11);
+    let mut calibrator = MagCalibrator::<64>::new();
+    for _ in 0..500 {
+        calibrator.add(distorted([0.0, 0.6, -0.8], &mut rng, 0.2));
+    }
+    assert!(calibrator.samples() < 5, "{}", calibrator.samples());
+    assert_eq!(calibrator.fit(), Err(FitError::TooFewSamples));
+}
+
+#[test]
+fn stops_collecting_when_full() {
+    let mut rng = Rng(13);
+    let mut calibrator = MagCalibrator::<40>::new().with_min_spacing(MagneticField::from_microtesla(0.1));
+    for direction in sphere(1000, &mut rng) {
+        calibrator.add(distorted(direction, &mut rng, 0.3));
+    }
+    assert!(calibrator.is_full());
+    assert_eq!(calibrator.samples(), 40);
+
+    calibrator.reset();
+    assert_eq!(calibrator.samples(), 0);
+}
+
+#[test]
+fn undistorted_readings_fit_to_identity() {
+    let mut rng = Rng(17);
+    let mut calibrator = MagCalibrator::<256>::new();
+    for direction in sphere(500, &mut rng) {
+        calibrator.add(Vector3::from_microtesla(direction.map(|d| d * FIELD_UT)));
+    }
+
+    let fit = calibrator.fit().unwrap();
+    let identity = MagCalibration::IDENTITY;
+    for i in 0..3 {
+        assert!(fit.calibration.hard_iron.as_microtesla()[i].abs() < 0.05);
+        for j in 0..3 {
+            assert!((fit.calibration.soft_iron[i][j] - identity.soft_iron[i][j]).abs() < 1e-3, "{fit:?}");
+        }
+    }
+    assert!(fit.residual < 1e-3);
+}
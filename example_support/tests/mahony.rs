@@ -0,0 +1,114 @@
+mod common;
+
+use common::motion::{angle_difference, MotionProfile};
+use example_support::mahony::Mahony;
+
+#[test]
+fn imu_variant_finds_the_tilt() {
+    let samples = MotionProfile::new(100.0)
+        .starting_at(-35.0, 15.0, 0.0)
+        .hold(15.0)
+        .with_noise(0.01, 0.2)
+        .simulate();
+
+    let mut filter = Mahony::default();
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+    let angles = filter.euler();
+    assert!((angles.roll + 35.0).abs() < 1.0, "{angles:?}");
+    assert!((angles.pitch - 15.0).abs() < 1.0, "{angles:?}");
+}
+
+#[test]
+fn marg_variant_finds_the_full_orientation() {
+    let samples = MotionProfile::new(100.0)
+        .starting_at(20.0, 30.0, -100.0)
+        .hold(15.0)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Mahony::new(5.0, 0.0);
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+    let error = filter.quaternion().angle_to(samples.last().unwrap().orientation());
+    assert!(error < 1.0, "{error}");
+}
+
+#[test]
+fn marg_variant_follows_a_scripted_motion() {
+    let samples = MotionProfile::new(200.0)
+        .hold(1.0)
+        .rotate(2.0, [0.0, 45.0, 0.0])
+        .rotate(2.0, [60.0, 0.0, -45.0])
+        .hold(1.0)
+        .rotate(3.0, [-30.0, -15.0, 120.0])
+        .hold(1.0)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Mahony::default();
+    for truth in &samples {
+        let error = filter.update(&truth.sample).angle_to(truth.orientation());
+        assert!(error < 2.0, "{error} at {} us", truth.sample.timestamp_us);
+    }
+}
+
+#[test]
+fn integral_term_estimates_the_gyro_bias() {
+    let bias = [1.5, -2.0, 1.0];
+    let samples = MotionProfile::new(100.0)
+        .starting_at(10.0, -10.0, 30.0)
+        .hold(60.0)
+        .with_gyro_bias(bias)
+        .with_noise(0.01, 0.2)
+        .with_magnetometer()
+        .simulate();
+
+    let mut filter = Mahony::new(2.0, 0.5);
+    filter.reset(samples[0].orientation());
+    for truth in &samples {
+        filter.update(&truth.sample);
+    }
+
+    let estimate = filter.gyro_bias().as_dps();
+    for (estimate, bias) in estimate.iter().zip(bias) {
+        assert!((estimate - bias as f32).abs() < 0.1, "{estimate} vs {bias}");
+    }
+    let truth = samples.last().unwrap();
+    assert!(filter.quaternion().angle_to(truth.orientation()) < 0.5);
+}
+
+#[test]
+fn proportional_only_leaves_a_steady_error_under_bias() {
+    let samples = MotionProfile::new(100.0)
+        .hold(30.0)
+        .with_gyro_bias([0.0, 0.0, 3.0])
+        .with_magnetometer()
+        .simulate();
+
+    let mut proportional = Mahony::new(2.0, 0.0);
+    let mut integral = Mahony::new(2.0, 0.5);
+    for truth in &samples {
+        proportional.update(&truth.sample);
+        integral.update(&truth.sample);
+    }
+
+    let truth = samples.last().unwrap();
+    let steady = angle_difference(proportional.euler().yaw, truth.yaw).abs();
+    let cancelled = angle_difference(integral.euler().yaw, truth.yaw).abs();
+    assert!(steady > 2.0, "{steady}");
+    assert!(cancelled < 0.5, "{cancelled}");
+}
+
+#[test]
+fn gains_can_be_changed() {
+    let mut filter = Mahony::new(2.0, 0.5);
+    assert_eq!(filter.gains(), (2.0, 0.5));
+    filter.set_gains(1.0, 0.0);
+    assert_eq!(filter.gains(), (1.0, 0.0));
+    assert_eq!(filter.gyro_bias().as_dps(), [0.0; 3]);
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use common::ppg::{Ppg, Reading};
+use example_support::motion_artifact::{MotionGate, MotionGateConfig, NlmsCanceller, ReferenceHistory};
+use example_support::ppg::{DcTracker, PulseRate, Spo2Curve, Spo2Estimator};
+use example_support::units::Acceleration;
+
+/// The AC part of the IR channel as measured, after the canceller, and without the
+/// motion artifact.
+fn cancel(readings: &[Reading]) -> Vec<(f32, f32, f32)> {
+    let mut gate = MotionGate::default();
+    let mut canceller = NlmsCanceller::<8>::default();
+    let mut dc = DcTracker::new(0.01);
+    let mut clean_dc = DcTracker::new(0.01);
+    readings
+        .iter()
+        .map(|reading| {
+            gate.update(&reading.imu);
+            let ac = dc.update(reading.ir);
+            (ac, canceller.update(ac, gate.linear_acceleration()), clean_dc.update(reading.clean_ir))
+        })
+        .collect()
+}
+
+/// Mean squared difference of the measured and the cleaned IR from the clean one.
+fn residuals(cancelled: &[(f32, f32, f32)]) -> (f32, f32) {
+    let n = cancelled.len() as f32;
+    let raw = cancelled.iter().map(|(ac, _, clean)| (ac - clean) * (ac - clean)).sum::<f32>() / n;
+    let cleaned = cancelled.iter().map(|(_, cleaned, clean)| (cleaned - clean) * (cleaned - clean)).sum::<f32>() / n;
+    (raw, cleaned)
+}
+
+#[test]
+fn canceller_removes_the_motion_artifact() {
+    let ppg = Ppg::new(70.0).still(5.0).moving(60.0, 1.3, 0.3);
+    let cancelled = cancel(ppg.readings());
+    // Give it 15 s to learn, then the artifact is down by 15 dB or more
+    let (raw, cleaned) = residuals(&cancelled[2_000..]);
+    assert!(cleaned < 0.03 * raw, "{raw} -> {cleaned}");
+}
+
+#[test]
+fn canceller_leaves_a_still_signal_alone() {
+    let ppg = Ppg::new(70.0).still(30.0);
+    let cancelled = cancel(ppg.readings());
+    let n = cancelled.len() as f32;
+    let pulse = cancelled.iter().map(|(_, _, clean)| clean * clean).sum::<f32>() / n;
+    let (_, cleaned) = residuals(&cancelled);
+    assert!(cleaned < 0.01 * pulse, "{cleaned} against a pulse of {pulse}");
+}
+
+#[test]
+fn pulse_rate_survives_motion() {
+    // Swinging the arm hard enough that the artifact swamps the pulse
+    let ppg = Ppg::new(66.0).still(5.0).moving(60.0, 1.3, 0.4);
+    let mut raw_pulse = PulseRate::new();
+    let mut cleaned_pulse = PulseRate::new();
+    let mut raw = Vec::new();
+    let mut cleaned = Vec::new();
+    for (reading, (ac, clean, _)) in ppg.readings().iter().zip(cancel(ppg.readings())) {
+        let timestamp = reading.imu.timestamp_us;
+        raw.extend(raw_pulse.update(timestamp, ac).filter(|_| timestamp > 25_000_000));
+        cleaned.extend(cleaned_pulse.update(timestamp, clean).filter(|_| timestamp > 25_000_000));
+    }
+    let worst = |rates: &[f32]| rates.iter().map(|rate| (rate - 66.0).abs()).fold(0.0, f32::max);
+    assert!(cleaned.len() > 30 && worst(&cleaned) < 3.0, "{cleaned:?}");
+    assert!(worst(&raw) > 10.0, "{raw:?}");
+}
+
+#[test]
+fn gate_holds_while_moving_and_after() {
+    let ppg = Ppg::new(70.0).still(10.0).moving(10.0, 1.3, 0.2).still(10.0);
+    let mut gate = MotionGate::default();
+    let steady: Vec<bool> = ppg.readings().iter().map(|reading| gate.update(&reading.imu)).collect();
+    let steady_at = |s: f64| steady[(s * 100.0) as usize];
+
+    // Not before the hold time has passed after starting
+    assert!(!steady_at(1.0) && steady_at(3.0) && steady_at(9.9));
+    assert!(!steady_at(10.5) && !steady_at(19.9));
+    // Still disturbed right after the motion, steady again after the hold time
+    assert!(!steady_at(21.0) && steady_at(23.0) && steady_at(29.9));
+    assert!(gate.is_steady());
+    assert!(gate.motion().as_g() < 0.02);
+}
+
+#[test]
+fn gated_spo2_ignores_motion() {
+    let ppg = Ppg::new(75.0).with_spo2(96.0).still(20.0).moving(20.0, 1.1, 0.3).still(20.0);
+    let mut gate = MotionGate::default();
+    let mut estimator = Spo2Estimator::new(Spo2Curve::default(), 100);
+    let mut accepted = Vec::new();
+    let mut rejected = Vec::new();
+    for reading in ppg.readings() {
+        let steady = gate.update(&reading.imu);
+        if let Some(spo2) = estimator.update(reading.red, reading.ir) {
+            if steady { accepted.push(spo2) } else { rejected.push(spo2) }
+        }
+    }
+    assert!(accepted.len() >= 30, "{accepted:?}");
+    assert!(accepted.iter().all(|spo2| (spo2 - 96.0).abs() < 1.5), "{accepted:?}");
+    assert!(rejected.iter().any(|spo2| (spo2 - 96.0).abs() > 3.0), "{rejected:?}");
+}
+
+#[test]
+fn gate_threshold_is_configurable() {
+    let ppg = Ppg::new(70.0).still(5.0).moving(10.0, 1.3, 0.03);
+    let mut gate = MotionGate::default();
+    assert!(ppg.readings().iter().map(|reading| gate.update(&reading.imu)).last().unwrap());
+
+    let config = MotionGateConfig { threshold: Acceleration::from_g(0.01), ..MotionGateConfig::default() };
+    let mut gate = MotionGate::new(config);
+    assert!(!ppg.readings().iter().map(|reading| gate.update(&reading.imu)).last().unwrap());
+    assert_eq!(gate.config(), config);
+}
+
+#[test]
+fn reset_gate_waits_out_the_hold_time_again() {
+    let ppg = Ppg::new(70.0).still(10.0);
+    let readings = ppg.readings();
+    let mut gate = MotionGate::default();
+    assert!(readings[..500].iter().map(|reading| gate.update(&reading.imu)).last().unwrap());
+
+    gate.reset();
+    assert!(!gate.is_steady());
+    let steady: Vec<bool> = readings[500..].iter().map(|reading| gate.update(&reading.imu)).collect();
+    assert!(!steady[100] && steady[300]);
+}
+
+#[test]
+fn history_interpolates_between_readings() {
+    let mut history = ReferenceHistory::<4>::new();
+    assert_eq!(history.at(0), None);
+
+    history.push(10_000, [0.0, 1.0, 0.0]);
+    history.push(20_000, [1.0, 1.0, -2.0]);
+    assert_eq!(history.at(15_000), Some([0.5, 1.0, -1.0]));
+    assert_eq!(history.at(20_000), Some([1.0, 1.0, -2.0]));
+    // Outside the readings the nearest one is used
+    assert_eq!(history.at(5_000), Some([0.0, 1.0, 0.0]));
+    assert_eq!(history.at(30_000), Some([1.0, 1.0, -2.0]));
+
+    // Only the last four are kept
+    for (i, t) in [30_000, 40_000, 50_000].into_iter().enumerate() {
+        history.push(t, [i as f32; 3]);
+    }
+    assert_eq!(history.at(15_000), Some([1.0, 1.0, -2.0]));
+
+    // Time going backwards starts over
+    history.push(0, [3.0; 3]);
+    assert_eq!(history.at(50_000), Some([3.0; 3]));
+    history.clear();
+    assert_eq!(history.at(0), None);
+}
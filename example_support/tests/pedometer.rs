@@ -0,0 +1,98 @@
+mod common;
+
+use common::activity::Trace;
+use example_support::pedometer::{Pedometer, PedometerConfig};
+
+/// Feeds the whole trace and returns the pedometer afterwards.
+fn count(trace: &Trace) -> Pedometer {
+    let mut pedometer = Pedometer::default();
+    let mut total = 0;
+    for sample in trace.samples() {
+        total += pedometer.update(sample);
+    }
+    assert_eq!(total, pedometer.steps());
+    pedometer
+}
+
+fn assert_steps(counted: u32, expected: f64, tolerance: f64) {
+    assert!((f64::from(counted) - expected).abs() <= expected * tolerance, "{counted} steps, expected {expected}");
+}
+
+#[test]
+fn counts_steps_while_walking() {
+    let trace = Trace::new(100.0).still(2.0).walk(60.0, 110.0, 0.3).still(2.0);
+    assert_steps(count(&trace).steps(), 110.0, 0.03);
+}
+
+#[test]
+fn counts_steps_while_running() {
+    let trace = Trace::new(100.0).still(2.0).run(30.0, 170.0, 1.5).still(2.0);
+    assert_steps(count(&trace).steps(), 85.0, 0.03);
+}
+
+#[test]
+fn reports_the_cadence() {
+    for (cadence, peak_g) in [(80.0, 0.2), (115.0, 0.35), (170.0, 1.2)] {
+        let trace = Trace::new(100.0).walk(20.0, cadence, peak_g);
+        let pedometer = count(&trace);
+        assert!(pedometer.is_walking());
+        let measured = pedometer.cadence();
+        assert!((measured - cadence as f32).abs() < 0.06 * cadence as f32, "{measured} vs {cadence}");
+    }
+}
+
+#[test]
+fn works_however_the_sensor_is_worn() {
+    let trace = Trace::new(100.0).tilted(70.0, -35.0).still(2.0).walk(40.0, 105.0, 0.3).still(2.0);
+    assert_steps(count(&trace).steps(), 70.0, 0.03);
+}
+
+#[test]
+fn follows_a_change_of_pace() {
+    // A stroll, then a run, then walking again, without stopping in between
+    let trace = Trace::new(100.0).walk(20.0, 100.0, 0.25).run(20.0, 165.0, 1.4).walk(20.0, 100.0, 0.25);
+    assert_steps(count(&trace).steps(), 33.3 + 55.0 + 33.3, 0.05);
+}
+
+#[test]
+fn shaking_is_not_walking() {
+    // Now and then a few jolts happen to line up, but five minutes of shaking should
+    // not add up to more than a handful of steps
+    let steps: u32 = (1..=10)
+        .map(|seed| count(&Trace::new(100.0).with_seed(seed).still(1.0).shake(30.0, 1.0).still(1.0)).steps())
+        .sum();
+    assert!(steps <= 25, "{steps} steps");
+}
+
+#[test]
+fn a_few_steps_are_not_enough() {
+    // Three steps across the room
+    let trace = Trace::new(100.0).still(2.0).walk(1.6, 110.0, 0.3).still(3.0);
+    assert_eq!(count(&trace).steps(), 0);
+
+    let strict = PedometerConfig { regular_steps: 2, ..PedometerConfig::default() };
+    let mut pedometer = Pedometer::new(strict);
+    for sample in trace.samples() {
+        pedometer.update(sample);
+    }
+    assert!(pedometer.steps() >= 2, "{}", pedometer.steps());
+}
+
+#[test]
+fn stopping_ends_the_walk() {
+    let trace = Trace::new(100.0).walk(10.0, 110.0, 0.3).still(3.0);
+    let mut pedometer = count(&trace);
+    assert!(!pedometer.is_walking());
+    assert_eq!(pedometer.cadence(), 0.0);
+
+    let counted = pedometer.steps();
+    assert!(counted > 0);
+    pedometer.reset();
+    assert_eq!(pedometer.steps(), 0);
+}
+
+#[test]
+fn still_sensor_counts_nothing() {
+    let trace = Trace::new(100.0).with_noise(0.05).still(60.0);
+    assert_eq!(count(&trace).steps(), 0);
+}
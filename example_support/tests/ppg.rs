@@ -0,0 +1,96 @@
+mod common;
+
+use common::ppg::Ppg;
+use example_support::ppg::{DcTracker, PulseRate, Spo2Curve, Spo2Estimator};
+
+/// Pulse rates reported over the readings, after the DC level is removed.
+fn pulse_rates(ppg: &Ppg) -> Vec<f32> {
+    let mut dc = DcTracker::new(0.01);
+    let mut pulse = PulseRate::new();
+    ppg.readings()
+        .iter()
+        .filter_map(|reading| pulse.update(reading.imu.timestamp_us, dc.update(reading.ir)))
+        .collect()
+}
+
+#[test]
+fn measures_the_pulse_rate() {
+    for bpm in [45.0, 72.0, 110.0, 170.0] {
+        let rates = pulse_rates(&Ppg::new(bpm).still(30.0));
+        assert!(rates.len() as f64 > 0.4 * bpm, "{bpm}: {rates:?}");
+        // Once a few beats are in, every reading is close
+        for &rate in &rates[5..] {
+            assert!((f64::from(rate) - bpm).abs() < 0.04 * bpm, "{bpm}: {rates:?}");
+        }
+    }
+}
+
+#[test]
+fn forgets_the_pulse_without_a_signal() {
+    let mut dc = DcTracker::new(0.01);
+    let mut pulse = PulseRate::new();
+    for reading in Ppg::new(72.0).still(10.0).readings() {
+        pulse.update(reading.imu.timestamp_us, dc.update(reading.ir));
+    }
+    assert!(pulse.bpm().is_some());
+
+    // The finger is lifted: a flat signal
+    for i in 0..400u64 {
+        pulse.update(10_000_000 + i * 10_000, 0.0);
+    }
+    assert_eq!(pulse.bpm(), None);
+
+    pulse.reset();
+    assert_eq!(pulse.bpm(), None);
+}
+
+#[test]
+fn measures_spo2() {
+    for spo2 in [98.0, 94.0, 88.0] {
+        let mut estimator = Spo2Estimator::new(Spo2Curve::default(), 100);
+        let estimates: Vec<f32> = Ppg::new(75.0)
+            .with_spo2(spo2)
+            .still(20.0)
+            .readings()
+            .iter()
+            .filter_map(|reading| estimator.update(reading.red, reading.ir))
+            .collect();
+        // The first second only settles the DC levels
+        assert_eq!(estimates.len(), 19);
+        for &estimate in &estimates[2..] {
+            assert!((f64::from(estimate) - spo2).abs() < 1.0, "{spo2}: {estimates:?}");
+        }
+    }
+}
+
+#[test]
+fn dc_tracker_splits_level_and_pulse() {
+    let mut dc = DcTracker::new(0.01);
+    assert_eq!(dc.level(), None);
+    assert_eq!(dc.update(50_000.0), 0.0);
+    for _ in 0..1_000 {
+        dc.update(50_100.0);
+    }
+    assert!((dc.level().unwrap() - 50_100.0).abs() < 1.0);
+    assert!((dc.update(50_300.0) - 198.0).abs() < 1.0);
+
+    dc.reset();
+    assert_eq!(dc.level(), None);
+}
+
+#[test]
+fn fits_the_curve_to_reference_readings() {
+    // Readings that follow SpO2 = 110 - 25 R + 2 R²
+    let curve = |ratio: f32| 110.0 - 25.0 * ratio + 2.0 * ratio * ratio;
+    let points = [0.5, 0.7, 0.9, 1.2].map(|ratio| (ratio, curve(ratio)));
+    let fitted = Spo2Curve::fit(&points).unwrap();
+    assert!((fitted.a - 110.0).abs() < 0.01 && (fitted.b + 25.0).abs() < 0.01 && (fitted.c - 2.0).abs() < 0.01, "{fitted:?}");
+
+    // Two readings give a straight line
+    let fitted = Spo2Curve::fit(&[(0.5, 95.5), (1.0, 87.0)]).unwrap();
+    assert_eq!(fitted.c, 0.0);
+    assert!((fitted.spo2(0.75) - 91.25).abs() < 0.01, "{fitted:?}");
+
+    assert_eq!(Spo2Curve::fit(&[(0.5, 95.0)]), None);
+    assert_eq!(Spo2Curve::fit(&[(0.6, 95.0), (0.6, 93.0), (0.6, 94.0)]), None);
+}
@@ -0,0 +1,70 @@
+mod common;
+
+use common::{SimBus, SimDevice};
+use embedded_hal::i2c::I2c;
+use example_support::presence::{PresenceEvent, PresenceMonitor, MAX30102, MPU6050, MPU9250};
+
+/// Stands in for the driver's initialisation: wakes the MPU and sets its ranges.
+fn configure_mpu(bus: &mut SimBus) {
+    bus.write(0x68, &[0x6B, 0x01]).unwrap();
+    bus.write(0x68, &[0x1B, 0x08, 0x08]).unwrap();
+}
+
+#[test]
+fn steady_device_reports_nothing() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x68)]);
+    let mut monitor = PresenceMonitor::new(MPU6050);
+    configure_mpu(&mut bus);
+    monitor.capture(&mut bus).unwrap();
+
+    assert_eq!(monitor.check(&mut bus), None);
+    assert_eq!(monitor.check(&mut bus), None);
+    assert!(monitor.is_connected());
+}
+
+#[test]
+fn unplug_and_replug_is_detected_once_each() {
+    let mut bus = SimBus::new(vec![SimDevice::max30102()]);
+    let mut monitor = PresenceMonitor::new(MAX30102);
+    bus.write(0x57, &[0x09, 0x03]).unwrap();
+    monitor.capture(&mut bus).unwrap();
+
+    bus.device(0x57).connected = false;
+    assert_eq!(monitor.check(&mut bus), Some(PresenceEvent::Disconnected));
+    assert_eq!(monitor.check(&mut bus), None);
+
+    bus.device(0x57).connected = true;
+    assert_eq!(monitor.check(&mut bus), Some(PresenceEvent::Reconnected));
+
+    bus.write(0x57, &[0x09, 0x03]).unwrap();
+    monitor.capture(&mut bus).unwrap();
+    assert_eq!(monitor.check(&mut bus), None);
+}
+
+#[test]
+fn power_on_defaults_are_reported_as_reset() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x71)]);
+    let mut monitor = PresenceMonitor::new(MPU9250);
+    configure_mpu(&mut bus);
+    monitor.capture(&mut bus).unwrap();
+
+    bus.device(0x68).power_cycle(&[(0x75, 0x71), (0x6B, 0x40)]);
+    assert_eq!(monitor.check(&mut bus), Some(PresenceEvent::Reset));
+    // Keeps asking for a re-initialisation until the configuration is captured again
+    assert_eq!(monitor.check(&mut bus), Some(PresenceEvent::Reset));
+
+    configure_mpu(&mut bus);
+    monitor.capture(&mut bus).unwrap();
+    assert_eq!(monitor.check(&mut bus), None);
+}
+
+#[test]
+fn wrong_device_counts_as_disconnected() {
+    // An MPU9250 where an MPU6050 is expected
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x68)]);
+    let mut monitor = PresenceMonitor::new(MPU6050);
+    monitor.capture(&mut bus).unwrap();
+
+    bus.device(0x68).registers[0x75] = 0x71;
+    assert_eq!(monitor.check(&mut bus), Some(PresenceEvent::Disconnected));
+}
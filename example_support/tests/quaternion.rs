@@ -0,0 +1,48 @@
+mod common;
+
+use common::motion::{angle_difference, MotionProfile};
+use example_support::quaternion::{EulerAngles, Quaternion};
+
+fn assert_close(a: [f32; 3], b: [f32; 3]) {
+    for (a, b) in a.iter().zip(b) {
+        assert!((a - b).abs() < 1e-5, "{a:?} != {b:?}");
+    }
+}
+
+#[test]
+fn euler_angles_round_trip() {
+    for (roll, pitch, yaw) in [(0.0, 0.0, 0.0), (30.0, -20.0, 120.0), (-170.0, 80.0, -45.0), (90.0, 10.0, 179.0)] {
+        let angles = Quaternion::from_euler(EulerAngles { roll, pitch, yaw }).to_euler();
+        assert!(angle_difference(angles.roll, roll).abs() < 0.01, "{angles:?}");
+        assert!(angle_difference(angles.pitch, pitch).abs() < 0.01, "{angles:?}");
+        assert!(angle_difference(angles.yaw, yaw).abs() < 0.01, "{angles:?}");
+    }
+}
+
+#[test]
+fn matches_the_orientation_of_the_motion_simulator() {
+    let samples = MotionProfile::new(100.0).starting_at(25.0, -40.0, 70.0).rotate(1.0, [10.0, 20.0, -30.0]).simulate();
+    for truth in [samples.first().unwrap(), samples.last().unwrap()] {
+        let q = Quaternion::from_euler(EulerAngles {
+            roll: truth.roll,
+            pitch: truth.pitch,
+            yaw: truth.yaw,
+        });
+        assert!(q.angle_to(truth.orientation()) < 0.05);
+
+        // Gravity seen by the sensor is the world's up axis in the sensor frame
+        assert_close(q.rotate_inverse([0.0, 0.0, 1.0]), truth.sample.acceleration.as_g());
+    }
+}
+
+#[test]
+fn rotates_between_frames() {
+    let yawed = Quaternion::from_euler(EulerAngles { roll: 0.0, pitch: 0.0, yaw: 90.0 });
+    assert_close(yawed.rotate([1.0, 0.0, 0.0]), [0.0, 1.0, 0.0]);
+    assert_close(yawed.rotate_inverse([0.0, 1.0, 0.0]), [1.0, 0.0, 0.0]);
+
+    let turned = Quaternion::from_rotation_vector([0.0, 0.0, core::f32::consts::FRAC_PI_2]);
+    assert!(turned.angle_to(yawed) < 0.01);
+    assert!((yawed.angle_to(Quaternion::IDENTITY) - 90.0).abs() < 0.01);
+    assert!((yawed * yawed.conjugate()).angle_to(Quaternion::IDENTITY) < 0.01);
+}
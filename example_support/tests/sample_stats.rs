@@ -0,0 +1,50 @@
+use example_support::imu::ImuSample;
+use example_support::sample_stats::SampleAverager;
+use example_support::units::{Temperature, Vector3};
+
+fn sample(timestamp_us: u64, acceleration: [f32; 3], angular_velocity: [f32; 3]) -> ImuSample {
+    ImuSample {
+        timestamp_us,
+        temperature: Temperature::from_celsius(25.0 + timestamp_us as f32 / 10_000.0),
+        acceleration: Vector3::from_g(acceleration),
+        angular_velocity: Vector3::from_dps(angular_velocity),
+        magnetic_field: None,
+    }
+}
+
+#[test]
+fn averages_every_decimation_readings() {
+    let mut averager = SampleAverager::new(5_000, 4);
+    let readings = [
+        sample(0, [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]),
+        sample(5_000, [0.0, 0.0, -1.0], [3.0, 0.0, 0.0]),
+        sample(10_000, [0.6, 0.0, 0.8], [1.0, 2.0, 0.0]),
+        sample(15_000, [0.6, 0.0, -0.8], [3.0, 2.0, 4.0]),
+    ];
+    let (last, first) = readings.split_last().unwrap();
+    assert!(first.iter().all(|reading| averager.update(reading).is_none()));
+    let summary = averager.update(last).unwrap();
+
+    assert_eq!(summary.samples, 4);
+    assert!((summary.temperature.as_celsius() - 25.75).abs() < 1e-4);
+    assert_eq!(summary.acceleration.as_g(), [0.3, 0.0, 0.0]);
+    // Every reading is 1 g, however little of that is left in the mean
+    assert!((summary.acceleration_magnitude.as_g() - 1.0).abs() < 1e-6);
+    assert_eq!(summary.angular_velocity.as_dps(), [2.0, 1.0, 1.0]);
+    assert_eq!(summary.max_jitter_us, 0);
+}
+
+#[test]
+fn tracks_the_worst_jitter_per_summary() {
+    let mut averager = SampleAverager::new(5_000, 3);
+    let timestamps = [0, 5_000, 10_700, 15_700, 20_700, 25_900];
+    let summaries: Vec<_> = timestamps
+        .iter()
+        .filter_map(|&t| averager.update(&sample(t, [0.0, 0.0, 1.0], [0.0; 3])))
+        .collect();
+    // One reading 700 us late, then one 200 us late; the interval leading into a
+    // summary's first reading counts towards that summary
+    assert_eq!(summaries.iter().map(|s| s.max_jitter_us).collect::<Vec<_>>(), [700, 200]);
+    assert_eq!(summaries[1].period_us, 5_000);
+    assert!(summaries[0].to_string().ends_with("Timing : 3 samples every 5000 us, max jitter 700 us"));
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{SimBus, SimDevice};
+use example_support::scan::{BusInventory, SensorKind};
+
+#[test]
+fn empty_bus_has_no_devices() {
+    let inventory = BusInventory::scan(&mut SimBus::default());
+    assert!(inventory.is_empty());
+}
+
+#[test]
+fn identifies_every_known_sensor() {
+    let mut bus = SimBus::new(vec![
+        SimDevice::max30102(),
+        SimDevice::mpu(0x68, 0x68),
+        SimDevice::mpu(0x69, 0x71),
+        SimDevice::new(0x3C),
+        SimDevice::ak8963(0x69),
+    ]);
+
+    let inventory = BusInventory::scan_with_bypass(&mut bus);
+
+    let found: Vec<(u8, SensorKind)> = inventory.iter().map(|d| (d.address, d.kind)).collect();
+    assert_eq!(
+        found,
+        [
+            (0x0C, SensorKind::Ak8963),
+            (0x3C, SensorKind::Unknown),
+            (0x57, SensorKind::Max30102),
+            (0x68, SensorKind::Mpu6050),
+            (0x69, SensorKind::Mpu9250),
+        ]
+    );
+    assert_eq!(inventory.find(SensorKind::Mpu9250), Some(0x69));
+}
+
+#[test]
+fn plain_scan_leaves_the_mpu9250_alone() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x71), SimDevice::ak8963(0x68)]);
+
+    let inventory = BusInventory::scan(&mut bus);
+
+    let found: Vec<(u8, SensorKind)> = inventory.iter().map(|d| (d.address, d.kind)).collect();
+    assert_eq!(found, [(0x68, SensorKind::Mpu9250)]);
+    assert_eq!(bus.device(0x68).registers[0x37], 0);
+}
+
+#[test]
+fn unknown_id_at_a_known_address_is_reported() {
+    let mut bus = SimBus::new(vec![SimDevice::mpu(0x68, 0x70)]);
+    let inventory = BusInventory::scan(&mut bus);
+    let device = inventory.iter().next().unwrap();
+    assert_eq!(device.kind, SensorKind::Unknown);
+    assert_eq!(device.id, Some(0x70));
+    assert_eq!(device.to_string(), "0x68 unknown device (id 0x70)");
+}
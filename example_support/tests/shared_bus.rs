@@ -0,0 +1,104 @@
+//! The hayasen drivers sharing one simulated bus.
+
+mod common;
+
+use core::cell::RefCell;
+
+use common::{SimBus, SimDevice};
+use critical_section::Mutex;
+use embedded_hal_bus::i2c::{CriticalSectionDevice, RefCellDevice};
+use hayasen::max30102::FifoSample;
+use hayasen::{max30102_hayasen, mpu6050_hayasen};
+
+const MPU_ADDRESS: u8 = 0x68;
+const ACCEL_XOUT_H: u8 = 0x3B;
+const ACCEL_CONFIG: u8 = 0x1C;
+const FIFO_WR_PTR: usize = 0x04;
+const FIFO_RD_PTR: usize = 0x06;
+
+fn bus() -> SimBus {
+    SimBus::new(vec![SimDevice::mpu(MPU_ADDRESS, 0x68), SimDevice::max30102()])
+}
+
+/// Puts the MPU flat on the table: 1 g on Z, in whatever range the driver selected.
+fn place_flat(bus: &mut SimBus) {
+    let mpu = bus.device(MPU_ADDRESS);
+    let range = (mpu.registers[usize::from(ACCEL_CONFIG)] >> 3) & 0x03;
+    let lsb_per_g = 16384 >> range;
+    mpu.set_i16(ACCEL_XOUT_H, 0);
+    mpu.set_i16(ACCEL_XOUT_H + 2, 0);
+    mpu.set_i16(ACCEL_XOUT_H + 4, lsb_per_g as i16);
+}
+
+fn empty_batch() -> [FifoSample; 8] {
+    core::array::from_fn(|_| FifoSample { red: 0, ir: 0 })
+}
+
+#[test]
+fn drivers_interleave_on_a_refcell_bus() {
+    let bus = RefCell::new(bus());
+
+    let mut imu = mpu6050_hayasen::create_default(RefCellDevice::new(&bus), MPU_ADDRESS).unwrap();
+    let mut ppg = max30102_hayasen::create_default_with_address(RefCellDevice::new(&bus)).unwrap();
+    place_flat(&mut bus.borrow_mut());
+    let mpu_registers = bus.borrow().devices[0].registers;
+
+    let mut batch = empty_batch();
+    for round in 0..5u32 {
+        // The MAX30102 fills its FIFO at its own rate, the MPU is read once per round
+        for i in 0..3 {
+            bus.borrow_mut().device(0x57).push_fifo_sample(50_000 + round * 10 + i, 60_000 + round * 10 + i);
+        }
+
+        let (_, acceleration, _) = mpu6050_hayasen::read_all(&mut imu).unwrap();
+        assert!((acceleration[2] - 1.0).abs() < 0.01, "round {round}: {acceleration:?}");
+        assert!(acceleration[0].abs() < 0.01 && acceleration[1].abs() < 0.01);
+
+        let count = max30102_hayasen::read_fifo_batch(&mut ppg, &mut batch).unwrap();
+        assert_eq!(count, 3);
+        for (i, sample) in batch[..count].iter().enumerate() {
+            assert_eq!(sample.red, 50_000 + round * 10 + i as u32);
+            assert_eq!(sample.ir, 60_000 + round * 10 + i as u32);
+        }
+    }
+
+    // The IMU registers were not disturbed by the FIFO reads, and the IMU reads did not
+    // get in the way of draining the FIFO
+    let bus = bus.borrow();
+    assert_eq!(bus.devices[0].registers, mpu_registers);
+    let ppg = &bus.devices[1];
+    assert!(ppg.fifo.as_ref().unwrap().is_empty());
+    assert_eq!(ppg.registers[FIFO_RD_PTR], ppg.registers[FIFO_WR_PTR]);
+}
+
+#[test]
+fn drivers_share_a_critical_section_bus() {
+    let bus = Mutex::new(RefCell::new(bus()));
+
+    let mut imu = mpu6050_hayasen::create_default(CriticalSectionDevice::new(&bus), MPU_ADDRESS).unwrap();
+    let mut ppg = max30102_hayasen::create_default_with_address(CriticalSectionDevice::new(&bus)).unwrap();
+    critical_section::with(|cs| {
+        let mut sim = bus.borrow_ref_mut(cs);
+        place_flat(&mut sim);
+        sim.device(0x57).push_fifo_sample(1234, 5678);
+    });
+
+    let (_, acceleration, _) = mpu6050_hayasen::read_all(&mut imu).unwrap();
+    assert!((acceleration[2] - 1.0).abs() < 0.01);
+
+    let mut batch = empty_batch();
+    assert_eq!(max30102_hayasen::read_fifo_batch(&mut ppg, &mut batch).unwrap(), 1);
+    assert_eq!((batch[0].red, batch[0].ir), (1234, 5678));
+}
+
+#[test]
+fn a_missing_sensor_does_not_affect_the_other() {
+    let bus = RefCell::new(SimBus::new(vec![SimDevice::max30102()]));
+
+    assert!(mpu6050_hayasen::create_default(RefCellDevice::new(&bus), MPU_ADDRESS).is_err());
+
+    let mut ppg = max30102_hayasen::create_default_with_address(RefCellDevice::new(&bus)).unwrap();
+    bus.borrow_mut().device(0x57).push_fifo_sample(42, 43);
+    let mut batch = empty_batch();
+    assert_eq!(max30102_hayasen::read_fifo_batch(&mut ppg, &mut batch).unwrap(), 1);
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::activity::Trace;
+use example_support::tap::{Axis, TapConfig, TapDetector, TapDirection, TapEvent, TapKind};
+use example_support::units::Acceleration;
+
+const RATE_HZ: f64 = 400.0;
+
+fn events(detector: &mut TapDetector, trace: &Trace) -> Vec<TapEvent> {
+    trace.samples().iter().filter_map(|sample| detector.update(sample)).collect()
+}
+
+/// Taps at the given offsets into a second of otherwise still time, each a sharp
+/// jolt in the world frame and a weaker rebound as the board springs back.
+fn tapped(trace: Trace, taps: &[(f64, [f64; 3])]) -> Trace {
+    let start = trace.duration_s();
+    let taps: Vec<_> = taps.iter().map(|&(offset, jolt)| (start + offset, jolt)).collect();
+    trace.custom(1.0, move |t| {
+        let mut total = [0.0; 3];
+        for &(at, jolt) in &taps {
+            let pulse = (-((t - at) / 0.004).powi(2)).exp() - 0.3 * (-((t - at - 0.01) / 0.006).powi(2)).exp();
+            for (total, jolt) in total.iter_mut().zip(jolt) {
+                *total += jolt * pulse;
+            }
+        }
+        total
+    })
+}
+
+fn direction(axis: Axis, positive: bool) -> TapDirection {
+    TapDirection { axis, positive }
+}
+
+#[test]
+fn reports_a_single_tap_with_its_direction() {
+    let cases = [
+        ([3.0, 0.0, 0.0], direction(Axis::X, true)),
+        ([-3.0, 0.0, 0.0], direction(Axis::X, false)),
+        ([0.0, 3.0, 0.0], direction(Axis::Y, true)),
+        ([0.0, -3.0, 0.0], direction(Axis::Y, false)),
+        ([0.0, 0.0, 3.0], direction(Axis::Z, true)),
+        ([0.5, 0.0, -3.0], direction(Axis::Z, false)),
+    ];
+    for (jolt, expected) in cases {
+        let trace = tapped(Trace::new(RATE_HZ).still(1.0), &[(0.2, jolt)]).still(0.5);
+        let events = events(&mut TapDetector::default(), &trace);
+        assert_eq!(events.len(), 1, "{jolt:?}: {events:?}");
+        let event = events[0];
+        assert_eq!(event.kind, TapKind::Single);
+        assert_eq!(event.direction, expected, "{jolt:?}");
+        assert!((event.timestamp_us as f64 / 1e6 - 1.2).abs() < 0.01, "{event:?}");
+        assert!((event.strength.as_g() - 3.0).abs() < 0.3, "{event:?}");
+    }
+}
+
+#[test]
+fn reports_a_double_tap() {
+    let trace = tapped(Trace::new(RATE_HZ).still(1.0), &[(0.2, [0.0, 0.0, -2.5]), (0.45, [0.0, 0.0, -2.0])]).still(0.5);
+    let events = events(&mut TapDetector::default(), &trace);
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert_eq!(events[0].kind, TapKind::Double);
+    assert_eq!(events[0].direction, direction(Axis::Z, false));
+    assert!((events[0].timestamp_us as f64 / 1e6 - 1.2).abs() < 0.01, "{events:?}");
+}
+
+#[test]
+fn taps_far_apart_are_single() {
+    let trace = tapped(Trace::new(RATE_HZ).still(1.0), &[(0.1, [2.5, 0.0, 0.0])]);
+    let trace = tapped(trace, &[(0.2, [0.0, 2.5, 0.0])]).still(0.5);
+    let events = events(&mut TapDetector::default(), &trace);
+    let kinds: Vec<_> = events.iter().map(|event| (event.kind, event.direction.axis)).collect();
+    assert_eq!(kinds, [(TapKind::Single, Axis::X), (TapKind::Single, Axis::Y)]);
+}
+
+#[test]
+fn a_bounce_is_not_a_second_tap() {
+    // The second jolt comes before the latency is over
+    let trace = tapped(Trace::new(RATE_HZ).still(1.0), &[(0.2, [0.0, 0.0, -3.0]), (0.25, [0.0, 0.0, -2.0])]).still(0.5);
+    assert_eq!(events(&mut TapDetector::default(), &trace), []);
+}
+
+#[test]
+fn pushes_and_vibration_are_not_taps() {
+    // Shoving the board along the table
+    let trace = Trace::new(RATE_HZ)
+        .still(1.0)
+        .custom(0.3, |t| [1.8 * (std::f64::consts::PI * (t - 1.0) / 0.3).sin(), 0.0, 0.0])
+        .still(1.0);
+    assert_eq!(events(&mut TapDetector::default(), &trace), []);
+
+    // A motor running nearby
+    let trace = Trace::new(RATE_HZ)
+        .still(1.0)
+        .custom(2.0, |t| [0.0, 0.0, 1.5 * (2.0 * std::f64::consts::PI * 40.0 * t).sin()])
+        .still(1.0);
+    assert_eq!(events(&mut TapDetector::default(), &trace), []);
+}
+
+#[test]
+fn walking_and_turning_are_not_taps() {
+    let trace = Trace::new(RATE_HZ).still(1.0).walk(10.0, 110.0, 0.3).tilted(60.0, -30.0).still(2.0);
+    assert_eq!(events(&mut TapDetector::default(), &trace), []);
+}
+
+#[test]
+fn works_however_the_sensor_is_mounted() {
+    // Standing on its edge, a tap from above jolts the sensor's X axis
+    let trace = tapped(Trace::new(RATE_HZ).tilted(0.0, 90.0).still(1.0), &[(0.2, [0.0, 0.0, -3.0])]).still(0.5);
+    let events = events(&mut TapDetector::default(), &trace);
+    assert_eq!(events.len(), 1, "{events:?}");
+    assert_eq!(events[0].direction.axis, Axis::X);
+}
+
+#[test]
+fn threshold_and_windows_are_configurable() {
+    let light = tapped(Trace::new(RATE_HZ).still(1.0), &[(0.2, [0.0, 0.0, -0.8])]).still(0.5);
+    assert_eq!(events(&mut TapDetector::default(), &light), []);
+    let sensitive = TapConfig { threshold: Acceleration::from_g(0.5), ..TapConfig::default() };
+    assert_eq!(events(&mut TapDetector::new(sensitive), &light).len(), 1);
+
+    let slow = tapped(Trace::new(RATE_HZ).still(1.0), &[(0.2, [0.0, 0.0, -2.5]), (0.7, [0.0, 0.0, -2.5])]).still(0.5);
+    let kinds = |config| events(&mut TapDetector::new(config), &slow).iter().map(|event| event.kind).collect::<Vec<_>>();
+    assert_eq!(kinds(TapConfig::default()), [TapKind::Single, TapKind::Single]);
+    assert_eq!(kinds(TapConfig { window_ms: 600, ..TapConfig::default() }), [TapKind::Double]);
+
+    let detector = TapDetector::new(sensitive);
+    assert_eq!(detector.config(), sensitive);
+}
@@ -0,0 +1,25 @@
+use example_support::tilt::Tilt;
+use example_support::units::Vector3;
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 0.01, "{actual} != {expected}");
+}
+
+#[test]
+fn tilt_of_a_level_unit_is_zero() {
+    let tilt = Tilt::from_acceleration(Vector3::from_g([0.0, 0.0, 1.0]));
+    assert_close(tilt.roll, 0.0);
+    assert_close(tilt.pitch, 0.0);
+}
+
+#[test]
+fn nose_up_is_positive_pitch_and_right_side_down_positive_roll() {
+    let radians = 30f32.to_radians();
+    let pitched = Tilt::from_acceleration(Vector3::from_g([-radians.sin(), 0.0, radians.cos()]));
+    assert_close(pitched.pitch, 30.0);
+    assert_close(pitched.roll, 0.0);
+
+    let rolled = Tilt::from_acceleration(Vector3::from_g([0.0, radians.sin(), radians.cos()]));
+    assert_close(rolled.roll, 30.0);
+    assert_close(rolled.pitch, 0.0);
+}
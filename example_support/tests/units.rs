@@ -0,0 +1,67 @@
+use example_support::units::{
+    Acceleration, AngularRate, MagneticField, Quantity, Temperature, Vector3, STANDARD_GRAVITY,
+};
+
+fn assert_close(actual: f32, expected: f32) {
+    assert!((actual - expected).abs() < 1e-4, "{actual} != {expected}");
+}
+
+#[test]
+fn acceleration_round_trips_through_g() {
+    let one_g = Acceleration::from_g(1.0);
+    assert_close(one_g.as_mps2(), STANDARD_GRAVITY);
+    assert_close(Acceleration::from_mps2(4.903_325).as_g(), 0.5);
+}
+
+#[test]
+fn angular_rate_is_stored_in_radians() {
+    let rate = AngularRate::from_dps(180.0);
+    assert_close(rate.as_rad_per_s(), core::f32::consts::PI);
+    assert_close(rate.base(), core::f32::consts::PI);
+    assert_close(AngularRate::from_rad_per_s(1.0).as_dps(), 57.29578);
+}
+
+#[test]
+fn magnetic_field_and_temperature_conversions() {
+    assert_close(MagneticField::from_gauss(0.5).as_microtesla(), 50.0);
+    assert_close(MagneticField::from_microtesla(25.0).as_gauss(), 0.25);
+    assert_close(Temperature::from_kelvin(300.0).as_celsius(), 26.85);
+    assert_close(Temperature::from_celsius(-273.15).as_kelvin(), 0.0);
+}
+
+#[test]
+fn quantities_of_the_same_kind_combine() {
+    let mut a = Acceleration::from_g(1.0) + Acceleration::from_g(0.5);
+    a -= Acceleration::from_g(0.25);
+    assert_close(a.as_g(), 1.25);
+    assert_close((-a).abs().as_g(), 1.25);
+    assert_close((a * 2.0 / 5.0).as_g(), 0.5);
+    assert_close(a / Acceleration::from_g(0.5), 2.5);
+}
+
+#[test]
+fn vector_norm_and_direction() {
+    let v = Vector3::from_g([3.0, 0.0, 4.0]);
+    assert_close(v.norm().as_g(), 5.0);
+    let [x, y, z] = v.direction().unwrap();
+    assert_close(x, 0.6);
+    assert_close(y, 0.0);
+    assert_close(z, 0.8);
+    assert_eq!(Vector3::<Acceleration>::default().direction(), None);
+}
+
+#[test]
+fn vector_arithmetic_is_per_axis() {
+    let a = Vector3::from_dps([10.0, 20.0, 30.0]);
+    let b = Vector3::from_dps([1.0, 2.0, 3.0]);
+    let [x, y, z] = ((a - b) * 2.0 / 3.0 + b).as_dps();
+    assert_close(x, 7.0);
+    assert_close(y, 14.0);
+    assert_close(z, 21.0);
+}
+
+#[test]
+fn display_includes_the_unit() {
+    assert_eq!(format!("{:.1}", Acceleration::from_mps2(9.81)), "9.8 m/s^2");
+    assert_eq!(format!("{:.2}", Temperature::from_celsius(21.5)), "21.50 C");
+}
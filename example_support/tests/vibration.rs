@@ -0,0 +1,153 @@
+use std::f64::consts::PI;
+
+use example_support::fft::Window;
+use example_support::units::Vector3;
+use example_support::vibration::{
+    Band, MachineClass, Spectrum, VibrationAnalyzer, VibrationConfig, VibrationReport, Zone,
+};
+
+const N: usize = 1024;
+const RATE_HZ: f64 = 1000.0;
+
+/// One block of readings at 1 kHz, starting at `start_s`.
+fn block(start_s: f64, acceleration_g: impl Fn(f64) -> [f64; 3]) -> Vec<Vector3<example_support::units::Acceleration>> {
+    (0..N)
+        .map(|i| Vector3::from_g(acceleration_g(start_s + i as f64 / RATE_HZ).map(|a| a as f32)))
+        .collect()
+}
+
+fn analyze(config: VibrationConfig, acceleration_g: impl Fn(f64) -> [f64; 3]) -> VibrationReport {
+    let mut analyzer = VibrationAnalyzer::<N>::new(config);
+    let readings = block(0.0, acceleration_g);
+    let (last, rest) = readings.split_last().unwrap();
+    assert!(rest.iter().all(|&reading| analyzer.update(reading).is_none()));
+    analyzer.update(*last).expect("a full block")
+}
+
+fn sine(amplitude_g: f64, frequency_hz: f64, t: f64) -> f64 {
+    amplitude_g * (2.0 * PI * frequency_hz * t).sin()
+}
+
+/// Standard gravity in mm/s².
+const G_MM_S2: f64 = 9806.65;
+
+#[test]
+fn measures_a_sine() {
+    let report = analyze(VibrationConfig::default(), |t| [sine(0.5, 50.0, t), 0.0, 1.0]);
+    let x = &report.axes[0];
+    assert!((x.rms.as_g() - 0.3536).abs() < 0.005, "{x:?}");
+    let velocity = 0.5 / 2f64.sqrt() * G_MM_S2 / (2.0 * PI * 50.0);
+    assert!((f64::from(x.velocity_rms_mm_s) - velocity).abs() < 0.02 * velocity, "{x:?}");
+    let peak = x.peaks()[0];
+    assert!((peak.frequency_hz - 50.0).abs() < 0.1, "{peak:?}");
+    assert!((peak.amplitude.as_g() - 0.5).abs() < 0.01, "{peak:?}");
+
+    // Gravity on Z is not vibration
+    assert!(report.axes[2].rms.as_g() < 1e-3, "{:?}", report.axes[2]);
+    assert_eq!(report.worst_axis(), 0);
+}
+
+#[test]
+fn reads_amplitudes_between_bins() {
+    for window in [Window::Hann, Window::FlatTop] {
+        let config = VibrationConfig { window, ..VibrationConfig::default() };
+        for frequency in [63.0, 63.25, 63.5, 63.75] {
+            let frequency = frequency * RATE_HZ / N as f64;
+            let report = analyze(config, |t| [0.0, sine(0.2, frequency, t), 0.0]);
+            let peak = report.axes[1].peaks()[0];
+            assert!((peak.amplitude.as_g() - 0.2).abs() < 0.004, "{window:?} at {frequency} Hz: {peak:?}");
+            assert!((f64::from(peak.frequency_hz) - frequency).abs() < 0.3, "{window:?} at {frequency} Hz: {peak:?}");
+        }
+    }
+}
+
+#[test]
+fn separates_peaks_and_bands() {
+    let report = analyze(VibrationConfig::default(), |t| {
+        [sine(0.1, 37.3, t) + sine(0.3, 120.0, t) + sine(0.05, 310.0, t + 0.1), 0.0, 0.0]
+    });
+    let x = &report.axes[0];
+    let peaks: Vec<_> = x.peaks().iter().map(|peak| (peak.frequency_hz.round(), (peak.amplitude.as_g() * 100.0).round())).collect();
+    assert_eq!(peaks[..3], [(120.0, 30.0), (37.0, 10.0), (310.0, 5.0)], "{x:?}");
+
+    // 10-50, 50-100, 100-200 and 200-500 Hz
+    let bands = x.bands().iter().map(|rms| rms.as_g()).collect::<Vec<_>>();
+    let expected = [0.1 / 2f32.sqrt(), 0.0, 0.3 / 2f32.sqrt(), 0.05 / 2f32.sqrt()];
+    for (band, expected) in bands.iter().zip(expected) {
+        assert!((band - expected).abs() < 0.003, "{bands:?}");
+    }
+    let total = expected.iter().map(|rms| rms * rms).sum::<f32>().sqrt();
+    assert!((x.rms.as_g() - total).abs() < 0.003, "{x:?}");
+}
+
+#[test]
+fn ignores_tilt_and_drift() {
+    // A slow sway of a few degrees, with a slowly drifting offset
+    let report = analyze(VibrationConfig::default(), |t| [sine(0.05, 2.0, t) + 0.01 * t, 0.0, 1.0]);
+    assert!(report.axes[0].rms.as_g() < 2e-3, "{:?}", report.axes[0]);
+    assert!(report.axes[0].velocity_rms_mm_s < 0.3, "{:?}", report.axes[0]);
+}
+
+#[test]
+fn classifies_severity_by_iso_10816() {
+    assert_eq!(MachineClass::I.zone(0.5), Zone::A);
+    assert_eq!(MachineClass::I.zone(1.0), Zone::B);
+    assert_eq!(MachineClass::I.zone(1.8), Zone::C);
+    assert_eq!(MachineClass::I.zone(5.0), Zone::D);
+    assert_eq!(MachineClass::III.zone(5.0), Zone::C);
+    assert_eq!(MachineClass::IV.zone(5.0), Zone::B);
+
+    // 4 mm/s at 25 Hz, the running speed of a 1500 rpm motor
+    let amplitude = 4.0 * 2f64.sqrt() * 2.0 * PI * 25.0 / G_MM_S2;
+    let vibration = |t| [0.0, 0.0, 1.0 + sine(amplitude, 25.0, t)];
+    assert_eq!(analyze(VibrationConfig::default(), vibration).axes[2].zone, None);
+    for (class, zone) in [(MachineClass::I, Zone::C), (MachineClass::II, Zone::C), (MachineClass::III, Zone::B), (MachineClass::IV, Zone::B)] {
+        let config = VibrationConfig { machine_class: Some(class), ..VibrationConfig::default() };
+        let report = analyze(config, vibration);
+        assert!((report.axes[2].velocity_rms_mm_s - 4.0).abs() < 0.1, "{:?}", report.axes[2]);
+        assert_eq!(report.axes[2].zone, Some(zone), "{class:?}");
+        assert_eq!(report.worst_axis(), 2);
+    }
+}
+
+#[test]
+fn analyses_back_to_back_blocks() {
+    let mut analyzer = VibrationAnalyzer::<256>::default();
+    let mut reports = 0;
+    for i in 0..1000 {
+        let t = i as f64 / RATE_HZ;
+        reports += usize::from(analyzer.update(Vector3::from_g([sine(0.1, 100.0, t) as f32, 0.0, 1.0])).is_some());
+    }
+    assert_eq!(reports, 3);
+
+    // A reset throws away the 232 readings collected since
+    analyzer.reset();
+    assert!((0..255).all(|_| analyzer.update(Vector3::from_g([0.0, 0.0, 1.0])).is_none()));
+    assert!(analyzer.update(Vector3::from_g([0.0, 0.0, 1.0])).is_some());
+}
+
+#[test]
+fn spectrum_adds_up_to_the_variance() {
+    let mut state = 0x2545_f491u32;
+    let samples: [f32; 512] = std::array::from_fn(|_| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        state as f32 / u32::MAX as f32 - 0.5
+    });
+    let mean = samples.iter().sum::<f32>() / 512.0;
+    let variance = samples.iter().map(|x| (x - mean) * (x - mean)).sum::<f32>() / 512.0;
+
+    let spectrum = Spectrum::new(&samples, 1000.0, Window::Rectangular);
+    assert_eq!(spectrum.bins(), 257);
+    assert!((spectrum.resolution_hz() - 1000.0 / 512.0).abs() < 1e-6);
+    let total = (0..spectrum.bins()).map(|bin| spectrum.power(bin)).sum::<f32>();
+    assert!((total - variance).abs() < 1e-3 * variance, "{total} vs {variance}");
+    assert!((spectrum.rms(Band::new(0.0, 1000.0)).as_g() - variance.sqrt()).abs() < 1e-3);
+}
+
+#[test]
+fn default_band_stops_at_nyquist() {
+    let config = VibrationConfig::default();
+    assert_eq!(config.band.high_hz, config.sample_rate_hz / 2.0);
+}
@@ -0,0 +1,163 @@
+#!/usr/bin/env python3
+"""Generates a magnetic declination table for `example_support::compass::DeclinationGrid`.
+
+The declination is evaluated from the World Magnetic Model at every point of a regular
+latitude and longitude grid and written out as a Rust source file that an example can
+include. The model coefficients are not bundled: download the current `WMM.COF` from
+NOAA (https://www.ncei.noaa.gov/products/world-magnetic-model) and pass its path.
+
+    python3 tools/declination_grid.py WMM.COF --year 2026.5 \
+        --south 30 --north 60 --west -15 --east 40 --step 2.5 -o src/declination.rs
+
+Declination changes by a fraction of a degree per year in most places, so regenerate
+the table now and then. Near the magnetic poles it changes quickly with position,
+and a coarse grid interpolates poorly there.
+"""
+
+import argparse
+import math
+import sys
+
+# WGS 84 ellipsoid, in km
+SEMI_MAJOR_AXIS = 6378.137
+FLATTENING = 1 / 298.257223563
+ECCENTRICITY_SQUARED = FLATTENING * (2 - FLATTENING)
+# Reference radius of the model's spherical harmonics, in km
+REFERENCE_RADIUS = 6371.2
+
+
+def read_coefficients(path):
+    """Returns the model's epoch and its coefficients as {(n, m): (g, h, dg, dh)} in nT."""
+    with open(path) as file:
+        header = file.readline().split()
+        epoch = float(header[0])
+        coefficients = {}
+        for line in file:
+            fields = line.split()
+            if not fields or fields[0].startswith("9999"):
+                break
+            n, m = int(fields[0]), int(fields[1])
+            coefficients[(n, m)] = tuple(float(value) for value in fields[2:6])
+    return epoch, coefficients
+
+
+def schmidt_legendre(degree, x):
+    """Schmidt semi-normalised associated Legendre functions P[n][m] of x = sin(latitude)
+    and their derivatives with respect to latitude."""
+    s = math.sqrt(max(0.0, 1 - x * x))
+    p = [[0.0] * (degree + 2) for _ in range(degree + 2)]
+    for m in range(degree + 1):
+        # P(m, m) = (2m - 1)!! s^m, then upwards in n
+        p[m][m] = math.prod(range(1, 2 * m, 2)) * s**m
+        if m + 1 <= degree:
+            p[m + 1][m] = x * (2 * m + 1) * p[m][m]
+        for n in range(m + 2, degree + 1):
+            p[n][m] = ((2 * n - 1) * x * p[n - 1][m] - (n + m - 1) * p[n - 2][m]) / (n - m)
+
+    dp = [[0.0] * (degree + 1) for _ in range(degree + 1)]
+    for n in range(1, degree + 1):
+        for m in range(n + 1):
+            previous = p[n - 1][m] if m <= n - 1 else 0.0
+            dp[n][m] = ((n + m) * previous - n * x * p[n][m]) / s
+
+    for n in range(degree + 1):
+        for m in range(1, n + 1):
+            factor = math.sqrt(2 * math.factorial(n - m) / math.factorial(n + m))
+            p[n][m] *= factor
+            dp[n][m] *= factor
+    return p, dp
+
+
+def declination(coefficients, epoch, year, latitude, longitude, altitude_km=0.0):
+    """Declination in degrees, east positive, at a geodetic position."""
+    if abs(latitude) >= 90:
+        raise ValueError("the declination is undefined at the poles")
+    lat = math.radians(latitude)
+    lon = math.radians(longitude)
+
+    # Geodetic to geocentric spherical coordinates
+    curvature = SEMI_MAJOR_AXIS / math.sqrt(1 - ECCENTRICITY_SQUARED * math.sin(lat) ** 2)
+    p = (curvature + altitude_km) * math.cos(lat)
+    z = (curvature * (1 - ECCENTRICITY_SQUARED) + altitude_km) * math.sin(lat)
+    r = math.hypot(p, z)
+    geocentric_lat = math.asin(z / r)
+
+    degree = max(n for n, _ in coefficients)
+    legendre, derivative = schmidt_legendre(degree, math.sin(geocentric_lat))
+    dt = year - epoch
+
+    north = east = down = 0.0
+    for (n, m), (g, h, dg, dh) in coefficients.items():
+        g += dt * dg
+        h += dt * dh
+        scale = (REFERENCE_RADIUS / r) ** (n + 2)
+        cos_m, sin_m = math.cos(m * lon), math.sin(m * lon)
+        north -= scale * (g * cos_m + h * sin_m) * derivative[n][m]
+        east += scale * m * (g * sin_m - h * cos_m) * legendre[n][m] / math.cos(geocentric_lat)
+        down -= scale * (n + 1) * (g * cos_m + h * sin_m) * legendre[n][m]
+
+    # Back from geocentric to geodetic north, east is the same in both
+    tilt = geocentric_lat - lat
+    north = north * math.cos(tilt) - down * math.sin(tilt)
+    return math.degrees(math.atan2(east, north))
+
+
+def frange(start, stop, step):
+    count = int(round((stop - start) / step))
+    if count < 1 or abs(start + count * step - stop) > 1e-6:
+        raise ValueError(f"{start}..{stop} is not a whole number of {step} degree steps")
+    return [start + i * step for i in range(count + 1)]
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("cof", help="WMM.COF coefficient file from NOAA")
+    parser.add_argument("--year", type=float, required=True, help="decimal year, e.g. 2026.5")
+    parser.add_argument("--south", type=float, default=-85.0)
+    parser.add_argument("--north", type=float, default=85.0)
+    parser.add_argument("--west", type=float, default=-180.0)
+    parser.add_argument("--east", type=float, default=180.0)
+    parser.add_argument("--step", type=float, default=5.0, help="grid spacing in degrees")
+    parser.add_argument("-o", "--output", help="Rust file to write, standard output if omitted")
+    args = parser.parse_args()
+
+    epoch, coefficients = read_coefficients(args.cof)
+    if not epoch <= args.year < epoch + 5:
+        print(f"warning: {args.year} is outside the model's validity, {epoch} to {epoch + 5}", file=sys.stderr)
+
+    latitudes = frange(args.south, args.north, args.step)
+    longitudes = frange(args.west, args.east, args.step)
+    rows = []
+    for latitude in latitudes:
+        values = [round(declination(coefficients, epoch, args.year, latitude, lon) * 100) for lon in longitudes]
+        rows.append("        " + ", ".join(str(v) for v in values) + ",")
+
+    source = "\n".join(
+        [
+            f"//! Magnetic declination from the World Magnetic Model {epoch:g} for {args.year:g}.",
+            "//!",
+            "//! Generated by `tools/declination_grid.py`, do not edit.",
+            "",
+            "use example_support::compass::DeclinationGrid;",
+            "",
+            "pub static DECLINATION: DeclinationGrid = DeclinationGrid {",
+            f"    south: {args.south!r},",
+            f"    west: {args.west!r},",
+            f"    step: {args.step!r},",
+            f"    columns: {len(longitudes)},",
+            "    values: &[",
+            *rows,
+            "    ],",
+            "};",
+            "",
+        ]
+    )
+    if args.output:
+        with open(args.output, "w") as file:
+            file.write(source)
+    else:
+        sys.stdout.write(source)
+
+
+if __name__ == "__main__":
+    main()
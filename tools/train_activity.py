@@ -0,0 +1,330 @@
+#!/usr/bin/env python3
+"""Trains the decision tree for `example_support::activity` and writes it as Rust source.
+
+Training data are accelerometer recordings in CSV files with a header line and the
+columns `label,timestamp_us,ax,ay,az`, acceleration in g and the label one of
+still, walking, running, cycling or vehicle. Windows never span two labels or two
+files. Without recordings, or in addition to them, `--synthetic` generates that many
+simulated sessions of each activity; that is what the built-in tree is trained on.
+
+    python3 tools/train_activity.py walk.csv bike.csv car.csv -o src/activity_model.rs
+    python3 tools/train_activity.py --synthetic 100 --use-path crate::activity \
+        -o example_support/src/activity_tree.rs
+
+The features are computed exactly as `FeatureExtractor` does, so a change to one has
+to be made to the other. The tree is grown with the Gini impurity (CART) and kept
+small enough to stay readable.
+"""
+
+import argparse
+import csv
+import math
+import random
+import sys
+
+ACTIVITIES = ["still", "walking", "running", "cycling", "vehicle"]
+FEATURES = ["variance", "energy", "dominant_frequency", "periodicity", "jerk"]
+
+# Must match `example_support::activity`
+BIN_RATE_HZ = 25.0
+BIN_US = 40_000
+WINDOW = 128
+HOP = 64
+MAX_GAP_US = 500_000
+MIN_FREQUENCY_BIN = 2
+MAX_FREQUENCY_BIN = 26
+
+
+def bins(samples):
+    """Averages (timestamp_us, [ax, ay, az]) samples into bins, as `FeatureExtractor`
+    does. Yields lists of consecutive bins, split at gaps."""
+    run = []
+    bin_start = None
+    last = 0
+    total, count = [0.0] * 3, 0
+    for timestamp, acceleration in samples:
+        if bin_start is not None and (timestamp < last or timestamp - last > MAX_GAP_US):
+            yield run
+            run, bin_start, total, count = [], None, [0.0] * 3, 0
+        last = timestamp
+        if bin_start is None:
+            bin_start = timestamp
+        while timestamp >= bin_start + BIN_US:
+            if count > 0:
+                run.append([t / count for t in total])
+            else:
+                run.append(run[-1] if run else [0.0] * 3)
+            total, count = [0.0] * 3, 0
+            bin_start += BIN_US
+        total = [t + a for t, a in zip(total, acceleration)]
+        count += 1
+    yield run
+
+
+def features(window):
+    """The features of a window of WINDOW bins, as `Features::compute`."""
+    n = float(WINDOW)
+    magnitudes = [math.sqrt(sum(a * a for a in bin)) for bin in window]
+    mean_magnitude = sum(magnitudes) / n
+    mean = [sum(bin[axis] for bin in window) / n for axis in range(3)]
+
+    deviations = [m - mean_magnitude for m in magnitudes]
+    power = sum(d * d for d in deviations)
+    energy = sum(sum((a - m) ** 2 for a, m in zip(bin, mean)) for bin in window)
+    jerk = sum(math.dist(b, a) for a, b in zip(window, window[1:])) * BIN_RATE_HZ / (n - 1)
+
+    powers = [0.0] * (MAX_FREQUENCY_BIN + 2)
+    for k in range(MIN_FREQUENCY_BIN - 1, MAX_FREQUENCY_BIN + 2):
+        re = im = 0.0
+        for i, d in enumerate(deviations):
+            angle = 2 * math.pi * ((k * i) % WINDOW) / n
+            re += d * math.cos(angle)
+            im += d * math.sin(angle)
+        powers[k] = re * re + im * im
+    best_bin = MIN_FREQUENCY_BIN
+    for k in range(MIN_FREQUENCY_BIN, MAX_FREQUENCY_BIN + 1):
+        if powers[k] > powers[best_bin]:
+            best_bin = k
+    peak_power = powers[best_bin - 1] + powers[best_bin] + powers[best_bin + 1]
+    periodicity = min(peak_power / (n * power / 2), 1.0) if power > 0 else 0.0
+
+    return [power / n, energy / n, best_bin * BIN_RATE_HZ / n, periodicity, jerk]
+
+
+def windows(samples):
+    """Features of every window of the samples, overlapping by half."""
+    for run in bins(samples):
+        for end in range(WINDOW, len(run) + 1, HOP):
+            yield features(run[end - WINDOW : end])
+
+
+def read_recording(path):
+    """Returns the recording as a list of (label, samples), one per stretch of a label."""
+    stretches = []
+    with open(path, newline="") as file:
+        for row in csv.DictReader(file):
+            label = row["label"].strip()
+            if label not in ACTIVITIES:
+                raise ValueError(f"{path}: unknown label {label!r}")
+            sample = (int(row["timestamp_us"]), [float(row[axis]) for axis in ("ax", "ay", "az")])
+            if not stretches or stretches[-1][0] != label:
+                stretches.append((label, []))
+            stretches[-1][1].append(sample)
+    return stretches
+
+
+class Lowpass:
+    """Gaussian noise through a first-order low-pass, scaled to the given deviation."""
+
+    def __init__(self, rng, deviation, cutoff_hz, rate_hz):
+        self.rng = rng
+        self.alpha = 1 - math.exp(-2 * math.pi * cutoff_hz / rate_hz)
+        self.drive = deviation * math.sqrt((2 - self.alpha) / self.alpha)
+        self.value = rng.gauss(0, deviation)
+
+    def next(self):
+        self.value += self.alpha * (self.rng.gauss(0, self.drive) - self.value)
+        return self.value
+
+
+def gait(rng, cadence, peak, harmonic):
+    """Bounce, surge and sway of walking or running, step lengths varying by 5%."""
+    state = {"start": 0.0, "period": 60 / cadence, "index": 0}
+
+    def motion(t):
+        while t >= state["start"] + state["period"]:
+            state["start"] += state["period"]
+            state["period"] = 60 / cadence * (1 + 0.05 * rng.uniform(-1, 1))
+            state["index"] += 1
+        phase = (t - state["start"]) / state["period"]
+        angle = 2 * math.pi * phase
+        vertical = peak * ((1 - harmonic) * math.cos(angle) + harmonic * math.cos(2 * angle + 0.5))
+        return [0.3 * peak * math.sin(angle), 0.1 * peak * math.sin((state["index"] + phase) * math.pi), vertical]
+
+    return motion
+
+
+def synthetic_motion(rng, activity, rate_hz):
+    """Acceleration in g in the world frame, gravity excluded, as a function of time."""
+    if activity == "still":
+        return lambda t: [0.0, 0.0, 0.0]
+    if activity == "walking":
+        return gait(rng, rng.uniform(90, 125), rng.uniform(0.15, 0.45), 0.3)
+    if activity == "running":
+        return gait(rng, rng.uniform(150, 185), rng.uniform(0.8, 2.0), 0.5)
+    if activity == "cycling":
+        # The hips rock with each pedal stroke, two per revolution, on a bumpy road
+        revolution = rng.uniform(55, 95) / 60
+        amplitude = rng.uniform(0.05, 0.2)
+        phase = rng.uniform(0, 2 * math.pi)
+        road = Lowpass(rng, 0.02, 3.0, rate_hz)
+
+        def motion(t):
+            angle = 2 * math.pi * revolution * t + phase
+            return [0.3 * amplitude * math.sin(2 * angle + 1), 0.6 * amplitude * math.sin(angle), amplitude * math.sin(2 * angle) + road.next()]
+
+        return motion
+    if activity == "vehicle":
+        # Suspension bumps, engine and road vibration, and slow braking and turning
+        bumps = Lowpass(rng, rng.uniform(0.03, 0.08), 2.0, rate_hz)
+        vibration = rng.uniform(0.01, 0.03)
+        manoeuvres = [(rng.uniform(0.05, 0.25), rng.uniform(8, 20), rng.uniform(0, 2 * math.pi)) for _ in range(2)]
+
+        def motion(t):
+            (forward, forward_s, forward_phase), (sideways, sideways_s, sideways_phase) = manoeuvres
+            bump = bumps.next()
+            return [
+                forward * math.sin(2 * math.pi * t / forward_s + forward_phase) + rng.gauss(0, vibration),
+                sideways * math.sin(2 * math.pi * t / sideways_s + sideways_phase) + 0.3 * bump + rng.gauss(0, vibration),
+                bump + rng.gauss(0, vibration),
+            ]
+
+        return motion
+    raise ValueError(activity)
+
+
+def synthetic_session(rng, activity, duration_s):
+    """A simulated recording. The features do not depend on how the sensor is worn,
+    so the sensor is level and the world and sensor frames are the same."""
+    rate_hz = rng.choice([50, 100, 200])
+    noise = rng.uniform(0.005, 0.02)
+    motion = synthetic_motion(rng, activity, rate_hz)
+    samples = []
+    for i in range(int(duration_s * rate_hz)):
+        t = i / rate_hz
+        x, y, z = motion(t)
+        acceleration = [x + rng.gauss(0, noise), y + rng.gauss(0, noise), z + 1 + rng.gauss(0, noise)]
+        samples.append((round(t * 1e6), acceleration))
+    return samples
+
+
+def gini(counts):
+    total = sum(counts)
+    return 1 - sum((c / total) ** 2 for c in counts) if total else 0.0
+
+
+def grow(rows, depth, max_depth, min_leaf):
+    """Returns a nested tree of ("leaf", label) and ("split", feature, threshold, below, above)."""
+    counts = [0] * len(ACTIVITIES)
+    for _, label in rows:
+        counts[label] += 1
+    majority = max(range(len(counts)), key=counts.__getitem__)
+    if depth == max_depth or counts[majority] == len(rows):
+        return ("leaf", majority)
+
+    best = None
+    impurity = gini(counts)
+    for feature in range(len(FEATURES)):
+        ordered = sorted(rows, key=lambda row: row[0][feature])
+        below = [0] * len(ACTIVITIES)
+        above = counts[:]
+        for i in range(len(ordered) - 1):
+            label = ordered[i][1]
+            below[label] += 1
+            above[label] -= 1
+            low, high = ordered[i][0][feature], ordered[i + 1][0][feature]
+            if low == high or i + 1 < min_leaf or len(ordered) - i - 1 < min_leaf:
+                continue
+            weighted = ((i + 1) * gini(below) + (len(ordered) - i - 1) * gini(above)) / len(ordered)
+            if best is None or weighted < best[0]:
+                best = (weighted, feature, (low + high) / 2)
+    if best is None or best[0] >= impurity:
+        return ("leaf", majority)
+
+    _, feature, threshold = best
+    below = grow([row for row in rows if row[0][feature] < threshold], depth + 1, max_depth, min_leaf)
+    above = grow([row for row in rows if row[0][feature] >= threshold], depth + 1, max_depth, min_leaf)
+    if below == above and below[0] == "leaf":
+        return below
+    return ("split", feature, threshold, below, above)
+
+
+def predict(tree, values):
+    while tree[0] == "split":
+        _, feature, threshold, below, above = tree
+        tree = below if values[feature] < threshold else above
+    return tree[1]
+
+
+def flatten(tree, nodes):
+    """Appends the tree to `nodes` in pre-order and returns the index of its root."""
+    index = len(nodes)
+    nodes.append(None)
+    if tree[0] == "leaf":
+        nodes[index] = f"Node::Leaf(Activity::{ACTIVITIES[tree[1]].capitalize()})"
+    else:
+        _, feature, threshold, below, above = tree
+        below_index = flatten(below, nodes)
+        above_index = flatten(above, nodes)
+        name = "".join(part.capitalize() for part in FEATURES[feature].split("_"))
+        nodes[index] = (
+            f"Node::Split {{ feature: Feature::{name}, threshold: {float_literal(threshold)}, "
+            f"below: {below_index}, above: {above_index} }}"
+        )
+    return index
+
+
+def float_literal(value):
+    text = f"{value:.6g}"
+    return text if "." in text or "e" in text else text + ".0"
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__.splitlines()[0])
+    parser.add_argument("recordings", nargs="*", help="labelled CSV recordings")
+    parser.add_argument("--synthetic", type=int, default=0, help="simulated sessions per activity")
+    parser.add_argument("--session", type=float, default=40.0, help="length of a simulated session in seconds")
+    parser.add_argument("--seed", type=int, default=1)
+    parser.add_argument("--max-depth", type=int, default=6)
+    parser.add_argument("--min-leaf", type=int, default=10, help="fewest windows in a leaf")
+    parser.add_argument("--use-path", default="example_support::activity", help="module the generated code imports from")
+    parser.add_argument("-o", "--output", help="Rust file to write, standard output if omitted")
+    args = parser.parse_args()
+
+    rows = []
+    for path in args.recordings:
+        for label, samples in read_recording(path):
+            rows += [(values, ACTIVITIES.index(label)) for values in windows(samples)]
+    rng = random.Random(args.seed)
+    for label, activity in enumerate(ACTIVITIES):
+        for _ in range(args.synthetic):
+            rows += [(values, label) for values in windows(synthetic_session(rng, activity, args.session))]
+    if not rows:
+        parser.error("no training data, pass recordings or --synthetic")
+
+    tree = grow(rows, 0, args.max_depth, args.min_leaf)
+
+    confusion = [[0] * len(ACTIVITIES) for _ in ACTIVITIES]
+    for values, label in rows:
+        confusion[label][predict(tree, values)] += 1
+    correct = sum(confusion[i][i] for i in range(len(ACTIVITIES)))
+    print(f"{len(rows)} windows, {correct / len(rows):.1%} classified correctly", file=sys.stderr)
+    print("actual \\ predicted  " + " ".join(f"{a:>8}" for a in ACTIVITIES), file=sys.stderr)
+    for activity, row in zip(ACTIVITIES, confusion):
+        print(f"{activity:>18}  " + " ".join(f"{c:>8}" for c in row), file=sys.stderr)
+
+    nodes = []
+    flatten(tree, nodes)
+    source = "\n".join(
+        [
+            "//! Decision tree for activity classification.",
+            "//!",
+            "//! Generated by `tools/train_activity.py`, do not edit.",
+            "",
+            f"use {args.use_path}::{{Activity, Feature, Node}};",
+            "",
+            "pub static NODES: &[Node] = &[",
+            *(f"    {node}," for node in nodes),
+            "];",
+            "",
+        ]
+    )
+    if args.output:
+        with open(args.output, "w") as file:
+            file.write(source)
+    else:
+        sys.stdout.write(source)
+
+
+if __name__ == "__main__":
+    main()